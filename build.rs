@@ -0,0 +1,7 @@
+// Generate the gRPC API's messages and service from its protobuf definition.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Use the vendored `protoc` so that building doesn't need one to be installed.
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::compile_protos("proto/arbiter.proto")?;
+    Ok(())
+}
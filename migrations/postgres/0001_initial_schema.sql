@@ -0,0 +1,31 @@
+-- Capacity that the schedule provides for each pool.
+CREATE TABLE capacity_schedule (
+    id                 SERIAL PRIMARY KEY,
+    start_time         INTEGER NOT NULL,
+    end_time           INTEGER NOT NULL,
+    capacity_amount    INTEGER NOT NULL,
+    pool               TEXT NOT NULL
+);
+
+-- Capacity that's been granted to users.
+CREATE TABLE user_reservations (
+    id                 SERIAL PRIMARY KEY,
+    start_time         INTEGER NOT NULL,
+    end_time           INTEGER NOT NULL,
+    reservation_amount INTEGER NOT NULL,
+    user_id            INTEGER NOT NULL,
+    pool               TEXT NOT NULL,
+    status             TEXT NOT NULL,
+    created_at         INTEGER NOT NULL,
+    updated_at         INTEGER NOT NULL
+);
+
+-- Capacity that's offline for maintenance. A missing `reduction_amount` means "all of it".
+CREATE TABLE maintenance_windows (
+    id                 SERIAL PRIMARY KEY,
+    pool               TEXT NOT NULL,
+    start_time         INTEGER NOT NULL,
+    end_time           INTEGER NOT NULL,
+    reduction_amount   INTEGER,
+    reason             TEXT NOT NULL
+);
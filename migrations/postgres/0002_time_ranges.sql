@@ -0,0 +1,58 @@
+-- Model timeframes as ranges so that Postgres can answer overlap questions with GiST indexes
+-- instead of scanning every row.
+--
+-- Times stay Unix epochs, so `INT8RANGE` is used instead of `TSTZRANGE`. The ranges are
+-- generated from `start_time` and `end_time`, so they can never disagree with them. Ranges
+-- include their start and exclude their end, just like capacity segments.
+
+-- `btree_gist` lets plain columns like `pool` share GiST indexes with ranges.
+CREATE EXTENSION IF NOT EXISTS btree_gist;
+
+ALTER TABLE capacity_schedule
+    ADD COLUMN time_range INT8RANGE GENERATED ALWAYS AS (int8range(start_time, end_time)) STORED;
+ALTER TABLE user_reservations
+    ADD COLUMN time_range INT8RANGE GENERATED ALWAYS AS (int8range(start_time, end_time)) STORED;
+ALTER TABLE maintenance_windows
+    ADD COLUMN time_range INT8RANGE GENERATED ALWAYS AS (int8range(start_time, end_time)) STORED;
+
+-- A pool can only have one capacity amount at a time, so its segments can't overlap.
+ALTER TABLE capacity_schedule
+    ADD CONSTRAINT capacity_schedule_no_overlap
+    EXCLUDE USING GIST (pool WITH =, time_range WITH &&);
+
+-- Reservations and maintenance windows can overlap each other, so they're only indexed.
+CREATE INDEX user_reservations_pool_time_range
+    ON user_reservations USING GIST (pool, time_range);
+CREATE INDEX maintenance_windows_pool_time_range
+    ON maintenance_windows USING GIST (pool, time_range);
+
+-- Find the most capacity that's reserved at once in a pool during a timeframe.
+--
+-- Usage only goes up when a reservation starts, so the peak is at the start of the timeframe or
+-- the start of a reservation inside of it. Cancelled reservations don't hold capacity.
+CREATE OR REPLACE FUNCTION peak_reserved_amount(target_pool TEXT, target_range INT8RANGE)
+RETURNS BIGINT
+LANGUAGE sql STABLE
+AS $$
+    WITH overlapping AS (
+        SELECT time_range, reservation_amount
+        FROM user_reservations
+        WHERE pool = target_pool
+          AND status <> 'cancelled'
+          AND time_range && target_range
+    ),
+    change_points AS (
+        SELECT lower(target_range) AS change_point
+        UNION
+        SELECT lower(time_range) FROM overlapping WHERE target_range @> lower(time_range)
+    )
+    SELECT COALESCE(MAX(usage), 0)::BIGINT
+    FROM (
+        SELECT (
+            SELECT COALESCE(SUM(reservation_amount), 0)
+            FROM overlapping
+            WHERE time_range @> change_point
+        ) AS usage
+        FROM change_points
+    ) AS usage_at_change_points
+$$;
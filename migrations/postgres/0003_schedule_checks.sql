@@ -0,0 +1,4 @@
+-- Refuse capacity segments that can't be right, instead of letting them confuse the hostess.
+ALTER TABLE capacity_schedule
+    ADD CONSTRAINT capacity_schedule_timeframe CHECK (start_time < end_time),
+    ADD CONSTRAINT capacity_schedule_amount CHECK (capacity_amount >= 0);
@@ -0,0 +1,75 @@
+-- Every change to a reservation request or reservation, in the order it happened.
+--
+-- Events carry the reservation's terms as they were after the change, so `user_reservations`
+-- can always be rebuilt from the ledger. Requests that never became reservations don't have a
+-- `reservation_id`.
+CREATE TABLE reservation_ledger (
+    id                 SERIAL PRIMARY KEY,
+    reservation_id     INTEGER,
+    event_kind         TEXT NOT NULL,
+    recorded_at        INTEGER NOT NULL,
+    start_time         INTEGER NOT NULL,
+    end_time           INTEGER NOT NULL,
+    capacity_amount    INTEGER NOT NULL,
+    user_id            INTEGER NOT NULL,
+    pool               TEXT NOT NULL,
+    reason             TEXT
+);
+CREATE INDEX reservation_ledger_reservation ON reservation_ledger (reservation_id);
+CREATE INDEX reservation_ledger_recorded_at ON reservation_ledger (recorded_at);
+
+-- The ledger is append-only.
+CREATE OR REPLACE FUNCTION reject_ledger_changes() RETURNS TRIGGER
+LANGUAGE plpgsql
+AS $$
+BEGIN
+    RAISE EXCEPTION 'reservation_ledger is append-only';
+END;
+$$;
+CREATE TRIGGER reservation_ledger_append_only
+    BEFORE UPDATE OR DELETE ON reservation_ledger
+    FOR EACH ROW EXECUTE FUNCTION reject_ledger_changes();
+CREATE TRIGGER reservation_ledger_no_truncate
+    BEFORE TRUNCATE ON reservation_ledger
+    FOR EACH STATEMENT EXECUTE FUNCTION reject_ledger_changes();
+
+-- Give reservations from before the ledger existed the history that they would have had.
+INSERT INTO reservation_ledger
+    (reservation_id, event_kind, recorded_at, start_time, end_time, capacity_amount, user_id, pool)
+SELECT id, 'approved', created_at, start_time, end_time, reservation_amount, user_id, pool
+FROM user_reservations
+ORDER BY id;
+INSERT INTO reservation_ledger
+    (reservation_id, event_kind, recorded_at, start_time, end_time, capacity_amount, user_id, pool)
+SELECT id, status, updated_at, start_time, end_time, reservation_amount, user_id, pool
+FROM user_reservations
+WHERE status <> 'pending'
+ORDER BY id;
+
+-- Preempted and expired reservations don't hold capacity either.
+CREATE OR REPLACE FUNCTION peak_reserved_amount(target_pool TEXT, target_range INT8RANGE)
+RETURNS BIGINT
+LANGUAGE sql STABLE
+AS $$
+    WITH overlapping AS (
+        SELECT time_range, reservation_amount
+        FROM user_reservations
+        WHERE pool = target_pool
+          AND status NOT IN ('cancelled', 'preempted', 'expired')
+          AND time_range && target_range
+    ),
+    change_points AS (
+        SELECT lower(target_range) AS change_point
+        UNION
+        SELECT lower(time_range) FROM overlapping WHERE target_range @> lower(time_range)
+    )
+    SELECT COALESCE(MAX(usage), 0)::BIGINT
+    FROM (
+        SELECT (
+            SELECT COALESCE(SUM(reservation_amount), 0)
+            FROM overlapping
+            WHERE time_range @> change_point
+        ) AS usage
+        FROM change_points
+    ) AS usage_at_change_points
+$$;
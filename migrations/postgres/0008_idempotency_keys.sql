@@ -0,0 +1,15 @@
+-- Responses to requests that were made with an `Idempotency-Key`, so that retries get the same
+-- response instead of being evaluated again.
+--
+-- Keys only have to be unique for each user. `response_body` is NULL while the request is still
+-- being evaluated.
+CREATE TABLE idempotency_keys (
+    idempotency_key TEXT NOT NULL,
+    user_id         INTEGER NOT NULL,
+    request_hash    TEXT NOT NULL,
+    response_body   TEXT,
+    created_at      INTEGER NOT NULL,
+    expires_at      INTEGER NOT NULL,
+    PRIMARY KEY (user_id, idempotency_key)
+);
+CREATE INDEX idempotency_keys_expires_at ON idempotency_keys (expires_at);
@@ -0,0 +1,4 @@
+-- Reservations move from pending to active when they start, and from active to completed when
+-- they end. The lifecycle engine looks for the ones that are due by status and boundary.
+CREATE INDEX user_reservations_status_start ON user_reservations (status, start_time);
+CREATE INDEX user_reservations_status_end ON user_reservations (status, end_time);
@@ -0,0 +1,3 @@
+-- Reservations are listed by user in ID order when no other sort is given, so users with a long
+-- history don't have to be read through by start time.
+CREATE INDEX user_reservations_user_id ON user_reservations (user_id, id);
@@ -0,0 +1,48 @@
+-- Capacity that the schedule provides for each pool.
+--
+-- Times are Unix epochs. Timeframes include their start and exclude their end, and they're
+-- indexed by pool and time so that overlap checks don't scan every row.
+CREATE TABLE capacity_schedule (
+    id                 INTEGER PRIMARY KEY AUTOINCREMENT,
+    start_time         INTEGER NOT NULL,
+    end_time           INTEGER NOT NULL,
+    capacity_amount    INTEGER NOT NULL,
+    pool               TEXT NOT NULL
+);
+CREATE INDEX capacity_schedule_pool_time ON capacity_schedule (pool, start_time, end_time);
+
+-- A pool can only have one capacity amount at a time, so its segments can't overlap.
+CREATE TRIGGER capacity_schedule_no_overlap
+BEFORE INSERT ON capacity_schedule
+WHEN EXISTS (
+    SELECT 1 FROM capacity_schedule
+    WHERE pool = NEW.pool AND start_time < NEW.end_time AND end_time > NEW.start_time
+)
+BEGIN
+    SELECT RAISE(ABORT, 'capacity segments in the same pool can''t overlap');
+END;
+
+-- Capacity that's been granted to users.
+CREATE TABLE user_reservations (
+    id                 INTEGER PRIMARY KEY AUTOINCREMENT,
+    start_time         INTEGER NOT NULL,
+    end_time           INTEGER NOT NULL,
+    reservation_amount INTEGER NOT NULL,
+    user_id            INTEGER NOT NULL,
+    pool               TEXT NOT NULL,
+    status             TEXT NOT NULL,
+    created_at         INTEGER NOT NULL,
+    updated_at         INTEGER NOT NULL
+);
+CREATE INDEX user_reservations_pool_time ON user_reservations (pool, start_time, end_time);
+
+-- Capacity that's offline for maintenance. A missing `reduction_amount` means "all of it".
+CREATE TABLE maintenance_windows (
+    id                 INTEGER PRIMARY KEY AUTOINCREMENT,
+    pool               TEXT NOT NULL,
+    start_time         INTEGER NOT NULL,
+    end_time           INTEGER NOT NULL,
+    reduction_amount   INTEGER,
+    reason             TEXT NOT NULL
+);
+CREATE INDEX maintenance_windows_pool_time ON maintenance_windows (pool, start_time, end_time);
@@ -0,0 +1,16 @@
+-- Refuse capacity segments that can't be right, instead of letting them confuse the hostess.
+--
+-- SQLite can't add constraints to existing tables, so triggers check new segments instead.
+CREATE TRIGGER capacity_schedule_timeframe
+BEFORE INSERT ON capacity_schedule
+WHEN NEW.start_time >= NEW.end_time
+BEGIN
+    SELECT RAISE(ABORT, 'capacity segments must begin before they end');
+END;
+
+CREATE TRIGGER capacity_schedule_amount
+BEFORE INSERT ON capacity_schedule
+WHEN NEW.capacity_amount < 0
+BEGIN
+    SELECT RAISE(ABORT, 'capacity segments can''t have negative capacity');
+END;
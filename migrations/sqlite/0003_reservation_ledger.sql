@@ -0,0 +1,44 @@
+-- Every change to a reservation request or reservation, in the order it happened.
+--
+-- Events carry the reservation's terms as they were after the change, so `user_reservations`
+-- can always be rebuilt from the ledger. Requests that never became reservations don't have a
+-- `reservation_id`.
+CREATE TABLE reservation_ledger (
+    id                 INTEGER PRIMARY KEY AUTOINCREMENT,
+    reservation_id     INTEGER,
+    event_kind         TEXT NOT NULL,
+    recorded_at        INTEGER NOT NULL,
+    start_time         INTEGER NOT NULL,
+    end_time           INTEGER NOT NULL,
+    capacity_amount    INTEGER NOT NULL,
+    user_id            INTEGER NOT NULL,
+    pool               TEXT NOT NULL,
+    reason             TEXT
+);
+CREATE INDEX reservation_ledger_reservation ON reservation_ledger (reservation_id);
+CREATE INDEX reservation_ledger_recorded_at ON reservation_ledger (recorded_at);
+
+-- The ledger is append-only.
+CREATE TRIGGER reservation_ledger_no_update
+BEFORE UPDATE ON reservation_ledger
+BEGIN
+    SELECT RAISE(ABORT, 'reservation_ledger is append-only');
+END;
+CREATE TRIGGER reservation_ledger_no_delete
+BEFORE DELETE ON reservation_ledger
+BEGIN
+    SELECT RAISE(ABORT, 'reservation_ledger is append-only');
+END;
+
+-- Give reservations from before the ledger existed the history that they would have had.
+INSERT INTO reservation_ledger
+    (reservation_id, event_kind, recorded_at, start_time, end_time, capacity_amount, user_id, pool)
+SELECT id, 'approved', created_at, start_time, end_time, reservation_amount, user_id, pool
+FROM user_reservations
+ORDER BY id;
+INSERT INTO reservation_ledger
+    (reservation_id, event_kind, recorded_at, start_time, end_time, capacity_amount, user_id, pool)
+SELECT id, status, updated_at, start_time, end_time, reservation_amount, user_id, pool
+FROM user_reservations
+WHERE status <> 'pending'
+ORDER BY id;
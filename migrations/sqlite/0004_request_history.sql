@@ -0,0 +1,22 @@
+-- Every reservation request that was evaluated, whether it was granted or not.
+--
+-- Denied requests are the unmet demand that capacity planning is based on. The bottleneck is
+-- the stretch of the requested timeframe with the least capacity left, if capacity was
+-- considered before the request was decided.
+CREATE TABLE reservation_requests (
+    id                          INTEGER PRIMARY KEY AUTOINCREMENT,
+    requested_at                INTEGER NOT NULL,
+    start_time                  INTEGER NOT NULL,
+    end_time                    INTEGER NOT NULL,
+    capacity_amount             INTEGER NOT NULL,
+    user_id                     INTEGER NOT NULL,
+    pool                        TEXT NOT NULL,
+    outcome                     TEXT NOT NULL CHECK (outcome IN ('approved', 'denied')),
+    denial_reason               TEXT,
+    reservation_id              INTEGER,
+    bottleneck_start            INTEGER,
+    bottleneck_end              INTEGER,
+    bottleneck_available_amount INTEGER
+);
+CREATE INDEX reservation_requests_pool_time ON reservation_requests (pool, start_time);
+CREATE INDEX reservation_requests_user_time ON reservation_requests (user_id, start_time);
@@ -0,0 +1,20 @@
+-- What capacity costs and what reservations were charged.
+--
+-- Money is counted in whole microcredits (millionths of a credit). Segments and reservations
+-- from before chargeback existed are free.
+ALTER TABLE capacity_schedule
+    ADD COLUMN microcredits_per_unit_second INTEGER NOT NULL DEFAULT 0
+        CHECK (microcredits_per_unit_second >= 0);
+
+ALTER TABLE user_reservations
+    ADD COLUMN cost_microcredits INTEGER NOT NULL DEFAULT 0 CHECK (cost_microcredits >= 0);
+ALTER TABLE user_reservations
+    ADD COLUMN refund_microcredits INTEGER NOT NULL DEFAULT 0 CHECK (refund_microcredits >= 0);
+-- Invoices add up the reservations that were made during a billing period.
+CREATE INDEX user_reservations_created_at ON user_reservations (created_at);
+
+-- Ledger events carry the reservation's charges after the change, so they're rebuilt too.
+ALTER TABLE reservation_ledger
+    ADD COLUMN cost_microcredits INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE reservation_ledger
+    ADD COLUMN refund_microcredits INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,18 @@
+-- Prices that reservation requests were quoted, which can be redeemed once before they expire.
+--
+-- Quotes don't hold capacity. They only promise a price for a request's exact terms.
+CREATE TABLE price_quotes (
+    quote_token            TEXT PRIMARY KEY,
+    start_time             INTEGER NOT NULL,
+    end_time               INTEGER NOT NULL,
+    capacity_amount        INTEGER NOT NULL,
+    user_id                INTEGER NOT NULL,
+    pool                   TEXT NOT NULL,
+    base_cost_microcredits INTEGER NOT NULL CHECK (base_cost_microcredits >= 0),
+    price_percent          INTEGER NOT NULL CHECK (price_percent >= 100),
+    price_microcredits     INTEGER NOT NULL CHECK (price_microcredits >= 0),
+    quoted_at              INTEGER NOT NULL,
+    expires_at             INTEGER NOT NULL,
+    redeemed_at            INTEGER
+);
+CREATE INDEX price_quotes_expires_at ON price_quotes (expires_at);
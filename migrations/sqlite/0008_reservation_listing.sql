@@ -0,0 +1,5 @@
+-- Reservations are listed by user, start, or end, and paged through in that order with their IDs
+-- breaking ties. Pools are already indexed with their timeframes, and creation times for billing.
+CREATE INDEX user_reservations_user_start ON user_reservations (user_id, start_time, id);
+CREATE INDEX user_reservations_start_time ON user_reservations (start_time, id);
+CREATE INDEX user_reservations_end_time ON user_reservations (end_time, id);
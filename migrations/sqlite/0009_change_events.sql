@@ -0,0 +1,14 @@
+-- Changes that followers of the event stream are told about, like reservations being created or
+-- a pool's schedule changing.
+--
+-- IDs only count up, so followers resume after the last event that they saw. `user_id` is NULL
+-- for changes that aren't to anyone's reservation. `payload` is what changed, as JSON.
+CREATE TABLE change_events (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    event_kind  TEXT NOT NULL,
+    recorded_at INTEGER NOT NULL,
+    pool        TEXT NOT NULL,
+    user_id     INTEGER,
+    payload     TEXT NOT NULL
+);
+CREATE INDEX change_events_pool ON change_events (pool, id);
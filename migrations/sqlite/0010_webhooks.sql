@@ -0,0 +1,29 @@
+-- URLs that are sent change events, and every attempt to send them.
+--
+-- `event_kinds` is a comma-separated list of change event kinds. `last_event_id` is the last
+-- change event that was handed out to the subscription, so deliveries pick up after it when
+-- Arbiter restarts. `secret` signs deliveries and is never sent back to callers.
+CREATE TABLE webhook_subscriptions (
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    url           TEXT NOT NULL,
+    secret        TEXT NOT NULL,
+    event_kinds   TEXT NOT NULL,
+    pool          TEXT,
+    user_id       INTEGER,
+    last_event_id INTEGER NOT NULL,
+    created_at    INTEGER NOT NULL
+);
+
+-- `status_code` is NULL when the receiver couldn't be reached, and `error` is NULL when the
+-- delivery was accepted. Deliveries outlive their subscriptions.
+CREATE TABLE webhook_deliveries (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    subscription_id INTEGER NOT NULL,
+    event_id        INTEGER NOT NULL,
+    attempt         INTEGER NOT NULL,
+    attempted_at    INTEGER NOT NULL,
+    status_code     INTEGER,
+    error           TEXT,
+    is_delivered    INTEGER NOT NULL
+);
+CREATE INDEX webhook_deliveries_subscription ON webhook_deliveries (subscription_id, id);
@@ -0,0 +1,548 @@
+//! Authentication
+//!
+//! `auth` works out who's calling the RESTful API and what they're allowed to do.
+//!
+//! Callers send an `Authorization: Bearer <token>` header. The token is either an API key from
+//! the config file, or a JWT that's signed with the configured HMAC secret (HS256) and verified
+//! locally, so there's no identity server to call. Either way, the caller becomes a `Principal`:
+//! the user that they are and the role that they have.
+//!
+//! ```toml
+//! [auth]
+//! jwt_secret = "at least 32 bytes of shared secret"
+//!
+//! [[auth.api_keys]]
+//! key = "..."
+//! user_id = 42
+//! role = "operator"
+//! ```
+
+// Standard library crates.
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+// External crates.
+use anyhow::{ensure, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use serde_derive::{Deserialize, Serialize};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+// Project crates.
+use crate::clock::Clock;
+
+/// Shortest JWT secret that's accepted, in bytes. HS256 keys shouldn't be shorter than its hash.
+const MIN_JWT_SECRET_LENGTH: usize = 32;
+
+/// What a caller is allowed to do. Every role can do everything that the roles before it can.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Reserves capacity for themselves.
+    #[default]
+    User,
+    /// Runs the schedule: maintenance, reports, and reserving on behalf of users.
+    Operator,
+    /// Also decides what capacity costs.
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let role_name = match self {
+            Role::User => "user",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        };
+        write!(formatter, "{role_name}")
+    }
+}
+
+/// Caller that proved who they are.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Principal {
+    pub user_id: u32,
+    pub role: Role,
+}
+
+impl Principal {
+    /// Ensure the caller has at least the given role.
+    pub fn ensure_role(&self, role: Role) -> Result<(), AuthError> {
+        match self.role >= role {
+            true => Ok(()),
+            false => Err(AuthError::Forbidden(format!(
+                "User \"{}\" needs the \"{}\" role",
+                self.user_id, role
+            ))),
+        }
+    }
+
+    /// Work out which user a request acts for.
+    ///
+    /// Requests act for the caller unless they name another user, which only operators can do.
+    pub fn act_for(&self, user_id: Option<u32>) -> Result<u32, AuthError> {
+        match user_id {
+            None => Ok(self.user_id),
+            Some(user_id) if user_id == self.user_id => Ok(user_id),
+            Some(user_id) => {
+                self.ensure_role(Role::Operator).map_err(|_| {
+                    AuthError::Forbidden(format!(
+                        "User \"{}\" can't act for user \"{}\"",
+                        self.user_id, user_id
+                    ))
+                })?;
+                Ok(user_id)
+            }
+        }
+    }
+}
+
+/// Why a caller was turned away.
+#[derive(Debug, PartialEq)]
+pub enum AuthError {
+    /// The caller didn't prove who they are.
+    Unauthenticated(String),
+    /// The caller isn't allowed to do what they asked.
+    Forbidden(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::Unauthenticated(auth_message) | AuthError::Forbidden(auth_message) => {
+                write!(formatter, "{auth_message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Long-lived credential for a service or a person.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKey {
+    /// Secret that's sent as the bearer token.
+    pub key: String,
+    /// User that the key belongs to.
+    pub user_id: u32,
+    #[serde(default)]
+    pub role: Role,
+}
+
+/// Credentials that the RESTful API accepts.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub api_keys: Vec<ApiKey>,
+    /// Shared secret that JWTs are signed with. JWTs aren't accepted without one.
+    pub jwt_secret: Option<String>,
+    /// Issuer that JWTs must name in their `iss` claim, if any.
+    pub jwt_issuer: Option<String>,
+}
+
+impl AuthConfig {
+    /// Ensure the credentials are usable.
+    pub fn validate(&self) -> Result<()> {
+        let mut seen_keys = HashSet::new();
+        for api_key in &self.api_keys {
+            ensure!(
+                !api_key.key.is_empty(),
+                format!("API key for user \"{}\" is empty", api_key.user_id)
+            );
+            ensure!(
+                seen_keys.insert(api_key.key.as_str()),
+                format!(
+                    "API key for user \"{}\" is used more than once",
+                    api_key.user_id
+                )
+            );
+        }
+        if let Some(jwt_secret) = &self.jwt_secret {
+            ensure!(
+                jwt_secret.len() >= MIN_JWT_SECRET_LENGTH,
+                format!("`jwt_secret` must be at least {MIN_JWT_SECRET_LENGTH} bytes")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// JOSE header of a JWT.
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+}
+
+/// Claims that Arbiter reads from a JWT.
+#[derive(Deserialize, Serialize)]
+pub struct JwtClaims {
+    /// User ID, as a string like JWT subjects usually are.
+    pub sub: String,
+    #[serde(default)]
+    pub role: Role,
+    /// When the token stops being accepted, represented by Unix epoch format.
+    pub exp: u64,
+    /// When the token starts being accepted, represented by Unix epoch format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+}
+
+/// Turns bearer tokens into principals.
+pub struct Authenticator {
+    clock: Arc<dyn Clock>,
+    config: AuthConfig,
+}
+
+impl Authenticator {
+    /// Create a new `Authenticator`.
+    ///
+    /// # Arguments
+    /// - `clock`: Decides whether JWTs have expired.
+    /// - `config`: Credentials to accept.
+    pub fn new(clock: Arc<dyn Clock>, config: AuthConfig) -> Self {
+        if config.api_keys.is_empty() && config.jwt_secret.is_none() {
+            warn!("No API keys or JWT secret configured, so only public routes will answer");
+        }
+        Self { clock, config }
+    }
+
+    /// Work out who's calling from their `Authorization` header, and ensure they have at least
+    /// the given role.
+    pub fn authorize(
+        &self,
+        authorization: Option<&str>,
+        role: Role,
+    ) -> Result<Principal, AuthError> {
+        let principal = self.authenticate(authorization)?;
+        principal.ensure_role(role)?;
+        Ok(principal)
+    }
+
+    /// Work out who's calling from their `Authorization` header.
+    pub fn authenticate(&self, authorization: Option<&str>) -> Result<Principal, AuthError> {
+        let authorization = authorization.ok_or_else(|| {
+            AuthError::Unauthenticated(String::from("Missing `Authorization` header"))
+        })?;
+        let bearer_token = authorization
+            .strip_prefix("Bearer ")
+            .map(str::trim)
+            .filter(|bearer_token| !bearer_token.is_empty())
+            .ok_or_else(|| {
+                AuthError::Unauthenticated(String::from(
+                    "`Authorization` header must be \"Bearer <token>\"",
+                ))
+            })?;
+        if let Some(principal) = self.find_api_key(bearer_token) {
+            return Ok(principal);
+        }
+        match &self.config.jwt_secret {
+            // Tokens that aren't API keys are only checked as JWTs if they look like one.
+            Some(jwt_secret) if bearer_token.contains('.') => {
+                self.verify_jwt(bearer_token, jwt_secret)
+            }
+            _ => Err(AuthError::Unauthenticated(String::from("Invalid API key"))),
+        }
+    }
+
+    /// Find the principal that an API key belongs to.
+    ///
+    /// Every key is compared in constant time so that timing doesn't give keys away.
+    fn find_api_key(&self, bearer_token: &str) -> Option<Principal> {
+        let mut found_key = None;
+        for api_key in &self.config.api_keys {
+            if bool::from(api_key.key.as_bytes().ct_eq(bearer_token.as_bytes())) {
+                found_key = Some(api_key);
+            }
+        }
+        found_key.map(|api_key| Principal {
+            user_id: api_key.user_id,
+            role: api_key.role,
+        })
+    }
+
+    /// Check a JWT's signature and claims.
+    fn verify_jwt(&self, jwt: &str, jwt_secret: &str) -> Result<Principal, AuthError> {
+        let invalid_token =
+            |reason: &str| AuthError::Unauthenticated(format!("Invalid bearer token: {reason}"));
+        let mut jwt_parts = jwt.split('.');
+        let (Some(encoded_header), Some(encoded_claims), Some(encoded_signature), None) = (
+            jwt_parts.next(),
+            jwt_parts.next(),
+            jwt_parts.next(),
+            jwt_parts.next(),
+        ) else {
+            return Err(invalid_token("it isn't a JWT"));
+        };
+        let header: JwtHeader = decode_jwt_part(encoded_header)
+            .ok_or_else(|| invalid_token("its header can't be read"))?;
+        // Only accept the algorithm that's configured, so tokens can't pick a weaker one.
+        if header.alg != "HS256" {
+            return Err(invalid_token("it isn't signed with HS256"));
+        }
+        let signature = URL_SAFE_NO_PAD
+            .decode(encoded_signature)
+            .map_err(|_| invalid_token("its signature can't be read"))?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(jwt_secret.as_bytes())
+            .map_err(|_| invalid_token("the JWT secret is unusable"))?;
+        mac.update(encoded_header.as_bytes());
+        mac.update(b".");
+        mac.update(encoded_claims.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| invalid_token("its signature doesn't match"))?;
+        let claims: JwtClaims = decode_jwt_part(encoded_claims)
+            .ok_or_else(|| invalid_token("its claims can't be read"))?;
+        let epoch_now = u64::from(self.clock.now());
+        if claims.exp <= epoch_now {
+            return Err(invalid_token("it expired"));
+        }
+        if claims.nbf.is_some_and(|not_before| not_before > epoch_now) {
+            return Err(invalid_token("it isn't valid yet"));
+        }
+        if let Some(jwt_issuer) = &self.config.jwt_issuer {
+            if claims.iss.as_ref() != Some(jwt_issuer) {
+                return Err(invalid_token("it's from the wrong issuer"));
+            }
+        }
+        let user_id = claims
+            .sub
+            .parse()
+            .map_err(|_| invalid_token("its subject isn't a user ID"))?;
+        Ok(Principal {
+            user_id,
+            role: claims.role,
+        })
+    }
+}
+
+/// Read one base64url encoded JSON part of a JWT.
+fn decode_jwt_part<Part: serde::de::DeserializeOwned>(encoded_part: &str) -> Option<Part> {
+    let json_part = URL_SAFE_NO_PAD.decode(encoded_part).ok()?;
+    serde_json::from_slice(&json_part).ok()
+}
+
+/// Sign claims into an HS256 JWT.
+///
+/// Arbiter only verifies tokens, but tests (and operators' scripts) need to make them.
+#[allow(unused)]
+pub fn sign_jwt(claims: &JwtClaims, jwt_secret: &str) -> Result<String> {
+    let encoded_header = URL_SAFE_NO_PAD.encode(br#"{"alg":"HS256","typ":"JWT"}"#);
+    let encoded_claims = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?);
+    let mut mac = Hmac::<Sha256>::new_from_slice(jwt_secret.as_bytes())?;
+    mac.update(encoded_header.as_bytes());
+    mac.update(b".");
+    mac.update(encoded_claims.as_bytes());
+    let encoded_signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+    Ok(format!(
+        "{encoded_header}.{encoded_claims}.{encoded_signature}"
+    ))
+}
+
+/// Example credentials that are available to all tests.
+#[cfg(test)]
+pub mod test_examples {
+    // Standard library crates.
+    use std::sync::Arc;
+
+    // Project crates.
+    use super::{ApiKey, AuthConfig, Authenticator, Role};
+    use crate::clock::test_examples::test_clock;
+
+    /// API key for user 42, who's an ordinary user.
+    pub const USER_KEY: &str = "test-user-key";
+    /// API key for user 7, who's an operator.
+    pub const OPERATOR_KEY: &str = "test-operator-key";
+    /// API key for user 1, who's an admin.
+    pub const ADMIN_KEY: &str = "test-admin-key";
+    /// Secret that test JWTs are signed with.
+    pub const JWT_SECRET: &str = "test-jwt-secret-that-is-32-bytes";
+
+    /// Credentials for one principal with every role, plus JWTs.
+    pub fn test_auth_config() -> AuthConfig {
+        let api_key = |key: &str, user_id, role| ApiKey {
+            key: key.to_string(),
+            user_id,
+            role,
+        };
+        AuthConfig {
+            api_keys: vec![
+                api_key(USER_KEY, 42, Role::User),
+                api_key(OPERATOR_KEY, 7, Role::Operator),
+                api_key(ADMIN_KEY, 1, Role::Admin),
+            ],
+            jwt_secret: Some(JWT_SECRET.to_string()),
+            jwt_issuer: None,
+        }
+    }
+
+    /// Authenticator that accepts the test credentials, on the test clock.
+    pub fn test_authenticator() -> Arc<Authenticator> {
+        Arc::new(Authenticator::new(
+            Arc::new(test_clock()),
+            test_auth_config(),
+        ))
+    }
+
+    /// `Authorization` header for a bearer token.
+    pub fn bearer(token: &str) -> String {
+        format!("Bearer {token}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Standard library crates.
+    use std::sync::Arc;
+
+    // External crates.
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+
+    // Project crates.
+    use super::test_examples::{
+        bearer, test_auth_config, test_authenticator, ADMIN_KEY, JWT_SECRET, OPERATOR_KEY, USER_KEY,
+    };
+    use super::{sign_jwt, AuthConfig, AuthError, Authenticator, JwtClaims, Principal, Role};
+    use crate::clock::test_examples::{test_clock, TEST_EPOCH_NOW};
+
+    /// Claims for user 42 that are good for an hour.
+    fn user_claims() -> JwtClaims {
+        JwtClaims {
+            sub: String::from("42"),
+            role: Role::User,
+            exp: u64::from(TEST_EPOCH_NOW) + 3600,
+            nbf: None,
+            iss: None,
+        }
+    }
+
+    #[test]
+    fn test_api_keys() {
+        let authenticator = test_authenticator();
+        let principal = authenticator
+            .authenticate(Some(&bearer(OPERATOR_KEY)))
+            .unwrap();
+        assert_eq!(
+            principal,
+            Principal {
+                user_id: 7,
+                role: Role::Operator
+            }
+        );
+        assert!(matches!(
+            authenticator.authenticate(None),
+            Err(AuthError::Unauthenticated(_))
+        ));
+        assert!(matches!(
+            authenticator.authenticate(Some(&bearer("wrong-key"))),
+            Err(AuthError::Unauthenticated(_))
+        ));
+        // Keys have to be sent as bearer tokens.
+        assert!(authenticator.authenticate(Some(USER_KEY)).is_err());
+    }
+
+    #[test]
+    fn test_roles() {
+        let authenticator = test_authenticator();
+        assert!(authenticator
+            .authorize(Some(&bearer(ADMIN_KEY)), Role::Operator)
+            .is_ok());
+        assert!(matches!(
+            authenticator.authorize(Some(&bearer(USER_KEY)), Role::Operator),
+            Err(AuthError::Forbidden(_))
+        ));
+        let user = authenticator.authenticate(Some(&bearer(USER_KEY))).unwrap();
+        assert_eq!(user.act_for(None), Ok(42));
+        assert_eq!(user.act_for(Some(42)), Ok(42));
+        assert!(matches!(
+            user.act_for(Some(43)),
+            Err(AuthError::Forbidden(_))
+        ));
+        // Operators can act for anyone.
+        let operator = authenticator
+            .authenticate(Some(&bearer(OPERATOR_KEY)))
+            .unwrap();
+        assert_eq!(operator.act_for(Some(43)), Ok(43));
+    }
+
+    #[test]
+    fn test_jwts() {
+        let authenticator = test_authenticator();
+        let user_jwt = sign_jwt(&user_claims(), JWT_SECRET).unwrap();
+        let principal = authenticator
+            .authenticate(Some(&bearer(&user_jwt)))
+            .unwrap();
+        assert_eq!(principal.user_id, 42);
+        assert_eq!(principal.role, Role::User);
+
+        // Tokens that weren't signed with the secret aren't accepted.
+        let forged_jwt = sign_jwt(
+            &JwtClaims {
+                role: Role::Admin,
+                ..user_claims()
+            },
+            "some-other-secret-that-is-32-bytes",
+        )
+        .unwrap();
+        assert!(authenticator
+            .authenticate(Some(&bearer(&forged_jwt)))
+            .is_err());
+        // Neither are tokens that were changed after they were signed.
+        let (signed_part, signature) = user_jwt.rsplit_once('.').unwrap();
+        let (encoded_header, _) = signed_part.split_once('.').unwrap();
+        let admin_claims = URL_SAFE_NO_PAD.encode(
+            serde_json::to_vec(&JwtClaims {
+                role: Role::Admin,
+                ..user_claims()
+            })
+            .unwrap(),
+        );
+        let tampered_jwt = format!("{encoded_header}.{admin_claims}.{signature}");
+        assert!(authenticator
+            .authenticate(Some(&bearer(&tampered_jwt)))
+            .is_err());
+        // Or expired ones.
+        let expired_jwt = sign_jwt(
+            &JwtClaims {
+                exp: u64::from(TEST_EPOCH_NOW),
+                ..user_claims()
+            },
+            JWT_SECRET,
+        )
+        .unwrap();
+        assert!(authenticator
+            .authenticate(Some(&bearer(&expired_jwt)))
+            .is_err());
+        // Or ones from other issuers, when an issuer is configured.
+        let issuer_authenticator = Authenticator::new(
+            Arc::new(test_clock()),
+            AuthConfig {
+                jwt_issuer: Some(String::from("arbiter")),
+                ..test_auth_config()
+            },
+        );
+        assert!(issuer_authenticator
+            .authenticate(Some(&bearer(&user_jwt)))
+            .is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(test_auth_config().validate().is_ok());
+        let short_secret = AuthConfig {
+            jwt_secret: Some(String::from("too short")),
+            ..Default::default()
+        };
+        assert!(short_secret.validate().is_err());
+        let mut repeated_keys = test_auth_config();
+        repeated_keys.api_keys[1].key = USER_KEY.to_string();
+        assert!(repeated_keys.validate().is_err());
+    }
+}
@@ -0,0 +1,195 @@
+//! Batches
+//!
+//! `batch` describes how a batch of reservation requests is worked through, and what became of
+//! every request in it.
+//!
+//! Requests are either tried in the order they were sent, or biggest first so that small
+//! requests can fill the gaps that big ones leave. Atomic batches are all-or-nothing: if any
+//! request can't be reserved, then none of them are.
+
+// External crates.
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use serde_derive::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Project crates.
+use crate::common::Reservation;
+use crate::policy::PolicyViolation;
+use crate::ReservationRequest;
+
+/// Most requests that one batch can have.
+pub const MAX_BATCH_SIZE: usize = 1000;
+
+/// Order that a batch's requests are tried in.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchOrdering {
+    /// Try requests in the order they were sent.
+    #[default]
+    InOrder,
+    /// Try the requests that need the most unit-seconds first, so that smaller requests fill in
+    /// around them.
+    OptimizePacking,
+}
+
+/// Why a request wasn't reserved.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DenialKind {
+    /// The request doesn't make sense, like a pool without a schedule or an unusable quote.
+    InvalidRequest,
+    /// The request breaks the reservation policy.
+    PolicyViolation,
+    /// There isn't enough capacity left during the request's timeframe.
+    NotEnoughCapacity,
+    /// Another request in the atomic batch wasn't reserved, so this one wasn't either.
+    BatchRefused,
+}
+
+impl DenialKind {
+    /// Name of the kind of denial as it's serialized.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DenialKind::InvalidRequest => "invalid_request",
+            DenialKind::PolicyViolation => "policy_violation",
+            DenialKind::NotEnoughCapacity => "not_enough_capacity",
+            DenialKind::BatchRefused => "batch_refused",
+        }
+    }
+
+    /// Work out what kind of denial an evaluation error is.
+    pub fn of_error(request_error: &anyhow::Error) -> Self {
+        match request_error.is::<PolicyViolation>() {
+            true => DenialKind::PolicyViolation,
+            false => DenialKind::InvalidRequest,
+        }
+    }
+}
+
+/// Why a request wasn't reserved, in a way that programs and people can both read.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct Denial {
+    pub kind: DenialKind,
+    pub user_message: String,
+}
+
+/// What became of one request in a batch.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct BatchItemResult {
+    /// Where the request was in the batch, starting at 0.
+    pub index: usize,
+    pub is_reserved: bool,
+    pub reservation_id: Option<u32>,
+    /// Start time that was evaluated after resolving relative times like `"now"`.
+    pub start_time: Option<u32>,
+    /// End time that was evaluated after resolving relative times like `"+1h"`.
+    pub end_time: Option<u32>,
+    pub cost_microcredits: Option<u64>,
+    pub denial: Option<Denial>,
+}
+
+impl BatchItemResult {
+    /// Result for a request that was reserved.
+    pub fn reserved(index: usize, reservation: &Reservation) -> Self {
+        Self {
+            index,
+            is_reserved: true,
+            reservation_id: Some(reservation.reservation_id),
+            start_time: Some(reservation.start_time),
+            end_time: Some(reservation.end_time),
+            cost_microcredits: Some(reservation.cost_microcredits),
+            denial: None,
+        }
+    }
+
+    /// Result for a request that wasn't reserved.
+    pub fn denied(index: usize, kind: DenialKind, user_message: String) -> Self {
+        Self {
+            index,
+            is_reserved: false,
+            reservation_id: None,
+            start_time: None,
+            end_time: None,
+            cost_microcredits: None,
+            denial: Some(Denial { kind, user_message }),
+        }
+    }
+
+    /// Report the exact timeframe that was asked for.
+    pub fn with_timeframe(mut self, reservation_request: &ReservationRequest) -> Self {
+        self.start_time = Some(reservation_request.start_time);
+        self.end_time = Some(reservation_request.end_time);
+        self
+    }
+}
+
+/// Work out the order to try a batch's requests in.
+///
+/// # Returns
+/// Indexes of the requests, in the order they should be tried.
+pub fn batch_order(
+    reservation_requests: &[&ReservationRequest],
+    ordering: BatchOrdering,
+) -> Vec<usize> {
+    let mut batch_order: Vec<usize> = (0..reservation_requests.len()).collect();
+    if ordering == BatchOrdering::OptimizePacking {
+        // Biggest first, like first-fit decreasing bin packing. Sorting is stable, so requests
+        // that are the same size keep their order.
+        batch_order.sort_by_key(|&index| {
+            let reservation_request = reservation_requests[index];
+            let duration = reservation_request
+                .end_time
+                .saturating_sub(reservation_request.start_time);
+            std::cmp::Reverse(u64::from(duration) * u64::from(reservation_request.capacity_amount))
+        });
+    }
+    batch_order
+}
+
+#[cfg(test)]
+mod tests {
+    // Project crates.
+    use super::{batch_order, BatchOrdering, DenialKind};
+    use crate::policy::PolicyViolation;
+    use crate::ReservationRequest;
+
+    #[test]
+    fn test_batch_order() {
+        let small_request = ReservationRequest::new(1707165008, 1707168608, 1, 42);
+        let big_request = ReservationRequest::new(1707165008, 1707251408, 32, 42);
+        let medium_request = ReservationRequest::new(1707165008, 1707168608, 32, 42);
+        let other_small_request = ReservationRequest::new(1707168608, 1707172208, 1, 42);
+        let batch = [
+            &small_request,
+            &big_request,
+            &medium_request,
+            &other_small_request,
+        ];
+        assert_eq!(
+            batch_order(&batch, BatchOrdering::InOrder),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(
+            batch_order(&batch, BatchOrdering::OptimizePacking),
+            vec![1, 2, 0, 3]
+        );
+    }
+
+    #[test]
+    fn test_denial_kind() {
+        let policy_error = anyhow::Error::from(PolicyViolation::OffGrid {
+            time: 1707165009,
+            granularity: 900,
+        });
+        assert_eq!(
+            DenialKind::of_error(&policy_error),
+            DenialKind::PolicyViolation
+        );
+        let pool_error = anyhow::anyhow!("Capacity pool \"elsewhere\" has no capacity schedule");
+        assert_eq!(
+            DenialKind::of_error(&pool_error),
+            DenialKind::InvalidRequest
+        );
+    }
+}
@@ -0,0 +1,692 @@
+//! Billing
+//!
+//! `billing` charges internal teams for the capacity that they reserve.
+//!
+//! Every capacity segment has a price per unit-second, and a reservation's cost is worked out
+//! (and kept on the reservation) when it's granted. Money is counted in whole microcredits
+//! (millionths of a credit) so that costs add up exactly.
+//!
+//! ```toml
+//! [hostess.billing]
+//! # Capacity costs half again as much from 09:00 to 17:00 UTC.
+//! peak_hours = [9, 17]
+//! peak_price_percent = 150
+//! # Cancelling a day ahead gets everything back. Later than that gets half of what's left.
+//! full_refund_notice = "1day"
+//! late_refund_percent = 50
+//!
+//! [hostess.billing.teams]
+//! platform = [42, 43]
+//!
+//! [hostess.billing.surge]
+//! # Quotes cost up to twice as much once a window is more than half booked.
+//! threshold_percent = 50
+//! max_price_percent = 200
+//! quote_lifetime = "5m"
+//! ```
+//!
+//! Teams are only known from `[hostess.billing.teams]`, which lists the user IDs on every team.
+//! Invoices that are grouped by team add up what those users owe, and everyone who isn't listed is
+//! invoiced together without a team.
+//!
+//! Quotes price a reservation request by how scarce capacity is during its timeframe, so
+//! flexible users can find cheaper, idle time. A quote's token can be redeemed once, before it
+//! expires, to reserve at the quoted price.
+
+// Standard library crates.
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// External crates.
+use anyhow::{ensure, Result};
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use serde_derive::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Project crates.
+use crate::common::{default_pool, AvailabilitySlot, CapacitySegment, Reservation};
+use crate::config::deserialize_duration;
+use crate::ReservationRequest;
+
+/// Seconds in a day, for finding peak hours.
+const SECONDS_PER_DAY: u32 = 86400;
+
+/// Settings that decide what reservations cost and what cancellations get back.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BillingPolicy {
+    /// Hours of the day (UTC) when capacity costs more, like `[9, 17]`. Windows that end before
+    /// they start wrap around midnight.
+    pub peak_hours: Option<(u32, u32)>,
+    /// What capacity costs during peak hours, as a percent of its usual price.
+    pub peak_price_percent: u32,
+    /// Least amount of warning before a reservation starts that gets a full refund.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub full_refund_notice: Duration,
+    /// Percent of the unused part of a reservation that's refunded when it's cancelled with
+    /// less notice than that.
+    pub late_refund_percent: u32,
+    /// Users that belong to every team, by team name. It's the only place that Arbiter learns
+    /// about teams, so invoices grouped by team put users that aren't listed here together
+    /// without a team.
+    pub teams: BTreeMap<String, Vec<u32>>,
+    /// How quotes get more expensive as capacity runs out.
+    pub surge: SurgePricing,
+}
+
+/// Settings that raise quoted prices when capacity is scarce.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SurgePricing {
+    /// How booked the busiest part of a timeframe can get before its price goes up, as a
+    /// percent of the capacity that the schedule provides.
+    pub threshold_percent: u32,
+    /// What capacity costs when it's fully booked, as a percent of its usual price. Prices go up
+    /// in a straight line from the threshold to here.
+    pub max_price_percent: u32,
+    /// How long quotes can be redeemed for.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub quote_lifetime: Duration,
+}
+
+impl Default for SurgePricing {
+    fn default() -> Self {
+        Self {
+            threshold_percent: 50,
+            // Quote the usual price unless surge pricing is turned on.
+            max_price_percent: 100,
+            quote_lifetime: Duration::from_secs(300),
+        }
+    }
+}
+
+impl SurgePricing {
+    /// Work out what a request's capacity costs, as a percent of its usual price.
+    ///
+    /// # Arguments
+    /// - `bottleneck`: Slot with the least capacity left during the request's timeframe.
+    /// - `capacity_amount`: Capacity that's requested.
+    pub fn price_percent(&self, bottleneck: &AvailabilitySlot, capacity_amount: u32) -> u32 {
+        let booked_amount = u64::from(
+            bottleneck
+                .scheduled_amount
+                .saturating_sub(bottleneck.available_amount),
+        ) + u64::from(capacity_amount);
+        let booked_percent = match bottleneck.scheduled_amount {
+            0 => 100,
+            scheduled_amount => (booked_amount * 100 / u64::from(scheduled_amount)).min(100),
+        };
+        let threshold_percent = u64::from(self.threshold_percent);
+        if booked_percent <= threshold_percent {
+            return 100;
+        }
+        let surge_range = u64::from(self.max_price_percent.saturating_sub(100));
+        let surge = surge_range * (booked_percent - threshold_percent) / (100 - threshold_percent);
+        100 + surge as u32
+    }
+
+    /// Charge a percent of a cost.
+    pub fn surged_cost(base_cost_microcredits: u64, price_percent: u32) -> u64 {
+        let cost = u128::from(base_cost_microcredits) * u128::from(price_percent) / 100;
+        u64::try_from(cost).unwrap_or(u64::MAX)
+    }
+}
+
+/// Price that a reservation request was quoted, which can be redeemed once before it expires.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct PriceQuote {
+    /// Secret that redeems the quote.
+    pub quote_token: String,
+    pub start_time: u32,
+    pub end_time: u32,
+    pub capacity_amount: u32,
+    pub user_id: u32,
+    pub pool: String,
+    /// What the request costs at the usual price.
+    pub base_cost_microcredits: u64,
+    /// Percent of the usual price that's charged because of how scarce capacity is.
+    pub price_percent: u32,
+    /// What the request costs if it's reserved with the quote.
+    pub price_microcredits: u64,
+    /// When the quote was made, represented by Unix epoch format.
+    pub quoted_at: u32,
+    /// When the quote can't be redeemed anymore, represented by Unix epoch format.
+    pub expires_at: u32,
+    /// When the quote was redeemed, if it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redeemed_at: Option<u32>,
+}
+
+impl PriceQuote {
+    /// Make an unguessable token for a new quote.
+    pub fn new_token() -> String {
+        format!("{:032x}", rand::random::<u128>())
+    }
+
+    /// Whether the quote is for exactly the given request.
+    pub fn is_for(&self, reservation_request: &ReservationRequest) -> bool {
+        self.start_time == reservation_request.start_time
+            && self.end_time == reservation_request.end_time
+            && self.capacity_amount == reservation_request.capacity_amount
+            && self.user_id == reservation_request.user_id
+            && self.pool == reservation_request.pool
+    }
+
+    /// Ensure the quote can be redeemed for a request.
+    ///
+    /// # Arguments
+    /// - `reservation_request`: Request that's being reserved with the quote.
+    /// - `epoch_now`: Current time, represented by Unix epoch format.
+    pub fn ensure_redeemable(
+        &self,
+        reservation_request: &ReservationRequest,
+        epoch_now: u32,
+    ) -> Result<()> {
+        ensure!(
+            self.redeemed_at.is_none(),
+            format!("Quote \"{}\" was already redeemed", self.quote_token)
+        );
+        ensure!(
+            epoch_now < self.expires_at,
+            format!(
+                "Quote \"{}\" expired on \"{}\"",
+                self.quote_token, self.expires_at
+            )
+        );
+        ensure!(
+            self.is_for(reservation_request),
+            format!(
+                "Quote \"{}\" isn't for {reservation_request}",
+                self.quote_token
+            )
+        );
+        Ok(())
+    }
+}
+
+impl Default for BillingPolicy {
+    fn default() -> Self {
+        Self {
+            peak_hours: None,
+            // Charge the same price all day unless there's a peak price.
+            peak_price_percent: 100,
+            full_refund_notice: Duration::ZERO,
+            late_refund_percent: 0,
+            teams: BTreeMap::new(),
+            surge: SurgePricing::default(),
+        }
+    }
+}
+
+/// What a reservation cost and what was given back.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Charges {
+    pub cost_microcredits: u64,
+    pub refund_microcredits: u64,
+}
+
+impl Charges {
+    /// Get a reservation's charges.
+    pub fn of(reservation: &Reservation) -> Self {
+        Self {
+            cost_microcredits: reservation.cost_microcredits,
+            refund_microcredits: reservation.refund_microcredits,
+        }
+    }
+}
+
+/// What a user or team owes for the reservations that they made during a billing period.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct Invoice {
+    /// User that's billed, if the invoice is for a user.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<u32>,
+    /// Team that's billed, if the invoice is for a team. Users that aren't on a team are billed
+    /// together without one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+    /// Beginning of the billing period, represented by Unix epoch format.
+    pub period_start: u32,
+    /// End of the billing period, represented by Unix epoch format.
+    pub period_end: u32,
+    /// Number of reservations that were made during the billing period.
+    pub reservations: u32,
+    pub cost_microcredits: u64,
+    pub refund_microcredits: u64,
+    /// What's owed after refunds.
+    pub total_microcredits: u64,
+}
+
+/// New price for one capacity segment.
+#[derive(Deserialize, Serialize, ToSchema)]
+// Reject unknown REST JSON params with descriptive message.
+#[serde(deny_unknown_fields)]
+pub struct SegmentPricing {
+    /// Capacity pool that the segment belongs to.
+    #[serde(default = "default_pool")]
+    pub pool: String,
+    /// When the segment starts, which picks it out of its pool.
+    pub start_time: u32,
+    pub microcredits_per_unit_second: u64,
+}
+
+/// Who invoices are made out to.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum InvoiceGrouping {
+    #[default]
+    User,
+    Team,
+}
+
+impl BillingPolicy {
+    /// Work out what a reservation request costs.
+    ///
+    /// Every second of capacity is charged at the price of the segment that provides it, and
+    /// seconds during peak hours are charged at the peak price.
+    ///
+    /// # Arguments
+    /// - `reservation_request`: Request with exact times.
+    /// - `capacity_segments`: Capacity segments of the request's pool.
+    pub fn reservation_cost(
+        &self,
+        reservation_request: &ReservationRequest,
+        capacity_segments: &[CapacitySegment],
+    ) -> u64 {
+        let request_timeframe = (reservation_request.start_time, reservation_request.end_time);
+        let cost: u128 = capacity_segments
+            .iter()
+            .filter(|capacity_segment| capacity_segment.pool == reservation_request.pool)
+            .map(|capacity_segment| {
+                let overlap = (
+                    request_timeframe.0.max(capacity_segment.start_time),
+                    request_timeframe.1.min(capacity_segment.end_time),
+                );
+                if overlap.0 >= overlap.1 {
+                    return 0;
+                }
+                let peak_seconds = u128::from(self.peak_seconds(overlap.0, overlap.1));
+                let off_peak_seconds = u128::from(overlap.1 - overlap.0) - peak_seconds;
+                // Keep everything in hundredths so peak percents don't round.
+                let weighted_seconds =
+                    off_peak_seconds * 100 + peak_seconds * u128::from(self.peak_price_percent);
+                weighted_seconds
+                    * u128::from(reservation_request.capacity_amount)
+                    * u128::from(capacity_segment.microcredits_per_unit_second)
+                    / 100
+            })
+            .sum();
+        u64::try_from(cost).unwrap_or(u64::MAX)
+    }
+
+    /// Work out what a reservation gets back if it's cancelled.
+    ///
+    /// Cancelling with at least `full_refund_notice` gets the whole cost back. Otherwise, the
+    /// `late_refund_percent` of the part that hasn't happened yet is refunded.
+    ///
+    /// # Arguments
+    /// - `reservation`: Reservation that's being cancelled.
+    /// - `cancelled_at`: When it's cancelled, represented by Unix epoch format.
+    pub fn cancellation_refund(&self, reservation: &Reservation, cancelled_at: u32) -> u64 {
+        let notice = reservation.start_time.saturating_sub(cancelled_at);
+        if u64::from(notice) >= self.full_refund_notice.as_secs() && notice > 0 {
+            return reservation.cost_microcredits;
+        }
+        let duration = u128::from(reservation.end_time.saturating_sub(reservation.start_time));
+        if duration == 0 {
+            return 0;
+        }
+        let unused_seconds = u128::from(
+            reservation
+                .end_time
+                .saturating_sub(cancelled_at.max(reservation.start_time)),
+        );
+        let refund = u128::from(reservation.cost_microcredits)
+            * unused_seconds
+            * u128::from(self.late_refund_percent.min(100))
+            / duration
+            / 100;
+        u64::try_from(refund).unwrap_or(u64::MAX)
+    }
+
+    /// Find the team that a user belongs to.
+    ///
+    /// Users that are listed on more than one team are billed to the first one alphabetically.
+    pub fn team_of(&self, user_id: u32) -> Option<&str> {
+        self.teams
+            .iter()
+            .find(|(_, team_members)| team_members.contains(&user_id))
+            .map(|(team, _)| team.as_str())
+    }
+
+    /// Add up what users or teams owe for the reservations that they made during a period.
+    ///
+    /// # Arguments
+    /// - `reservations`: Reservations that were made during the billing period.
+    /// - `period`: Beginning and end of the billing period, represented by Unix epoch format.
+    /// - `grouping`: Whether invoices are for users or teams.
+    ///
+    /// # Returns
+    /// One invoice for every user or team, ordered by user ID or team name.
+    pub fn invoices(
+        &self,
+        reservations: &[Reservation],
+        period: (u32, u32),
+        grouping: InvoiceGrouping,
+    ) -> Vec<Invoice> {
+        let mut invoices: BTreeMap<(Option<u32>, Option<String>), Invoice> = BTreeMap::new();
+        for reservation in reservations {
+            let (user_id, team) = match grouping {
+                InvoiceGrouping::User => (Some(reservation.user_id), None),
+                InvoiceGrouping::Team => {
+                    (None, self.team_of(reservation.user_id).map(String::from))
+                }
+            };
+            let invoice = invoices
+                .entry((user_id, team.clone()))
+                .or_insert_with(|| Invoice {
+                    user_id,
+                    team,
+                    period_start: period.0,
+                    period_end: period.1,
+                    ..Default::default()
+                });
+            invoice.reservations += 1;
+            invoice.cost_microcredits = invoice
+                .cost_microcredits
+                .saturating_add(reservation.cost_microcredits);
+            invoice.refund_microcredits = invoice
+                .refund_microcredits
+                .saturating_add(reservation.refund_microcredits);
+            invoice.total_microcredits = invoice
+                .cost_microcredits
+                .saturating_sub(invoice.refund_microcredits);
+        }
+        invoices.into_values().collect()
+    }
+
+    /// Ensure the policy makes sense before it's used.
+    pub fn validate(&self) -> Result<()> {
+        if let Some((peak_start, peak_end)) = self.peak_hours {
+            ensure!(
+                peak_start < 24 && peak_end <= 24 && peak_start != peak_end,
+                format!("Peak hours \"{peak_start}\" to \"{peak_end}\" aren't hours of a day")
+            );
+        }
+        ensure!(
+            self.late_refund_percent <= 100,
+            format!(
+                "Late refunds can't be more than 100 percent, not \"{}\"",
+                self.late_refund_percent
+            )
+        );
+        ensure!(
+            self.surge.threshold_percent < 100,
+            format!(
+                "Surge threshold has to be less than 100 percent, not \"{}\"",
+                self.surge.threshold_percent
+            )
+        );
+        ensure!(
+            self.surge.max_price_percent >= 100,
+            format!(
+                "Surge prices can't be less than 100 percent, not \"{}\"",
+                self.surge.max_price_percent
+            )
+        );
+        ensure!(
+            self.surge.quote_lifetime.as_secs() > 0
+                && self.surge.quote_lifetime.as_secs() <= u64::from(u32::MAX),
+            format!("Quotes can't last for {:?}", self.surge.quote_lifetime)
+        );
+        Ok(())
+    }
+
+    /// Count the seconds of a timeframe that are during peak hours.
+    fn peak_seconds(&self, start_time: u32, end_time: u32) -> u32 {
+        let Some((peak_start, peak_end)) = self.peak_hours else {
+            return 0;
+        };
+        let (peak_start, peak_end) = (peak_start * 3600, peak_end * 3600);
+        // Windows that wrap around midnight are two windows in the same day.
+        let daily_windows = match peak_start < peak_end {
+            true => vec![(peak_start, peak_end)],
+            false => vec![(0, peak_end), (peak_start, SECONDS_PER_DAY)],
+        };
+        let first_day = start_time - start_time % SECONDS_PER_DAY;
+        (u64::from(first_day)..u64::from(end_time))
+            .step_by(SECONDS_PER_DAY as usize)
+            .flat_map(|day_start| {
+                daily_windows.iter().map(move |(window_start, window_end)| {
+                    let window_start = day_start + u64::from(*window_start);
+                    let window_end = day_start + u64::from(*window_end);
+                    let overlap_start = window_start.max(u64::from(start_time));
+                    let overlap_end = window_end.min(u64::from(end_time));
+                    overlap_end.saturating_sub(overlap_start)
+                })
+            })
+            .sum::<u64>() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Standard library crates.
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    // Project crates.
+    use super::{BillingPolicy, InvoiceGrouping, PriceQuote, SurgePricing};
+    use crate::common::{AvailabilitySlot, CapacitySegment, Reservation, ReservationStatus};
+    use crate::ReservationRequest;
+
+    /// Schedule 1's first segment at one microcredit per unit-second.
+    fn priced_segments() -> Vec<CapacitySegment> {
+        vec![CapacitySegment::new(1707165008, 1708374608, 64).priced_at(1)]
+    }
+
+    /// Reservation that cost a million microcredits and starts when Schedule 1 begins.
+    fn costly_reservation() -> Reservation {
+        Reservation {
+            reservation_id: 1,
+            start_time: 1707165008,
+            end_time: 1707175008,
+            capacity_amount: 100,
+            user_id: 42,
+            pool: String::from("default"),
+            status: ReservationStatus::Pending,
+            created_at: 1707078608,
+            updated_at: 1707078608,
+            cost_microcredits: 1_000_000,
+            refund_microcredits: 0,
+        }
+    }
+
+    #[test]
+    fn test_flat_cost() {
+        let test_request = ReservationRequest::new(1707165008, 1707168608, 8, 42);
+        let cost = BillingPolicy::default().reservation_cost(&test_request, &priced_segments());
+        assert_eq!(cost, 8 * 3600);
+        // Segments in other pools don't charge anything.
+        let other_pool = test_request.in_pool("elsewhere");
+        let cost = BillingPolicy::default().reservation_cost(&other_pool, &priced_segments());
+        assert_eq!(cost, 0);
+    }
+
+    #[test]
+    fn test_peak_cost() {
+        let billing_policy = BillingPolicy {
+            peak_hours: Some((9, 17)),
+            peak_price_percent: 150,
+            ..Default::default()
+        };
+        // 2024-02-06 08:00 to 10:00 UTC has one hour off peak and one hour on peak.
+        let test_request = ReservationRequest::new(1707206400, 1707213600, 2, 42);
+        let cost = billing_policy.reservation_cost(&test_request, &priced_segments());
+        assert_eq!(cost, 2 * 3600 + 3 * 3600);
+        // Peak hours that wrap around midnight.
+        let night_policy = BillingPolicy {
+            peak_hours: Some((22, 2)),
+            peak_price_percent: 200,
+            ..Default::default()
+        };
+        // 2024-02-06 00:00 to 2024-02-07 00:00 UTC has four peak hours.
+        let test_request = ReservationRequest::new(1707177600, 1707264000, 1, 42);
+        let cost = night_policy.reservation_cost(&test_request, &priced_segments());
+        assert_eq!(cost, 20 * 3600 + 2 * 4 * 3600);
+    }
+
+    #[test]
+    fn test_cancellation_refund() {
+        let billing_policy = BillingPolicy {
+            full_refund_notice: Duration::from_secs(86400),
+            late_refund_percent: 50,
+            ..Default::default()
+        };
+        let reservation = costly_reservation();
+        // A day of notice gets everything back.
+        assert_eq!(
+            billing_policy.cancellation_refund(&reservation, reservation.start_time - 86400),
+            1_000_000
+        );
+        // Less notice gets half back.
+        assert_eq!(
+            billing_policy.cancellation_refund(&reservation, reservation.start_time - 1),
+            500_000
+        );
+        // Halfway through, half of what's left is refunded.
+        assert_eq!(
+            billing_policy.cancellation_refund(&reservation, reservation.start_time + 5000),
+            250_000
+        );
+        // Without a refund policy, only cancellations before the start are refunded.
+        let default_policy = BillingPolicy::default();
+        assert_eq!(
+            default_policy.cancellation_refund(&reservation, reservation.start_time - 1),
+            1_000_000
+        );
+        assert_eq!(
+            default_policy.cancellation_refund(&reservation, reservation.start_time),
+            0
+        );
+    }
+
+    #[test]
+    fn test_invoices() {
+        let billing_policy = BillingPolicy {
+            teams: BTreeMap::from([(String::from("platform"), vec![42, 43])]),
+            ..Default::default()
+        };
+        let mut refunded_reservation = costly_reservation();
+        refunded_reservation.user_id = 43;
+        refunded_reservation.refund_microcredits = 400_000;
+        let mut teamless_reservation = costly_reservation();
+        teamless_reservation.user_id = 44;
+        let reservations = vec![
+            costly_reservation(),
+            refunded_reservation,
+            teamless_reservation,
+        ];
+        let period = (1707004800, 1709510400);
+        let user_invoices = billing_policy.invoices(&reservations, period, InvoiceGrouping::User);
+        assert_eq!(user_invoices.len(), 3);
+        assert_eq!(user_invoices[1].user_id, Some(43));
+        assert_eq!(user_invoices[1].total_microcredits, 600_000);
+        let team_invoices = billing_policy.invoices(&reservations, period, InvoiceGrouping::Team);
+        assert_eq!(team_invoices.len(), 2);
+        assert_eq!(team_invoices[0].team, None);
+        assert_eq!(team_invoices[0].total_microcredits, 1_000_000);
+        assert_eq!(team_invoices[1].team.as_deref(), Some("platform"));
+        assert_eq!(team_invoices[1].reservations, 2);
+        assert_eq!(team_invoices[1].total_microcredits, 1_600_000);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(BillingPolicy::default().validate().is_ok());
+        let backwards_hours = BillingPolicy {
+            peak_hours: Some((25, 3)),
+            ..Default::default()
+        };
+        assert!(backwards_hours.validate().is_err());
+        let generous_refunds = BillingPolicy {
+            late_refund_percent: 150,
+            ..Default::default()
+        };
+        assert!(generous_refunds.validate().is_err());
+        let discounted_surge = BillingPolicy {
+            surge: SurgePricing {
+                max_price_percent: 50,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(discounted_surge.validate().is_err());
+    }
+
+    #[test]
+    fn test_surge_price_percent() {
+        let surge = SurgePricing {
+            threshold_percent: 50,
+            max_price_percent: 300,
+            ..Default::default()
+        };
+        let bottleneck = AvailabilitySlot {
+            start_time: 1707165008,
+            end_time: 1708374608,
+            scheduled_amount: 64,
+            maintenance_amount: 0,
+            reserved_amount: 16,
+            available_amount: 48,
+        };
+        // Idle capacity costs the usual price.
+        assert_eq!(surge.price_percent(&bottleneck, 16), 100);
+        // Three quarters booked is halfway from the threshold to fully booked.
+        assert_eq!(surge.price_percent(&bottleneck, 32), 200);
+        assert_eq!(surge.price_percent(&bottleneck, 48), 300);
+        assert_eq!(SurgePricing::surged_cost(1_000, 200), 2_000);
+        // Surge pricing is off by default.
+        assert_eq!(SurgePricing::default().price_percent(&bottleneck, 48), 100);
+    }
+
+    #[test]
+    fn test_quote_redemption() {
+        let test_request = ReservationRequest::new(1707165008, 1707168608, 8, 42);
+        let price_quote = PriceQuote {
+            quote_token: PriceQuote::new_token(),
+            start_time: 1707165008,
+            end_time: 1707168608,
+            capacity_amount: 8,
+            user_id: 42,
+            pool: String::from("default"),
+            base_cost_microcredits: 28_800,
+            price_percent: 150,
+            price_microcredits: 43_200,
+            quoted_at: 1707078608,
+            expires_at: 1707078908,
+            redeemed_at: None,
+        };
+        assert_eq!(price_quote.quote_token.len(), 32);
+        assert!(price_quote
+            .ensure_redeemable(&test_request, 1707078608)
+            .is_ok());
+        // Expired quotes can't be redeemed.
+        assert!(price_quote
+            .ensure_redeemable(&test_request, 1707078908)
+            .is_err());
+        // Neither can quotes for other terms.
+        let bigger_request = ReservationRequest::new(1707165008, 1707168608, 16, 42);
+        assert!(price_quote
+            .ensure_redeemable(&bigger_request, 1707078608)
+            .is_err());
+        // Or quotes that were already redeemed.
+        let redeemed_quote = PriceQuote {
+            redeemed_at: Some(1707078700),
+            ..price_quote
+        };
+        assert!(redeemed_quote
+            .ensure_redeemable(&test_request, 1707078800)
+            .is_err());
+    }
+}
@@ -0,0 +1,85 @@
+//! Clock
+//!
+//! `clock` tells Arbiter what time it is.
+//!
+//! Scheduling decisions like "does this reservation start in the past?" depend on the current
+//! time, so the hostess asks a `Clock` instead of the system directly. Production uses
+//! `SystemClock` and tests use `FixedClock` so that they're pinned to the example schedules'
+//! (historical) timeframes.
+
+// Standard library crates.
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// External crates.
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+
+/// Source of the current time, represented by Unix epoch format.
+pub trait Clock: Send + Sync {
+    /// Get the current time in seconds since the Unix epoch.
+    fn now(&self) -> u32;
+}
+
+/// Clock that reads the host's system time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u32 {
+        let time_since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System clock is set before the Unix epoch");
+        time_since_epoch.as_secs() as u32
+    }
+}
+
+/// Clock that stays put until it's told otherwise.
+///
+/// This lets tests decide what "now" is so that evaluations are deterministic.
+pub struct FixedClock {
+    epoch_now: AtomicU32,
+}
+
+// Only tests stop the clock for now.
+#[allow(unused)]
+impl FixedClock {
+    /// Create a new `FixedClock` that's stopped at the given time.
+    ///
+    /// # Arguments
+    /// - `epoch_now`: Time to report as "now", represented by Unix epoch format.
+    pub fn new(epoch_now: u32) -> Self {
+        Self {
+            epoch_now: AtomicU32::new(epoch_now),
+        }
+    }
+
+    /// Move the clock to the given time.
+    pub fn set(&self, epoch_now: u32) {
+        self.epoch_now.store(epoch_now, Ordering::SeqCst);
+    }
+
+    /// Move the clock forward by the given number of seconds.
+    pub fn advance(&self, seconds: u32) {
+        self.epoch_now.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> u32 {
+        self.epoch_now.load(Ordering::SeqCst)
+    }
+}
+
+/// Example clocks that are available to all tests.
+#[cfg(test)]
+pub mod test_examples {
+    use super::FixedClock;
+
+    /// One day before Schedule 1 begins.
+    pub const TEST_EPOCH_NOW: u32 = 1707078608;
+
+    /// Test clock that's stopped one day before Schedule 1 begins.
+    pub fn test_clock() -> FixedClock {
+        FixedClock::new(TEST_EPOCH_NOW)
+    }
+}
@@ -2,19 +2,30 @@
 
 // Standard library crates.
 use std::fmt;
+use std::str::FromStr;
 
 // External crates.
+use anyhow::{anyhow, Error};
 #[allow(unused)]
 use log::{debug, error, info, trace, warn};
 // Serialize JSON payloads.
 use serde_derive::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Name of the capacity pool that's used when one isn't given.
+pub const DEFAULT_POOL: &str = "default";
+
+/// Get the name of the default capacity pool for serde defaults.
+pub fn default_pool() -> String {
+    String::from(DEFAULT_POOL)
+}
 
 /// A capacity reservation request.
 ///
-/// This is used for RESTful JSON parameters, reservation logic, test creation, and datastore
-/// retrieval. It can represent a request for a portion of a resource or a portion that's already
-/// been allocated.
-#[derive(Deserialize, Serialize)]
+/// This is used for RESTful JSON parameters, reservation logic, and test creation. It only ever
+/// represents a request for a portion of a resource. Portions that have already been allocated are
+/// `Reservation`s.
+#[derive(Clone, Deserialize, Serialize, ToSchema)]
 // Reject unknown REST JSON params with descriptive message.
 #[serde(deny_unknown_fields)]
 pub struct ReservationRequest {
@@ -22,10 +33,13 @@ pub struct ReservationRequest {
     pub end_time: u32,
     pub capacity_amount: u32,
     pub user_id: u32,
+    /// Capacity pool to reserve from.
+    #[serde(default = "default_pool")]
+    pub pool: String,
 }
 
 impl ReservationRequest {
-    /// Create a new `ReservationRequest`.
+    /// Create a new `ReservationRequest` for the default capacity pool.
     ///
     ///
     /// # Arguments
@@ -39,8 +53,15 @@ impl ReservationRequest {
             end_time,
             capacity_amount,
             user_id,
+            pool: default_pool(),
         }
     }
+
+    /// Reserve from the given capacity pool instead of the default one.
+    pub fn in_pool(mut self, pool: &str) -> Self {
+        self.pool = String::from(pool);
+        self
+    }
 }
 
 // Print instantiated struct nicely.
@@ -50,17 +71,357 @@ impl fmt::Display for ReservationRequest {
             formatter,
             "reservation request from user ID \"{}\" \
             for \"{}\" of capacity \
+            in pool \"{}\" \
             from \"{}\" to \"{}\"",
-            self.user_id, self.capacity_amount, self.start_time, self.end_time
+            self.user_id, self.capacity_amount, self.pool, self.start_time, self.end_time
+        )
+    }
+}
+
+/// A point in time as the user asked for it.
+///
+/// Users can give an exact time, ask for "now", or give an offset from now like "+30m". The
+/// hostess resolves these into Unix epochs with her own clock because the server's idea of "now"
+/// is the one that matters.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum RequestedTime {
+    /// Exact time, represented by Unix epoch format.
+    Epoch(u32),
+    /// Relative time, like `"now"` or `"+30m"`.
+    Relative(String),
+}
+
+// Print instantiated enum nicely.
+impl fmt::Display for RequestedTime {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RequestedTime::Epoch(epoch) => write!(formatter, "{}", epoch),
+            RequestedTime::Relative(relative_time) => write!(formatter, "{}", relative_time),
+        }
+    }
+}
+
+/// Reservation request parameters as they arrive from the user.
+///
+/// This is like `ReservationRequest`, except that its times haven't been resolved into Unix epochs
+/// yet.
+#[derive(Deserialize, Serialize, ToSchema)]
+// Reject unknown REST JSON params with descriptive message.
+#[serde(deny_unknown_fields)]
+pub struct ReservationParams {
+    pub start_time: RequestedTime,
+    pub end_time: RequestedTime,
+    pub capacity_amount: u32,
+    /// User to reserve for. The RESTful API fills this in with the caller.
+    #[serde(default)]
+    pub user_id: Option<u32>,
+    /// Capacity pool to reserve from.
+    #[serde(default = "default_pool")]
+    pub pool: String,
+    /// Quote to redeem, so the reservation costs what it was quoted.
+    #[serde(default)]
+    pub quote_token: Option<String>,
+}
+
+impl ReservationParams {
+    /// Create new `ReservationParams`.
+    ///
+    /// # Arguments
+    /// - `start_time`: Reservation start time: a Unix epoch, `"now"`, or an offset from now like
+    ///   `"+30m"`.
+    /// - `end_time`: Reservation end time: a Unix epoch or an offset from the start time like
+    ///   `"+2h"`.
+    /// - `capacity_amount`: Amount of resource the user would like to have allocated.
+    /// - `user_id`: Your unique identifier.
+    #[allow(unused)]
+    pub fn new(
+        start_time: RequestedTime,
+        end_time: RequestedTime,
+        capacity_amount: u32,
+        user_id: u32,
+    ) -> Self {
+        Self {
+            start_time,
+            end_time,
+            capacity_amount,
+            user_id: Some(user_id),
+            pool: default_pool(),
+            quote_token: None,
+        }
+    }
+
+    /// Redeem a quote when reserving.
+    #[allow(unused)]
+    pub fn with_quote(mut self, quote_token: &str) -> Self {
+        self.quote_token = Some(quote_token.to_string());
+        self
+    }
+}
+
+/// Where a reservation is in its life.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReservationStatus {
+    /// Booked, but hasn't started yet.
+    Pending,
+    /// Started, and hasn't ended yet.
+    Active,
+    /// Ran until its end time.
+    Completed,
+    /// Given back before it ended.
+    Cancelled,
+    /// Taken away to make room for something more important.
+    Preempted,
+    /// Ran out of time before it was used.
+    Expired,
+}
+
+impl ReservationStatus {
+    /// Name of the status as it's stored in the datastore.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReservationStatus::Pending => "pending",
+            ReservationStatus::Active => "active",
+            ReservationStatus::Completed => "completed",
+            ReservationStatus::Cancelled => "cancelled",
+            ReservationStatus::Preempted => "preempted",
+            ReservationStatus::Expired => "expired",
+        }
+    }
+
+    /// Whether reservations with this status are using up capacity.
+    ///
+    /// Completed reservations used their capacity for their whole timeframe, so they still count
+    /// in reports about it.
+    pub fn holds_capacity(&self) -> bool {
+        match self {
+            ReservationStatus::Pending
+            | ReservationStatus::Active
+            | ReservationStatus::Completed => true,
+            ReservationStatus::Cancelled
+            | ReservationStatus::Preempted
+            | ReservationStatus::Expired => false,
+        }
+    }
+}
+
+// Read statuses back out of the datastore.
+impl FromStr for ReservationStatus {
+    type Err = Error;
+
+    fn from_str(status_name: &str) -> Result<Self, Self::Err> {
+        match status_name {
+            "pending" => Ok(ReservationStatus::Pending),
+            "active" => Ok(ReservationStatus::Active),
+            "completed" => Ok(ReservationStatus::Completed),
+            "cancelled" => Ok(ReservationStatus::Cancelled),
+            "preempted" => Ok(ReservationStatus::Preempted),
+            "expired" => Ok(ReservationStatus::Expired),
+            unknown_status => Err(anyhow!("Unknown reservation status \"{unknown_status}\"")),
+        }
+    }
+}
+
+// Print instantiated enum nicely.
+impl fmt::Display for ReservationStatus {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+/// A capacity reservation that's been granted to a user.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct Reservation {
+    pub reservation_id: u32,
+    pub start_time: u32,
+    pub end_time: u32,
+    pub capacity_amount: u32,
+    pub user_id: u32,
+    pub pool: String,
+    pub status: ReservationStatus,
+    /// When the reservation was granted, represented by Unix epoch format.
+    pub created_at: u32,
+    /// When the reservation last changed, represented by Unix epoch format.
+    pub updated_at: u32,
+    /// What the reservation cost when it was granted, in microcredits.
+    pub cost_microcredits: u64,
+    /// What was given back when the reservation was cancelled, in microcredits.
+    pub refund_microcredits: u64,
+}
+
+// Print instantiated struct nicely.
+impl fmt::Display for Reservation {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "{} reservation \"{}\" for user ID \"{}\" \
+            of \"{}\" capacity \
+            in pool \"{}\" \
+            from \"{}\" to \"{}\"",
+            self.status,
+            self.reservation_id,
+            self.user_id,
+            self.capacity_amount,
+            self.pool,
+            self.start_time,
+            self.end_time
+        )
+    }
+}
+
+/// A period of time during which a fixed amount of capacity is available.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CapacitySegment {
+    pub start_time: u32,
+    pub end_time: u32,
+    pub capacity_amount: u32,
+    pub pool: String,
+    /// What one unit of capacity costs for one second, in microcredits.
+    pub microcredits_per_unit_second: u64,
+}
+
+impl CapacitySegment {
+    /// Create a new `CapacitySegment` for the default capacity pool.
+    ///
+    /// # Arguments
+    /// - `start_time`: Segment start time, represented by Unix epoch format.
+    /// - `end_time`: Segment end time, represented by Unix epoch format.
+    /// - `capacity_amount`: Total amount of resource that's available during the segment.
+    pub fn new(start_time: u32, end_time: u32, capacity_amount: u32) -> Self {
+        Self {
+            start_time,
+            end_time,
+            capacity_amount,
+            pool: default_pool(),
+            microcredits_per_unit_second: 0,
+        }
+    }
+
+    /// Put the segment in the given capacity pool instead of the default one.
+    pub fn in_pool(mut self, pool: &str) -> Self {
+        self.pool = String::from(pool);
+        self
+    }
+
+    /// Charge the given price for the segment's capacity instead of giving it away.
+    pub fn priced_at(mut self, microcredits_per_unit_second: u64) -> Self {
+        self.microcredits_per_unit_second = microcredits_per_unit_second;
+        self
+    }
+}
+
+// Print instantiated struct nicely.
+impl fmt::Display for CapacitySegment {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "capacity segment of \"{}\" in pool \"{}\" from \"{}\" to \"{}\"",
+            self.capacity_amount, self.pool, self.start_time, self.end_time
         )
     }
 }
 
 /// A capacity schedule for a resource.
 ///
-/// This schedule will never change or fail.
+/// This schedule will never change or fail. Unplanned reductions are `MaintenanceWindow`s.
 pub struct CapacitySchedule {
-    pub reservations: Vec<ReservationRequest>,
+    pub segments: Vec<CapacitySegment>,
+}
+
+impl CapacitySchedule {
+    /// Get the part of the schedule that belongs to the given capacity pool.
+    pub fn for_pool(&self, pool: &str) -> CapacitySchedule {
+        CapacitySchedule {
+            segments: self
+                .segments
+                .iter()
+                .filter(|capacity_segment| capacity_segment.pool == pool)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// How much capacity a maintenance window takes out of its pool.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CapacityReduction {
+    /// Take the whole pool offline.
+    All,
+    /// Take a set amount of capacity offline.
+    Amount(u32),
+}
+
+impl CapacityReduction {
+    /// Get how much capacity is left after the reduction.
+    pub fn apply(&self, capacity_amount: u32) -> u32 {
+        match self {
+            CapacityReduction::All => 0,
+            CapacityReduction::Amount(reduction) => capacity_amount.saturating_sub(*reduction),
+        }
+    }
+}
+
+/// A period of time during which some (or all) of a pool's capacity is offline.
+///
+/// This is for planned maintenance and unplanned outages. Unlike `CapacitySegment`s, maintenance
+/// windows come and go, so they're managed by admins instead of being seeded with the schedule.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct MaintenanceWindow {
+    pub window_id: u32,
+    pub pool: String,
+    pub start_time: u32,
+    pub end_time: u32,
+    pub reduction: CapacityReduction,
+    /// Why capacity is offline, like "replacing PDUs in rack 12".
+    pub reason: String,
+}
+
+// Print instantiated struct nicely.
+impl fmt::Display for MaintenanceWindow {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let reduction = match self.reduction {
+            CapacityReduction::All => String::from("all"),
+            CapacityReduction::Amount(reduction) => reduction.to_string(),
+        };
+        write!(
+            formatter,
+            "maintenance window \"{}\" taking \"{}\" capacity \
+            out of pool \"{}\" \
+            from \"{}\" to \"{}\" for \"{}\"",
+            self.window_id, reduction, self.pool, self.start_time, self.end_time, self.reason
+        )
+    }
+}
+
+/// Maintenance window parameters as they arrive from an admin.
+#[derive(Deserialize, Serialize, ToSchema)]
+// Reject unknown REST JSON params with descriptive message.
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceParams {
+    /// Capacity pool to take capacity out of.
+    #[serde(default = "default_pool")]
+    pub pool: String,
+    pub start_time: u32,
+    pub end_time: u32,
+    pub reduction: CapacityReduction,
+    pub reason: String,
+}
+
+/// Capacity that's available in a pool during a stretch of time where nothing changes.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct AvailabilitySlot {
+    pub start_time: u32,
+    pub end_time: u32,
+    /// Capacity that the schedule provides.
+    pub scheduled_amount: u32,
+    /// Capacity that's offline for maintenance.
+    pub maintenance_amount: u32,
+    /// Capacity that's held by user reservations.
+    pub reserved_amount: u32,
+    /// Capacity that's left for new reservations.
+    pub available_amount: u32,
 }
 
 /// Example instances of common scheduling structs that are available to all tests.
@@ -0,0 +1,217 @@
+//! Configuration
+//!
+//! `config` reads Arbiter's settings from a TOML file.
+//!
+//! Every setting has a default, so a missing file (or a missing section) is fine. Durations are
+//! written in "human time", like `"30m"` or `"1h 15m"`.
+//!
+//! ```toml
+//! [hostess]
+//! provisioning_lead_time = "10m"
+//! utilization_thresholds = [80, 100]
+//!
+//! [hostess.policy]
+//! max_duration = "30days"
+//! time_granularity = "15m"
+//! granularity_mode = "snap"
+//!
+//! [hostess.billing]
+//! peak_hours = [9, 17]
+//! peak_price_percent = 150
+//!
+//! [datastore]
+//! backend = "sqlite"
+//! sqlite_path = "arbiter.sqlite3"
+//!
+//! [[auth.api_keys]]
+//! key = "..."
+//! user_id = 42
+//!
+//! [api]
+//! unversioned_routes = false
+//!
+//! [grpc]
+//! port = 4243
+//!
+//! [webhooks]
+//! max_attempts = 5
+//! initial_backoff = "1s"
+//! ```
+
+// Standard library crates.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// External crates.
+use anyhow::{ensure, Context, Result};
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use serde::de::Deserialize as _;
+use serde::Deserializer;
+use serde_derive::Deserialize;
+
+// Project crates.
+use crate::auth::AuthConfig;
+use crate::billing::BillingPolicy;
+use crate::grpc_api::GrpcConfig;
+use crate::policy::ReservationPolicy;
+use crate::restful_api::RESTFUL_API_PORT;
+use crate::versioning::ApiConfig;
+use crate::webhooks::WebhookConfig;
+
+/// Default location of Arbiter's configuration file.
+pub const DEFAULT_CONFIG_PATH: &str = "arbiter.toml";
+
+/// All of Arbiter's settings.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArbiterConfig {
+    pub hostess: HostessConfig,
+    pub datastore: DatastoreConfig,
+    pub auth: AuthConfig,
+    pub api: ApiConfig,
+    pub grpc: GrpcConfig,
+    pub webhooks: WebhookConfig,
+}
+
+/// Database that Arbiter keeps its schedule and reservations in.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DatastoreBackend {
+    /// PostgreSQL server, for deployments with more than one node.
+    #[default]
+    Postgres,
+    /// SQLite file that's embedded in Arbiter, for single-node deployments.
+    Sqlite,
+}
+
+/// Settings that choose and connect to the datastore.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatastoreConfig {
+    /// Database that Arbiter keeps its schedule and reservations in.
+    pub backend: DatastoreBackend,
+    /// Connection parameters for the PostgreSQL backend.
+    pub postgres_connection: String,
+    /// Database file for the SQLite backend. It's created if it doesn't exist.
+    pub sqlite_path: PathBuf,
+}
+
+impl Default for DatastoreConfig {
+    fn default() -> Self {
+        Self {
+            backend: DatastoreBackend::default(),
+            postgres_connection: String::from("host=localhost user=postgres"),
+            sqlite_path: PathBuf::from("arbiter.sqlite3"),
+        }
+    }
+}
+
+/// Settings that change how the hostess evaluates reservation requests.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostessConfig {
+    /// Time that capacity needs to spin up before it's usable.
+    ///
+    /// Reservations that start "now" (or soon after) are pushed back by this much so that
+    /// users don't pay for capacity that's still booting.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub provisioning_lead_time: Duration,
+    /// Limits on the shape of reservations.
+    pub policy: ReservationPolicy,
+    /// What reservations cost and what cancellations get back.
+    pub billing: BillingPolicy,
+    /// How long responses to requests with an `Idempotency-Key` are kept for retries.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub idempotency_retention: Duration,
+    /// Percents of a pool's scheduled capacity that followers of the event stream are told
+    /// about when utilization crosses them.
+    pub utilization_thresholds: Vec<u32>,
+}
+
+impl Default for HostessConfig {
+    fn default() -> Self {
+        Self {
+            provisioning_lead_time: Duration::ZERO,
+            policy: ReservationPolicy::default(),
+            billing: BillingPolicy::default(),
+            idempotency_retention: Duration::from_secs(24 * 60 * 60),
+            utilization_thresholds: vec![80, 100],
+        }
+    }
+}
+
+/// Read Arbiter's configuration file.
+///
+/// If there's no file at the given path, then the defaults are used.
+pub fn load_config(config_path: &Path) -> Result<ArbiterConfig> {
+    if !config_path.exists() {
+        info!(
+            "No config file found at \"{}\", using defaults",
+            config_path.display()
+        );
+        return Ok(ArbiterConfig::default());
+    }
+    let raw_config = fs::read_to_string(config_path)
+        .with_context(|| format!("Couldn't read config file \"{}\"", config_path.display()))?;
+    let config: ArbiterConfig = toml::from_str(&raw_config)
+        .with_context(|| format!("Couldn't parse config file \"{}\"", config_path.display()))?;
+    config.hostess.billing.validate().with_context(|| {
+        format!(
+            "Invalid billing in config file \"{}\"",
+            config_path.display()
+        )
+    })?;
+    let idempotency_retention = config.hostess.idempotency_retention.as_secs();
+    ensure!(
+        idempotency_retention > 0 && idempotency_retention <= u64::from(u32::MAX),
+        format!(
+            "Invalid `idempotency_retention` in config file \"{}\": it must be between a second and {} seconds",
+            config_path.display(),
+            u32::MAX
+        )
+    );
+    ensure!(
+        !config.hostess.utilization_thresholds.contains(&0),
+        format!(
+            "Invalid `utilization_thresholds` in config file \"{}\": every threshold must be at least 1 percent",
+            config_path.display()
+        )
+    );
+    config
+        .auth
+        .validate()
+        .with_context(|| format!("Invalid auth in config file \"{}\"", config_path.display()))?;
+    config.api.validate().with_context(|| {
+        format!(
+            "Invalid API versions in config file \"{}\"",
+            config_path.display()
+        )
+    })?;
+    ensure!(
+        config.grpc.port != RESTFUL_API_PORT,
+        format!(
+            "Invalid gRPC `port` in config file \"{}\": the RESTful API already listens on {}",
+            config_path.display(),
+            RESTFUL_API_PORT
+        )
+    );
+    config.webhooks.validate().with_context(|| {
+        format!(
+            "Invalid webhooks in config file \"{}\"",
+            config_path.display()
+        )
+    })?;
+    info!("Loaded config from \"{}\"", config_path.display());
+    Ok(config)
+}
+
+/// Read a "human time" duration like `"30m"` from the config file.
+pub(crate) fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let human_duration = String::deserialize(deserializer)?;
+    humantime::parse_duration(&human_duration).map_err(serde::de::Error::custom)
+}
@@ -1,179 +1,455 @@
+//! Datastore
+//!
+//! `datastore` keeps Arbiter's capacity schedule, user reservations, maintenance windows,
+//! request history, price quotes, change events, and webhooks.
+//!
+//! Every backend implements the same `Datastore` operations, and the backend is chosen by the
+//! `[datastore]` section of the config file:
+//! - `postgres`: PostgreSQL server, for deployments with more than one node.
+//! - `sqlite`: SQLite file that's embedded in Arbiter, for single-node deployments.
+//!
+//! Either way, the backend is wrapped in a `MeteredDatastore` that times every operation.
+
+// Standard library crates.
+use std::sync::Arc;
+
 // External crates.
-use anyhow::Result;
-use postgres::{Client, NoTls};
+use anyhow::{Context, Result};
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
 
 // Project crates.
+use crate::billing::PriceQuote;
+use crate::common::{CapacitySegment, MaintenanceParams, MaintenanceWindow, Reservation};
+use crate::config::{DatastoreBackend, DatastoreConfig};
+use crate::demand::{EvaluatedRequest, RequestEvaluation, RequestFilter};
+use crate::events::{ChangeEvent, ChangeEventParams, ChangeFilter};
+use crate::idempotency::IdempotencyRecord;
+use crate::ledger::{LedgerEvent, LedgerEventKind};
+use crate::listing::{PageRequest, ReservationFilter};
+use crate::metrics::ReservationCount;
+use crate::webhooks::{WebhookDelivery, WebhookDeliveryParams, WebhookParams, WebhookSubscription};
 use crate::CapacitySchedule;
 use crate::ReservationRequest;
-#[allow(unused)]
-use log::{debug, error, info, trace, warn};
 
-/// Initialize Arbiter's database.
+// Project modules
+#[cfg(test)]
+pub mod failing;
+mod metered;
+pub use metered::MeteredDatastore;
+mod postgres;
+pub use postgres::PostgresDatastore;
+mod sqlite;
+pub use sqlite::SqliteDatastore;
+
+/// Reservations that are being booked while no one else can book from the same pools.
 ///
-/// Warning: If PostgreSQL was in stalled with Homebrew, then the "postgres" role needs to be added
-/// before this will work. Without it, `Client::connect()` will hang forever.
-/// `user@host: /opt/homebrew/opt/postgresql@14/bin/createuser -s postgres`
-/// credit: https://stackoverflow.com/questions/15301826/psql-fatal-role-postgres-does-not-exist#comment91332745_15309551
-pub fn initialize_database() -> Result<Client> {
-    info!("Initializing database");
-    let _ = cleanup_database();
-    // todo: Clean up DB on init.
-    let mut db_client = Client::connect("host=localhost user=postgres", NoTls)?;
-    // Ensure tables exist.
-    let _ = create_schedule_tables(&mut db_client);
-    // Populate tables with dummy data.
-    let _ = populate_schedule_tables(&mut db_client);
-    info!("Initialized database");
-    Ok(db_client)
-}
+/// Capacity is read and reserved in one transaction, so capacity that's seen to be free stays
+/// free until it's reserved. Nothing that's booked is kept until the booking is committed, and
+/// dropping the booking without committing it rolls everything back.
+pub trait Booking {
+    /// Get the capacity schedule of every pool.
+    fn get_schedule(&mut self) -> Result<CapacitySchedule>;
 
-/// Delete all known database tables.
-fn cleanup_database() -> Result<()> {
-    let mut db_client = Client::connect("host=localhost user=postgres", NoTls)?;
-    db_client.execute("DROP TABLE capacity_schedule, user_reservations;", &[])?;
-    info!("Deleted DB tables: capacity_schedule, user_reservations");
-    Ok(())
-}
+    /// Get maintenance windows in a pool that overlap a timeframe, earliest first.
+    fn get_overlapping_maintenance_windows(
+        &mut self,
+        pool: &str,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<MaintenanceWindow>>;
 
-///// Get mocked capacity schedule from testing function.
-//pub fn get_schedule() -> Result<CapacitySchedule> {
-//    let schedule = schedule_one();
-//    Ok(schedule)
-//}
-
-/// Get capacity schedule from Database.
-pub fn get_schedule() -> Result<CapacitySchedule> {
-    let mut db_client = Client::connect("host=localhost user=postgres", NoTls)?;
-    let mut capacities = Vec::new();
-    for query_row in db_client.query(
-        "SELECT id, start_time, end_time, capacity_amount, user_id FROM capacity_schedule",
-        &[],
-    )? {
-        // todo: Disregard id.
-        let start_time: i32 = query_row.get(1);
-        let end_time: i32 = query_row.get(2);
-        let capacity_amount: i32 = query_row.get(3);
-        let user_id: i32 = query_row.get(4);
-        let existing_reservation = ReservationRequest::new(
-            start_time as u32,
-            end_time as u32,
-            capacity_amount as u32,
-            user_id as u32,
-        );
-        capacities.push(existing_reservation)
-    }
-    let queried_schedule = CapacitySchedule {
-        reservations: capacities,
-    };
-    Ok(queried_schedule)
-}
+    /// Get user reservations in a pool that overlap a timeframe, including ones that were added
+    /// earlier in this booking.
+    fn get_overlapping_reservations(
+        &mut self,
+        pool: &str,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<Reservation>>;
 
-/// Add reservation to user reservation table.
-///
-/// Assume that the reservation's timeframe and capacity have already been validated.
-pub fn add_user_reservation(new_reservation: &ReservationRequest) -> Result<()> {
-    let mut db_client = Client::connect("host=localhost user=postgres", NoTls)?;
-    db_client.execute(
-        "INSERT INTO user_reservations 
-                      (start_time, end_time, reservation_amount, user_id) 
-                      VALUES ($1, $2, $3, $4)",
-        &[
-            &(new_reservation.start_time as i32),
-            &(new_reservation.end_time as i32),
-            &(new_reservation.capacity_amount as i32),
-            &(new_reservation.user_id as i32),
-        ],
-    )?;
-    info!("Added reservation to DB");
-    Ok(())
-}
+    /// Get a price quote by its token, whether it's been redeemed or not.
+    fn get_price_quote(&mut self, quote_token: &str) -> Result<Option<PriceQuote>>;
 
-/// Get user reservation schedule from Database.
-pub fn get_user_reservation_schedule() -> Result<CapacitySchedule> {
-    let mut db_client = Client::connect("host=localhost user=postgres", NoTls)?;
-    let mut capacities = Vec::new();
-    for query_row in db_client.query(
-        "SELECT id, start_time, end_time, reservation_amount, user_id FROM user_reservations",
-        &[],
-    )? {
-        // todo: Disregard id.
-        let start_time: i32 = query_row.get(1);
-        let end_time: i32 = query_row.get(2);
-        let reservation_amount: i32 = query_row.get(3);
-        let user_id: i32 = query_row.get(4);
-        let user_reservations = ReservationRequest::new(
-            start_time as u32,
-            end_time as u32,
-            reservation_amount as u32,
-            user_id as u32,
-        );
-        capacities.push(user_reservations)
-    }
-    let queried_schedule = CapacitySchedule {
-        reservations: capacities,
-    };
-    Ok(queried_schedule)
-}
+    /// Add reservation to user reservation table and record its approval in the ledger.
+    ///
+    /// Assume that the reservation's timeframe and capacity have already been validated.
+    ///
+    /// # Arguments
+    /// - `new_reservation`: Reservation request that was approved.
+    /// - `created_at`: When the reservation was approved, represented by Unix epoch format.
+    /// - `cost_microcredits`: What the reservation costs.
+    ///
+    /// # Returns
+    /// The granted reservation with its unique ID.
+    fn add_user_reservation(
+        &mut self,
+        new_reservation: &ReservationRequest,
+        created_at: u32,
+        cost_microcredits: u64,
+    ) -> Result<Reservation>;
 
-fn create_schedule_tables(db_client: &mut Client) -> Result<()> {
-    let _ = db_client.execute(
-        "CREATE TABLE capacity_schedule (
-                                 id                 SERIAL PRIMARY KEY,
-                                 start_time         INTEGER NOT NULL,
-                                 end_time           INTEGER NOT NULL,
-                                 capacity_amount    INTEGER NOT NULL,
-                                 user_id            INTEGER NOT NULL
-                                 )",
-        &[],
-    );
-    debug!("Created capacity schedule table");
-    let _ = db_client.execute(
-        "CREATE TABLE user_reservations (
-                                 id                 SERIAL PRIMARY KEY,
-                                 start_time         INTEGER NOT NULL,
-                                 end_time           INTEGER NOT NULL,
-                                 reservation_amount INTEGER NOT NULL,
-                                 user_id            INTEGER NOT NULL
-                                 )",
-        &[],
-    );
-    debug!("Created user reservation table");
-    info!("Created DB Tables");
-    Ok(())
-}
+    /// Redeem a price quote and add the reservation that it was quoted for, together.
+    ///
+    /// Assume that the quote's terms have already been checked against the reservation.
+    ///
+    /// # Arguments
+    /// - `new_reservation`: Reservation request that was approved.
+    /// - `created_at`: When the reservation was approved and the quote redeemed, represented by
+    ///   Unix epoch format.
+    /// - `cost_microcredits`: What the reservation costs.
+    /// - `quote_token`: Quote that's redeemed for the reservation.
+    ///
+    /// # Returns
+    /// The granted reservation, or `None` if the quote was already redeemed or it's expired, in
+    /// which case nothing is added. Only one redemption of a quote ever succeeds.
+    fn add_quoted_reservation(
+        &mut self,
+        new_reservation: &ReservationRequest,
+        created_at: u32,
+        cost_microcredits: u64,
+        quote_token: &str,
+    ) -> Result<Option<Reservation>>;
+
+    /// Record a change for followers of the event stream, which they only see once the booking
+    /// is committed.
+    ///
+    /// # Returns
+    /// The recorded change with its unique ID.
+    fn record_change_event(&mut self, change_params: &ChangeEventParams) -> Result<ChangeEvent>;
 
-fn populate_schedule_row(
-    existing_reservation: ReservationRequest,
-    table_name: &str,
-    db_client: &mut Client,
-) -> Result<()> {
-    let insertion_command = format!(
-        "INSERT INTO {} \
-                     (start_time, end_time, capacity_amount, user_id) \
-                     VALUES ({}, {}, {}, {})",
-        table_name,
-        existing_reservation.start_time as i32,
-        existing_reservation.end_time as i32,
-        existing_reservation.capacity_amount as i32,
-        existing_reservation.user_id as i32
-    );
-    let _ = db_client.batch_execute(&insertion_command);
-    Ok(())
+    /// Keep everything that was booked, and let others book from the pools again.
+    fn commit(self: Box<Self>) -> Result<()>;
 }
 
-fn populate_schedule_tables(db_client: &mut Client) -> Result<()> {
-    for existing_reservation in schedule_one().reservations {
-        populate_schedule_row(existing_reservation, "capacity_schedule", db_client)?;
+/// Place that Arbiter keeps its schedule and reservations.
+pub trait Datastore: Send + Sync {
+    /// Apply every schema migration that hasn't been applied yet.
+    ///
+    /// # Returns
+    /// The database's schema version after migrating.
+    fn migrate(&self) -> Result<i32>;
+
+    /// Delete every table that Arbiter knows about.
+    fn drop_tables(&self) -> Result<()>;
+
+    /// Get the capacity schedule of every pool.
+    fn get_schedule(&self) -> Result<CapacitySchedule>;
+
+    /// Add a segment to a pool's capacity schedule.
+    ///
+    /// Segments in the same pool can't overlap.
+    fn add_capacity_segment(&self, capacity_segment: &CapacitySegment) -> Result<()>;
+
+    /// Change what a capacity segment costs. Reservations that were already made keep their cost.
+    ///
+    /// # Arguments
+    /// - `pool`: Pool that the segment belongs to.
+    /// - `start_time`: When the segment starts, which identifies it within its pool.
+    /// - `microcredits_per_unit_second`: New price.
+    ///
+    /// # Returns
+    /// Whether there was a segment that starts at the given time in the pool.
+    fn set_segment_price(
+        &self,
+        pool: &str,
+        start_time: u32,
+        microcredits_per_unit_second: u64,
+    ) -> Result<bool>;
+
+    /// Start booking reservations from some pools.
+    ///
+    /// Until the booking is committed or dropped, anyone else that starts booking from the same
+    /// pools waits for it, even in another Arbiter that shares the database.
+    fn begin_booking(&self, pools: &[&str]) -> Result<Box<dyn Booking + '_>>;
+
+    /// Get a user reservation by its ID.
+    fn get_reservation(&self, reservation_id: u32) -> Result<Option<Reservation>>;
+
+    /// Get user reservations that were made during a timeframe, in the order they were made.
+    fn get_reservations_created_between(
+        &self,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<Reservation>>;
+
+    /// Get a page of user reservations that match a filter, in the page's order.
+    fn list_reservations(
+        &self,
+        reservation_filter: &ReservationFilter,
+        page_request: &PageRequest,
+    ) -> Result<Vec<Reservation>>;
+
+    /// End a reservation that's pending or active and record why in the ledger.
+    ///
+    /// # Arguments
+    /// - `reservation_id`: Reservation to end.
+    /// - `ending_kind`: Kind of ledger event that ends it, like `Cancelled` or `Expired`.
+    /// - `recorded_at`: When the reservation ended, represented by Unix epoch format.
+    /// - `reason`: Why the reservation ended.
+    /// - `refund_microcredits`: What's given back for the part of the reservation that won't be
+    ///   used.
+    ///
+    /// # Returns
+    /// The ended reservation, or `None` if there wasn't a reservation with the given ID that
+    /// was pending or active.
+    fn end_reservation(
+        &self,
+        reservation_id: u32,
+        ending_kind: LedgerEventKind,
+        recorded_at: u32,
+        reason: Option<&str>,
+        refund_microcredits: u64,
+    ) -> Result<Option<Reservation>>;
+
+    /// Get reservations that are due to move along their lifecycle: pending ones that have
+    /// started and active ones that have ended, by `epoch_now`.
+    fn get_due_reservations(&self, epoch_now: u32) -> Result<Vec<Reservation>>;
+
+    /// Get the next time after `epoch_now` that a pending reservation starts or an active one
+    /// ends, or `None` if there isn't one.
+    fn get_next_lifecycle_boundary(&self, epoch_now: u32) -> Result<Option<u32>>;
+
+    /// Count the reservations in each pool that are pending or active.
+    ///
+    /// Pools and statuses without any reservations are left out.
+    fn get_reservation_counts(&self) -> Result<Vec<ReservationCount>>;
+
+    /// Move a reservation to the next status in its lifecycle and record it in the ledger.
+    ///
+    /// # Arguments
+    /// - `reservation_id`: Reservation to move along.
+    /// - `lifecycle_kind`: Kind of ledger event that moves it, `Started` or `Completed`.
+    /// - `recorded_at`: When the boundary was reached, represented by Unix epoch format.
+    ///
+    /// # Returns
+    /// The reservation after it moved, or `None` if there wasn't a reservation with the given ID
+    /// in the status that the event moves from.
+    fn advance_reservation(
+        &self,
+        reservation_id: u32,
+        lifecycle_kind: LedgerEventKind,
+        recorded_at: u32,
+    ) -> Result<Option<Reservation>>;
+
+    /// Keep a price quote so that it can be redeemed later.
+    fn add_price_quote(&self, price_quote: &PriceQuote) -> Result<()>;
+
+    /// Claim an idempotency key for a request, unless someone already has.
+    ///
+    /// Expired keys are forgotten first, so they can be claimed again.
+    ///
+    /// # Returns
+    /// `None` if the key was claimed, or the record of the request that claimed it first.
+    fn claim_idempotency_key(
+        &self,
+        idempotency_record: &IdempotencyRecord,
+        epoch_now: u32,
+    ) -> Result<Option<IdempotencyRecord>>;
+
+    /// Keep the response that was given to the request that claimed an idempotency key.
+    fn answer_idempotency_key(
+        &self,
+        idempotency_key: &str,
+        user_id: u32,
+        response_body: &str,
+    ) -> Result<()>;
+
+    /// Forget the claim on an idempotency key whose request was never answered, so that it can be
+    /// claimed again. Keys that were answered are kept.
+    fn release_idempotency_key(&self, idempotency_key: &str, user_id: u32) -> Result<()>;
+
+    /// Record something that happened to a reservation request that isn't a reservation (yet),
+    /// like it being requested or denied.
+    ///
+    /// # Arguments
+    /// - `reservation_id`: Reservation that the request became, if it was granted.
+    fn record_request_event(
+        &self,
+        kind: LedgerEventKind,
+        reservation_id: Option<u32>,
+        reservation_request: &ReservationRequest,
+        recorded_at: u32,
+        reason: Option<&str>,
+    ) -> Result<()>;
+
+    /// Get every ledger event, in the order they were recorded.
+    fn get_ledger_events(&self) -> Result<Vec<LedgerEvent>>;
+
+    /// Replace every user reservation, like with reservations that were rebuilt from the ledger.
+    ///
+    /// The ledger isn't changed.
+    fn replace_reservations(&self, reservations: &[Reservation]) -> Result<()>;
+
+    /// Record a reservation request that was evaluated and how it turned out.
+    ///
+    /// # Returns
+    /// The recorded request with its unique ID.
+    fn record_evaluated_request(
+        &self,
+        request_evaluation: &RequestEvaluation,
+    ) -> Result<EvaluatedRequest>;
+
+    /// Get evaluated reservation requests that match a filter, in the order they were recorded.
+    fn get_evaluated_requests(
+        &self,
+        request_filter: &RequestFilter,
+    ) -> Result<Vec<EvaluatedRequest>>;
+
+    /// Record a change for followers of the event stream.
+    ///
+    /// # Returns
+    /// The recorded change with its unique ID, which is bigger than every ID before it.
+    fn record_change_event(&self, change_params: &ChangeEventParams) -> Result<ChangeEvent>;
+
+    /// Get changes that match a filter and were recorded after an event, in the order they were
+    /// recorded.
+    ///
+    /// # Arguments
+    /// - `change_filter`: Which changes to get.
+    /// - `after_event_id`: Last event that the caller saw, or 0 for every change.
+    /// - `limit`: Most changes to get.
+    fn get_change_events(
+        &self,
+        change_filter: &ChangeFilter,
+        after_event_id: u32,
+        limit: u32,
+    ) -> Result<Vec<ChangeEvent>>;
+
+    /// Get the latest change event's ID, or 0 if no changes have been recorded.
+    fn get_last_change_event_id(&self) -> Result<u32>;
+
+    /// Subscribe a webhook to changes.
+    ///
+    /// Assume that the subscription has already been validated.
+    ///
+    /// # Arguments
+    /// - `webhook_params`: Subscription that was asked for.
+    /// - `last_event_id`: Last change that the subscription shouldn't be sent.
+    /// - `created_at`: When the subscription was registered, represented by Unix epoch format.
+    ///
+    /// # Returns
+    /// The subscription with its unique ID.
+    fn add_webhook_subscription(
+        &self,
+        webhook_params: &WebhookParams,
+        last_event_id: u32,
+        created_at: u32,
+    ) -> Result<WebhookSubscription>;
+
+    /// Get every webhook subscription, oldest first.
+    fn get_webhook_subscriptions(&self) -> Result<Vec<WebhookSubscription>>;
+
+    /// Delete webhook subscription. Its deliveries are kept.
+    ///
+    /// # Returns
+    /// Whether there was a subscription with the given ID.
+    fn delete_webhook_subscription(&self, subscription_id: u32) -> Result<bool>;
+
+    /// Mark every change up to an event as handed out to every webhook subscription.
+    fn advance_webhook_subscriptions(&self, event_id: u32) -> Result<()>;
+
+    /// Log an attempt to deliver a change to a webhook.
+    ///
+    /// # Returns
+    /// The logged attempt with its unique ID.
+    fn record_webhook_delivery(
+        &self,
+        delivery_params: &WebhookDeliveryParams,
+    ) -> Result<WebhookDelivery>;
+
+    /// Get every attempt to deliver changes to a webhook subscription, in the order they were
+    /// made.
+    fn get_webhook_deliveries(&self, subscription_id: u32) -> Result<Vec<WebhookDelivery>>;
+
+    /// Get user reservations in a pool that overlap a timeframe.
+    fn get_overlapping_reservations(
+        &self,
+        pool: &str,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<Reservation>>;
+
+    /// Get the most capacity that's reserved at once in a pool during a timeframe.
+    fn get_peak_reserved_amount(&self, pool: &str, start_time: u32, end_time: u32) -> Result<u32>;
+
+    /// Add maintenance window to maintenance window table.
+    ///
+    /// Assume that the window's timeframe has already been validated.
+    ///
+    /// # Returns
+    /// The scheduled maintenance window with its unique ID.
+    fn add_maintenance_window(&self, new_window: &MaintenanceParams) -> Result<MaintenanceWindow>;
+
+    /// Get every maintenance window, earliest first.
+    fn get_maintenance_windows(&self) -> Result<Vec<MaintenanceWindow>>;
+
+    /// Get maintenance windows in a pool that overlap a timeframe, earliest first.
+    fn get_overlapping_maintenance_windows(
+        &self,
+        pool: &str,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<MaintenanceWindow>>;
+
+    /// Delete maintenance window.
+    ///
+    /// # Returns
+    /// Whether there was a maintenance window with the given ID.
+    fn delete_maintenance_window(&self, window_id: u32) -> Result<bool>;
+
+    /// Initialize Arbiter's database.
+    ///
+    /// Pending schema migrations are applied and the capacity schedule is seeded if it's empty.
+    /// Existing reservations are never touched.
+    ///
+    /// # Returns
+    /// The database's schema version.
+    fn initialize(&self) -> Result<i32> {
+        info!("Initializing database");
+        // Ensure tables exist and match what this version of Arbiter expects.
+        let schema_version = self.migrate()?;
+        // Populate tables with dummy data the first time around.
+        if self.get_schedule()?.segments.is_empty() {
+            for capacity_segment in schedule_one().segments {
+                self.add_capacity_segment(&capacity_segment)
+                    .context("Failed to seed capacity schedule")?;
+            }
+            info!("Seeded capacity schedule");
+        }
+        info!("Initialized database");
+        Ok(schema_version)
+    }
+
+    /// Delete all known database tables and start over.
+    ///
+    /// **This deletes every reservation.** It's only meant for development, so it's only
+    /// available behind the `--dev-reset-database` flag.
+    fn reset(&self) -> Result<i32> {
+        warn!("Resetting database");
+        self.drop_tables()?;
+        self.initialize()
     }
-    //for existing_reservation in schedule_two()
-    Ok(())
 }
 
-/// Test reservation request
-///
-/// This is based off Schedule 1's first reservation.
-pub fn test_reservation_alpha() -> ReservationRequest {
-    ReservationRequest::new(1707165008, 1708374608, 64, 42)
+/// Get a reservation's terms, like they'd be written in a ledger event.
+fn reservation_terms(reservation: &Reservation) -> ReservationRequest {
+    ReservationRequest::new(
+        reservation.start_time,
+        reservation.end_time,
+        reservation.capacity_amount,
+        reservation.user_id,
+    )
+    .in_pool(&reservation.pool)
+}
+
+/// Connect to the datastore that's chosen by the config, timing every operation for `/metrics`.
+pub fn open_datastore(config: &DatastoreConfig) -> Result<Arc<dyn Datastore>> {
+    let datastore: Arc<dyn Datastore> = match config.backend {
+        DatastoreBackend::Postgres => Arc::new(PostgresDatastore::new(&config.postgres_connection)),
+        DatastoreBackend::Sqlite => Arc::new(SqliteDatastore::open(&config.sqlite_path)?),
+    };
+    Ok(Arc::new(MeteredDatastore::new(datastore)))
 }
 
 /// - Schedule 1
@@ -183,12 +459,11 @@ pub fn test_reservation_alpha() -> ReservationRequest {
 ///    - `{1711398608, 1713213008, 128}`
 pub fn schedule_one() -> CapacitySchedule {
     CapacitySchedule {
-        // Assume that `user_id` "88" is on-site maintenance team.
-        reservations: vec![
-            test_reservation_alpha(),
-            ReservationRequest::new(1708374608, 1710793808, 96, 88),
-            ReservationRequest::new(1710793808, 1711398608, 32, 88),
-            ReservationRequest::new(1711398608, 1713213008, 128, 88),
+        segments: vec![
+            CapacitySegment::new(1707165008, 1708374608, 64),
+            CapacitySegment::new(1708374608, 1710793808, 96),
+            CapacitySegment::new(1710793808, 1711398608, 32),
+            CapacitySegment::new(1711398608, 1713213008, 128),
         ],
     }
 }
@@ -204,21 +479,1055 @@ pub fn schedule_one() -> CapacitySchedule {
 #[allow(unused)]
 pub fn schedule_two() -> CapacitySchedule {
     CapacitySchedule {
-        // Assume that `user_id` "88" is on-site maintenance team.
-        reservations: vec![
-            test_reservation_alpha(),
-            ReservationRequest::new(1707769808, 1708979408, 80, 88),
-            ReservationRequest::new(1708979408, 1709584208, 40, 88),
-            ReservationRequest::new(1709584208, 1712003408, 100, 88),
-            ReservationRequest::new(1712003408, 1712608208, 20, 88),
-            ReservationRequest::new(1712608208, 1714422608, 60, 88),
+        segments: vec![
+            CapacitySegment::new(1707165008, 1707769808, 50),
+            CapacitySegment::new(1707769808, 1708979408, 80),
+            CapacitySegment::new(1708979408, 1709584208, 40),
+            CapacitySegment::new(1709584208, 1712003408, 100),
+            CapacitySegment::new(1712003408, 1712608208, 20),
+            CapacitySegment::new(1712608208, 1714422608, 60),
         ],
     }
 }
 
 #[cfg(test)]
 pub mod test_examples {
+    // Standard library crates.
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::{Arc, OnceLock};
+
+    // External crates.
+    use anyhow::Result;
+
+    // Project crates.
+    use super::{open_datastore, Datastore};
+    use crate::common::{Reservation, ReservationRequest};
+    use crate::config::{DatastoreBackend, DatastoreConfig};
+
     // Make mock schedules available to other tests.
     pub use super::schedule_one;
+    #[allow(unused)]
     pub use super::schedule_two;
+
+    /// Get a path for a SQLite test database that belongs to this test run, so test runs that
+    /// happen at the same time don't reset each other's databases.
+    pub fn test_sqlite_path(test_name: &str) -> PathBuf {
+        env::temp_dir().join(format!("arbiter-{test_name}-{}.sqlite3", process::id()))
+    }
+
+    /// Test datastores that have been reset during this test run.
+    static TEST_POSTGRES: OnceLock<Arc<dyn Datastore>> = OnceLock::new();
+    static TEST_SQLITE: OnceLock<Arc<dyn Datastore>> = OnceLock::new();
+
+    /// Get a test datastore with the given backend, reset to Schedule 1 with no user reservations.
+    ///
+    /// This only happens once per test run so that tests in flight don't lose each other's
+    /// reservations. Tests that reserve capacity must choose timeframes that don't collide.
+    pub fn backend_datastore(backend: DatastoreBackend) -> Arc<dyn Datastore> {
+        let test_datastore = match backend {
+            DatastoreBackend::Postgres => &TEST_POSTGRES,
+            DatastoreBackend::Sqlite => &TEST_SQLITE,
+        };
+        test_datastore
+            .get_or_init(|| {
+                let config = DatastoreConfig {
+                    backend,
+                    sqlite_path: test_sqlite_path("test"),
+                    ..Default::default()
+                };
+                let datastore = open_datastore(&config).expect("Failed to open test datastore");
+                datastore
+                    .reset()
+                    .expect("Failed to initialize test database");
+                datastore
+            })
+            .clone()
+    }
+
+    /// Get the test datastore that the rest of the test suite runs against.
+    ///
+    /// It's PostgreSQL unless `ARBITER_TEST_DATASTORE=sqlite` is set.
+    pub fn test_datastore() -> Arc<dyn Datastore> {
+        match env::var("ARBITER_TEST_DATASTORE").as_deref() {
+            Ok("sqlite") => backend_datastore(DatastoreBackend::Sqlite),
+            _ => backend_datastore(DatastoreBackend::Postgres),
+        }
+    }
+
+    /// Give a capacity pool its own copy of Schedule 1.
+    ///
+    /// Tests that change a pool's capacity (like scheduling maintenance) should use a pool that's
+    /// only theirs so they don't starve other tests.
+    pub fn seed_test_pool(pool: &str) {
+        seed_backend_pool(&*test_datastore(), pool);
+    }
+
+    /// Add a reservation in a booking of its own, like the hostess does once it's granted.
+    pub fn add_test_reservation(
+        datastore: &dyn Datastore,
+        new_reservation: &ReservationRequest,
+        created_at: u32,
+        cost_microcredits: u64,
+    ) -> Result<Reservation> {
+        let mut booking = datastore.begin_booking(&[&new_reservation.pool])?;
+        let added_reservation =
+            booking.add_user_reservation(new_reservation, created_at, cost_microcredits)?;
+        booking.commit()?;
+        Ok(added_reservation)
+    }
+
+    /// Give a capacity pool in the given datastore its own copy of Schedule 1.
+    pub fn seed_backend_pool(datastore: &dyn Datastore, pool: &str) {
+        for capacity_segment in schedule_one().segments {
+            datastore
+                .add_capacity_segment(&capacity_segment.in_pool(pool))
+                .expect("Failed to seed test pool");
+        }
+    }
+}
+
+// Every backend must pass the same tests.
+#[cfg(test)]
+mod tests {
+    // Project crates.
+    use super::test_examples::{
+        add_test_reservation, backend_datastore, schedule_one, seed_backend_pool, test_sqlite_path,
+    };
+    use super::{open_datastore, Datastore};
+    use crate::billing::PriceQuote;
+    use crate::common::{
+        CapacityReduction, CapacitySegment, MaintenanceParams, Reservation, ReservationRequest,
+        ReservationStatus,
+    };
+    use crate::config::{DatastoreBackend, DatastoreConfig};
+    use crate::demand::{Bottleneck, RequestEvaluation, RequestFilter, RequestOutcome};
+    use crate::events::{ChangeEventKind, ChangeEventParams, ChangeFilter};
+    use crate::idempotency::IdempotencyRecord;
+    use crate::ledger::{rebuild_reservations, replay, LedgerEvent, LedgerEventKind};
+    use crate::listing::{
+        PageCursor, PageRequest, ReservationFilter, ReservationSort, SortOrder, TimeMatch,
+    };
+    use crate::webhooks::{WebhookDeliveryParams, WebhookParams};
+
+    /// Run a test against every backend.
+    fn for_each_backend(datastore_test: impl Fn(&dyn Datastore)) {
+        for backend in [DatastoreBackend::Postgres, DatastoreBackend::Sqlite] {
+            datastore_test(&*backend_datastore(backend));
+        }
+    }
+
+    #[test]
+    fn test_initialize_is_repeatable() {
+        for_each_backend(|datastore| {
+            let schema_version = datastore.initialize().unwrap();
+            assert!(schema_version > 0);
+            assert_eq!(datastore.initialize().unwrap(), schema_version);
+            // Seeding only happens once.
+            let default_schedule = datastore.get_schedule().unwrap().for_pool("default");
+            assert_eq!(default_schedule.segments, schedule_one().segments);
+        });
+    }
+
+    #[test]
+    fn test_capacity_segments_round_trip() {
+        for_each_backend(|datastore| {
+            let test_pool = "test_datastore_segments";
+            seed_backend_pool(datastore, test_pool);
+            let pool_schedule = datastore.get_schedule().unwrap().for_pool(test_pool);
+            let expected_segments: Vec<CapacitySegment> = schedule_one()
+                .segments
+                .into_iter()
+                .map(|capacity_segment| capacity_segment.in_pool(test_pool))
+                .collect();
+            assert_eq!(pool_schedule.segments, expected_segments);
+        });
+    }
+
+    #[test]
+    fn test_segment_prices_round_trip() {
+        for_each_backend(|datastore| {
+            let test_pool = "test_datastore_prices";
+            seed_backend_pool(datastore, test_pool);
+            assert!(datastore
+                .set_segment_price(test_pool, 1707165008, 3)
+                .unwrap());
+            // Segments are picked out by when they start.
+            assert!(!datastore
+                .set_segment_price(test_pool, 1707165009, 3)
+                .unwrap());
+            let segment_prices: Vec<(u32, u64)> = datastore
+                .get_schedule()
+                .unwrap()
+                .for_pool(test_pool)
+                .segments
+                .iter()
+                .map(|capacity_segment| {
+                    (
+                        capacity_segment.start_time,
+                        capacity_segment.microcredits_per_unit_second,
+                    )
+                })
+                .collect();
+            assert!(segment_prices.contains(&(1707165008, 3)));
+            // Other segments are still free.
+            assert!(segment_prices.contains(&(1708374608, 0)));
+        });
+    }
+
+    #[test]
+    fn test_reservations_created_between() {
+        for_each_backend(|datastore| {
+            let test_pool = "test_datastore_created_between";
+            let test_request =
+                ReservationRequest::new(1707165008, 1707251408, 10, 42).in_pool(test_pool);
+            let early_reservation =
+                add_test_reservation(datastore, &test_request, 1707078608, 100).unwrap();
+            add_test_reservation(datastore, &test_request, 1707165008, 200).unwrap();
+            let created: Vec<Reservation> = datastore
+                .get_reservations_created_between(1707078608, 1707165008)
+                .unwrap()
+                .into_iter()
+                .filter(|reservation| reservation.pool == test_pool)
+                .collect();
+            assert_eq!(created, vec![early_reservation]);
+        });
+    }
+
+    #[test]
+    fn test_list_reservations() {
+        for_each_backend(|datastore| {
+            let test_pool = "test_datastore_list_reservations";
+            let reserve = |start_time: u32, user_id: u32| {
+                let test_request =
+                    ReservationRequest::new(start_time, start_time + 3600, 1, user_id)
+                        .in_pool(test_pool);
+                add_test_reservation(datastore, &test_request, 1707078608, 0).unwrap()
+            };
+            let first_reservation = reserve(1707165008, 42);
+            let second_reservation = reserve(1707168608, 43);
+            let third_reservation = reserve(1707172208, 42);
+            let second_reservation = datastore
+                .end_reservation(
+                    second_reservation.reservation_id,
+                    LedgerEventKind::Cancelled,
+                    1707078608,
+                    None,
+                    0,
+                )
+                .unwrap()
+                .unwrap();
+            let first_page = PageRequest {
+                sort: ReservationSort::Id,
+                order: SortOrder::Asc,
+                after: None,
+                limit: 10,
+            };
+            let pool_filter = ReservationFilter {
+                pool: Some(test_pool.to_string()),
+                ..Default::default()
+            };
+            let listed = |reservation_filter: &ReservationFilter, page_request: &PageRequest| {
+                datastore
+                    .list_reservations(reservation_filter, page_request)
+                    .unwrap()
+            };
+            let user_filter = ReservationFilter {
+                user_id: Some(42),
+                ..pool_filter.clone()
+            };
+            assert_eq!(
+                listed(&user_filter, &first_page),
+                vec![first_reservation.clone(), third_reservation.clone()]
+            );
+            let status_filter = ReservationFilter {
+                status: Some(ReservationStatus::Cancelled),
+                ..pool_filter.clone()
+            };
+            assert_eq!(
+                listed(&status_filter, &first_page),
+                vec![second_reservation.clone()]
+            );
+            // The second reservation's hour is matched differently by each kind of time filter.
+            let time_filter = |time_match: TimeMatch| ReservationFilter {
+                start_time: Some(1707168608),
+                end_time: Some(1707172208),
+                time_match,
+                ..pool_filter.clone()
+            };
+            assert_eq!(
+                listed(&time_filter(TimeMatch::Overlapping), &first_page),
+                vec![second_reservation.clone()]
+            );
+            assert_eq!(
+                listed(&time_filter(TimeMatch::StartingIn), &first_page),
+                vec![second_reservation.clone()]
+            );
+            assert_eq!(
+                listed(&time_filter(TimeMatch::EndingIn), &first_page),
+                vec![first_reservation.clone()]
+            );
+            // Pages pick up right after their cursor.
+            let latest_first = PageRequest {
+                sort: ReservationSort::StartTime,
+                order: SortOrder::Desc,
+                after: None,
+                limit: 2,
+            };
+            assert_eq!(
+                listed(&pool_filter, &latest_first),
+                vec![third_reservation, second_reservation.clone()]
+            );
+            let next_page = PageRequest {
+                after: Some(PageCursor::after(
+                    &second_reservation,
+                    ReservationSort::StartTime,
+                    SortOrder::Desc,
+                )),
+                ..latest_first
+            };
+            assert_eq!(listed(&pool_filter, &next_page), vec![first_reservation]);
+        });
+    }
+
+    #[test]
+    fn test_price_quotes_redeem_once() {
+        for_each_backend(|datastore| {
+            let price_quote = PriceQuote {
+                quote_token: PriceQuote::new_token(),
+                start_time: 1707165008,
+                end_time: 1707168608,
+                capacity_amount: 8,
+                user_id: 42,
+                pool: String::from("test_datastore_quotes"),
+                base_cost_microcredits: 28_800,
+                price_percent: 150,
+                price_microcredits: 43_200,
+                quoted_at: 1707078608,
+                expires_at: 1707078908,
+                redeemed_at: None,
+            };
+            datastore.add_price_quote(&price_quote).unwrap();
+            let get_price_quote = |quote_token: &str| {
+                datastore
+                    .begin_booking(&[&price_quote.pool])
+                    .unwrap()
+                    .get_price_quote(quote_token)
+                    .unwrap()
+            };
+            assert_eq!(
+                get_price_quote(&price_quote.quote_token),
+                Some(price_quote.clone())
+            );
+            assert_eq!(get_price_quote("no such quote"), None);
+            let quoted_request = ReservationRequest::new(1707165008, 1707168608, 8, 42)
+                .in_pool("test_datastore_quotes");
+            let redeem = |redeemed_at: u32, cost_microcredits: u64| {
+                let mut booking = datastore.begin_booking(&[&price_quote.pool])?;
+                let quoted_reservation = booking.add_quoted_reservation(
+                    &quoted_request,
+                    redeemed_at,
+                    cost_microcredits,
+                    &price_quote.quote_token,
+                )?;
+                booking.commit()?;
+                anyhow::Ok(quoted_reservation)
+            };
+            // Expired quotes can't be redeemed.
+            assert_eq!(redeem(1707078908, 43_200).unwrap(), None);
+            // Quotes aren't used up by reservations that fail to be added.
+            assert!(redeem(1707078700, u64::MAX).is_err());
+            assert_eq!(
+                get_price_quote(&price_quote.quote_token)
+                    .unwrap()
+                    .redeemed_at,
+                None
+            );
+            let quoted_reservation = redeem(1707078700, 43_200).unwrap().unwrap();
+            assert_eq!(quoted_reservation.cost_microcredits, 43_200);
+            assert_eq!(redeem(1707078800, 43_200).unwrap(), None);
+            let redeemed_quote = get_price_quote(&price_quote.quote_token).unwrap();
+            assert_eq!(redeemed_quote.redeemed_at, Some(1707078700));
+        });
+    }
+
+    #[test]
+    fn test_idempotency_keys_claim_once() {
+        for_each_backend(|datastore| {
+            let idempotency_record = IdempotencyRecord {
+                idempotency_key: String::from("test-datastore-retry"),
+                user_id: 4545,
+                request_hash: String::from("request-hash"),
+                response_body: None,
+                created_at: 1707078608,
+                expires_at: 1707078908,
+            };
+            assert_eq!(
+                datastore
+                    .claim_idempotency_key(&idempotency_record, 1707078608)
+                    .unwrap(),
+                None
+            );
+            // Whoever claims it next finds the first claim, which hasn't been answered yet.
+            assert_eq!(
+                datastore
+                    .claim_idempotency_key(&idempotency_record, 1707078700)
+                    .unwrap(),
+                Some(idempotency_record.clone())
+            );
+            // Claims that are released can be claimed again.
+            datastore
+                .release_idempotency_key("test-datastore-retry", 4545)
+                .unwrap();
+            assert_eq!(
+                datastore
+                    .claim_idempotency_key(&idempotency_record, 1707078700)
+                    .unwrap(),
+                None
+            );
+            datastore
+                .answer_idempotency_key("test-datastore-retry", 4545, "{}")
+                .unwrap();
+            // Answered keys aren't released.
+            datastore
+                .release_idempotency_key("test-datastore-retry", 4545)
+                .unwrap();
+            let answered_record = datastore
+                .claim_idempotency_key(&idempotency_record, 1707078700)
+                .unwrap()
+                .unwrap();
+            assert_eq!(answered_record.response_body.as_deref(), Some("{}"));
+            // Other users can use the same key.
+            let other_user_record = IdempotencyRecord {
+                user_id: 4546,
+                ..idempotency_record.clone()
+            };
+            assert_eq!(
+                datastore
+                    .claim_idempotency_key(&other_user_record, 1707078700)
+                    .unwrap(),
+                None
+            );
+            // Expired keys are forgotten, so they can be claimed again.
+            let renewed_record = IdempotencyRecord {
+                created_at: 1707078908,
+                expires_at: 1707079208,
+                ..idempotency_record
+            };
+            assert_eq!(
+                datastore
+                    .claim_idempotency_key(&renewed_record, 1707078908)
+                    .unwrap(),
+                None
+            );
+        });
+    }
+
+    #[test]
+    fn test_reservations_round_trip() {
+        for_each_backend(|datastore| {
+            let test_pool = "test_datastore_reservations";
+            let first_request =
+                ReservationRequest::new(1707165008, 1707251408, 10, 42).in_pool(test_pool);
+            let second_request =
+                ReservationRequest::new(1707251408, 1707337808, 20, 43).in_pool(test_pool);
+            let first_reservation =
+                add_test_reservation(datastore, &first_request, 1707078608, 0).unwrap();
+            let second_reservation =
+                add_test_reservation(datastore, &second_request, 1707078608, 5000).unwrap();
+            // Every reservation gets its own ID.
+            assert!(second_reservation.reservation_id > first_reservation.reservation_id);
+            assert_eq!(first_reservation.status, ReservationStatus::Pending);
+            assert_eq!(
+                datastore
+                    .get_reservation(second_reservation.reservation_id)
+                    .unwrap(),
+                Some(second_reservation.clone())
+            );
+            assert_eq!(datastore.get_reservation(u32::MAX).unwrap(), None);
+            // Reservations that only touch the timeframe's edges don't overlap it.
+            let overlapping = datastore
+                .get_overlapping_reservations(test_pool, 1707251408, 1707337808)
+                .unwrap();
+            assert_eq!(overlapping, vec![second_reservation.clone()]);
+            let overlapping = datastore
+                .get_overlapping_reservations(test_pool, 1707165008, 1707337808)
+                .unwrap();
+            assert_eq!(overlapping.len(), 2);
+            // Other pools are left out.
+            let overlapping = datastore
+                .get_overlapping_reservations("test_datastore_elsewhere", 1707165008, 1707337808)
+                .unwrap();
+            assert!(overlapping.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_reservation_lifecycle() {
+        for_each_backend(|datastore| {
+            let test_request = ReservationRequest::new(1707165008, 1707168608, 10, 42)
+                .in_pool("test_datastore_lifecycle");
+            let reservation =
+                add_test_reservation(datastore, &test_request, 1707078608, 0).unwrap();
+            let is_due = |epoch_now: u32| {
+                datastore
+                    .get_due_reservations(epoch_now)
+                    .unwrap()
+                    .iter()
+                    .any(|due| due.reservation_id == reservation.reservation_id)
+            };
+            assert!(!is_due(1707165007));
+            let next_boundary = datastore.get_next_lifecycle_boundary(1707165007).unwrap();
+            assert!(next_boundary.is_some_and(|next_boundary| next_boundary <= 1707165008));
+            assert!(is_due(1707165008));
+
+            let started_reservation = datastore
+                .advance_reservation(
+                    reservation.reservation_id,
+                    LedgerEventKind::Started,
+                    1707165008,
+                )
+                .unwrap()
+                .unwrap();
+            assert_eq!(started_reservation.status, ReservationStatus::Active);
+            assert_eq!(started_reservation.updated_at, 1707165008);
+            // Reservations only start once, and don't end before their end time.
+            assert_eq!(
+                datastore
+                    .advance_reservation(
+                        reservation.reservation_id,
+                        LedgerEventKind::Started,
+                        1707165008
+                    )
+                    .unwrap(),
+                None
+            );
+            assert!(!is_due(1707168607));
+            assert!(is_due(1707168608));
+
+            let completed_reservation = datastore
+                .advance_reservation(
+                    reservation.reservation_id,
+                    LedgerEventKind::Completed,
+                    1707168608,
+                )
+                .unwrap()
+                .unwrap();
+            assert_eq!(completed_reservation.status, ReservationStatus::Completed);
+            assert!(!is_due(1707168608));
+            // Completed reservations can't be cancelled.
+            assert_eq!(
+                datastore
+                    .end_reservation(
+                        reservation.reservation_id,
+                        LedgerEventKind::Cancelled,
+                        1707168609,
+                        None,
+                        0
+                    )
+                    .unwrap(),
+                None
+            );
+            assert!(datastore
+                .advance_reservation(
+                    reservation.reservation_id,
+                    LedgerEventKind::Cancelled,
+                    1707168609
+                )
+                .is_err());
+            let ledger_kinds: Vec<LedgerEventKind> = datastore
+                .get_ledger_events()
+                .unwrap()
+                .into_iter()
+                .filter(|ledger_event| {
+                    ledger_event.reservation_id == Some(reservation.reservation_id)
+                })
+                .map(|ledger_event| ledger_event.kind)
+                .collect();
+            assert_eq!(
+                ledger_kinds,
+                vec![
+                    LedgerEventKind::Approved,
+                    LedgerEventKind::Started,
+                    LedgerEventKind::Completed
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_peak_reserved_amount() {
+        for_each_backend(|datastore| {
+            let test_pool = "test_datastore_peak";
+            for (start_time, end_time, capacity_amount) in [
+                (1707165008, 1707251408, 10),
+                (1707208208, 1707294608, 20),
+                (1707294608, 1707381008, 5),
+            ] {
+                let test_request =
+                    ReservationRequest::new(start_time, end_time, capacity_amount, 42)
+                        .in_pool(test_pool);
+                add_test_reservation(datastore, &test_request, 1707078608, 0).unwrap();
+            }
+            let peak = |start_time, end_time| {
+                datastore
+                    .get_peak_reserved_amount(test_pool, start_time, end_time)
+                    .unwrap()
+            };
+            assert_eq!(peak(1707165008, 1707381008), 30);
+            assert_eq!(peak(1707251408, 1707381008), 20);
+            assert_eq!(peak(1707294608, 1707381008), 5);
+            assert_eq!(peak(1707381008, 1707467408), 0);
+        });
+    }
+
+    #[test]
+    fn test_maintenance_windows_round_trip() {
+        for_each_backend(|datastore| {
+            let test_pool = "test_datastore_maintenance";
+            let partial_window = datastore
+                .add_maintenance_window(&MaintenanceParams {
+                    pool: String::from(test_pool),
+                    start_time: 1707165008,
+                    end_time: 1707251408,
+                    reduction: CapacityReduction::Amount(16),
+                    reason: String::from("replacing PDUs"),
+                })
+                .unwrap();
+            let full_window = datastore
+                .add_maintenance_window(&MaintenanceParams {
+                    pool: String::from(test_pool),
+                    start_time: 1707251408,
+                    end_time: 1707337808,
+                    reduction: CapacityReduction::All,
+                    reason: String::from("moving racks"),
+                })
+                .unwrap();
+            let listed_windows = datastore.get_maintenance_windows().unwrap();
+            assert!(listed_windows.contains(&partial_window));
+            assert!(listed_windows.contains(&full_window));
+            let overlapping = datastore
+                .get_overlapping_maintenance_windows(test_pool, 1707251408, 1707337808)
+                .unwrap();
+            assert_eq!(overlapping, vec![full_window.clone()]);
+            assert!(datastore
+                .delete_maintenance_window(full_window.window_id)
+                .unwrap());
+            assert!(!datastore
+                .delete_maintenance_window(full_window.window_id)
+                .unwrap());
+            let overlapping = datastore
+                .get_overlapping_maintenance_windows(test_pool, 1707165008, 1707337808)
+                .unwrap();
+            assert_eq!(overlapping, vec![partial_window]);
+        });
+    }
+
+    #[test]
+    fn test_change_events_round_trip() {
+        for_each_backend(|datastore| {
+            let test_pool = "test_datastore_changes";
+            let first_event_id = datastore.get_last_change_event_id().unwrap();
+            let change_params = |user_id: Option<u32>| ChangeEventParams {
+                kind: ChangeEventKind::ReservationCreated,
+                recorded_at: 1707078608,
+                pool: String::from(test_pool),
+                user_id,
+                payload: serde_json::json!({ "capacity_amount": 8 }),
+            };
+            let own_event = datastore
+                .record_change_event(&change_params(Some(42)))
+                .unwrap();
+            let others_event = datastore
+                .record_change_event(&change_params(Some(7)))
+                .unwrap();
+            let pool_event = datastore.record_change_event(&change_params(None)).unwrap();
+            assert!(own_event.event_id > first_event_id);
+            assert!(others_event.event_id > own_event.event_id);
+            assert_eq!(own_event.payload["capacity_amount"], 8);
+            assert_eq!(
+                datastore.get_last_change_event_id().unwrap(),
+                pool_event.event_id
+            );
+
+            let pool_filter = ChangeFilter {
+                pool: Some(String::from(test_pool)),
+                user_id: None,
+            };
+            let pool_events = datastore
+                .get_change_events(&pool_filter, first_event_id, 10)
+                .unwrap();
+            assert_eq!(
+                pool_events,
+                vec![own_event.clone(), others_event.clone(), pool_event.clone()]
+            );
+            // Changes that aren't to anyone's reservation match every user.
+            let user_filter = ChangeFilter {
+                user_id: Some(42),
+                ..pool_filter.clone()
+            };
+            let user_events = datastore
+                .get_change_events(&user_filter, first_event_id, 10)
+                .unwrap();
+            assert_eq!(user_events, vec![own_event.clone(), pool_event.clone()]);
+            // Followers pick up after the last event they saw, a page at a time.
+            let resumed_events = datastore
+                .get_change_events(&pool_filter, own_event.event_id, 1)
+                .unwrap();
+            assert_eq!(resumed_events, vec![others_event]);
+            let other_pool_filter = ChangeFilter {
+                pool: Some(String::from("test_datastore_no_changes")),
+                user_id: None,
+            };
+            assert!(datastore
+                .get_change_events(&other_pool_filter, first_event_id, 10)
+                .unwrap()
+                .is_empty());
+
+            // Changes recorded by a booking are only kept if the booking is.
+            let mut booking = datastore.begin_booking(&[test_pool]).unwrap();
+            booking
+                .record_change_event(&change_params(Some(42)))
+                .unwrap();
+            drop(booking);
+            assert!(datastore
+                .get_change_events(&pool_filter, pool_event.event_id, 10)
+                .unwrap()
+                .is_empty());
+            let mut booking = datastore.begin_booking(&[test_pool]).unwrap();
+            let booked_event = booking
+                .record_change_event(&change_params(Some(42)))
+                .unwrap();
+            booking.commit().unwrap();
+            assert_eq!(
+                datastore
+                    .get_change_events(&pool_filter, pool_event.event_id, 10)
+                    .unwrap(),
+                vec![booked_event]
+            );
+        });
+    }
+
+    #[test]
+    fn test_webhooks_round_trip() {
+        for_each_backend(|datastore| {
+            let webhook_params = WebhookParams {
+                url: String::from("http://127.0.0.1:9/arbiter"),
+                event_kinds: vec![
+                    ChangeEventKind::ReservationStarted,
+                    ChangeEventKind::ReservationEnded,
+                ],
+                pool: Some(String::from("test_datastore_webhooks")),
+                user_id: Some(42),
+                secret: String::from("correct horse battery staple"),
+            };
+            let subscription = datastore
+                .add_webhook_subscription(&webhook_params, 12, 1707078608)
+                .unwrap();
+            assert_eq!(subscription.event_kinds, webhook_params.event_kinds);
+            assert_eq!(subscription.secret, webhook_params.secret);
+            assert_eq!(subscription.last_event_id, 12);
+            let listed_subscriptions = datastore.get_webhook_subscriptions().unwrap();
+            assert!(listed_subscriptions.contains(&subscription));
+
+            // Subscriptions only move forward.
+            datastore.advance_webhook_subscriptions(14).unwrap();
+            datastore.advance_webhook_subscriptions(13).unwrap();
+            let advanced_subscription = datastore
+                .get_webhook_subscriptions()
+                .unwrap()
+                .into_iter()
+                .find(|listed| listed.subscription_id == subscription.subscription_id)
+                .unwrap();
+            assert!(advanced_subscription.last_event_id >= 14);
+
+            let delivery_params = |attempt: u32, status_code: Option<u16>| WebhookDeliveryParams {
+                subscription_id: subscription.subscription_id,
+                event_id: 14,
+                attempt,
+                attempted_at: 1707078608 + attempt,
+                status_code,
+                error: match status_code {
+                    Some(200) => None,
+                    _ => Some(String::from("Receiver couldn't be reached")),
+                },
+                is_delivered: status_code == Some(200),
+            };
+            let failed_delivery = datastore
+                .record_webhook_delivery(&delivery_params(1, None))
+                .unwrap();
+            let accepted_delivery = datastore
+                .record_webhook_delivery(&delivery_params(2, Some(200)))
+                .unwrap();
+            assert!(!failed_delivery.is_delivered);
+            assert!(accepted_delivery.is_delivered);
+            assert_eq!(accepted_delivery.status_code, Some(200));
+            assert_eq!(
+                datastore
+                    .get_webhook_deliveries(subscription.subscription_id)
+                    .unwrap(),
+                vec![failed_delivery.clone(), accepted_delivery.clone()]
+            );
+
+            // Deliveries are still logged after their subscription is gone.
+            assert!(datastore
+                .delete_webhook_subscription(subscription.subscription_id)
+                .unwrap());
+            assert!(!datastore
+                .delete_webhook_subscription(subscription.subscription_id)
+                .unwrap());
+            assert_eq!(
+                datastore
+                    .get_webhook_deliveries(subscription.subscription_id)
+                    .unwrap(),
+                vec![failed_delivery, accepted_delivery]
+            );
+        });
+    }
+
+    #[test]
+    fn test_pool_names_are_not_sql() {
+        for_each_backend(|datastore| {
+            let test_pool = "test_datastore_o'brien'); DROP TABLE capacity_schedule; --";
+            seed_backend_pool(datastore, test_pool);
+            let pool_schedule = datastore.get_schedule().unwrap().for_pool(test_pool);
+            assert_eq!(pool_schedule.segments.len(), schedule_one().segments.len());
+            let test_request =
+                ReservationRequest::new(1707165008, 1707251408, 10, 42).in_pool(test_pool);
+            let added_reservation =
+                add_test_reservation(datastore, &test_request, 1707078608, 0).unwrap();
+            assert_eq!(added_reservation.pool, test_pool);
+            let overlapping = datastore
+                .get_overlapping_reservations(test_pool, 1707165008, 1707251408)
+                .unwrap();
+            assert_eq!(overlapping, vec![added_reservation]);
+        });
+    }
+
+    #[test]
+    fn test_refuse_overlapping_segments() {
+        for_each_backend(|datastore| {
+            let test_pool = "test_datastore_overlap";
+            seed_backend_pool(datastore, test_pool);
+            // Starts a day before Schedule 1's first segment ends.
+            let overlapping_segment =
+                CapacitySegment::new(1708288208, 1708461008, 8).in_pool(test_pool);
+            assert!(datastore
+                .add_capacity_segment(&overlapping_segment)
+                .is_err());
+            // Segments that only touch are fine.
+            let touching_segment =
+                CapacitySegment::new(1713213008, 1713299408, 8).in_pool(test_pool);
+            assert!(datastore.add_capacity_segment(&touching_segment).is_ok());
+        });
+    }
+
+    #[test]
+    fn test_refuse_backwards_segments() {
+        for_each_backend(|datastore| {
+            let test_pool = "test_datastore_backwards";
+            let backwards_segment =
+                CapacitySegment::new(1708374608, 1707165008, 8).in_pool(test_pool);
+            assert!(datastore.add_capacity_segment(&backwards_segment).is_err());
+            let empty_segment = CapacitySegment::new(1707165008, 1707165008, 8).in_pool(test_pool);
+            assert!(datastore.add_capacity_segment(&empty_segment).is_err());
+            assert!(datastore
+                .get_schedule()
+                .unwrap()
+                .for_pool(test_pool)
+                .segments
+                .is_empty());
+        });
+    }
+
+    #[test]
+    fn test_ledger_records_reservation_changes() {
+        for_each_backend(|datastore| {
+            let test_pool = "test_datastore_ledger";
+            let test_request =
+                ReservationRequest::new(1707165008, 1707251408, 10, 42).in_pool(test_pool);
+            let test_reservation =
+                add_test_reservation(datastore, &test_request, 1707078608, 1000).unwrap();
+            // Requests are recorded once they're decided, along with what they became.
+            datastore
+                .record_request_event(
+                    LedgerEventKind::Requested,
+                    Some(test_reservation.reservation_id),
+                    &test_request,
+                    1707078608,
+                    None,
+                )
+                .unwrap();
+            let cancelled_reservation = datastore
+                .end_reservation(
+                    test_reservation.reservation_id,
+                    LedgerEventKind::Cancelled,
+                    1707078708,
+                    Some("Plans changed"),
+                    400,
+                )
+                .unwrap()
+                .unwrap();
+            assert_eq!(cancelled_reservation.status, ReservationStatus::Cancelled);
+            assert_eq!(cancelled_reservation.updated_at, 1707078708);
+            assert_eq!(cancelled_reservation.cost_microcredits, 1000);
+            assert_eq!(cancelled_reservation.refund_microcredits, 400);
+            // Reservations only end once.
+            let ended_again = datastore
+                .end_reservation(
+                    test_reservation.reservation_id,
+                    LedgerEventKind::Expired,
+                    1707078808,
+                    None,
+                    0,
+                )
+                .unwrap();
+            assert!(ended_again.is_none());
+            // Ended reservations don't hold capacity.
+            let peak = datastore
+                .get_peak_reserved_amount(test_pool, 1707165008, 1707251408)
+                .unwrap();
+            assert_eq!(peak, 0);
+            let pool_events: Vec<LedgerEvent> = datastore
+                .get_ledger_events()
+                .unwrap()
+                .into_iter()
+                .filter(|ledger_event| ledger_event.pool == test_pool)
+                .collect();
+            let event_kinds: Vec<LedgerEventKind> = pool_events
+                .iter()
+                .map(|ledger_event| ledger_event.kind)
+                .collect();
+            assert_eq!(
+                event_kinds,
+                vec![
+                    LedgerEventKind::Approved,
+                    LedgerEventKind::Requested,
+                    LedgerEventKind::Cancelled
+                ]
+            );
+            assert_eq!(
+                pool_events[1].reservation_id,
+                Some(test_reservation.reservation_id)
+            );
+            assert_eq!(pool_events[2].reason.as_deref(), Some("Plans changed"));
+            // Replaying the ledger gives back the stored reservation.
+            let replayed = replay(&pool_events, None).unwrap();
+            assert_eq!(replayed, vec![cancelled_reservation]);
+        });
+    }
+
+    #[test]
+    fn test_evaluated_requests_round_trip() {
+        for_each_backend(|datastore| {
+            let test_pool = "test_datastore_requests";
+            let denied_evaluation = RequestEvaluation {
+                reservation_request: ReservationRequest::new(1707165008, 1707251408, 10, 42)
+                    .in_pool(test_pool),
+                requested_at: 1707078608,
+                outcome: RequestOutcome::Denied,
+                denial_reason: Some(String::from("Not enough capacity")),
+                reservation_id: None,
+                bottleneck: Some(Bottleneck {
+                    start_time: 1707165008,
+                    end_time: 1707208208,
+                    available_amount: 4,
+                }),
+            };
+            let approved_evaluation = RequestEvaluation {
+                reservation_request: ReservationRequest::new(1707251408, 1707337808, 20, 43)
+                    .in_pool(test_pool),
+                requested_at: 1707078708,
+                outcome: RequestOutcome::Approved,
+                denial_reason: None,
+                reservation_id: Some(7),
+                bottleneck: None,
+            };
+            let denied_request = datastore
+                .record_evaluated_request(&denied_evaluation)
+                .unwrap();
+            let approved_request = datastore
+                .record_evaluated_request(&approved_evaluation)
+                .unwrap();
+            assert!(approved_request.request_id > denied_request.request_id);
+            assert_eq!(denied_request.bottleneck, denied_evaluation.bottleneck);
+            assert_eq!(approved_request.reservation_id, Some(7));
+            let pool_filter = RequestFilter {
+                pool: Some(String::from(test_pool)),
+                ..Default::default()
+            };
+            let evaluated_requests = datastore.get_evaluated_requests(&pool_filter).unwrap();
+            assert_eq!(
+                evaluated_requests,
+                vec![denied_request.clone(), approved_request.clone()]
+            );
+            // Every filter narrows the requests down.
+            let filtered = |request_filter: RequestFilter| {
+                datastore
+                    .get_evaluated_requests(&RequestFilter {
+                        pool: Some(String::from(test_pool)),
+                        ..request_filter
+                    })
+                    .unwrap()
+            };
+            let denied_filter = RequestFilter {
+                outcome: Some(RequestOutcome::Denied),
+                ..Default::default()
+            };
+            assert_eq!(filtered(denied_filter), vec![denied_request.clone()]);
+            let user_filter = RequestFilter {
+                user_id: Some(43),
+                ..Default::default()
+            };
+            assert_eq!(filtered(user_filter), vec![approved_request.clone()]);
+            // Requests that only touch the timeframe's edges don't overlap it.
+            let timeframe_filter = RequestFilter {
+                start_time: Some(1707251408),
+                end_time: Some(1707337808),
+                ..Default::default()
+            };
+            assert_eq!(filtered(timeframe_filter), vec![approved_request]);
+        });
+    }
+
+    // Rebuilding is destructive, so it gets a database of its own.
+    #[test]
+    fn test_rebuild_reservations_from_ledger() {
+        let config = DatastoreConfig {
+            backend: DatastoreBackend::Sqlite,
+            sqlite_path: test_sqlite_path("test-rebuild"),
+            ..Default::default()
+        };
+        let datastore = open_datastore(&config).unwrap();
+        datastore.reset().unwrap();
+        let first_request = ReservationRequest::new(1707165008, 1707251408, 10, 42);
+        let second_request = ReservationRequest::new(1707251408, 1707337808, 20, 43);
+        let first_reservation =
+            add_test_reservation(&*datastore, &first_request, 1707078608, 1000).unwrap();
+        add_test_reservation(&*datastore, &second_request, 1707078608, 2000).unwrap();
+        datastore
+            .end_reservation(
+                first_reservation.reservation_id,
+                LedgerEventKind::Preempted,
+                1707078708,
+                None,
+                1000,
+            )
+            .unwrap();
+        let stored = datastore
+            .get_overlapping_reservations("default", 1707165008, 1707337808)
+            .unwrap();
+        assert_eq!(rebuild_reservations(&*datastore).unwrap(), 2);
+        let rebuilt = datastore
+            .get_overlapping_reservations("default", 1707165008, 1707337808)
+            .unwrap();
+        assert_eq!(rebuilt, stored);
+        // New reservations keep getting fresh IDs after a rebuild.
+        let third_reservation =
+            add_test_reservation(&*datastore, &first_request, 1707078808, 0).unwrap();
+        assert!(rebuilt
+            .iter()
+            .all(|reservation| reservation.reservation_id < third_reservation.reservation_id));
+    }
 }
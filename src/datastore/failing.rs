@@ -0,0 +1,370 @@
+//! Failing Datastore
+//!
+//! `failing` wraps another datastore and fails one of its operations once, so tests can see how
+//! Arbiter copes with the database going away for a moment.
+
+// Standard library crates.
+use std::sync::{Arc, Mutex};
+
+// External crates.
+use anyhow::{bail, Result};
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+
+// Project crates.
+use super::{Booking, Datastore};
+use crate::billing::PriceQuote;
+use crate::common::{CapacitySegment, MaintenanceParams, MaintenanceWindow, Reservation};
+use crate::demand::{EvaluatedRequest, RequestEvaluation, RequestFilter};
+use crate::events::{ChangeEvent, ChangeEventParams, ChangeFilter};
+use crate::idempotency::IdempotencyRecord;
+use crate::ledger::{LedgerEvent, LedgerEventKind};
+use crate::listing::{PageRequest, ReservationFilter};
+use crate::metrics::ReservationCount;
+use crate::webhooks::{WebhookDelivery, WebhookDeliveryParams, WebhookParams, WebhookSubscription};
+use crate::CapacitySchedule;
+use crate::ReservationRequest;
+
+/// Datastore that fails the next call to an operation, and passes everything else through.
+pub struct FailingDatastore {
+    inner: Arc<dyn Datastore>,
+    failing_operation: Mutex<Option<String>>,
+}
+
+impl FailingDatastore {
+    pub fn new(inner: Arc<dyn Datastore>) -> Self {
+        Self {
+            inner,
+            failing_operation: Mutex::new(None),
+        }
+    }
+
+    /// Fail the next call to an operation, like `"advance_webhook_subscriptions"`.
+    pub fn fail_next(&self, operation: &str) {
+        *self.failing_operation.lock().unwrap() = Some(String::from(operation));
+    }
+
+    /// Run an operation, unless it's the one that's supposed to fail next.
+    fn checked<T>(&self, operation: &str, query: impl FnOnce() -> Result<T>) -> Result<T> {
+        let mut failing_operation = self.failing_operation.lock().unwrap();
+        if failing_operation.as_deref() == Some(operation) {
+            *failing_operation = None;
+            bail!("Datastore failed \"{operation}\" on purpose");
+        }
+        drop(failing_operation);
+        query()
+    }
+}
+
+impl Datastore for FailingDatastore {
+    fn migrate(&self) -> Result<i32> {
+        self.checked("migrate", || self.inner.migrate())
+    }
+
+    fn drop_tables(&self) -> Result<()> {
+        self.checked("drop_tables", || self.inner.drop_tables())
+    }
+
+    fn get_schedule(&self) -> Result<CapacitySchedule> {
+        self.checked("get_schedule", || self.inner.get_schedule())
+    }
+
+    fn add_capacity_segment(&self, capacity_segment: &CapacitySegment) -> Result<()> {
+        self.checked("add_capacity_segment", || {
+            self.inner.add_capacity_segment(capacity_segment)
+        })
+    }
+
+    fn set_segment_price(
+        &self,
+        pool: &str,
+        start_time: u32,
+        microcredits_per_unit_second: u64,
+    ) -> Result<bool> {
+        self.checked("set_segment_price", || {
+            self.inner
+                .set_segment_price(pool, start_time, microcredits_per_unit_second)
+        })
+    }
+
+    fn begin_booking(&self, pools: &[&str]) -> Result<Box<dyn Booking + '_>> {
+        self.checked("begin_booking", || self.inner.begin_booking(pools))
+    }
+
+    fn get_reservation(&self, reservation_id: u32) -> Result<Option<Reservation>> {
+        self.checked("get_reservation", || {
+            self.inner.get_reservation(reservation_id)
+        })
+    }
+
+    fn get_reservations_created_between(
+        &self,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<Reservation>> {
+        self.checked("get_reservations_created_between", || {
+            self.inner
+                .get_reservations_created_between(start_time, end_time)
+        })
+    }
+
+    fn list_reservations(
+        &self,
+        reservation_filter: &ReservationFilter,
+        page_request: &PageRequest,
+    ) -> Result<Vec<Reservation>> {
+        self.checked("list_reservations", || {
+            self.inner
+                .list_reservations(reservation_filter, page_request)
+        })
+    }
+
+    fn end_reservation(
+        &self,
+        reservation_id: u32,
+        ending_kind: LedgerEventKind,
+        recorded_at: u32,
+        reason: Option<&str>,
+        refund_microcredits: u64,
+    ) -> Result<Option<Reservation>> {
+        self.checked("end_reservation", || {
+            self.inner.end_reservation(
+                reservation_id,
+                ending_kind,
+                recorded_at,
+                reason,
+                refund_microcredits,
+            )
+        })
+    }
+
+    fn get_due_reservations(&self, epoch_now: u32) -> Result<Vec<Reservation>> {
+        self.checked("get_due_reservations", || {
+            self.inner.get_due_reservations(epoch_now)
+        })
+    }
+
+    fn get_next_lifecycle_boundary(&self, epoch_now: u32) -> Result<Option<u32>> {
+        self.checked("get_next_lifecycle_boundary", || {
+            self.inner.get_next_lifecycle_boundary(epoch_now)
+        })
+    }
+
+    fn get_reservation_counts(&self) -> Result<Vec<ReservationCount>> {
+        self.checked("get_reservation_counts", || {
+            self.inner.get_reservation_counts()
+        })
+    }
+
+    fn advance_reservation(
+        &self,
+        reservation_id: u32,
+        lifecycle_kind: LedgerEventKind,
+        recorded_at: u32,
+    ) -> Result<Option<Reservation>> {
+        self.checked("advance_reservation", || {
+            self.inner
+                .advance_reservation(reservation_id, lifecycle_kind, recorded_at)
+        })
+    }
+
+    fn add_price_quote(&self, price_quote: &PriceQuote) -> Result<()> {
+        self.checked("add_price_quote", || {
+            self.inner.add_price_quote(price_quote)
+        })
+    }
+
+    fn claim_idempotency_key(
+        &self,
+        idempotency_record: &IdempotencyRecord,
+        epoch_now: u32,
+    ) -> Result<Option<IdempotencyRecord>> {
+        self.checked("claim_idempotency_key", || {
+            self.inner
+                .claim_idempotency_key(idempotency_record, epoch_now)
+        })
+    }
+
+    fn answer_idempotency_key(
+        &self,
+        idempotency_key: &str,
+        user_id: u32,
+        response_body: &str,
+    ) -> Result<()> {
+        self.checked("answer_idempotency_key", || {
+            self.inner
+                .answer_idempotency_key(idempotency_key, user_id, response_body)
+        })
+    }
+
+    fn release_idempotency_key(&self, idempotency_key: &str, user_id: u32) -> Result<()> {
+        self.checked("release_idempotency_key", || {
+            self.inner.release_idempotency_key(idempotency_key, user_id)
+        })
+    }
+
+    fn record_request_event(
+        &self,
+        kind: LedgerEventKind,
+        reservation_id: Option<u32>,
+        reservation_request: &ReservationRequest,
+        recorded_at: u32,
+        reason: Option<&str>,
+    ) -> Result<()> {
+        self.checked("record_request_event", || {
+            self.inner.record_request_event(
+                kind,
+                reservation_id,
+                reservation_request,
+                recorded_at,
+                reason,
+            )
+        })
+    }
+
+    fn get_ledger_events(&self) -> Result<Vec<LedgerEvent>> {
+        self.checked("get_ledger_events", || self.inner.get_ledger_events())
+    }
+
+    fn replace_reservations(&self, reservations: &[Reservation]) -> Result<()> {
+        self.checked("replace_reservations", || {
+            self.inner.replace_reservations(reservations)
+        })
+    }
+
+    fn record_evaluated_request(
+        &self,
+        request_evaluation: &RequestEvaluation,
+    ) -> Result<EvaluatedRequest> {
+        self.checked("record_evaluated_request", || {
+            self.inner.record_evaluated_request(request_evaluation)
+        })
+    }
+
+    fn get_evaluated_requests(
+        &self,
+        request_filter: &RequestFilter,
+    ) -> Result<Vec<EvaluatedRequest>> {
+        self.checked("get_evaluated_requests", || {
+            self.inner.get_evaluated_requests(request_filter)
+        })
+    }
+
+    fn record_change_event(&self, change_params: &ChangeEventParams) -> Result<ChangeEvent> {
+        self.checked("record_change_event", || {
+            self.inner.record_change_event(change_params)
+        })
+    }
+
+    fn get_change_events(
+        &self,
+        change_filter: &ChangeFilter,
+        after_event_id: u32,
+        limit: u32,
+    ) -> Result<Vec<ChangeEvent>> {
+        self.checked("get_change_events", || {
+            self.inner
+                .get_change_events(change_filter, after_event_id, limit)
+        })
+    }
+
+    fn get_last_change_event_id(&self) -> Result<u32> {
+        self.checked("get_last_change_event_id", || {
+            self.inner.get_last_change_event_id()
+        })
+    }
+
+    fn add_webhook_subscription(
+        &self,
+        webhook_params: &WebhookParams,
+        last_event_id: u32,
+        created_at: u32,
+    ) -> Result<WebhookSubscription> {
+        self.checked("add_webhook_subscription", || {
+            self.inner
+                .add_webhook_subscription(webhook_params, last_event_id, created_at)
+        })
+    }
+
+    fn get_webhook_subscriptions(&self) -> Result<Vec<WebhookSubscription>> {
+        self.checked("get_webhook_subscriptions", || {
+            self.inner.get_webhook_subscriptions()
+        })
+    }
+
+    fn delete_webhook_subscription(&self, subscription_id: u32) -> Result<bool> {
+        self.checked("delete_webhook_subscription", || {
+            self.inner.delete_webhook_subscription(subscription_id)
+        })
+    }
+
+    fn advance_webhook_subscriptions(&self, event_id: u32) -> Result<()> {
+        self.checked("advance_webhook_subscriptions", || {
+            self.inner.advance_webhook_subscriptions(event_id)
+        })
+    }
+
+    fn record_webhook_delivery(
+        &self,
+        delivery_params: &WebhookDeliveryParams,
+    ) -> Result<WebhookDelivery> {
+        self.checked("record_webhook_delivery", || {
+            self.inner.record_webhook_delivery(delivery_params)
+        })
+    }
+
+    fn get_webhook_deliveries(&self, subscription_id: u32) -> Result<Vec<WebhookDelivery>> {
+        self.checked("get_webhook_deliveries", || {
+            self.inner.get_webhook_deliveries(subscription_id)
+        })
+    }
+
+    fn get_overlapping_reservations(
+        &self,
+        pool: &str,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<Reservation>> {
+        self.checked("get_overlapping_reservations", || {
+            self.inner
+                .get_overlapping_reservations(pool, start_time, end_time)
+        })
+    }
+
+    fn get_peak_reserved_amount(&self, pool: &str, start_time: u32, end_time: u32) -> Result<u32> {
+        self.checked("get_peak_reserved_amount", || {
+            self.inner
+                .get_peak_reserved_amount(pool, start_time, end_time)
+        })
+    }
+
+    fn add_maintenance_window(&self, new_window: &MaintenanceParams) -> Result<MaintenanceWindow> {
+        self.checked("add_maintenance_window", || {
+            self.inner.add_maintenance_window(new_window)
+        })
+    }
+
+    fn get_maintenance_windows(&self) -> Result<Vec<MaintenanceWindow>> {
+        self.checked("get_maintenance_windows", || {
+            self.inner.get_maintenance_windows()
+        })
+    }
+
+    fn get_overlapping_maintenance_windows(
+        &self,
+        pool: &str,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<MaintenanceWindow>> {
+        self.checked("get_overlapping_maintenance_windows", || {
+            self.inner
+                .get_overlapping_maintenance_windows(pool, start_time, end_time)
+        })
+    }
+
+    fn delete_maintenance_window(&self, window_id: u32) -> Result<bool> {
+        self.checked("delete_maintenance_window", || {
+            self.inner.delete_maintenance_window(window_id)
+        })
+    }
+}
@@ -0,0 +1,1493 @@
+//! PostgreSQL Datastore
+//!
+//! `postgres` keeps Arbiter's schedule and reservations on a PostgreSQL server so that more than
+//! one Arbiter can share them.
+//!
+//! Warning: If PostgreSQL was in stalled with Homebrew, then the "postgres" role needs to be added
+//! before this will work. Without it, `Client::connect()` will hang forever.
+//! `user@host: /opt/homebrew/opt/postgresql@14/bin/createuser -s postgres`
+//! credit: https://stackoverflow.com/questions/15301826/psql-fatal-role-postgres-does-not-exist#comment91332745_15309551
+
+// Standard library crates.
+use std::collections::BTreeSet;
+
+// External crates.
+use anyhow::{bail, Context, Result};
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use postgres::types::ToSql;
+use postgres::{Client, GenericClient, NoTls, Row};
+
+// Project crates.
+use super::{reservation_terms, Booking, Datastore};
+use crate::billing::{Charges, PriceQuote};
+use crate::common::{
+    CapacityReduction, CapacitySegment, MaintenanceParams, MaintenanceWindow, Reservation,
+    ReservationStatus,
+};
+use crate::demand::{Bottleneck, EvaluatedRequest, RequestEvaluation, RequestFilter};
+use crate::events::{ChangeEvent, ChangeEventParams, ChangeFilter};
+use crate::idempotency::IdempotencyRecord;
+use crate::ledger::{LedgerEvent, LedgerEventKind};
+use crate::listing::{sql_where, PageRequest, ReservationFilter};
+use crate::metrics::ReservationCount;
+use crate::migrations::run_postgres_migrations;
+use crate::webhooks::{
+    join_event_kinds, split_event_kinds, WebhookDelivery, WebhookDeliveryParams, WebhookParams,
+    WebhookSubscription,
+};
+use crate::CapacitySchedule;
+use crate::ReservationRequest;
+
+/// Datastore that's kept on a PostgreSQL server.
+pub struct PostgresDatastore {
+    /// Connection parameters, like `"host=localhost user=postgres"`.
+    connection: String,
+}
+
+impl PostgresDatastore {
+    pub fn new(connection: &str) -> Self {
+        Self {
+            connection: String::from(connection),
+        }
+    }
+
+    /// Open a new connection to the PostgreSQL server.
+    fn connect(&self) -> Result<Client> {
+        Ok(Client::connect(&self.connection, NoTls)?)
+    }
+}
+
+impl Datastore for PostgresDatastore {
+    fn migrate(&self) -> Result<i32> {
+        let mut db_client = self.connect()?;
+        run_postgres_migrations(&mut db_client)
+    }
+
+    fn drop_tables(&self) -> Result<()> {
+        let mut db_client = self.connect()?;
+        db_client.execute(
+            "DROP TABLE IF EXISTS capacity_schedule, user_reservations, maintenance_windows, reservation_ledger, reservation_requests, price_quotes, idempotency_keys, change_events, webhook_subscriptions, webhook_deliveries, schema_version;",
+            &[],
+        )?;
+        info!("Deleted DB tables: capacity_schedule, user_reservations, maintenance_windows, reservation_ledger, reservation_requests, price_quotes, idempotency_keys, change_events, webhook_subscriptions, webhook_deliveries, schema_version");
+        db_client.batch_execute(
+            "DROP FUNCTION IF EXISTS peak_reserved_amount;
+             DROP FUNCTION IF EXISTS reject_ledger_changes;",
+        )?;
+        info!("Deleted DB functions: peak_reserved_amount, reject_ledger_changes");
+        Ok(())
+    }
+
+    fn get_schedule(&self) -> Result<CapacitySchedule> {
+        query_schedule(&mut self.connect()?)
+    }
+
+    fn add_capacity_segment(&self, capacity_segment: &CapacitySegment) -> Result<()> {
+        let mut db_client = self.connect()?;
+        populate_schedule_row(
+            capacity_segment,
+            ScheduleTable::CapacitySchedule,
+            &mut db_client,
+        )
+    }
+
+    fn set_segment_price(
+        &self,
+        pool: &str,
+        start_time: u32,
+        microcredits_per_unit_second: u64,
+    ) -> Result<bool> {
+        // Times that don't fit in the table can't belong to a segment.
+        let Ok(db_start_time) = i32::try_from(start_time) else {
+            return Ok(false);
+        };
+        let mut db_client = self.connect()?;
+        let updated_count = db_client.execute(
+            "UPDATE capacity_schedule
+             SET microcredits_per_unit_second = $3
+             WHERE pool = $1 AND start_time = $2",
+            &[
+                &pool,
+                &db_start_time,
+                &db_bigint(microcredits_per_unit_second, "microcredits_per_unit_second")?,
+            ],
+        )?;
+        info!(
+            "Priced capacity segment in pool \"{}\" starting at \"{}\" at \"{}\" microcredits per unit-second",
+            pool, start_time, microcredits_per_unit_second
+        );
+        Ok(updated_count > 0)
+    }
+
+    // Pools are locked with advisory locks that are held until the transaction ends. They're
+    // taken in the same order by everyone, so two bookings can't wait on each other.
+    fn begin_booking(&self, pools: &[&str]) -> Result<Box<dyn Booking + '_>> {
+        let mut db_client = self.connect()?;
+        db_client.batch_execute("BEGIN")?;
+        for pool in pools.iter().collect::<BTreeSet<_>>() {
+            db_client.execute("SELECT pg_advisory_xact_lock(hashtext($1))", &[pool])?;
+        }
+        Ok(Box::new(PostgresBooking { db_client }))
+    }
+
+    fn get_reservation(&self, reservation_id: u32) -> Result<Option<Reservation>> {
+        // IDs that don't fit in the table can't belong to a reservation.
+        let Ok(db_reservation_id) = i32::try_from(reservation_id) else {
+            return Ok(None);
+        };
+        let mut db_client = self.connect()?;
+        db_client
+            .query_opt(
+                &format!("SELECT {RESERVATION_COLUMNS} FROM user_reservations WHERE id = $1"),
+                &[&db_reservation_id],
+            )?
+            .map(|query_row| reservation_from_row(&query_row))
+            .transpose()
+    }
+
+    // Reservations are found with the index on when they were made.
+    fn get_reservations_created_between(
+        &self,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<Reservation>> {
+        let mut db_client = self.connect()?;
+        let mut user_reservations = Vec::new();
+        for query_row in db_client.query(
+            &format!(
+                "SELECT {RESERVATION_COLUMNS}
+                 FROM user_reservations
+                 WHERE created_at >= $1::BIGINT AND created_at < $2::BIGINT
+                 ORDER BY id"
+            ),
+            &[&(start_time as i64), &(end_time as i64)],
+        )? {
+            user_reservations.push(reservation_from_row(&query_row)?)
+        }
+        Ok(user_reservations)
+    }
+
+    // Filters that aren't given are left out of the query, and the page's sort column is chosen
+    // from a fixed list so it's never user input.
+    fn list_reservations(
+        &self,
+        reservation_filter: &ReservationFilter,
+        page_request: &PageRequest,
+    ) -> Result<Vec<Reservation>> {
+        let mut query_params: Vec<Box<dyn ToSql + Sync>> = Vec::new();
+        // Add a parameter to the query and get its placeholder.
+        let mut bind = |query_param: Box<dyn ToSql + Sync>, cast: &str| {
+            query_params.push(query_param);
+            format!("${}{cast}", query_params.len())
+        };
+        let mut conditions = Vec::new();
+        if let Some(user_id) = reservation_filter.user_id {
+            let user_param = bind(Box::new(i64::from(user_id)), "::BIGINT");
+            conditions.push(format!("user_id = {user_param}"));
+        }
+        if let Some(pool) = &reservation_filter.pool {
+            let pool_param = bind(Box::new(pool.clone()), "");
+            conditions.push(format!("pool = {pool_param}"));
+        }
+        if let Some(status) = reservation_filter.status {
+            let status_param = bind(Box::new(status.as_str()), "");
+            conditions.push(format!("status = {status_param}"));
+        }
+        let time_match = reservation_filter.time_match;
+        if let Some(start_time) = reservation_filter.start_time {
+            let start_param = bind(Box::new(i64::from(start_time)), "::BIGINT");
+            conditions.push(time_match.sql_start_condition(&start_param));
+        }
+        if let Some(end_time) = reservation_filter.end_time {
+            let end_param = bind(Box::new(i64::from(end_time)), "::BIGINT");
+            conditions.push(time_match.sql_end_condition(&end_param));
+        }
+        if let Some(cursor) = &page_request.after {
+            let value_param = bind(Box::new(i64::from(cursor.sort_value)), "::BIGINT");
+            let id_param = bind(Box::new(i64::from(cursor.reservation_id)), "::BIGINT");
+            conditions.push(page_request.sql_after(&value_param, &id_param));
+        }
+        let limit_param = bind(Box::new(i64::from(page_request.limit)), "::BIGINT");
+        let query_params: Vec<&(dyn ToSql + Sync)> = query_params
+            .iter()
+            .map(|query_param| query_param.as_ref())
+            .collect();
+        let mut db_client = self.connect()?;
+        let mut user_reservations = Vec::new();
+        for query_row in db_client.query(
+            &format!(
+                "SELECT {RESERVATION_COLUMNS}
+                 FROM user_reservations
+                 {}
+                 {}
+                 LIMIT {limit_param}",
+                sql_where(&conditions),
+                page_request.sql_order_by(),
+            ),
+            &query_params,
+        )? {
+            user_reservations.push(reservation_from_row(&query_row)?)
+        }
+        Ok(user_reservations)
+    }
+
+    // The status change and its ledger event are recorded together.
+    fn end_reservation(
+        &self,
+        reservation_id: u32,
+        ending_kind: LedgerEventKind,
+        recorded_at: u32,
+        reason: Option<&str>,
+        refund_microcredits: u64,
+    ) -> Result<Option<Reservation>> {
+        let Some(ending_status) = ending_kind.ending_status() else {
+            bail!("Reservations can't be ended by \"{ending_kind}\" events");
+        };
+        // IDs that don't fit in the table can't belong to a reservation.
+        let Ok(db_reservation_id) = i32::try_from(reservation_id) else {
+            return Ok(None);
+        };
+        let mut db_client = self.connect()?;
+        let mut transaction = db_client.transaction()?;
+        let Some(updated_row) = transaction.query_opt(
+            &format!(
+                "UPDATE user_reservations
+                 SET status = $2, updated_at = $3, refund_microcredits = $4
+                 WHERE id = $1 AND status IN ('pending', 'active')
+                 RETURNING {RESERVATION_COLUMNS}"
+            ),
+            &[
+                &db_reservation_id,
+                &ending_status.as_str(),
+                &db_integer(recorded_at, "updated_at")?,
+                &db_bigint(refund_microcredits, "refund_microcredits")?,
+            ],
+        )?
+        else {
+            return Ok(None);
+        };
+        let ended_reservation = reservation_from_row(&updated_row)?;
+        append_ledger_event(
+            &mut transaction,
+            Some(db_reservation_id),
+            ending_kind,
+            &reservation_terms(&ended_reservation),
+            recorded_at,
+            reason,
+            Charges::of(&ended_reservation),
+        )?;
+        transaction.commit()?;
+        info!("Ended reservation in DB: {}", ended_reservation);
+        Ok(Some(ended_reservation))
+    }
+
+    fn get_due_reservations(&self, epoch_now: u32) -> Result<Vec<Reservation>> {
+        let mut db_client = self.connect()?;
+        let mut due_reservations = Vec::new();
+        for query_row in db_client.query(
+            &format!(
+                "SELECT {RESERVATION_COLUMNS}
+                 FROM user_reservations
+                 WHERE (status = 'pending' AND start_time <= $1::BIGINT)
+                    OR (status = 'active' AND end_time <= $1::BIGINT)
+                 ORDER BY id"
+            ),
+            &[&i64::from(epoch_now)],
+        )? {
+            due_reservations.push(reservation_from_row(&query_row)?)
+        }
+        Ok(due_reservations)
+    }
+
+    fn get_next_lifecycle_boundary(&self, epoch_now: u32) -> Result<Option<u32>> {
+        let mut db_client = self.connect()?;
+        let boundary_row = db_client.query_one(
+            "SELECT LEAST(
+                 (SELECT MIN(start_time) FROM user_reservations
+                  WHERE status = 'pending' AND start_time > $1::BIGINT),
+                 (SELECT MIN(end_time) FROM user_reservations
+                  WHERE status = 'active' AND end_time > $1::BIGINT)
+             )",
+            &[&i64::from(epoch_now)],
+        )?;
+        let next_boundary: Option<i32> = boundary_row.get(0);
+        Ok(next_boundary.map(|next_boundary| next_boundary as u32))
+    }
+
+    fn get_reservation_counts(&self) -> Result<Vec<ReservationCount>> {
+        let mut db_client = self.connect()?;
+        let mut reservation_counts = Vec::new();
+        for query_row in db_client.query(
+            "SELECT pool, status, COUNT(*)
+             FROM user_reservations
+             WHERE status IN ('pending', 'active')
+             GROUP BY pool, status
+             ORDER BY pool, status",
+            &[],
+        )? {
+            let status: String = query_row.get(1);
+            let count: i64 = query_row.get(2);
+            reservation_counts.push(ReservationCount {
+                pool: query_row.get(0),
+                status: status.parse()?,
+                count: count as u32,
+            })
+        }
+        Ok(reservation_counts)
+    }
+
+    // The status change and its ledger event are recorded together.
+    fn advance_reservation(
+        &self,
+        reservation_id: u32,
+        lifecycle_kind: LedgerEventKind,
+        recorded_at: u32,
+    ) -> Result<Option<Reservation>> {
+        let Some((current_status, next_status)) = lifecycle_kind.lifecycle_step() else {
+            bail!("Reservations can't be moved along by \"{lifecycle_kind}\" events");
+        };
+        // IDs that don't fit in the table can't belong to a reservation.
+        let Ok(db_reservation_id) = i32::try_from(reservation_id) else {
+            return Ok(None);
+        };
+        let mut db_client = self.connect()?;
+        let mut transaction = db_client.transaction()?;
+        let Some(updated_row) = transaction.query_opt(
+            &format!(
+                "UPDATE user_reservations
+                 SET status = $3, updated_at = $4
+                 WHERE id = $1 AND status = $2
+                 RETURNING {RESERVATION_COLUMNS}"
+            ),
+            &[
+                &db_reservation_id,
+                &current_status.as_str(),
+                &next_status.as_str(),
+                &db_integer(recorded_at, "updated_at")?,
+            ],
+        )?
+        else {
+            return Ok(None);
+        };
+        let advanced_reservation = reservation_from_row(&updated_row)?;
+        append_ledger_event(
+            &mut transaction,
+            Some(db_reservation_id),
+            lifecycle_kind,
+            &reservation_terms(&advanced_reservation),
+            recorded_at,
+            None,
+            Charges::of(&advanced_reservation),
+        )?;
+        transaction.commit()?;
+        debug!("Advanced reservation in DB: {}", advanced_reservation);
+        Ok(Some(advanced_reservation))
+    }
+
+    fn add_price_quote(&self, price_quote: &PriceQuote) -> Result<()> {
+        let mut db_client = self.connect()?;
+        db_client.execute(
+            "INSERT INTO price_quotes
+                          (quote_token, start_time, end_time, capacity_amount, user_id, pool,
+                           base_cost_microcredits, price_percent, price_microcredits, quoted_at, expires_at)
+                          VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+            &[
+                &price_quote.quote_token,
+                &db_integer(price_quote.start_time, "start_time")?,
+                &db_integer(price_quote.end_time, "end_time")?,
+                &db_integer(price_quote.capacity_amount, "capacity_amount")?,
+                &db_integer(price_quote.user_id, "user_id")?,
+                &price_quote.pool,
+                &db_bigint(price_quote.base_cost_microcredits, "base_cost_microcredits")?,
+                &db_integer(price_quote.price_percent, "price_percent")?,
+                &db_bigint(price_quote.price_microcredits, "price_microcredits")?,
+                &db_integer(price_quote.quoted_at, "quoted_at")?,
+                &db_integer(price_quote.expires_at, "expires_at")?,
+            ],
+        )?;
+        debug!("Added quote \"{}\" to DB", price_quote.quote_token);
+        Ok(())
+    }
+
+    // Claims race on the primary key, so only one request ever claims a key.
+    fn claim_idempotency_key(
+        &self,
+        idempotency_record: &IdempotencyRecord,
+        epoch_now: u32,
+    ) -> Result<Option<IdempotencyRecord>> {
+        let mut db_client = self.connect()?;
+        db_client.execute(
+            "DELETE FROM idempotency_keys WHERE expires_at <= $1",
+            &[&db_integer(epoch_now, "epoch_now")?],
+        )?;
+        let user_id = db_integer(idempotency_record.user_id, "user_id")?;
+        let inserted_count = db_client.execute(
+            "INSERT INTO idempotency_keys
+                          (idempotency_key, user_id, request_hash, created_at, expires_at)
+                          VALUES ($1, $2, $3, $4, $5)
+                          ON CONFLICT DO NOTHING",
+            &[
+                &idempotency_record.idempotency_key,
+                &user_id,
+                &idempotency_record.request_hash,
+                &db_integer(idempotency_record.created_at, "created_at")?,
+                &db_integer(idempotency_record.expires_at, "expires_at")?,
+            ],
+        )?;
+        if inserted_count > 0 {
+            debug!(
+                "Claimed idempotency key \"{}\" for user \"{}\"",
+                idempotency_record.idempotency_key, idempotency_record.user_id
+            );
+            return Ok(None);
+        }
+        let query_row = db_client
+            .query_opt(
+                "SELECT idempotency_key, user_id, request_hash, response_body, created_at, expires_at
+                 FROM idempotency_keys
+                 WHERE user_id = $1 AND idempotency_key = $2",
+                &[&user_id, &idempotency_record.idempotency_key],
+            )?
+            .with_context(|| {
+                format!(
+                    "Idempotency key \"{}\" expired while it was being claimed",
+                    idempotency_record.idempotency_key
+                )
+            })?;
+        let user_id: i32 = query_row.get(1);
+        let created_at: i32 = query_row.get(4);
+        let expires_at: i32 = query_row.get(5);
+        Ok(Some(IdempotencyRecord {
+            idempotency_key: query_row.get(0),
+            user_id: user_id as u32,
+            request_hash: query_row.get(2),
+            response_body: query_row.get(3),
+            created_at: created_at as u32,
+            expires_at: expires_at as u32,
+        }))
+    }
+
+    fn answer_idempotency_key(
+        &self,
+        idempotency_key: &str,
+        user_id: u32,
+        response_body: &str,
+    ) -> Result<()> {
+        let mut db_client = self.connect()?;
+        db_client.execute(
+            "UPDATE idempotency_keys
+             SET response_body = $3
+             WHERE user_id = $1 AND idempotency_key = $2",
+            &[
+                &db_integer(user_id, "user_id")?,
+                &idempotency_key,
+                &response_body,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn release_idempotency_key(&self, idempotency_key: &str, user_id: u32) -> Result<()> {
+        let mut db_client = self.connect()?;
+        db_client.execute(
+            "DELETE FROM idempotency_keys
+             WHERE user_id = $1 AND idempotency_key = $2 AND response_body IS NULL",
+            &[&db_integer(user_id, "user_id")?, &idempotency_key],
+        )?;
+        debug!(
+            "Released idempotency key \"{}\" for user \"{}\"",
+            idempotency_key, user_id
+        );
+        Ok(())
+    }
+
+    fn record_request_event(
+        &self,
+        kind: LedgerEventKind,
+        reservation_id: Option<u32>,
+        reservation_request: &ReservationRequest,
+        recorded_at: u32,
+        reason: Option<&str>,
+    ) -> Result<()> {
+        let mut db_client = self.connect()?;
+        append_ledger_event(
+            &mut db_client,
+            reservation_id
+                .map(|reservation_id| db_integer(reservation_id, "reservation_id"))
+                .transpose()?,
+            kind,
+            reservation_request,
+            recorded_at,
+            reason,
+            Charges::default(),
+        )
+    }
+
+    fn get_ledger_events(&self) -> Result<Vec<LedgerEvent>> {
+        let mut db_client = self.connect()?;
+        let mut ledger_events = Vec::new();
+        for query_row in db_client.query(
+            "SELECT id, reservation_id, event_kind, recorded_at,
+                    start_time, end_time, capacity_amount, user_id, pool, reason,
+                    cost_microcredits, refund_microcredits
+             FROM reservation_ledger
+             ORDER BY id",
+            &[],
+        )? {
+            let event_id: i32 = query_row.get(0);
+            let reservation_id: Option<i32> = query_row.get(1);
+            let event_kind: &str = query_row.get(2);
+            let recorded_at: i32 = query_row.get(3);
+            let start_time: i32 = query_row.get(4);
+            let end_time: i32 = query_row.get(5);
+            let capacity_amount: i32 = query_row.get(6);
+            let user_id: i32 = query_row.get(7);
+            let cost_microcredits: i64 = query_row.get(10);
+            let refund_microcredits: i64 = query_row.get(11);
+            let ledger_event = LedgerEvent {
+                event_id: event_id as u32,
+                reservation_id: reservation_id.map(|reservation_id| reservation_id as u32),
+                kind: event_kind.parse()?,
+                recorded_at: recorded_at as u32,
+                start_time: start_time as u32,
+                end_time: end_time as u32,
+                capacity_amount: capacity_amount as u32,
+                user_id: user_id as u32,
+                pool: query_row.get(8),
+                reason: query_row.get(9),
+                cost_microcredits: cost_microcredits as u64,
+                refund_microcredits: refund_microcredits as u64,
+            };
+            ledger_events.push(ledger_event)
+        }
+        Ok(ledger_events)
+    }
+
+    // Reservation IDs are kept, and new reservations keep counting up from the highest one.
+    fn replace_reservations(&self, reservations: &[Reservation]) -> Result<()> {
+        let mut db_client = self.connect()?;
+        let mut transaction = db_client.transaction()?;
+        transaction.execute("DELETE FROM user_reservations", &[])?;
+        for reservation in reservations {
+            transaction.execute(
+                "INSERT INTO user_reservations
+                              (id, start_time, end_time, reservation_amount, user_id, pool, status, created_at, updated_at,
+                               cost_microcredits, refund_microcredits)
+                              VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+                &[
+                    &db_integer(reservation.reservation_id, "id")?,
+                    &db_integer(reservation.start_time, "start_time")?,
+                    &db_integer(reservation.end_time, "end_time")?,
+                    &db_integer(reservation.capacity_amount, "reservation_amount")?,
+                    &db_integer(reservation.user_id, "user_id")?,
+                    &reservation.pool,
+                    &reservation.status.as_str(),
+                    &db_integer(reservation.created_at, "created_at")?,
+                    &db_integer(reservation.updated_at, "updated_at")?,
+                    &db_bigint(reservation.cost_microcredits, "cost_microcredits")?,
+                    &db_bigint(reservation.refund_microcredits, "refund_microcredits")?,
+                ],
+            )?;
+        }
+        transaction.execute(
+            "SELECT setval(pg_get_serial_sequence('user_reservations', 'id'), COALESCE(MAX(id), 0) + 1, false)
+             FROM user_reservations",
+            &[],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn record_evaluated_request(
+        &self,
+        request_evaluation: &RequestEvaluation,
+    ) -> Result<EvaluatedRequest> {
+        let mut db_client = self.connect()?;
+        let reservation_request = &request_evaluation.reservation_request;
+        let bottleneck = request_evaluation.bottleneck.as_ref();
+        let bottleneck_integer = |value: Option<u32>, column: &str| {
+            value.map(|value| db_integer(value, column)).transpose()
+        };
+        let request_row = db_client.query_one(
+            "INSERT INTO reservation_requests
+                          (requested_at, start_time, end_time, capacity_amount, user_id, pool, outcome, denial_reason,
+                           reservation_id, bottleneck_start, bottleneck_end, bottleneck_available_amount)
+                          VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+                          RETURNING id, requested_at, start_time, end_time, capacity_amount, user_id, pool, outcome,
+                                    denial_reason, reservation_id, bottleneck_start, bottleneck_end, bottleneck_available_amount",
+            &[
+                &db_integer(request_evaluation.requested_at, "requested_at")?,
+                &db_integer(reservation_request.start_time, "start_time")?,
+                &db_integer(reservation_request.end_time, "end_time")?,
+                &db_integer(reservation_request.capacity_amount, "capacity_amount")?,
+                &db_integer(reservation_request.user_id, "user_id")?,
+                &reservation_request.pool,
+                &request_evaluation.outcome.as_str(),
+                &request_evaluation.denial_reason,
+                &bottleneck_integer(request_evaluation.reservation_id, "reservation_id")?,
+                &bottleneck_integer(bottleneck.map(|slot| slot.start_time), "bottleneck_start")?,
+                &bottleneck_integer(bottleneck.map(|slot| slot.end_time), "bottleneck_end")?,
+                &bottleneck_integer(
+                    bottleneck.map(|slot| slot.available_amount),
+                    "bottleneck_available_amount",
+                )?,
+            ],
+        )?;
+        let evaluated_request = evaluated_request_from_row(&request_row)?;
+        debug!("Recorded {} in DB", evaluated_request);
+        Ok(evaluated_request)
+    }
+
+    // Filters that are missing match everything.
+    fn get_evaluated_requests(
+        &self,
+        request_filter: &RequestFilter,
+    ) -> Result<Vec<EvaluatedRequest>> {
+        let mut db_client = self.connect()?;
+        let filter_integer = |value: Option<u32>, column: &str| {
+            value.map(|value| db_integer(value, column)).transpose()
+        };
+        let mut evaluated_requests = Vec::new();
+        for query_row in db_client.query(
+            "SELECT id, requested_at, start_time, end_time, capacity_amount, user_id, pool, outcome,
+                    denial_reason, reservation_id, bottleneck_start, bottleneck_end, bottleneck_available_amount
+             FROM reservation_requests
+             WHERE ($1::TEXT IS NULL OR pool = $1)
+               AND ($2::INTEGER IS NULL OR user_id = $2)
+               AND ($3::INTEGER IS NULL OR end_time > $3)
+               AND ($4::INTEGER IS NULL OR start_time < $4)
+               AND ($5::TEXT IS NULL OR outcome = $5)
+             ORDER BY id",
+            &[
+                &request_filter.pool,
+                &filter_integer(request_filter.user_id, "user_id")?,
+                &filter_integer(request_filter.start_time, "start_time")?,
+                &filter_integer(request_filter.end_time, "end_time")?,
+                &request_filter.outcome.map(|outcome| outcome.as_str()),
+            ],
+        )? {
+            evaluated_requests.push(evaluated_request_from_row(&query_row)?)
+        }
+        Ok(evaluated_requests)
+    }
+
+    fn record_change_event(&self, change_params: &ChangeEventParams) -> Result<ChangeEvent> {
+        insert_change_event(&mut self.connect()?, change_params)
+    }
+
+    // Filters that are missing match everything, and changes that aren't to anyone's reservation
+    // match every user.
+    fn get_change_events(
+        &self,
+        change_filter: &ChangeFilter,
+        after_event_id: u32,
+        limit: u32,
+    ) -> Result<Vec<ChangeEvent>> {
+        let mut db_client = self.connect()?;
+        let mut change_events = Vec::new();
+        for query_row in db_client.query(
+            "SELECT id, event_kind, recorded_at, pool, user_id, payload
+             FROM change_events
+             WHERE id > $1::BIGINT
+               AND ($2::TEXT IS NULL OR pool = $2)
+               AND ($3::INTEGER IS NULL OR user_id IS NULL OR user_id = $3)
+             ORDER BY id
+             LIMIT $4::BIGINT",
+            &[
+                &(after_event_id as i64),
+                &change_filter.pool,
+                &change_filter
+                    .user_id
+                    .map(|user_id| db_integer(user_id, "user_id"))
+                    .transpose()?,
+                &(limit as i64),
+            ],
+        )? {
+            change_events.push(change_event_from_row(&query_row)?)
+        }
+        Ok(change_events)
+    }
+
+    fn get_last_change_event_id(&self) -> Result<u32> {
+        let mut db_client = self.connect()?;
+        let last_row =
+            db_client.query_one("SELECT COALESCE(MAX(id), 0) FROM change_events", &[])?;
+        let last_event_id: i32 = last_row.get(0);
+        Ok(last_event_id as u32)
+    }
+
+    fn add_webhook_subscription(
+        &self,
+        webhook_params: &WebhookParams,
+        last_event_id: u32,
+        created_at: u32,
+    ) -> Result<WebhookSubscription> {
+        let mut db_client = self.connect()?;
+        let inserted_row = db_client.query_one(
+            "INSERT INTO webhook_subscriptions
+                          (url, secret, event_kinds, pool, user_id, last_event_id, created_at)
+                          VALUES ($1, $2, $3, $4, $5, $6, $7)
+                          RETURNING id",
+            &[
+                &webhook_params.url,
+                &webhook_params.secret,
+                &join_event_kinds(&webhook_params.event_kinds),
+                &webhook_params.pool,
+                &webhook_params
+                    .user_id
+                    .map(|user_id| db_integer(user_id, "user_id"))
+                    .transpose()?,
+                &db_integer(last_event_id, "last_event_id")?,
+                &db_integer(created_at, "created_at")?,
+            ],
+        )?;
+        let subscription_id: i32 = inserted_row.get(0);
+        let subscription = WebhookSubscription {
+            subscription_id: subscription_id as u32,
+            url: webhook_params.url.clone(),
+            event_kinds: webhook_params.event_kinds.clone(),
+            pool: webhook_params.pool.clone(),
+            user_id: webhook_params.user_id,
+            secret: webhook_params.secret.clone(),
+            last_event_id,
+            created_at,
+        };
+        info!("Added {} to DB", subscription);
+        Ok(subscription)
+    }
+
+    fn get_webhook_subscriptions(&self) -> Result<Vec<WebhookSubscription>> {
+        let mut db_client = self.connect()?;
+        let mut subscriptions = Vec::new();
+        for query_row in db_client.query(
+            "SELECT id, url, secret, event_kinds, pool, user_id, last_event_id, created_at
+             FROM webhook_subscriptions
+             ORDER BY id",
+            &[],
+        )? {
+            subscriptions.push(webhook_subscription_from_row(&query_row)?)
+        }
+        Ok(subscriptions)
+    }
+
+    fn delete_webhook_subscription(&self, subscription_id: u32) -> Result<bool> {
+        let mut db_client = self.connect()?;
+        let deleted_count = db_client.execute(
+            "DELETE FROM webhook_subscriptions WHERE id = $1",
+            &[&db_integer(subscription_id, "id")?],
+        )?;
+        info!(
+            "Deleted webhook subscription \"{}\" from DB",
+            subscription_id
+        );
+        Ok(deleted_count > 0)
+    }
+
+    fn advance_webhook_subscriptions(&self, event_id: u32) -> Result<()> {
+        let mut db_client = self.connect()?;
+        db_client.execute(
+            "UPDATE webhook_subscriptions SET last_event_id = $1 WHERE last_event_id < $1",
+            &[&db_integer(event_id, "last_event_id")?],
+        )?;
+        Ok(())
+    }
+
+    fn record_webhook_delivery(
+        &self,
+        delivery_params: &WebhookDeliveryParams,
+    ) -> Result<WebhookDelivery> {
+        let mut db_client = self.connect()?;
+        let delivery_row = db_client.query_one(
+            "INSERT INTO webhook_deliveries
+                 (subscription_id, event_id, attempt, attempted_at, status_code, error, is_delivered)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             RETURNING id, subscription_id, event_id, attempt, attempted_at, status_code, error,
+                       is_delivered",
+            &[
+                &db_integer(delivery_params.subscription_id, "subscription_id")?,
+                &db_integer(delivery_params.event_id, "event_id")?,
+                &db_integer(delivery_params.attempt, "attempt")?,
+                &db_integer(delivery_params.attempted_at, "attempted_at")?,
+                &delivery_params.status_code.map(i32::from),
+                &delivery_params.error,
+                &delivery_params.is_delivered,
+            ],
+        )?;
+        let webhook_delivery = webhook_delivery_from_row(&delivery_row);
+        debug!("Recorded {} in DB", webhook_delivery);
+        Ok(webhook_delivery)
+    }
+
+    fn get_webhook_deliveries(&self, subscription_id: u32) -> Result<Vec<WebhookDelivery>> {
+        let mut db_client = self.connect()?;
+        let query_rows = db_client.query(
+            "SELECT id, subscription_id, event_id, attempt, attempted_at, status_code, error,
+                    is_delivered
+             FROM webhook_deliveries
+             WHERE subscription_id = $1
+             ORDER BY id",
+            &[&db_integer(subscription_id, "subscription_id")?],
+        )?;
+        Ok(query_rows.iter().map(webhook_delivery_from_row).collect())
+    }
+
+    fn get_overlapping_reservations(
+        &self,
+        pool: &str,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<Reservation>> {
+        query_overlapping_reservations(&mut self.connect()?, pool, start_time, end_time)
+    }
+
+    // The database computes this itself with `peak_reserved_amount()`, so reservations never
+    // leave it.
+    fn get_peak_reserved_amount(&self, pool: &str, start_time: u32, end_time: u32) -> Result<u32> {
+        let mut db_client = self.connect()?;
+        let peak_row = db_client.query_one(
+            "SELECT peak_reserved_amount($1, int8range($2, $3))",
+            &[&pool, &(start_time as i64), &(end_time as i64)],
+        )?;
+        let peak_reserved_amount: i64 = peak_row.get(0);
+        Ok(peak_reserved_amount as u32)
+    }
+
+    fn add_maintenance_window(&self, new_window: &MaintenanceParams) -> Result<MaintenanceWindow> {
+        let mut db_client = self.connect()?;
+        // Represent "all capacity" with a missing reduction amount.
+        let reduction_amount: Option<i32> = match new_window.reduction {
+            CapacityReduction::All => None,
+            CapacityReduction::Amount(reduction) => {
+                Some(db_integer(reduction, "reduction_amount")?)
+            }
+        };
+        let inserted_row = db_client.query_one(
+            "INSERT INTO maintenance_windows
+                          (pool, start_time, end_time, reduction_amount, reason)
+                          VALUES ($1, $2, $3, $4, $5)
+                          RETURNING id",
+            &[
+                &new_window.pool,
+                &db_integer(new_window.start_time, "start_time")?,
+                &db_integer(new_window.end_time, "end_time")?,
+                &reduction_amount,
+                &new_window.reason,
+            ],
+        )?;
+        let window_id: i32 = inserted_row.get(0);
+        let added_window = MaintenanceWindow {
+            window_id: window_id as u32,
+            pool: new_window.pool.clone(),
+            start_time: new_window.start_time,
+            end_time: new_window.end_time,
+            reduction: new_window.reduction,
+            reason: new_window.reason.clone(),
+        };
+        info!("Added {} to DB", added_window);
+        Ok(added_window)
+    }
+
+    fn get_maintenance_windows(&self) -> Result<Vec<MaintenanceWindow>> {
+        let mut db_client = self.connect()?;
+        let query_rows = db_client.query(
+            "SELECT id, pool, start_time, end_time, reduction_amount, reason
+             FROM maintenance_windows
+             ORDER BY start_time, id",
+            &[],
+        )?;
+        Ok(query_rows.iter().map(maintenance_window_from_row).collect())
+    }
+
+    fn get_overlapping_maintenance_windows(
+        &self,
+        pool: &str,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<MaintenanceWindow>> {
+        query_overlapping_maintenance_windows(&mut self.connect()?, pool, start_time, end_time)
+    }
+
+    fn delete_maintenance_window(&self, window_id: u32) -> Result<bool> {
+        // IDs that don't fit in the table can't belong to a maintenance window.
+        let Ok(db_window_id) = i32::try_from(window_id) else {
+            return Ok(false);
+        };
+        let mut db_client = self.connect()?;
+        let deleted_count = db_client.execute(
+            "DELETE FROM maintenance_windows WHERE id = $1",
+            &[&db_window_id],
+        )?;
+        info!("Deleted maintenance window \"{}\" from DB", window_id);
+        Ok(deleted_count > 0)
+    }
+}
+
+/// Booking that's kept in a transaction on a connection of its own.
+///
+/// Dropping the booking closes its connection, which rolls the transaction back and releases
+/// its pools' locks.
+struct PostgresBooking {
+    db_client: Client,
+}
+
+impl Booking for PostgresBooking {
+    fn get_schedule(&mut self) -> Result<CapacitySchedule> {
+        query_schedule(&mut self.db_client)
+    }
+
+    fn get_overlapping_maintenance_windows(
+        &mut self,
+        pool: &str,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<MaintenanceWindow>> {
+        query_overlapping_maintenance_windows(&mut self.db_client, pool, start_time, end_time)
+    }
+
+    fn get_overlapping_reservations(
+        &mut self,
+        pool: &str,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<Reservation>> {
+        query_overlapping_reservations(&mut self.db_client, pool, start_time, end_time)
+    }
+
+    fn get_price_quote(&mut self, quote_token: &str) -> Result<Option<PriceQuote>> {
+        query_price_quote(&mut self.db_client, quote_token)
+    }
+
+    fn add_user_reservation(
+        &mut self,
+        new_reservation: &ReservationRequest,
+        created_at: u32,
+        cost_microcredits: u64,
+    ) -> Result<Reservation> {
+        insert_user_reservation(
+            &mut self.db_client,
+            new_reservation,
+            created_at,
+            cost_microcredits,
+        )
+    }
+
+    fn add_quoted_reservation(
+        &mut self,
+        new_reservation: &ReservationRequest,
+        created_at: u32,
+        cost_microcredits: u64,
+        quote_token: &str,
+    ) -> Result<Option<Reservation>> {
+        if !redeem_price_quote(&mut self.db_client, quote_token, created_at)? {
+            return Ok(None);
+        }
+        insert_user_reservation(
+            &mut self.db_client,
+            new_reservation,
+            created_at,
+            cost_microcredits,
+        )
+        .map(Some)
+    }
+
+    fn record_change_event(&mut self, change_params: &ChangeEventParams) -> Result<ChangeEvent> {
+        insert_change_event(&mut self.db_client, change_params)
+    }
+
+    fn commit(mut self: Box<Self>) -> Result<()> {
+        self.db_client.batch_execute("COMMIT")?;
+        Ok(())
+    }
+}
+
+/// Record a change event.
+fn insert_change_event(
+    db_client: &mut impl GenericClient,
+    change_params: &ChangeEventParams,
+) -> Result<ChangeEvent> {
+    let change_row = db_client.query_one(
+        "INSERT INTO change_events (event_kind, recorded_at, pool, user_id, payload)
+         VALUES ($1, $2, $3, $4, $5)
+         RETURNING id, event_kind, recorded_at, pool, user_id, payload",
+        &[
+            &change_params.kind.as_str(),
+            &db_integer(change_params.recorded_at, "recorded_at")?,
+            &change_params.pool,
+            &change_params
+                .user_id
+                .map(|user_id| db_integer(user_id, "user_id"))
+                .transpose()?,
+            &change_params.payload.to_string(),
+        ],
+    )?;
+    let change_event = change_event_from_row(&change_row)?;
+    debug!("Recorded {} in DB", change_event);
+    Ok(change_event)
+}
+
+/// Get the capacity schedule of every pool.
+fn query_schedule(db_client: &mut impl GenericClient) -> Result<CapacitySchedule> {
+    let mut segments = Vec::new();
+    for query_row in db_client.query(
+        "SELECT start_time, end_time, capacity_amount, pool, microcredits_per_unit_second
+         FROM capacity_schedule",
+        &[],
+    )? {
+        let start_time: i32 = query_row.get(0);
+        let end_time: i32 = query_row.get(1);
+        let capacity_amount: i32 = query_row.get(2);
+        let pool: &str = query_row.get(3);
+        let microcredits_per_unit_second: i64 = query_row.get(4);
+        let capacity_segment =
+            CapacitySegment::new(start_time as u32, end_time as u32, capacity_amount as u32)
+                .in_pool(pool)
+                .priced_at(microcredits_per_unit_second as u64);
+        segments.push(capacity_segment)
+    }
+    let queried_schedule = CapacitySchedule { segments };
+    Ok(queried_schedule)
+}
+
+/// Get user reservations in a pool that overlap a timeframe.
+///
+/// The overlap is found by the database with the reservations' time range index.
+fn query_overlapping_reservations(
+    db_client: &mut impl GenericClient,
+    pool: &str,
+    start_time: u32,
+    end_time: u32,
+) -> Result<Vec<Reservation>> {
+    let mut user_reservations = Vec::new();
+    for query_row in db_client.query(
+        &format!(
+            "SELECT {RESERVATION_COLUMNS}
+             FROM user_reservations
+             WHERE pool = $1 AND time_range && int8range($2, $3)"
+        ),
+        &[&pool, &(start_time as i64), &(end_time as i64)],
+    )? {
+        user_reservations.push(reservation_from_row(&query_row)?)
+    }
+    Ok(user_reservations)
+}
+
+/// Get maintenance windows in a pool that overlap a timeframe, earliest first.
+///
+/// The overlap is found by the database with the maintenance windows' time range index.
+fn query_overlapping_maintenance_windows(
+    db_client: &mut impl GenericClient,
+    pool: &str,
+    start_time: u32,
+    end_time: u32,
+) -> Result<Vec<MaintenanceWindow>> {
+    let query_rows = db_client.query(
+        "SELECT id, pool, start_time, end_time, reduction_amount, reason
+         FROM maintenance_windows
+         WHERE pool = $1 AND time_range && int8range($2, $3)
+         ORDER BY start_time, id",
+        &[&pool, &(start_time as i64), &(end_time as i64)],
+    )?;
+    Ok(query_rows.iter().map(maintenance_window_from_row).collect())
+}
+
+/// Get a price quote by its token, whether it's been redeemed or not.
+fn query_price_quote(
+    db_client: &mut impl GenericClient,
+    quote_token: &str,
+) -> Result<Option<PriceQuote>> {
+    let Some(query_row) = db_client.query_opt(
+        "SELECT quote_token, start_time, end_time, capacity_amount, user_id, pool,
+                base_cost_microcredits, price_percent, price_microcredits, quoted_at, expires_at, redeemed_at
+         FROM price_quotes
+         WHERE quote_token = $1",
+        &[&quote_token],
+    )?
+    else {
+        return Ok(None);
+    };
+    let start_time: i32 = query_row.get(1);
+    let end_time: i32 = query_row.get(2);
+    let capacity_amount: i32 = query_row.get(3);
+    let user_id: i32 = query_row.get(4);
+    let base_cost_microcredits: i64 = query_row.get(6);
+    let price_percent: i32 = query_row.get(7);
+    let price_microcredits: i64 = query_row.get(8);
+    let quoted_at: i32 = query_row.get(9);
+    let expires_at: i32 = query_row.get(10);
+    let redeemed_at: Option<i32> = query_row.get(11);
+    Ok(Some(PriceQuote {
+        quote_token: query_row.get(0),
+        start_time: start_time as u32,
+        end_time: end_time as u32,
+        capacity_amount: capacity_amount as u32,
+        user_id: user_id as u32,
+        pool: query_row.get(5),
+        base_cost_microcredits: base_cost_microcredits as u64,
+        price_percent: price_percent as u32,
+        price_microcredits: price_microcredits as u64,
+        quoted_at: quoted_at as u32,
+        expires_at: expires_at as u32,
+        redeemed_at: redeemed_at.map(|redeemed_at| redeemed_at as u32),
+    }))
+}
+
+/// Columns of `user_reservations` in the order that `reservation_from_row()` reads them.
+const RESERVATION_COLUMNS: &str = "id, start_time, end_time, reservation_amount, user_id, pool, \
+     status, created_at, updated_at, cost_microcredits, refund_microcredits";
+
+/// Read a reservation out of a `user_reservations` row.
+fn reservation_from_row(query_row: &Row) -> Result<Reservation> {
+    let reservation_id: i32 = query_row.get(0);
+    let start_time: i32 = query_row.get(1);
+    let end_time: i32 = query_row.get(2);
+    let reservation_amount: i32 = query_row.get(3);
+    let user_id: i32 = query_row.get(4);
+    let status: &str = query_row.get(6);
+    let created_at: i32 = query_row.get(7);
+    let updated_at: i32 = query_row.get(8);
+    let cost_microcredits: i64 = query_row.get(9);
+    let refund_microcredits: i64 = query_row.get(10);
+    Ok(Reservation {
+        reservation_id: reservation_id as u32,
+        start_time: start_time as u32,
+        end_time: end_time as u32,
+        capacity_amount: reservation_amount as u32,
+        user_id: user_id as u32,
+        pool: query_row.get(5),
+        status: status.parse()?,
+        created_at: created_at as u32,
+        updated_at: updated_at as u32,
+        cost_microcredits: cost_microcredits as u64,
+        refund_microcredits: refund_microcredits as u64,
+    })
+}
+
+/// Add a reservation and record its approval in the ledger.
+///
+/// # Arguments
+/// - `db_client`: Transaction that the reservation is added with.
+/// - `new_reservation`: Reservation request that was approved.
+/// - `created_at`: When the reservation was approved, represented by Unix epoch format.
+/// - `cost_microcredits`: What the reservation costs.
+fn insert_user_reservation(
+    db_client: &mut impl GenericClient,
+    new_reservation: &ReservationRequest,
+    created_at: u32,
+    cost_microcredits: u64,
+) -> Result<Reservation> {
+    let status = ReservationStatus::Pending;
+    let inserted_row = db_client.query_one(
+        "INSERT INTO user_reservations
+                      (start_time, end_time, reservation_amount, user_id, pool, status, created_at, updated_at,
+                       cost_microcredits)
+                      VALUES ($1, $2, $3, $4, $5, $6, $7, $7, $8)
+                      RETURNING id",
+        &[
+            &db_integer(new_reservation.start_time, "start_time")?,
+            &db_integer(new_reservation.end_time, "end_time")?,
+            &db_integer(new_reservation.capacity_amount, "reservation_amount")?,
+            &db_integer(new_reservation.user_id, "user_id")?,
+            &new_reservation.pool,
+            &status.as_str(),
+            &db_integer(created_at, "created_at")?,
+            &db_bigint(cost_microcredits, "cost_microcredits")?,
+        ],
+    )?;
+    let reservation_id: i32 = inserted_row.get(0);
+    append_ledger_event(
+        db_client,
+        Some(reservation_id),
+        LedgerEventKind::Approved,
+        new_reservation,
+        created_at,
+        None,
+        Charges {
+            cost_microcredits,
+            refund_microcredits: 0,
+        },
+    )?;
+    let added_reservation = Reservation {
+        reservation_id: reservation_id as u32,
+        start_time: new_reservation.start_time,
+        end_time: new_reservation.end_time,
+        capacity_amount: new_reservation.capacity_amount,
+        user_id: new_reservation.user_id,
+        pool: new_reservation.pool.clone(),
+        status,
+        created_at,
+        updated_at: created_at,
+        cost_microcredits,
+        refund_microcredits: 0,
+    };
+    info!("Added reservation to DB: {}", added_reservation);
+    Ok(added_reservation)
+}
+
+/// Mark a price quote as redeemed, unless it's already been redeemed or it's expired.
+///
+/// Checking and marking the quote in one statement keeps it from being redeemed twice.
+///
+/// # Returns
+/// Whether the quote was redeemed.
+fn redeem_price_quote(
+    db_client: &mut impl GenericClient,
+    quote_token: &str,
+    redeemed_at: u32,
+) -> Result<bool> {
+    let updated_count = db_client.execute(
+        "UPDATE price_quotes
+         SET redeemed_at = $2
+         WHERE quote_token = $1 AND redeemed_at IS NULL AND expires_at > $2",
+        &[&quote_token, &db_integer(redeemed_at, "redeemed_at")?],
+    )?;
+    Ok(updated_count > 0)
+}
+
+/// Append an event to the reservation ledger.
+///
+/// # Arguments
+/// - `db_client`: Connection or transaction that the event is recorded with.
+/// - `reservation_id`: Reservation that changed, if the request became one.
+/// - `kind`: Kind of change.
+/// - `reservation_terms`: Reservation's terms after the change.
+/// - `recorded_at`: When the change happened, represented by Unix epoch format.
+/// - `reason`: Why the change happened.
+/// - `charges`: What the reservation cost and got back after the change.
+fn append_ledger_event(
+    db_client: &mut impl GenericClient,
+    reservation_id: Option<i32>,
+    kind: LedgerEventKind,
+    reservation_terms: &ReservationRequest,
+    recorded_at: u32,
+    reason: Option<&str>,
+    charges: Charges,
+) -> Result<()> {
+    db_client.execute(
+        "INSERT INTO reservation_ledger
+                      (reservation_id, event_kind, recorded_at, start_time, end_time, capacity_amount, user_id, pool, reason,
+                       cost_microcredits, refund_microcredits)
+                      VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+        &[
+            &reservation_id,
+            &kind.as_str(),
+            &db_integer(recorded_at, "recorded_at")?,
+            &db_integer(reservation_terms.start_time, "start_time")?,
+            &db_integer(reservation_terms.end_time, "end_time")?,
+            &db_integer(reservation_terms.capacity_amount, "capacity_amount")?,
+            &db_integer(reservation_terms.user_id, "user_id")?,
+            &reservation_terms.pool,
+            &reason,
+            &db_bigint(charges.cost_microcredits, "cost_microcredits")?,
+            &db_bigint(charges.refund_microcredits, "refund_microcredits")?,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Read an evaluated request out of a `reservation_requests` row.
+fn evaluated_request_from_row(query_row: &Row) -> Result<EvaluatedRequest> {
+    let request_id: i32 = query_row.get(0);
+    let requested_at: i32 = query_row.get(1);
+    let start_time: i32 = query_row.get(2);
+    let end_time: i32 = query_row.get(3);
+    let capacity_amount: i32 = query_row.get(4);
+    let user_id: i32 = query_row.get(5);
+    let outcome: &str = query_row.get(7);
+    let reservation_id: Option<i32> = query_row.get(9);
+    let bottleneck_start: Option<i32> = query_row.get(10);
+    let bottleneck_end: Option<i32> = query_row.get(11);
+    let bottleneck_available_amount: Option<i32> = query_row.get(12);
+    let bottleneck = match (
+        bottleneck_start,
+        bottleneck_end,
+        bottleneck_available_amount,
+    ) {
+        (Some(start_time), Some(end_time), Some(available_amount)) => Some(Bottleneck {
+            start_time: start_time as u32,
+            end_time: end_time as u32,
+            available_amount: available_amount as u32,
+        }),
+        _ => None,
+    };
+    Ok(EvaluatedRequest {
+        request_id: request_id as u32,
+        requested_at: requested_at as u32,
+        start_time: start_time as u32,
+        end_time: end_time as u32,
+        capacity_amount: capacity_amount as u32,
+        user_id: user_id as u32,
+        pool: query_row.get(6),
+        outcome: outcome.parse()?,
+        denial_reason: query_row.get(8),
+        reservation_id: reservation_id.map(|reservation_id| reservation_id as u32),
+        bottleneck,
+    })
+}
+
+/// Read a change event out of a `change_events` row.
+fn change_event_from_row(query_row: &Row) -> Result<ChangeEvent> {
+    let event_id: i32 = query_row.get(0);
+    let event_kind: &str = query_row.get(1);
+    let recorded_at: i32 = query_row.get(2);
+    let user_id: Option<i32> = query_row.get(4);
+    let payload: &str = query_row.get(5);
+    Ok(ChangeEvent {
+        event_id: event_id as u32,
+        kind: event_kind.parse()?,
+        recorded_at: recorded_at as u32,
+        pool: query_row.get(3),
+        user_id: user_id.map(|user_id| user_id as u32),
+        payload: serde_json::from_str(payload)?,
+    })
+}
+
+/// Read a webhook subscription out of a `webhook_subscriptions` row.
+fn webhook_subscription_from_row(query_row: &Row) -> Result<WebhookSubscription> {
+    let subscription_id: i32 = query_row.get(0);
+    let event_kinds: &str = query_row.get(3);
+    let user_id: Option<i32> = query_row.get(5);
+    let last_event_id: i32 = query_row.get(6);
+    let created_at: i32 = query_row.get(7);
+    Ok(WebhookSubscription {
+        subscription_id: subscription_id as u32,
+        url: query_row.get(1),
+        secret: query_row.get(2),
+        event_kinds: split_event_kinds(event_kinds)?,
+        pool: query_row.get(4),
+        user_id: user_id.map(|user_id| user_id as u32),
+        last_event_id: last_event_id as u32,
+        created_at: created_at as u32,
+    })
+}
+
+/// Read a webhook delivery out of a `webhook_deliveries` row.
+fn webhook_delivery_from_row(query_row: &Row) -> WebhookDelivery {
+    let delivery_id: i32 = query_row.get(0);
+    let subscription_id: i32 = query_row.get(1);
+    let event_id: i32 = query_row.get(2);
+    let attempt: i32 = query_row.get(3);
+    let attempted_at: i32 = query_row.get(4);
+    let status_code: Option<i32> = query_row.get(5);
+    WebhookDelivery {
+        delivery_id: delivery_id as u32,
+        subscription_id: subscription_id as u32,
+        event_id: event_id as u32,
+        attempt: attempt as u32,
+        attempted_at: attempted_at as u32,
+        status_code: status_code.map(|status_code| status_code as u16),
+        error: query_row.get(6),
+        is_delivered: query_row.get(7),
+    }
+}
+
+/// Read a maintenance window out of a `maintenance_windows` row.
+fn maintenance_window_from_row(query_row: &Row) -> MaintenanceWindow {
+    let window_id: i32 = query_row.get(0);
+    let start_time: i32 = query_row.get(2);
+    let end_time: i32 = query_row.get(3);
+    let reduction_amount: Option<i32> = query_row.get(4);
+    MaintenanceWindow {
+        window_id: window_id as u32,
+        pool: query_row.get(1),
+        start_time: start_time as u32,
+        end_time: end_time as u32,
+        reduction: match reduction_amount {
+            Some(reduction) => CapacityReduction::Amount(reduction as u32),
+            None => CapacityReduction::All,
+        },
+        reason: query_row.get(5),
+    }
+}
+
+/// Tables that capacity segments can be written to.
+///
+/// Table names can't be query parameters, so they're limited to this fixed set instead of
+/// taking arbitrary strings.
+#[derive(Clone, Copy)]
+enum ScheduleTable {
+    CapacitySchedule,
+}
+
+impl ScheduleTable {
+    fn name(self) -> &'static str {
+        match self {
+            ScheduleTable::CapacitySchedule => "capacity_schedule",
+        }
+    }
+}
+
+/// Insert a capacity segment into a schedule table.
+///
+/// Failed inserts (like segments that overlap others in their pool) are reported, not ignored.
+fn populate_schedule_row(
+    capacity_segment: &CapacitySegment,
+    schedule_table: ScheduleTable,
+    db_client: &mut Client,
+) -> Result<()> {
+    let insertion_command = format!(
+        "INSERT INTO {} (start_time, end_time, capacity_amount, pool, microcredits_per_unit_second)
+         VALUES ($1, $2, $3, $4, $5)",
+        schedule_table.name()
+    );
+    db_client
+        .execute(
+            &insertion_command,
+            &[
+                &db_integer(capacity_segment.start_time, "start_time")?,
+                &db_integer(capacity_segment.end_time, "end_time")?,
+                &db_integer(capacity_segment.capacity_amount, "capacity_amount")?,
+                &capacity_segment.pool,
+                &db_bigint(
+                    capacity_segment.microcredits_per_unit_second,
+                    "microcredits_per_unit_second",
+                )?,
+            ],
+        )
+        .with_context(|| {
+            format!(
+                "Failed to add capacity segment to \"{}\": {:?}",
+                schedule_table.name(),
+                capacity_segment
+            )
+        })?;
+    Ok(())
+}
+
+/// Fit a value into an `INTEGER` column.
+///
+/// Values that are too big are refused instead of wrapping around to negative numbers.
+fn db_integer(value: u32, column: &str) -> Result<i32> {
+    i32::try_from(value)
+        .with_context(|| format!("Value \"{value}\" is too big for column \"{column}\""))
+}
+
+/// Fit a value into a `BIGINT` column, like an amount of money.
+///
+/// Values that are too big are refused instead of wrapping around to negative numbers.
+fn db_bigint(value: u64, column: &str) -> Result<i64> {
+    i64::try_from(value)
+        .with_context(|| format!("Value \"{value}\" is too big for column \"{column}\""))
+}
+
+#[cfg(test)]
+mod tests {
+    // Project crates.
+    use crate::common::{CapacitySegment, ReservationRequest};
+    use crate::config::DatastoreBackend;
+    use crate::datastore::test_examples::{add_test_reservation, backend_datastore};
+
+    // Values that don't fit in `INTEGER` columns are refused instead of wrapping around.
+    #[test]
+    fn test_refuse_values_too_big_for_columns() {
+        let datastore = backend_datastore(DatastoreBackend::Postgres);
+        let test_pool = "test_postgres_too_big";
+        let huge_segment =
+            CapacitySegment::new(1707165008, 1708374608, u32::MAX).in_pool(test_pool);
+        assert!(datastore.add_capacity_segment(&huge_segment).is_err());
+        let huge_request =
+            ReservationRequest::new(1707165008, 1708374608, 1, u32::MAX).in_pool(test_pool);
+        assert!(add_test_reservation(&*datastore, &huge_request, 1707078608, 0).is_err());
+        assert!(!datastore.delete_maintenance_window(u32::MAX).unwrap());
+    }
+}
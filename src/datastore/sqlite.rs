@@ -0,0 +1,1367 @@
+//! SQLite Datastore
+//!
+//! `sqlite` keeps Arbiter's schedule and reservations in a single file, so single-node
+//! deployments don't need a database server.
+//!
+//! SQLite is compiled into Arbiter, and the database file is created the first time Arbiter
+//! starts. There's one connection that every request takes turns with.
+
+// Standard library crates.
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+// External crates.
+use anyhow::{anyhow, bail, Context, Result};
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use rusqlite::{params, params_from_iter, Connection, Row, ToSql};
+
+// Project crates.
+use super::{reservation_terms, Booking, Datastore};
+use crate::billing::{Charges, PriceQuote};
+use crate::common::{
+    CapacityReduction, CapacitySegment, MaintenanceParams, MaintenanceWindow, Reservation,
+    ReservationStatus,
+};
+use crate::demand::{Bottleneck, EvaluatedRequest, RequestEvaluation, RequestFilter};
+use crate::events::{ChangeEvent, ChangeEventParams, ChangeFilter};
+use crate::idempotency::IdempotencyRecord;
+use crate::ledger::{LedgerEvent, LedgerEventKind};
+use crate::listing::{sql_where, PageRequest, ReservationFilter};
+use crate::metrics::ReservationCount;
+use crate::migrations::run_sqlite_migrations;
+use crate::webhooks::{
+    join_event_kinds, split_event_kinds, WebhookDelivery, WebhookDeliveryParams, WebhookParams,
+    WebhookSubscription,
+};
+use crate::CapacitySchedule;
+use crate::ReservationRequest;
+
+/// Datastore that's kept in a SQLite file.
+pub struct SqliteDatastore {
+    db_connection: Mutex<Connection>,
+}
+
+impl SqliteDatastore {
+    /// Open the SQLite database file, or create it if it doesn't exist.
+    pub fn open(sqlite_path: &Path) -> Result<Self> {
+        let db_connection = Connection::open(sqlite_path)?;
+        // Wait for other processes instead of failing right away.
+        db_connection.busy_timeout(Duration::from_secs(5))?;
+        info!("Opened SQLite database \"{}\"", sqlite_path.display());
+        Ok(Self {
+            db_connection: Mutex::new(db_connection),
+        })
+    }
+
+    /// Wait for a turn with the connection.
+    fn connection(&self) -> Result<MutexGuard<'_, Connection>> {
+        self.db_connection
+            .lock()
+            .map_err(|_| anyhow!("SQLite connection was poisoned by a panic"))
+    }
+}
+
+impl Datastore for SqliteDatastore {
+    fn migrate(&self) -> Result<i32> {
+        let mut db_connection = self.connection()?;
+        run_sqlite_migrations(&mut db_connection)
+    }
+
+    fn drop_tables(&self) -> Result<()> {
+        let db_connection = self.connection()?;
+        db_connection.execute_batch(
+            "DROP TABLE IF EXISTS capacity_schedule;
+             DROP TABLE IF EXISTS user_reservations;
+             DROP TABLE IF EXISTS maintenance_windows;
+             DROP TABLE IF EXISTS reservation_ledger;
+             DROP TABLE IF EXISTS reservation_requests;
+             DROP TABLE IF EXISTS price_quotes;
+             DROP TABLE IF EXISTS idempotency_keys;
+             DROP TABLE IF EXISTS change_events;
+             DROP TABLE IF EXISTS webhook_subscriptions;
+             DROP TABLE IF EXISTS webhook_deliveries;
+             DROP TABLE IF EXISTS schema_version;",
+        )?;
+        info!("Deleted DB tables: capacity_schedule, user_reservations, maintenance_windows, reservation_ledger, reservation_requests, price_quotes, idempotency_keys, change_events, webhook_subscriptions, webhook_deliveries, schema_version");
+        Ok(())
+    }
+
+    fn get_schedule(&self) -> Result<CapacitySchedule> {
+        let db_connection = self.connection()?;
+        query_schedule(&db_connection)
+    }
+
+    fn add_capacity_segment(&self, capacity_segment: &CapacitySegment) -> Result<()> {
+        let db_connection = self.connection()?;
+        db_connection
+            .execute(
+                "INSERT INTO capacity_schedule (start_time, end_time, capacity_amount, pool, microcredits_per_unit_second)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    capacity_segment.start_time,
+                    capacity_segment.end_time,
+                    capacity_segment.capacity_amount,
+                    capacity_segment.pool,
+                    db_bigint(
+                        capacity_segment.microcredits_per_unit_second,
+                        "microcredits_per_unit_second"
+                    )?,
+                ],
+            )
+            .with_context(|| {
+                format!(
+                    "Failed to add capacity segment to \"capacity_schedule\": {:?}",
+                    capacity_segment
+                )
+            })?;
+        Ok(())
+    }
+
+    fn set_segment_price(
+        &self,
+        pool: &str,
+        start_time: u32,
+        microcredits_per_unit_second: u64,
+    ) -> Result<bool> {
+        let db_connection = self.connection()?;
+        let updated_count = db_connection.execute(
+            "UPDATE capacity_schedule
+             SET microcredits_per_unit_second = ?3
+             WHERE pool = ?1 AND start_time = ?2",
+            params![
+                pool,
+                start_time,
+                db_bigint(microcredits_per_unit_second, "microcredits_per_unit_second")?,
+            ],
+        )?;
+        info!(
+            "Priced capacity segment in pool \"{}\" starting at \"{}\" at \"{}\" microcredits per unit-second",
+            pool, start_time, microcredits_per_unit_second
+        );
+        Ok(updated_count > 0)
+    }
+
+    // SQLite can only lock the whole database, so every pool is booked at once. The booking
+    // holds the connection, and its write transaction keeps other processes from booking too.
+    fn begin_booking(&self, _pools: &[&str]) -> Result<Box<dyn Booking + '_>> {
+        let db_connection = self.connection()?;
+        db_connection.execute_batch("BEGIN IMMEDIATE")?;
+        Ok(Box::new(SqliteBooking {
+            db_connection,
+            is_committed: false,
+        }))
+    }
+
+    fn get_reservation(&self, reservation_id: u32) -> Result<Option<Reservation>> {
+        let db_connection = self.connection()?;
+        let mut statement = db_connection.prepare(&format!(
+            "SELECT {RESERVATION_COLUMNS} FROM user_reservations WHERE id = ?1"
+        ))?;
+        let mut query_rows = statement.query(params![reservation_id])?;
+        match query_rows.next()? {
+            Some(query_row) => Ok(Some(reservation_from_row(query_row)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Reservations are found with the index on when they were made.
+    fn get_reservations_created_between(
+        &self,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<Reservation>> {
+        let db_connection = self.connection()?;
+        let mut statement = db_connection.prepare(&format!(
+            "SELECT {RESERVATION_COLUMNS}
+             FROM user_reservations
+             WHERE created_at >= ?1 AND created_at < ?2
+             ORDER BY id"
+        ))?;
+        let mut query_rows = statement.query(params![start_time, end_time])?;
+        let mut user_reservations = Vec::new();
+        while let Some(query_row) = query_rows.next()? {
+            user_reservations.push(reservation_from_row(query_row)?)
+        }
+        Ok(user_reservations)
+    }
+
+    // Filters that aren't given are left out of the query, and the page's sort column is chosen
+    // from a fixed list so it's never user input.
+    fn list_reservations(
+        &self,
+        reservation_filter: &ReservationFilter,
+        page_request: &PageRequest,
+    ) -> Result<Vec<Reservation>> {
+        let mut query_params: Vec<Box<dyn ToSql>> = Vec::new();
+        // Add a parameter to the query and get its placeholder.
+        let mut bind = |query_param: Box<dyn ToSql>| {
+            query_params.push(query_param);
+            format!("?{}", query_params.len())
+        };
+        let mut conditions = Vec::new();
+        if let Some(user_id) = reservation_filter.user_id {
+            conditions.push(format!("user_id = {}", bind(Box::new(user_id))));
+        }
+        if let Some(pool) = &reservation_filter.pool {
+            conditions.push(format!("pool = {}", bind(Box::new(pool.clone()))));
+        }
+        if let Some(status) = reservation_filter.status {
+            conditions.push(format!("status = {}", bind(Box::new(status.as_str()))));
+        }
+        let time_match = reservation_filter.time_match;
+        if let Some(start_time) = reservation_filter.start_time {
+            conditions.push(time_match.sql_start_condition(&bind(Box::new(start_time))));
+        }
+        if let Some(end_time) = reservation_filter.end_time {
+            conditions.push(time_match.sql_end_condition(&bind(Box::new(end_time))));
+        }
+        if let Some(cursor) = &page_request.after {
+            let value_param = bind(Box::new(cursor.sort_value));
+            let id_param = bind(Box::new(cursor.reservation_id));
+            conditions.push(page_request.sql_after(&value_param, &id_param));
+        }
+        let limit_param = bind(Box::new(page_request.limit));
+        let db_connection = self.connection()?;
+        let mut statement = db_connection.prepare(&format!(
+            "SELECT {RESERVATION_COLUMNS}
+             FROM user_reservations
+             {}
+             {}
+             LIMIT {limit_param}",
+            sql_where(&conditions),
+            page_request.sql_order_by(),
+        ))?;
+        let mut query_rows = statement.query(params_from_iter(query_params))?;
+        let mut user_reservations = Vec::new();
+        while let Some(query_row) = query_rows.next()? {
+            user_reservations.push(reservation_from_row(query_row)?)
+        }
+        Ok(user_reservations)
+    }
+
+    // The status change and its ledger event are recorded together.
+    fn end_reservation(
+        &self,
+        reservation_id: u32,
+        ending_kind: LedgerEventKind,
+        recorded_at: u32,
+        reason: Option<&str>,
+        refund_microcredits: u64,
+    ) -> Result<Option<Reservation>> {
+        let Some(ending_status) = ending_kind.ending_status() else {
+            bail!("Reservations can't be ended by \"{ending_kind}\" events");
+        };
+        let mut db_connection = self.connection()?;
+        let transaction = db_connection.transaction()?;
+        let ended_reservation = {
+            let mut statement = transaction.prepare(&format!(
+                "UPDATE user_reservations
+                 SET status = ?2, updated_at = ?3, refund_microcredits = ?4
+                 WHERE id = ?1 AND status IN ('pending', 'active')
+                 RETURNING {RESERVATION_COLUMNS}"
+            ))?;
+            let mut updated_rows = statement.query(params![
+                reservation_id,
+                ending_status.as_str(),
+                recorded_at,
+                db_bigint(refund_microcredits, "refund_microcredits")?,
+            ])?;
+            match updated_rows.next()? {
+                Some(updated_row) => reservation_from_row(updated_row)?,
+                None => return Ok(None),
+            }
+        };
+        append_ledger_event(
+            &transaction,
+            Some(reservation_id),
+            ending_kind,
+            &reservation_terms(&ended_reservation),
+            recorded_at,
+            reason,
+            Charges::of(&ended_reservation),
+        )?;
+        transaction.commit()?;
+        info!("Ended reservation in DB: {}", ended_reservation);
+        Ok(Some(ended_reservation))
+    }
+
+    fn get_due_reservations(&self, epoch_now: u32) -> Result<Vec<Reservation>> {
+        let db_connection = self.connection()?;
+        let mut statement = db_connection.prepare(&format!(
+            "SELECT {RESERVATION_COLUMNS}
+             FROM user_reservations
+             WHERE (status = 'pending' AND start_time <= ?1)
+                OR (status = 'active' AND end_time <= ?1)
+             ORDER BY id"
+        ))?;
+        let mut query_rows = statement.query(params![epoch_now])?;
+        let mut due_reservations = Vec::new();
+        while let Some(query_row) = query_rows.next()? {
+            due_reservations.push(reservation_from_row(query_row)?)
+        }
+        Ok(due_reservations)
+    }
+
+    fn get_next_lifecycle_boundary(&self, epoch_now: u32) -> Result<Option<u32>> {
+        let db_connection = self.connection()?;
+        let next_boundary: Option<u32> = db_connection.query_row(
+            "SELECT MIN(boundary) FROM (
+                 SELECT MIN(start_time) AS boundary FROM user_reservations
+                 WHERE status = 'pending' AND start_time > ?1
+                 UNION ALL
+                 SELECT MIN(end_time) AS boundary FROM user_reservations
+                 WHERE status = 'active' AND end_time > ?1
+             )",
+            params![epoch_now],
+            |boundary_row| boundary_row.get(0),
+        )?;
+        Ok(next_boundary)
+    }
+
+    fn get_reservation_counts(&self) -> Result<Vec<ReservationCount>> {
+        let db_connection = self.connection()?;
+        let mut statement = db_connection.prepare(
+            "SELECT pool, status, COUNT(*)
+             FROM user_reservations
+             WHERE status IN ('pending', 'active')
+             GROUP BY pool, status
+             ORDER BY pool, status",
+        )?;
+        let mut query_rows = statement.query([])?;
+        let mut reservation_counts = Vec::new();
+        while let Some(query_row) = query_rows.next()? {
+            let status: String = query_row.get(1)?;
+            reservation_counts.push(ReservationCount {
+                pool: query_row.get(0)?,
+                status: status.parse()?,
+                count: query_row.get(2)?,
+            })
+        }
+        Ok(reservation_counts)
+    }
+
+    // The status change and its ledger event are recorded together.
+    fn advance_reservation(
+        &self,
+        reservation_id: u32,
+        lifecycle_kind: LedgerEventKind,
+        recorded_at: u32,
+    ) -> Result<Option<Reservation>> {
+        let Some((current_status, next_status)) = lifecycle_kind.lifecycle_step() else {
+            bail!("Reservations can't be moved along by \"{lifecycle_kind}\" events");
+        };
+        let mut db_connection = self.connection()?;
+        let transaction = db_connection.transaction()?;
+        let advanced_reservation = {
+            let mut statement = transaction.prepare(&format!(
+                "UPDATE user_reservations
+                 SET status = ?3, updated_at = ?4
+                 WHERE id = ?1 AND status = ?2
+                 RETURNING {RESERVATION_COLUMNS}"
+            ))?;
+            let mut updated_rows = statement.query(params![
+                reservation_id,
+                current_status.as_str(),
+                next_status.as_str(),
+                recorded_at,
+            ])?;
+            match updated_rows.next()? {
+                Some(updated_row) => reservation_from_row(updated_row)?,
+                None => return Ok(None),
+            }
+        };
+        append_ledger_event(
+            &transaction,
+            Some(reservation_id),
+            lifecycle_kind,
+            &reservation_terms(&advanced_reservation),
+            recorded_at,
+            None,
+            Charges::of(&advanced_reservation),
+        )?;
+        transaction.commit()?;
+        debug!("Advanced reservation in DB: {}", advanced_reservation);
+        Ok(Some(advanced_reservation))
+    }
+
+    fn add_price_quote(&self, price_quote: &PriceQuote) -> Result<()> {
+        let db_connection = self.connection()?;
+        db_connection.execute(
+            "INSERT INTO price_quotes
+                          (quote_token, start_time, end_time, capacity_amount, user_id, pool,
+                           base_cost_microcredits, price_percent, price_microcredits, quoted_at, expires_at)
+                          VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                price_quote.quote_token,
+                price_quote.start_time,
+                price_quote.end_time,
+                price_quote.capacity_amount,
+                price_quote.user_id,
+                price_quote.pool,
+                db_bigint(price_quote.base_cost_microcredits, "base_cost_microcredits")?,
+                price_quote.price_percent,
+                db_bigint(price_quote.price_microcredits, "price_microcredits")?,
+                price_quote.quoted_at,
+                price_quote.expires_at,
+            ],
+        )?;
+        debug!("Added quote \"{}\" to DB", price_quote.quote_token);
+        Ok(())
+    }
+
+    // The connection is shared behind a lock, so claims can't interleave.
+    fn claim_idempotency_key(
+        &self,
+        idempotency_record: &IdempotencyRecord,
+        epoch_now: u32,
+    ) -> Result<Option<IdempotencyRecord>> {
+        let db_connection = self.connection()?;
+        db_connection.execute(
+            "DELETE FROM idempotency_keys WHERE expires_at <= ?1",
+            params![epoch_now],
+        )?;
+        let inserted_count = db_connection.execute(
+            "INSERT INTO idempotency_keys
+                          (idempotency_key, user_id, request_hash, created_at, expires_at)
+                          VALUES (?1, ?2, ?3, ?4, ?5)
+                          ON CONFLICT DO NOTHING",
+            params![
+                idempotency_record.idempotency_key,
+                idempotency_record.user_id,
+                idempotency_record.request_hash,
+                idempotency_record.created_at,
+                idempotency_record.expires_at,
+            ],
+        )?;
+        if inserted_count > 0 {
+            debug!(
+                "Claimed idempotency key \"{}\" for user \"{}\"",
+                idempotency_record.idempotency_key, idempotency_record.user_id
+            );
+            return Ok(None);
+        }
+        let existing_record = db_connection.query_row(
+            "SELECT idempotency_key, user_id, request_hash, response_body, created_at, expires_at
+             FROM idempotency_keys
+             WHERE user_id = ?1 AND idempotency_key = ?2",
+            params![
+                idempotency_record.user_id,
+                idempotency_record.idempotency_key
+            ],
+            |query_row| {
+                Ok(IdempotencyRecord {
+                    idempotency_key: query_row.get(0)?,
+                    user_id: query_row.get(1)?,
+                    request_hash: query_row.get(2)?,
+                    response_body: query_row.get(3)?,
+                    created_at: query_row.get(4)?,
+                    expires_at: query_row.get(5)?,
+                })
+            },
+        )?;
+        Ok(Some(existing_record))
+    }
+
+    fn answer_idempotency_key(
+        &self,
+        idempotency_key: &str,
+        user_id: u32,
+        response_body: &str,
+    ) -> Result<()> {
+        let db_connection = self.connection()?;
+        db_connection.execute(
+            "UPDATE idempotency_keys
+             SET response_body = ?3
+             WHERE user_id = ?1 AND idempotency_key = ?2",
+            params![user_id, idempotency_key, response_body],
+        )?;
+        Ok(())
+    }
+
+    fn release_idempotency_key(&self, idempotency_key: &str, user_id: u32) -> Result<()> {
+        let db_connection = self.connection()?;
+        db_connection.execute(
+            "DELETE FROM idempotency_keys
+             WHERE user_id = ?1 AND idempotency_key = ?2 AND response_body IS NULL",
+            params![user_id, idempotency_key],
+        )?;
+        debug!(
+            "Released idempotency key \"{}\" for user \"{}\"",
+            idempotency_key, user_id
+        );
+        Ok(())
+    }
+
+    fn record_request_event(
+        &self,
+        kind: LedgerEventKind,
+        reservation_id: Option<u32>,
+        reservation_request: &ReservationRequest,
+        recorded_at: u32,
+        reason: Option<&str>,
+    ) -> Result<()> {
+        let db_connection = self.connection()?;
+        append_ledger_event(
+            &db_connection,
+            reservation_id,
+            kind,
+            reservation_request,
+            recorded_at,
+            reason,
+            Charges::default(),
+        )
+    }
+
+    fn get_ledger_events(&self) -> Result<Vec<LedgerEvent>> {
+        let db_connection = self.connection()?;
+        let mut statement = db_connection.prepare(
+            "SELECT id, reservation_id, event_kind, recorded_at,
+                    start_time, end_time, capacity_amount, user_id, pool, reason,
+                    cost_microcredits, refund_microcredits
+             FROM reservation_ledger
+             ORDER BY id",
+        )?;
+        let mut query_rows = statement.query([])?;
+        let mut ledger_events = Vec::new();
+        while let Some(query_row) = query_rows.next()? {
+            let event_kind: String = query_row.get(2)?;
+            let ledger_event = LedgerEvent {
+                event_id: query_row.get(0)?,
+                reservation_id: query_row.get(1)?,
+                kind: event_kind.parse()?,
+                recorded_at: query_row.get(3)?,
+                start_time: query_row.get(4)?,
+                end_time: query_row.get(5)?,
+                capacity_amount: query_row.get(6)?,
+                user_id: query_row.get(7)?,
+                pool: query_row.get(8)?,
+                reason: query_row.get(9)?,
+                cost_microcredits: query_row.get::<_, i64>(10)? as u64,
+                refund_microcredits: query_row.get::<_, i64>(11)? as u64,
+            };
+            ledger_events.push(ledger_event)
+        }
+        Ok(ledger_events)
+    }
+
+    // Reservation IDs are kept. SQLite never hands out an ID twice, so new reservations keep
+    // counting up from the highest one.
+    fn replace_reservations(&self, reservations: &[Reservation]) -> Result<()> {
+        let mut db_connection = self.connection()?;
+        let transaction = db_connection.transaction()?;
+        transaction.execute("DELETE FROM user_reservations", [])?;
+        for reservation in reservations {
+            transaction.execute(
+                "INSERT INTO user_reservations
+                              (id, start_time, end_time, reservation_amount, user_id, pool, status, created_at, updated_at,
+                               cost_microcredits, refund_microcredits)
+                              VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![
+                    reservation.reservation_id,
+                    reservation.start_time,
+                    reservation.end_time,
+                    reservation.capacity_amount,
+                    reservation.user_id,
+                    reservation.pool,
+                    reservation.status.as_str(),
+                    reservation.created_at,
+                    reservation.updated_at,
+                    db_bigint(reservation.cost_microcredits, "cost_microcredits")?,
+                    db_bigint(reservation.refund_microcredits, "refund_microcredits")?,
+                ],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn record_evaluated_request(
+        &self,
+        request_evaluation: &RequestEvaluation,
+    ) -> Result<EvaluatedRequest> {
+        let db_connection = self.connection()?;
+        let reservation_request = &request_evaluation.reservation_request;
+        let bottleneck = request_evaluation.bottleneck.as_ref();
+        let mut statement = db_connection.prepare(
+            "INSERT INTO reservation_requests
+                          (requested_at, start_time, end_time, capacity_amount, user_id, pool, outcome, denial_reason,
+                           reservation_id, bottleneck_start, bottleneck_end, bottleneck_available_amount)
+                          VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+                          RETURNING id, requested_at, start_time, end_time, capacity_amount, user_id, pool, outcome,
+                                    denial_reason, reservation_id, bottleneck_start, bottleneck_end, bottleneck_available_amount",
+        )?;
+        let mut inserted_rows = statement.query(params![
+            request_evaluation.requested_at,
+            reservation_request.start_time,
+            reservation_request.end_time,
+            reservation_request.capacity_amount,
+            reservation_request.user_id,
+            reservation_request.pool,
+            request_evaluation.outcome.as_str(),
+            request_evaluation.denial_reason,
+            request_evaluation.reservation_id,
+            bottleneck.map(|slot| slot.start_time),
+            bottleneck.map(|slot| slot.end_time),
+            bottleneck.map(|slot| slot.available_amount),
+        ])?;
+        let evaluated_request = match inserted_rows.next()? {
+            Some(inserted_row) => evaluated_request_from_row(inserted_row)?,
+            None => bail!("Evaluated request wasn't recorded"),
+        };
+        debug!("Recorded {} in DB", evaluated_request);
+        Ok(evaluated_request)
+    }
+
+    // Filters that are missing match everything.
+    fn get_evaluated_requests(
+        &self,
+        request_filter: &RequestFilter,
+    ) -> Result<Vec<EvaluatedRequest>> {
+        let db_connection = self.connection()?;
+        let mut statement = db_connection.prepare(
+            "SELECT id, requested_at, start_time, end_time, capacity_amount, user_id, pool, outcome,
+                    denial_reason, reservation_id, bottleneck_start, bottleneck_end, bottleneck_available_amount
+             FROM reservation_requests
+             WHERE (?1 IS NULL OR pool = ?1)
+               AND (?2 IS NULL OR user_id = ?2)
+               AND (?3 IS NULL OR end_time > ?3)
+               AND (?4 IS NULL OR start_time < ?4)
+               AND (?5 IS NULL OR outcome = ?5)
+             ORDER BY id",
+        )?;
+        let mut query_rows = statement.query(params![
+            request_filter.pool,
+            request_filter.user_id,
+            request_filter.start_time,
+            request_filter.end_time,
+            request_filter.outcome.map(|outcome| outcome.as_str()),
+        ])?;
+        let mut evaluated_requests = Vec::new();
+        while let Some(query_row) = query_rows.next()? {
+            evaluated_requests.push(evaluated_request_from_row(query_row)?)
+        }
+        Ok(evaluated_requests)
+    }
+
+    fn record_change_event(&self, change_params: &ChangeEventParams) -> Result<ChangeEvent> {
+        let db_connection = self.connection()?;
+        insert_change_event(&db_connection, change_params)
+    }
+
+    // Filters that are missing match everything, and changes that aren't to anyone's reservation
+    // match every user.
+    fn get_change_events(
+        &self,
+        change_filter: &ChangeFilter,
+        after_event_id: u32,
+        limit: u32,
+    ) -> Result<Vec<ChangeEvent>> {
+        let db_connection = self.connection()?;
+        let mut statement = db_connection.prepare(
+            "SELECT id, event_kind, recorded_at, pool, user_id, payload
+             FROM change_events
+             WHERE id > ?1
+               AND (?2 IS NULL OR pool = ?2)
+               AND (?3 IS NULL OR user_id IS NULL OR user_id = ?3)
+             ORDER BY id
+             LIMIT ?4",
+        )?;
+        let mut query_rows = statement.query(params![
+            after_event_id,
+            change_filter.pool,
+            change_filter.user_id,
+            limit,
+        ])?;
+        let mut change_events = Vec::new();
+        while let Some(query_row) = query_rows.next()? {
+            change_events.push(change_event_from_row(query_row)?)
+        }
+        Ok(change_events)
+    }
+
+    fn get_last_change_event_id(&self) -> Result<u32> {
+        let db_connection = self.connection()?;
+        let last_event_id: u32 = db_connection.query_row(
+            "SELECT COALESCE(MAX(id), 0) FROM change_events",
+            [],
+            |query_row| query_row.get(0),
+        )?;
+        Ok(last_event_id)
+    }
+
+    fn add_webhook_subscription(
+        &self,
+        webhook_params: &WebhookParams,
+        last_event_id: u32,
+        created_at: u32,
+    ) -> Result<WebhookSubscription> {
+        let db_connection = self.connection()?;
+        let subscription_id: u32 = db_connection.query_row(
+            "INSERT INTO webhook_subscriptions
+                          (url, secret, event_kinds, pool, user_id, last_event_id, created_at)
+                          VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                          RETURNING id",
+            params![
+                webhook_params.url,
+                webhook_params.secret,
+                join_event_kinds(&webhook_params.event_kinds),
+                webhook_params.pool,
+                webhook_params.user_id,
+                last_event_id,
+                created_at,
+            ],
+            |inserted_row| inserted_row.get(0),
+        )?;
+        let subscription = WebhookSubscription {
+            subscription_id,
+            url: webhook_params.url.clone(),
+            event_kinds: webhook_params.event_kinds.clone(),
+            pool: webhook_params.pool.clone(),
+            user_id: webhook_params.user_id,
+            secret: webhook_params.secret.clone(),
+            last_event_id,
+            created_at,
+        };
+        info!("Added {} to DB", subscription);
+        Ok(subscription)
+    }
+
+    fn get_webhook_subscriptions(&self) -> Result<Vec<WebhookSubscription>> {
+        let db_connection = self.connection()?;
+        let mut statement = db_connection.prepare(
+            "SELECT id, url, secret, event_kinds, pool, user_id, last_event_id, created_at
+             FROM webhook_subscriptions
+             ORDER BY id",
+        )?;
+        let mut query_rows = statement.query([])?;
+        let mut subscriptions = Vec::new();
+        while let Some(query_row) = query_rows.next()? {
+            subscriptions.push(webhook_subscription_from_row(query_row)?)
+        }
+        Ok(subscriptions)
+    }
+
+    fn delete_webhook_subscription(&self, subscription_id: u32) -> Result<bool> {
+        let db_connection = self.connection()?;
+        let deleted_count = db_connection.execute(
+            "DELETE FROM webhook_subscriptions WHERE id = ?1",
+            params![subscription_id],
+        )?;
+        info!(
+            "Deleted webhook subscription \"{}\" from DB",
+            subscription_id
+        );
+        Ok(deleted_count > 0)
+    }
+
+    fn advance_webhook_subscriptions(&self, event_id: u32) -> Result<()> {
+        let db_connection = self.connection()?;
+        db_connection.execute(
+            "UPDATE webhook_subscriptions SET last_event_id = ?1 WHERE last_event_id < ?1",
+            params![event_id],
+        )?;
+        Ok(())
+    }
+
+    fn record_webhook_delivery(
+        &self,
+        delivery_params: &WebhookDeliveryParams,
+    ) -> Result<WebhookDelivery> {
+        let db_connection = self.connection()?;
+        let mut statement = db_connection.prepare(
+            "INSERT INTO webhook_deliveries
+                 (subscription_id, event_id, attempt, attempted_at, status_code, error, is_delivered)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             RETURNING id, subscription_id, event_id, attempt, attempted_at, status_code, error,
+                       is_delivered",
+        )?;
+        let webhook_delivery = statement.query_row(
+            params![
+                delivery_params.subscription_id,
+                delivery_params.event_id,
+                delivery_params.attempt,
+                delivery_params.attempted_at,
+                delivery_params.status_code,
+                delivery_params.error,
+                delivery_params.is_delivered,
+            ],
+            webhook_delivery_from_row,
+        )?;
+        debug!("Recorded {} in DB", webhook_delivery);
+        Ok(webhook_delivery)
+    }
+
+    fn get_webhook_deliveries(&self, subscription_id: u32) -> Result<Vec<WebhookDelivery>> {
+        let db_connection = self.connection()?;
+        let mut statement = db_connection.prepare(
+            "SELECT id, subscription_id, event_id, attempt, attempted_at, status_code, error,
+                    is_delivered
+             FROM webhook_deliveries
+             WHERE subscription_id = ?1
+             ORDER BY id",
+        )?;
+        let webhook_deliveries = statement
+            .query_map(params![subscription_id], webhook_delivery_from_row)?
+            .collect::<rusqlite::Result<Vec<WebhookDelivery>>>()?;
+        Ok(webhook_deliveries)
+    }
+
+    // The overlap is found by the database with the reservations' pool and time index.
+    fn get_overlapping_reservations(
+        &self,
+        pool: &str,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<Reservation>> {
+        let db_connection = self.connection()?;
+        query_overlapping_reservations(&db_connection, pool, start_time, end_time)
+    }
+
+    // SQLite can't store functions, so this is the same query as Postgres's
+    // `peak_reserved_amount()`. Usage only goes up when a reservation starts, so the peak is at
+    // the start of the timeframe or the start of a reservation inside of it.
+    fn get_peak_reserved_amount(&self, pool: &str, start_time: u32, end_time: u32) -> Result<u32> {
+        let db_connection = self.connection()?;
+        let peak_reserved_amount = db_connection.query_row(
+            "WITH overlapping AS (
+                 SELECT start_time, end_time, reservation_amount
+                 FROM user_reservations
+                 WHERE pool = ?1
+                   AND status NOT IN ('cancelled', 'preempted', 'expired')
+                   AND start_time < ?3 AND end_time > ?2
+             ),
+             change_points AS (
+                 SELECT ?2 AS change_point
+                 UNION
+                 SELECT start_time FROM overlapping WHERE start_time >= ?2
+             )
+             SELECT COALESCE(MAX(usage), 0)
+             FROM (
+                 SELECT (
+                     SELECT COALESCE(SUM(reservation_amount), 0)
+                     FROM overlapping
+                     WHERE start_time <= change_point AND end_time > change_point
+                 ) AS usage
+                 FROM change_points
+             )",
+            params![pool, start_time, end_time],
+            |peak_row| peak_row.get(0),
+        )?;
+        Ok(peak_reserved_amount)
+    }
+
+    fn add_maintenance_window(&self, new_window: &MaintenanceParams) -> Result<MaintenanceWindow> {
+        let db_connection = self.connection()?;
+        // Represent "all capacity" with a missing reduction amount.
+        let reduction_amount: Option<u32> = match new_window.reduction {
+            CapacityReduction::All => None,
+            CapacityReduction::Amount(reduction) => Some(reduction),
+        };
+        let window_id: u32 = db_connection.query_row(
+            "INSERT INTO maintenance_windows
+                          (pool, start_time, end_time, reduction_amount, reason)
+                          VALUES (?1, ?2, ?3, ?4, ?5)
+                          RETURNING id",
+            params![
+                new_window.pool,
+                new_window.start_time,
+                new_window.end_time,
+                reduction_amount,
+                new_window.reason,
+            ],
+            |inserted_row| inserted_row.get(0),
+        )?;
+        let added_window = MaintenanceWindow {
+            window_id,
+            pool: new_window.pool.clone(),
+            start_time: new_window.start_time,
+            end_time: new_window.end_time,
+            reduction: new_window.reduction,
+            reason: new_window.reason.clone(),
+        };
+        info!("Added {} to DB", added_window);
+        Ok(added_window)
+    }
+
+    fn get_maintenance_windows(&self) -> Result<Vec<MaintenanceWindow>> {
+        let db_connection = self.connection()?;
+        let mut statement = db_connection.prepare(
+            "SELECT id, pool, start_time, end_time, reduction_amount, reason
+             FROM maintenance_windows
+             ORDER BY start_time, id",
+        )?;
+        let maintenance_windows = statement
+            .query_map([], maintenance_window_from_row)?
+            .collect::<rusqlite::Result<Vec<MaintenanceWindow>>>()?;
+        Ok(maintenance_windows)
+    }
+
+    // The overlap is found by the database with the maintenance windows' pool and time index.
+    fn get_overlapping_maintenance_windows(
+        &self,
+        pool: &str,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<MaintenanceWindow>> {
+        let db_connection = self.connection()?;
+        query_overlapping_maintenance_windows(&db_connection, pool, start_time, end_time)
+    }
+
+    fn delete_maintenance_window(&self, window_id: u32) -> Result<bool> {
+        let db_connection = self.connection()?;
+        let deleted_count = db_connection.execute(
+            "DELETE FROM maintenance_windows WHERE id = ?1",
+            params![window_id],
+        )?;
+        info!("Deleted maintenance window \"{}\" from DB", window_id);
+        Ok(deleted_count > 0)
+    }
+}
+
+/// Columns of `user_reservations` in the order that `reservation_from_row()` reads them.
+const RESERVATION_COLUMNS: &str = "id, start_time, end_time, reservation_amount, user_id, pool, \
+     status, created_at, updated_at, cost_microcredits, refund_microcredits";
+
+/// Booking that's kept in a write transaction on the shared connection.
+struct SqliteBooking<'a> {
+    db_connection: MutexGuard<'a, Connection>,
+    is_committed: bool,
+}
+
+impl Booking for SqliteBooking<'_> {
+    fn get_schedule(&mut self) -> Result<CapacitySchedule> {
+        query_schedule(&self.db_connection)
+    }
+
+    fn get_overlapping_maintenance_windows(
+        &mut self,
+        pool: &str,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<MaintenanceWindow>> {
+        query_overlapping_maintenance_windows(&self.db_connection, pool, start_time, end_time)
+    }
+
+    fn get_overlapping_reservations(
+        &mut self,
+        pool: &str,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<Reservation>> {
+        query_overlapping_reservations(&self.db_connection, pool, start_time, end_time)
+    }
+
+    fn get_price_quote(&mut self, quote_token: &str) -> Result<Option<PriceQuote>> {
+        query_price_quote(&self.db_connection, quote_token)
+    }
+
+    fn add_user_reservation(
+        &mut self,
+        new_reservation: &ReservationRequest,
+        created_at: u32,
+        cost_microcredits: u64,
+    ) -> Result<Reservation> {
+        insert_user_reservation(
+            &self.db_connection,
+            new_reservation,
+            created_at,
+            cost_microcredits,
+        )
+    }
+
+    fn add_quoted_reservation(
+        &mut self,
+        new_reservation: &ReservationRequest,
+        created_at: u32,
+        cost_microcredits: u64,
+        quote_token: &str,
+    ) -> Result<Option<Reservation>> {
+        if !redeem_price_quote(&self.db_connection, quote_token, created_at)? {
+            return Ok(None);
+        }
+        insert_user_reservation(
+            &self.db_connection,
+            new_reservation,
+            created_at,
+            cost_microcredits,
+        )
+        .map(Some)
+    }
+
+    fn record_change_event(&mut self, change_params: &ChangeEventParams) -> Result<ChangeEvent> {
+        insert_change_event(&self.db_connection, change_params)
+    }
+
+    fn commit(mut self: Box<Self>) -> Result<()> {
+        self.db_connection.execute_batch("COMMIT")?;
+        self.is_committed = true;
+        Ok(())
+    }
+}
+
+// Bookings that weren't committed are rolled back before the connection is handed to anyone else.
+impl Drop for SqliteBooking<'_> {
+    fn drop(&mut self) {
+        if self.is_committed {
+            return;
+        }
+        if let Err(rollback_error) = self.db_connection.execute_batch("ROLLBACK") {
+            error!("Failed to roll back booking: {}", rollback_error);
+        }
+    }
+}
+
+/// Record a change event.
+fn insert_change_event(
+    db_connection: &Connection,
+    change_params: &ChangeEventParams,
+) -> Result<ChangeEvent> {
+    let mut statement = db_connection.prepare(
+        "INSERT INTO change_events (event_kind, recorded_at, pool, user_id, payload)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         RETURNING id, event_kind, recorded_at, pool, user_id, payload",
+    )?;
+    let mut inserted_rows = statement.query(params![
+        change_params.kind.as_str(),
+        change_params.recorded_at,
+        change_params.pool,
+        change_params.user_id,
+        change_params.payload.to_string(),
+    ])?;
+    let change_event = match inserted_rows.next()? {
+        Some(inserted_row) => change_event_from_row(inserted_row)?,
+        None => bail!("Change event wasn't recorded"),
+    };
+    debug!("Recorded {} in DB", change_event);
+    Ok(change_event)
+}
+
+/// Get the capacity schedule of every pool.
+fn query_schedule(db_connection: &Connection) -> Result<CapacitySchedule> {
+    let mut statement = db_connection
+        .prepare("SELECT start_time, end_time, capacity_amount, pool, microcredits_per_unit_second FROM capacity_schedule")?;
+    let segments = statement
+        .query_map([], |query_row| {
+            let pool: String = query_row.get(3)?;
+            Ok(
+                CapacitySegment::new(query_row.get(0)?, query_row.get(1)?, query_row.get(2)?)
+                    .in_pool(&pool)
+                    .priced_at(query_row.get::<_, i64>(4)? as u64),
+            )
+        })?
+        .collect::<rusqlite::Result<Vec<CapacitySegment>>>()?;
+    Ok(CapacitySchedule { segments })
+}
+
+/// Get a price quote by its token, whether it's been redeemed or not.
+fn query_price_quote(db_connection: &Connection, quote_token: &str) -> Result<Option<PriceQuote>> {
+    let mut statement = db_connection.prepare(
+        "SELECT quote_token, start_time, end_time, capacity_amount, user_id, pool,
+                base_cost_microcredits, price_percent, price_microcredits, quoted_at, expires_at, redeemed_at
+         FROM price_quotes
+         WHERE quote_token = ?1",
+    )?;
+    let mut query_rows = statement.query(params![quote_token])?;
+    let Some(query_row) = query_rows.next()? else {
+        return Ok(None);
+    };
+    Ok(Some(PriceQuote {
+        quote_token: query_row.get(0)?,
+        start_time: query_row.get(1)?,
+        end_time: query_row.get(2)?,
+        capacity_amount: query_row.get(3)?,
+        user_id: query_row.get(4)?,
+        pool: query_row.get(5)?,
+        base_cost_microcredits: query_row.get::<_, i64>(6)? as u64,
+        price_percent: query_row.get(7)?,
+        price_microcredits: query_row.get::<_, i64>(8)? as u64,
+        quoted_at: query_row.get(9)?,
+        expires_at: query_row.get(10)?,
+        redeemed_at: query_row.get(11)?,
+    }))
+}
+
+/// Get user reservations in a pool that overlap a timeframe.
+fn query_overlapping_reservations(
+    db_connection: &Connection,
+    pool: &str,
+    start_time: u32,
+    end_time: u32,
+) -> Result<Vec<Reservation>> {
+    let mut statement = db_connection.prepare(&format!(
+        "SELECT {RESERVATION_COLUMNS}
+         FROM user_reservations
+         WHERE pool = ?1 AND start_time < ?3 AND end_time > ?2"
+    ))?;
+    let mut query_rows = statement.query(params![pool, start_time, end_time])?;
+    let mut user_reservations = Vec::new();
+    while let Some(query_row) = query_rows.next()? {
+        user_reservations.push(reservation_from_row(query_row)?)
+    }
+    Ok(user_reservations)
+}
+
+/// Get maintenance windows in a pool that overlap a timeframe, earliest first.
+fn query_overlapping_maintenance_windows(
+    db_connection: &Connection,
+    pool: &str,
+    start_time: u32,
+    end_time: u32,
+) -> Result<Vec<MaintenanceWindow>> {
+    let mut statement = db_connection.prepare(
+        "SELECT id, pool, start_time, end_time, reduction_amount, reason
+         FROM maintenance_windows
+         WHERE pool = ?1 AND start_time < ?3 AND end_time > ?2
+         ORDER BY start_time, id",
+    )?;
+    let maintenance_windows = statement
+        .query_map(
+            params![pool, start_time, end_time],
+            maintenance_window_from_row,
+        )?
+        .collect::<rusqlite::Result<Vec<MaintenanceWindow>>>()?;
+    Ok(maintenance_windows)
+}
+
+/// Read a reservation out of a `user_reservations` row.
+fn reservation_from_row(query_row: &Row) -> Result<Reservation> {
+    let status: String = query_row.get(6)?;
+    Ok(Reservation {
+        reservation_id: query_row.get(0)?,
+        start_time: query_row.get(1)?,
+        end_time: query_row.get(2)?,
+        capacity_amount: query_row.get(3)?,
+        user_id: query_row.get(4)?,
+        pool: query_row.get(5)?,
+        status: status.parse()?,
+        created_at: query_row.get(7)?,
+        updated_at: query_row.get(8)?,
+        cost_microcredits: query_row.get::<_, i64>(9)? as u64,
+        refund_microcredits: query_row.get::<_, i64>(10)? as u64,
+    })
+}
+
+/// Add a reservation and record its approval in the ledger.
+///
+/// # Arguments
+/// - `db_connection`: Transaction that the reservation is added with.
+/// - `new_reservation`: Reservation request that was approved.
+/// - `created_at`: When the reservation was approved, represented by Unix epoch format.
+/// - `cost_microcredits`: What the reservation costs.
+fn insert_user_reservation(
+    db_connection: &Connection,
+    new_reservation: &ReservationRequest,
+    created_at: u32,
+    cost_microcredits: u64,
+) -> Result<Reservation> {
+    let status = ReservationStatus::Pending;
+    let reservation_id: u32 = db_connection.query_row(
+        "INSERT INTO user_reservations
+                      (start_time, end_time, reservation_amount, user_id, pool, status, created_at, updated_at,
+                       cost_microcredits)
+                      VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7, ?8)
+                      RETURNING id",
+        params![
+            new_reservation.start_time,
+            new_reservation.end_time,
+            new_reservation.capacity_amount,
+            new_reservation.user_id,
+            new_reservation.pool,
+            status.as_str(),
+            created_at,
+            db_bigint(cost_microcredits, "cost_microcredits")?,
+        ],
+        |inserted_row| inserted_row.get(0),
+    )?;
+    append_ledger_event(
+        db_connection,
+        Some(reservation_id),
+        LedgerEventKind::Approved,
+        new_reservation,
+        created_at,
+        None,
+        Charges {
+            cost_microcredits,
+            refund_microcredits: 0,
+        },
+    )?;
+    let added_reservation = Reservation {
+        reservation_id,
+        start_time: new_reservation.start_time,
+        end_time: new_reservation.end_time,
+        capacity_amount: new_reservation.capacity_amount,
+        user_id: new_reservation.user_id,
+        pool: new_reservation.pool.clone(),
+        status,
+        created_at,
+        updated_at: created_at,
+        cost_microcredits,
+        refund_microcredits: 0,
+    };
+    info!("Added reservation to DB: {}", added_reservation);
+    Ok(added_reservation)
+}
+
+/// Mark a price quote as redeemed, unless it's already been redeemed or it's expired.
+///
+/// Checking and marking the quote in one statement keeps it from being redeemed twice.
+///
+/// # Returns
+/// Whether the quote was redeemed.
+fn redeem_price_quote(
+    db_connection: &Connection,
+    quote_token: &str,
+    redeemed_at: u32,
+) -> Result<bool> {
+    let updated_count = db_connection.execute(
+        "UPDATE price_quotes
+         SET redeemed_at = ?2
+         WHERE quote_token = ?1 AND redeemed_at IS NULL AND expires_at > ?2",
+        params![quote_token, redeemed_at],
+    )?;
+    Ok(updated_count > 0)
+}
+
+/// Append an event to the reservation ledger.
+///
+/// # Arguments
+/// - `db_connection`: Connection or transaction that the event is recorded with.
+/// - `reservation_id`: Reservation that changed, if the request became one.
+/// - `kind`: Kind of change.
+/// - `reservation_terms`: Reservation's terms after the change.
+/// - `recorded_at`: When the change happened, represented by Unix epoch format.
+/// - `reason`: Why the change happened.
+/// - `charges`: What the reservation cost and got back after the change.
+fn append_ledger_event(
+    db_connection: &Connection,
+    reservation_id: Option<u32>,
+    kind: LedgerEventKind,
+    reservation_terms: &ReservationRequest,
+    recorded_at: u32,
+    reason: Option<&str>,
+    charges: Charges,
+) -> Result<()> {
+    db_connection.execute(
+        "INSERT INTO reservation_ledger
+                      (reservation_id, event_kind, recorded_at, start_time, end_time, capacity_amount, user_id, pool, reason,
+                       cost_microcredits, refund_microcredits)
+                      VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            reservation_id,
+            kind.as_str(),
+            recorded_at,
+            reservation_terms.start_time,
+            reservation_terms.end_time,
+            reservation_terms.capacity_amount,
+            reservation_terms.user_id,
+            reservation_terms.pool,
+            reason,
+            db_bigint(charges.cost_microcredits, "cost_microcredits")?,
+            db_bigint(charges.refund_microcredits, "refund_microcredits")?,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Read an evaluated request out of a `reservation_requests` row.
+fn evaluated_request_from_row(query_row: &Row) -> Result<EvaluatedRequest> {
+    let outcome: String = query_row.get(7)?;
+    let bottleneck_start: Option<u32> = query_row.get(10)?;
+    let bottleneck_end: Option<u32> = query_row.get(11)?;
+    let bottleneck_available_amount: Option<u32> = query_row.get(12)?;
+    let bottleneck = match (
+        bottleneck_start,
+        bottleneck_end,
+        bottleneck_available_amount,
+    ) {
+        (Some(start_time), Some(end_time), Some(available_amount)) => Some(Bottleneck {
+            start_time,
+            end_time,
+            available_amount,
+        }),
+        _ => None,
+    };
+    Ok(EvaluatedRequest {
+        request_id: query_row.get(0)?,
+        requested_at: query_row.get(1)?,
+        start_time: query_row.get(2)?,
+        end_time: query_row.get(3)?,
+        capacity_amount: query_row.get(4)?,
+        user_id: query_row.get(5)?,
+        pool: query_row.get(6)?,
+        outcome: outcome.parse()?,
+        denial_reason: query_row.get(8)?,
+        reservation_id: query_row.get(9)?,
+        bottleneck,
+    })
+}
+
+/// Read a change event out of a `change_events` row.
+fn change_event_from_row(query_row: &Row) -> Result<ChangeEvent> {
+    let event_kind: String = query_row.get(1)?;
+    let payload: String = query_row.get(5)?;
+    Ok(ChangeEvent {
+        event_id: query_row.get(0)?,
+        kind: event_kind.parse()?,
+        recorded_at: query_row.get(2)?,
+        pool: query_row.get(3)?,
+        user_id: query_row.get(4)?,
+        payload: serde_json::from_str(&payload)?,
+    })
+}
+
+/// Read a webhook subscription out of a `webhook_subscriptions` row.
+fn webhook_subscription_from_row(query_row: &Row) -> Result<WebhookSubscription> {
+    let event_kinds: String = query_row.get(3)?;
+    Ok(WebhookSubscription {
+        subscription_id: query_row.get(0)?,
+        url: query_row.get(1)?,
+        secret: query_row.get(2)?,
+        event_kinds: split_event_kinds(&event_kinds)?,
+        pool: query_row.get(4)?,
+        user_id: query_row.get(5)?,
+        last_event_id: query_row.get(6)?,
+        created_at: query_row.get(7)?,
+    })
+}
+
+/// Read a webhook delivery out of a `webhook_deliveries` row.
+fn webhook_delivery_from_row(query_row: &Row) -> rusqlite::Result<WebhookDelivery> {
+    Ok(WebhookDelivery {
+        delivery_id: query_row.get(0)?,
+        subscription_id: query_row.get(1)?,
+        event_id: query_row.get(2)?,
+        attempt: query_row.get(3)?,
+        attempted_at: query_row.get(4)?,
+        status_code: query_row.get(5)?,
+        error: query_row.get(6)?,
+        is_delivered: query_row.get(7)?,
+    })
+}
+
+/// Fit a value into an `INTEGER` column, like an amount of money.
+///
+/// SQLite integers are signed, so values that are too big are refused instead of wrapping around
+/// to negative numbers.
+fn db_bigint(value: u64, column: &str) -> Result<i64> {
+    i64::try_from(value)
+        .with_context(|| format!("Value \"{value}\" is too big for column \"{column}\""))
+}
+
+/// Read a maintenance window out of a `maintenance_windows` row.
+fn maintenance_window_from_row(query_row: &Row) -> rusqlite::Result<MaintenanceWindow> {
+    let reduction_amount: Option<u32> = query_row.get(4)?;
+    Ok(MaintenanceWindow {
+        window_id: query_row.get(0)?,
+        pool: query_row.get(1)?,
+        start_time: query_row.get(2)?,
+        end_time: query_row.get(3)?,
+        reduction: match reduction_amount {
+            Some(reduction) => CapacityReduction::Amount(reduction),
+            None => CapacityReduction::All,
+        },
+        reason: query_row.get(5)?,
+    })
+}
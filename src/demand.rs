@@ -0,0 +1,365 @@
+//! Request History
+//!
+//! `demand` keeps every reservation request that the hostess evaluates, whether it's granted or
+//! not, so BI, marketing, and SRE folks can see how much capacity users wanted and didn't get.
+//! Unmet demand is what tells us what to include in the next datacenter that we build.
+
+// Standard library crates.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+// External crates.
+use anyhow::{anyhow, ensure, Error, Result};
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use serde_derive::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+// Project crates.
+use crate::common::AvailabilitySlot;
+use crate::ReservationRequest;
+
+/// How the hostess answered a reservation request.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestOutcome {
+    /// Request was granted and became a reservation.
+    Approved,
+    /// Request was refused.
+    Denied,
+}
+
+impl RequestOutcome {
+    /// Name of the outcome as it's stored in the datastore.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RequestOutcome::Approved => "approved",
+            RequestOutcome::Denied => "denied",
+        }
+    }
+}
+
+// Read outcomes back out of the datastore and query strings.
+impl FromStr for RequestOutcome {
+    type Err = Error;
+
+    fn from_str(outcome_name: &str) -> Result<Self, Self::Err> {
+        match outcome_name {
+            "approved" => Ok(RequestOutcome::Approved),
+            "denied" => Ok(RequestOutcome::Denied),
+            unknown_outcome => Err(anyhow!("Unknown request outcome \"{unknown_outcome}\"")),
+        }
+    }
+}
+
+// Print instantiated enum nicely.
+impl fmt::Display for RequestOutcome {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+/// Stretch of time that had the least capacity left during a requested timeframe.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct Bottleneck {
+    pub start_time: u32,
+    pub end_time: u32,
+    pub available_amount: u32,
+}
+
+impl From<&AvailabilitySlot> for Bottleneck {
+    fn from(slot: &AvailabilitySlot) -> Self {
+        Self {
+            start_time: slot.start_time,
+            end_time: slot.end_time,
+            available_amount: slot.available_amount,
+        }
+    }
+}
+
+/// A reservation request that the hostess evaluated, before it's recorded.
+pub struct RequestEvaluation {
+    /// Request as the user made it.
+    pub reservation_request: ReservationRequest,
+    /// When the request was evaluated, represented by Unix epoch format.
+    pub requested_at: u32,
+    pub outcome: RequestOutcome,
+    /// Why the request was refused.
+    pub denial_reason: Option<String>,
+    /// Reservation that the request became, if it was granted.
+    pub reservation_id: Option<u32>,
+    /// Least capacity left during the requested timeframe, if capacity was considered.
+    pub bottleneck: Option<Bottleneck>,
+}
+
+impl RequestEvaluation {
+    /// Start evaluating a request, which stays denied unless it's approved.
+    pub fn new(reservation_request: &ReservationRequest, requested_at: u32) -> Self {
+        Self {
+            reservation_request: reservation_request.clone(),
+            requested_at,
+            outcome: RequestOutcome::Denied,
+            denial_reason: None,
+            reservation_id: None,
+            bottleneck: None,
+        }
+    }
+}
+
+/// A reservation request that's been recorded in the request history.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct EvaluatedRequest {
+    pub request_id: u32,
+    /// When the request was evaluated, represented by Unix epoch format.
+    pub requested_at: u32,
+    pub start_time: u32,
+    pub end_time: u32,
+    pub capacity_amount: u32,
+    pub user_id: u32,
+    pub pool: String,
+    pub outcome: RequestOutcome,
+    /// Why the request was refused.
+    pub denial_reason: Option<String>,
+    /// Reservation that the request became, if it was granted.
+    pub reservation_id: Option<u32>,
+    /// Least capacity left during the requested timeframe, if capacity was considered.
+    pub bottleneck: Option<Bottleneck>,
+}
+
+impl EvaluatedRequest {
+    /// Capacity that was asked for, multiplied by how many seconds it was asked for.
+    pub fn unit_seconds(&self) -> u64 {
+        u64::from(self.capacity_amount) * u64::from(self.end_time.saturating_sub(self.start_time))
+    }
+}
+
+// Print instantiated struct nicely.
+impl fmt::Display for EvaluatedRequest {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "{} request \"{}\" for user ID \"{}\" \
+            of \"{}\" capacity \
+            in pool \"{}\" \
+            from \"{}\" to \"{}\"",
+            self.outcome,
+            self.request_id,
+            self.user_id,
+            self.capacity_amount,
+            self.pool,
+            self.start_time,
+            self.end_time
+        )
+    }
+}
+
+/// Which evaluated requests to look at.
+///
+/// Every filter is optional. Requests match the timeframe if the time they asked for overlaps it.
+#[derive(Clone, Debug, Default, Deserialize, IntoParams)]
+#[serde(default, deny_unknown_fields)]
+#[into_params(parameter_in = Query)]
+pub struct RequestFilter {
+    pub pool: Option<String>,
+    pub user_id: Option<u32>,
+    pub start_time: Option<u32>,
+    pub end_time: Option<u32>,
+    pub outcome: Option<RequestOutcome>,
+}
+
+/// Ways that unmet demand can be grouped.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DemandDimension {
+    /// Bucket of time that the requested timeframe starts in, like a day.
+    Time,
+    Pool,
+    User,
+}
+
+// Read dimensions out of query strings.
+impl FromStr for DemandDimension {
+    type Err = Error;
+
+    fn from_str(dimension_name: &str) -> Result<Self, Self::Err> {
+        match dimension_name {
+            "time" => Ok(DemandDimension::Time),
+            "pool" => Ok(DemandDimension::Pool),
+            "user" => Ok(DemandDimension::User),
+            unknown_dimension => Err(anyhow!(
+                "Unknown demand grouping \"{unknown_dimension}\", expected \"time\", \"pool\", or \"user\""
+            )),
+        }
+    }
+}
+
+/// Denied requests that share a bucket of time, pool, and user (or whichever of those they're
+/// grouped by).
+///
+/// Dimensions that aren't grouped by are left out.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct UnmetDemand {
+    /// Start of the bucket of time that the requested timeframes start in, represented by Unix
+    /// epoch format.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub period_start: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<u32>,
+    /// Number of requests that were denied.
+    pub denied_requests: u32,
+    /// Sum of the capacity that the denied requests asked for.
+    pub denied_capacity_amount: u64,
+    /// Sum of the capacity that the denied requests asked for, multiplied by their durations.
+    pub denied_unit_seconds: u64,
+}
+
+/// Bucket, pool, and user that denied requests are grouped under.
+type DemandKey = (Option<u32>, Option<String>, Option<u32>);
+
+/// Add up denied requests by the given dimensions.
+///
+/// # Arguments
+/// - `evaluated_requests`: Requests to add up. Approved requests are skipped.
+/// - `group_by`: Dimensions to group by. Everything's added up together if it's empty.
+/// - `bucket_length`: How long the buckets of time that requests are grouped into are, like a
+///   day. Buckets line up with the Unix epoch, so days start at midnight UTC.
+///
+/// # Returns
+/// Unmet demand, ordered by bucket, then pool, then user.
+pub fn unmet_demand(
+    evaluated_requests: &[EvaluatedRequest],
+    group_by: &[DemandDimension],
+    bucket_length: Duration,
+) -> Result<Vec<UnmetDemand>> {
+    let bucket_length = u32::try_from(bucket_length.as_secs()).unwrap_or(u32::MAX);
+    ensure!(
+        bucket_length > 0,
+        "Demand buckets must be at least a second long"
+    );
+    let mut demand_groups: BTreeMap<DemandKey, UnmetDemand> = BTreeMap::new();
+    for evaluated_request in evaluated_requests
+        .iter()
+        .filter(|evaluated_request| evaluated_request.outcome == RequestOutcome::Denied)
+    {
+        let period_start = group_by
+            .contains(&DemandDimension::Time)
+            .then(|| evaluated_request.start_time - evaluated_request.start_time % bucket_length);
+        let pool = group_by
+            .contains(&DemandDimension::Pool)
+            .then(|| evaluated_request.pool.clone());
+        let user_id = group_by
+            .contains(&DemandDimension::User)
+            .then_some(evaluated_request.user_id);
+        let demand_group = demand_groups
+            .entry((period_start, pool.clone(), user_id))
+            .or_insert_with(|| UnmetDemand {
+                period_start,
+                pool,
+                user_id,
+                ..Default::default()
+            });
+        demand_group.denied_requests += 1;
+        demand_group.denied_capacity_amount += u64::from(evaluated_request.capacity_amount);
+        demand_group.denied_unit_seconds += evaluated_request.unit_seconds();
+    }
+    Ok(demand_groups.into_values().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    // Standard library crates.
+    use std::time::Duration;
+
+    // Project crates.
+    use super::{unmet_demand, DemandDimension, EvaluatedRequest, RequestOutcome};
+
+    /// One day, which unmet demand is usually grouped by.
+    const ONE_DAY: Duration = Duration::from_secs(86400);
+
+    /// Evaluated request for an hour starting on Schedule 1's first day.
+    fn test_request(
+        request_id: u32,
+        pool: &str,
+        user_id: u32,
+        outcome: RequestOutcome,
+    ) -> EvaluatedRequest {
+        EvaluatedRequest {
+            request_id,
+            requested_at: 1707078608,
+            start_time: 1707165008,
+            end_time: 1707168608,
+            capacity_amount: 8,
+            user_id,
+            pool: String::from(pool),
+            outcome,
+            denial_reason: None,
+            reservation_id: None,
+            bottleneck: None,
+        }
+    }
+
+    #[test]
+    fn test_unmet_demand_by_pool_and_user() {
+        let evaluated_requests = vec![
+            test_request(1, "default", 42, RequestOutcome::Denied),
+            test_request(2, "default", 42, RequestOutcome::Denied),
+            test_request(3, "default", 43, RequestOutcome::Denied),
+            test_request(4, "gpu", 42, RequestOutcome::Denied),
+            test_request(5, "gpu", 42, RequestOutcome::Approved),
+        ];
+        let demand = unmet_demand(
+            &evaluated_requests,
+            &[DemandDimension::Pool, DemandDimension::User],
+            ONE_DAY,
+        )
+        .unwrap();
+        assert_eq!(demand.len(), 3);
+        assert_eq!(demand[0].pool.as_deref(), Some("default"));
+        assert_eq!(demand[0].user_id, Some(42));
+        assert_eq!(demand[0].denied_requests, 2);
+        assert_eq!(demand[0].denied_capacity_amount, 16);
+        assert_eq!(demand[0].denied_unit_seconds, 16 * 3600);
+        assert_eq!(demand[0].period_start, None);
+        // Approved requests aren't unmet demand.
+        assert_eq!(demand[2].pool.as_deref(), Some("gpu"));
+        assert_eq!(demand[2].denied_requests, 1);
+    }
+
+    #[test]
+    fn test_unmet_demand_by_time() {
+        let next_day = EvaluatedRequest {
+            start_time: 1707251408,
+            end_time: 1707255008,
+            ..test_request(2, "default", 42, RequestOutcome::Denied)
+        };
+        let evaluated_requests = vec![
+            test_request(1, "default", 42, RequestOutcome::Denied),
+            next_day,
+        ];
+        let demand = unmet_demand(&evaluated_requests, &[DemandDimension::Time], ONE_DAY).unwrap();
+        assert_eq!(demand.len(), 2);
+        assert_eq!(demand[0].period_start, Some(1707091200));
+        assert_eq!(demand[1].period_start, Some(1707177600));
+        // Both requests start in the same week.
+        let one_week = Duration::from_secs(7 * 86400);
+        let demand = unmet_demand(&evaluated_requests, &[DemandDimension::Time], one_week).unwrap();
+        assert_eq!(demand.len(), 1);
+        assert_eq!(demand[0].period_start, Some(1706745600));
+        assert_eq!(demand[0].denied_requests, 2);
+        // Without any dimensions, everything's added up together.
+        let demand = unmet_demand(&evaluated_requests, &[], ONE_DAY).unwrap();
+        assert_eq!(demand.len(), 1);
+        assert_eq!(demand[0].denied_requests, 2);
+        // Buckets have to have some length.
+        assert!(unmet_demand(
+            &evaluated_requests,
+            &[DemandDimension::Time],
+            Duration::ZERO
+        )
+        .is_err());
+    }
+}
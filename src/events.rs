@@ -0,0 +1,463 @@
+//! Change Events
+//!
+//! `events` tells dashboards what changed as soon as it changes, so they don't have to poll.
+//!
+//! The hostess records a change event whenever a reservation is created, cancelled, started, or
+//! ended, whenever a pool's schedule changes (like maintenance or prices), and whenever the
+//! busiest moment of a changed timeframe crosses one of the configured utilization thresholds.
+//! Events are kept in the `change_events` table with IDs that only count up, so followers can pick
+//! up where they left off by naming the last event that they saw, even after Arbiter restarts.
+
+// Standard library crates.
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+// External crates.
+use anyhow::{anyhow, Error, Result};
+use futures_util::stream::{self, Stream};
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use serde::Serialize as SerializeJson;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::watch;
+use utoipa::ToSchema;
+
+// Project crates.
+use crate::common::{AvailabilitySlot, Reservation};
+use crate::hostess::Hostess;
+
+/// Most change events that are read from the datastore at once.
+pub const CHANGE_PAGE_SIZE: u32 = 100;
+
+/// How often followers check for changes that they weren't told about, like ones made by another
+/// Arbiter that shares the datastore.
+const CHANGE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Kinds of changes that followers are told about.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeEventKind {
+    /// Reservation was granted.
+    ReservationCreated,
+    /// Reservation was given back before it ended.
+    ReservationCancelled,
+    /// Pool's capacity changed, like maintenance being scheduled or a segment's price changing.
+    ScheduleChanged,
+    /// Busiest moment of a changed timeframe became busier or quieter than a threshold.
+    UtilizationThresholdCrossed,
+    /// Reservation's start time arrived.
+    ReservationStarted,
+    /// Reservation's end time arrived.
+    ReservationEnded,
+    /// Reservation was taken away to make room for something more important.
+    ReservationPreempted,
+    /// Request that was waiting for capacity got booked.
+    WaitlistBooked,
+}
+
+impl ChangeEventKind {
+    /// Name of the event kind as it's stored in the datastore and sent to followers.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeEventKind::ReservationCreated => "reservation_created",
+            ChangeEventKind::ReservationCancelled => "reservation_cancelled",
+            ChangeEventKind::ScheduleChanged => "schedule_changed",
+            ChangeEventKind::UtilizationThresholdCrossed => "utilization_threshold_crossed",
+            ChangeEventKind::ReservationStarted => "reservation_started",
+            ChangeEventKind::ReservationEnded => "reservation_ended",
+            ChangeEventKind::ReservationPreempted => "reservation_preempted",
+            ChangeEventKind::WaitlistBooked => "waitlist_booked",
+        }
+    }
+}
+
+// Read event kinds back out of the datastore.
+impl FromStr for ChangeEventKind {
+    type Err = Error;
+
+    fn from_str(kind_name: &str) -> Result<Self, Self::Err> {
+        match kind_name {
+            "reservation_created" => Ok(ChangeEventKind::ReservationCreated),
+            "reservation_cancelled" => Ok(ChangeEventKind::ReservationCancelled),
+            "schedule_changed" => Ok(ChangeEventKind::ScheduleChanged),
+            "utilization_threshold_crossed" => Ok(ChangeEventKind::UtilizationThresholdCrossed),
+            "reservation_started" => Ok(ChangeEventKind::ReservationStarted),
+            "reservation_ended" => Ok(ChangeEventKind::ReservationEnded),
+            "reservation_preempted" => Ok(ChangeEventKind::ReservationPreempted),
+            "waitlist_booked" => Ok(ChangeEventKind::WaitlistBooked),
+            unknown_kind => Err(anyhow!("Unknown change event kind \"{unknown_kind}\"")),
+        }
+    }
+}
+
+// Print instantiated enum nicely.
+impl fmt::Display for ChangeEventKind {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+/// Change that's about to be recorded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChangeEventParams {
+    pub kind: ChangeEventKind,
+    /// When the change happened, represented by Unix epoch format.
+    pub recorded_at: u32,
+    /// Pool that changed.
+    pub pool: String,
+    /// User whose reservation changed, if the change was to a reservation.
+    pub user_id: Option<u32>,
+    /// What changed, like the reservation after the change.
+    pub payload: Value,
+}
+
+impl ChangeEventParams {
+    /// Change to a reservation, with the reservation as it was after the change.
+    pub fn reservation(
+        kind: ChangeEventKind,
+        reservation: &Reservation,
+        recorded_at: u32,
+    ) -> Result<Self> {
+        Ok(Self {
+            kind,
+            recorded_at,
+            pool: reservation.pool.clone(),
+            user_id: Some(reservation.user_id),
+            payload: serde_json::to_value(reservation)?,
+        })
+    }
+
+    /// Change to a pool's schedule.
+    ///
+    /// # Arguments
+    /// - `change`: What kind of schedule change it was, like `"maintenance_scheduled"`.
+    /// - `details`: What changed, like the maintenance window.
+    pub fn schedule(
+        pool: &str,
+        change: &str,
+        details: &impl SerializeJson,
+        recorded_at: u32,
+    ) -> Result<Self> {
+        Ok(Self {
+            kind: ChangeEventKind::ScheduleChanged,
+            recorded_at,
+            pool: pool.to_string(),
+            user_id: None,
+            payload: serde_json::json!({ "change": change, "details": details }),
+        })
+    }
+
+    /// Utilization threshold that a pool's timeframe crossed.
+    pub fn threshold_crossed(
+        pool: &str,
+        threshold_crossing: &ThresholdCrossing,
+        recorded_at: u32,
+    ) -> Result<Self> {
+        Ok(Self {
+            kind: ChangeEventKind::UtilizationThresholdCrossed,
+            recorded_at,
+            pool: pool.to_string(),
+            user_id: None,
+            payload: serde_json::to_value(threshold_crossing)?,
+        })
+    }
+}
+
+/// Change that was recorded.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct ChangeEvent {
+    /// Order that the change was recorded in. Followers resume after the last one they saw.
+    pub event_id: u32,
+    pub kind: ChangeEventKind,
+    /// When the change happened, represented by Unix epoch format.
+    pub recorded_at: u32,
+    /// Pool that changed.
+    pub pool: String,
+    /// User whose reservation changed, if the change was to a reservation.
+    pub user_id: Option<u32>,
+    /// What changed: the reservation for reservation events, the schedule change for schedule
+    /// events, and the crossing for utilization events.
+    #[schema(value_type = Object)]
+    pub payload: Value,
+}
+
+// Print instantiated struct nicely.
+impl fmt::Display for ChangeEvent {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "change event \"{}\" ({}) on \"{}\" in pool \"{}\"",
+            self.event_id, self.kind, self.recorded_at, self.pool
+        )
+    }
+}
+
+/// Which changes a follower is told about.
+///
+/// Filters that are missing match everything. Changes that aren't to anyone's reservation (like
+/// schedule changes) match every user.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChangeFilter {
+    pub pool: Option<String>,
+    pub user_id: Option<u32>,
+}
+
+/// Whether a timeframe got busier or quieter than a threshold.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrossingDirection {
+    Rising,
+    Falling,
+}
+
+/// Utilization threshold that a timeframe's busiest moment crossed.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ThresholdCrossing {
+    /// Percent of scheduled capacity that was crossed.
+    pub threshold_percent: u32,
+    pub direction: CrossingDirection,
+    /// Busiest moment's utilization before the change, in percent.
+    pub previous_utilization_percent: u32,
+    /// Busiest moment's utilization after the change, in percent.
+    pub utilization_percent: u32,
+    /// Timeframe that changed, represented by Unix epoch format.
+    pub start_time: u32,
+    pub end_time: u32,
+}
+
+/// Find the percent of scheduled capacity that's reserved or offline at a timeline's busiest
+/// moment.
+///
+/// Slots without scheduled capacity don't count. Pools can be more than 100% utilized if
+/// maintenance takes capacity that was already reserved.
+pub fn peak_utilization_percent(timeline: &[AvailabilitySlot]) -> u32 {
+    timeline
+        .iter()
+        .filter(|slot| slot.scheduled_amount > 0)
+        .map(|slot| {
+            let used_amount = u64::from(slot.reserved_amount) + u64::from(slot.maintenance_amount);
+            (used_amount * 100 / u64::from(slot.scheduled_amount)) as u32
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Find the thresholds that utilization crossed when it changed.
+///
+/// A threshold is crossed when utilization reaches it from below, or drops back below it.
+///
+/// # Arguments
+/// - `thresholds`: Utilization thresholds, in percent.
+/// - `timeframe`: Timeframe that changed, represented by Unix epoch format.
+/// - `previous_utilization_percent`: Utilization before the change.
+/// - `utilization_percent`: Utilization after the change.
+pub fn threshold_crossings(
+    thresholds: &[u32],
+    timeframe: (u32, u32),
+    previous_utilization_percent: u32,
+    utilization_percent: u32,
+) -> Vec<ThresholdCrossing> {
+    thresholds
+        .iter()
+        .filter_map(|&threshold_percent| {
+            let was_over = previous_utilization_percent >= threshold_percent;
+            let is_over = utilization_percent >= threshold_percent;
+            let direction = match (was_over, is_over) {
+                (false, true) => CrossingDirection::Rising,
+                (true, false) => CrossingDirection::Falling,
+                _ => return None,
+            };
+            Some(ThresholdCrossing {
+                threshold_percent,
+                direction,
+                previous_utilization_percent,
+                utilization_percent,
+                start_time: timeframe.0,
+                end_time: timeframe.1,
+            })
+        })
+        .collect()
+}
+
+/// Where a follower is in the stream of changes.
+struct ChangeFollower {
+    hostess: Arc<Hostess>,
+    change_filter: ChangeFilter,
+    change_notices: watch::Receiver<u32>,
+    /// Last event that the follower saw, or `None` until it's worked out where "now" is.
+    last_event_id: Option<u32>,
+    unsent_events: VecDeque<ChangeEvent>,
+}
+
+impl ChangeFollower {
+    /// Wait for the next change that matches the follower's filter.
+    ///
+    /// # Returns
+    /// The change, or `None` if changes can't be read anymore.
+    async fn next_change(&mut self) -> Option<ChangeEvent> {
+        loop {
+            if let Some(change_event) = self.unsent_events.pop_front() {
+                self.last_event_id = Some(change_event.event_id);
+                return Some(change_event);
+            }
+            // Changes that are recorded from here on wake the follower up.
+            self.change_notices.borrow_and_update();
+            let hostess = self.hostess.clone();
+            let change_filter = self.change_filter.clone();
+            let last_event_id = self.last_event_id;
+            // The DB driver blocks, so changes are read on a thread that's allowed to block.
+            let change_page = tokio::task::spawn_blocking(move || {
+                read_changes(&hostess, &change_filter, last_event_id)
+            })
+            .await;
+            match change_page {
+                Ok(Ok((change_events, _))) if !change_events.is_empty() => {
+                    self.unsent_events.extend(change_events);
+                    continue;
+                }
+                Ok(Ok((_, last_change_id))) => {
+                    self.last_event_id.get_or_insert(last_change_id);
+                }
+                Ok(Err(error_message)) => {
+                    error!("Couldn't read change events: {:#}", error_message);
+                    return None;
+                }
+                Err(join_error) => {
+                    error!(
+                        "Hostess didn't finish reading change events: {}",
+                        join_error
+                    );
+                    return None;
+                }
+            }
+            tokio::select! {
+                _ = self.change_notices.changed() => {}
+                _ = tokio::time::sleep(CHANGE_POLL_INTERVAL) => {}
+            }
+        }
+    }
+}
+
+/// Read the changes after the last one that a follower saw.
+///
+/// # Returns
+/// The changes, and the last event that the follower has seen. Followers that haven't seen any
+/// start from the latest change, so they only hear about new ones.
+fn read_changes(
+    hostess: &Hostess,
+    change_filter: &ChangeFilter,
+    last_event_id: Option<u32>,
+) -> Result<(Vec<ChangeEvent>, u32)> {
+    match last_event_id {
+        Some(last_event_id) => Ok((
+            hostess.changes_after(change_filter, last_event_id, CHANGE_PAGE_SIZE)?,
+            last_event_id,
+        )),
+        None => Ok((Vec::new(), hostess.last_change_id()?)),
+    }
+}
+
+/// Follow the changes that match a filter, starting after the last one that the follower saw.
+///
+/// Changes that were already recorded come first, then new ones as they're recorded. Followers
+/// that don't name an event only hear about changes from now on. The stream only ends if changes
+/// can't be read anymore, so followers should resume from the last event that they saw.
+pub fn follow_changes(
+    hostess: Arc<Hostess>,
+    change_filter: ChangeFilter,
+    after_event_id: Option<u32>,
+) -> impl Stream<Item = ChangeEvent> + Send + 'static {
+    let change_follower = ChangeFollower {
+        change_notices: hostess.subscribe_changes(),
+        hostess,
+        change_filter,
+        last_event_id: after_event_id,
+        unsent_events: VecDeque::new(),
+    };
+    stream::unfold(change_follower, |mut change_follower| async move {
+        let change_event = change_follower.next_change().await?;
+        Some((change_event, change_follower))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    // Project crates.
+    use super::{
+        peak_utilization_percent, threshold_crossings, ChangeEventKind, CrossingDirection,
+    };
+    use crate::common::AvailabilitySlot;
+
+    /// Slot of a pool with 100 scheduled capacity.
+    fn test_slot(maintenance_amount: u32, reserved_amount: u32) -> AvailabilitySlot {
+        AvailabilitySlot {
+            start_time: 1707165008,
+            end_time: 1707168608,
+            scheduled_amount: 100,
+            maintenance_amount,
+            reserved_amount,
+            available_amount: (100 - maintenance_amount).saturating_sub(reserved_amount),
+        }
+    }
+
+    #[test]
+    fn test_peak_utilization() {
+        assert_eq!(peak_utilization_percent(&[]), 0);
+        let timeline = vec![test_slot(0, 10), test_slot(20, 50), test_slot(0, 0)];
+        assert_eq!(peak_utilization_percent(&timeline), 70);
+        // Maintenance can take capacity that was already reserved.
+        assert_eq!(peak_utilization_percent(&[test_slot(100, 20)]), 120);
+        // Time outside of the schedule isn't utilized.
+        let unscheduled_slot = AvailabilitySlot {
+            scheduled_amount: 0,
+            ..test_slot(0, 0)
+        };
+        assert_eq!(peak_utilization_percent(&[unscheduled_slot]), 0);
+    }
+
+    #[test]
+    fn test_threshold_crossings() {
+        let timeframe = (1707165008, 1707168608);
+        let rising = threshold_crossings(&[50, 80, 100], timeframe, 40, 80);
+        let crossed: Vec<(u32, CrossingDirection)> = rising
+            .iter()
+            .map(|crossing| (crossing.threshold_percent, crossing.direction))
+            .collect();
+        assert_eq!(
+            crossed,
+            vec![
+                (50, CrossingDirection::Rising),
+                (80, CrossingDirection::Rising)
+            ]
+        );
+        let falling = threshold_crossings(&[50, 80, 100], timeframe, 100, 79);
+        assert_eq!(falling.len(), 2);
+        assert!(falling
+            .iter()
+            .all(|crossing| crossing.direction == CrossingDirection::Falling));
+        // Staying on one side of a threshold doesn't cross it.
+        assert!(threshold_crossings(&[50], timeframe, 60, 90).is_empty());
+        assert!(threshold_crossings(&[50], timeframe, 10, 49).is_empty());
+    }
+
+    #[test]
+    fn test_change_event_kinds_round_trip() {
+        for kind in [
+            ChangeEventKind::ReservationCreated,
+            ChangeEventKind::ReservationCancelled,
+            ChangeEventKind::ScheduleChanged,
+            ChangeEventKind::UtilizationThresholdCrossed,
+            ChangeEventKind::ReservationStarted,
+            ChangeEventKind::ReservationEnded,
+            ChangeEventKind::ReservationPreempted,
+            ChangeEventKind::WaitlistBooked,
+        ] {
+            assert_eq!(kind.as_str().parse::<ChangeEventKind>().unwrap(), kind);
+        }
+        assert!("reservation_exploded".parse::<ChangeEventKind>().is_err());
+    }
+}
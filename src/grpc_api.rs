@@ -0,0 +1,706 @@
+//! gRPC API
+//!
+//! `grpc_api` serves the RESTful API's reservation routes over gRPC, for services that only talk
+//! gRPC. The service is defined in `proto/arbiter.proto`, and its messages and server are
+//! generated from that when Arbiter is built.
+//!
+//! It asks the same hostess the same questions that the RESTful API does, and callers
+//! authenticate with the same bearer tokens, sent as `authorization` metadata.
+//!
+//! ```toml
+//! [grpc]
+//! port = 4243
+//! ```
+
+// `tonic::Status` is big, but it's what gRPC handlers have to answer with.
+#![allow(clippy::result_large_err)]
+
+// Standard library crates.
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+// External crates.
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use serde_derive::Deserialize;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+// Project crates.
+use crate::auth::{AuthError, Authenticator, Principal, Role};
+use crate::batch::{BatchItemResult, BatchOrdering, Denial, DenialKind};
+use crate::common::{
+    default_pool, AvailabilitySlot, RequestedTime, Reservation, ReservationParams,
+    ReservationStatus,
+};
+use crate::hostess::Hostess;
+use crate::listing::{ReservationFilter, ReservationSort, SortOrder, TimeMatch, DEFAULT_PAGE_SIZE};
+
+/// Messages and service that are generated from `proto/arbiter.proto`.
+// The generated client is only used by the tests.
+#[allow(unused)]
+pub mod proto {
+    tonic::include_proto!("arbiter.v1");
+}
+
+use proto::reservations_server::{Reservations, ReservationsServer};
+
+/// Port that the gRPC API listens on unless it's configured.
+pub const DEFAULT_GRPC_PORT: u16 = 4243;
+
+/// Settings for the gRPC API.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GrpcConfig {
+    /// Port that the gRPC API listens on.
+    pub port: u16,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_GRPC_PORT,
+        }
+    }
+}
+
+impl From<Reservation> for proto::Reservation {
+    fn from(reservation: Reservation) -> Self {
+        Self {
+            reservation_id: reservation.reservation_id,
+            start_time: reservation.start_time,
+            end_time: reservation.end_time,
+            capacity_amount: reservation.capacity_amount,
+            user_id: reservation.user_id,
+            pool: reservation.pool,
+            status: proto::ReservationStatus::from(reservation.status).into(),
+            created_at: reservation.created_at,
+            updated_at: reservation.updated_at,
+            cost_microcredits: reservation.cost_microcredits,
+            refund_microcredits: reservation.refund_microcredits,
+        }
+    }
+}
+
+impl From<ReservationStatus> for proto::ReservationStatus {
+    fn from(status: ReservationStatus) -> Self {
+        match status {
+            ReservationStatus::Pending => proto::ReservationStatus::Pending,
+            ReservationStatus::Active => proto::ReservationStatus::Active,
+            ReservationStatus::Completed => proto::ReservationStatus::Completed,
+            ReservationStatus::Cancelled => proto::ReservationStatus::Cancelled,
+            ReservationStatus::Preempted => proto::ReservationStatus::Preempted,
+            ReservationStatus::Expired => proto::ReservationStatus::Expired,
+        }
+    }
+}
+
+impl From<BatchItemResult> for proto::ReserveResponse {
+    fn from(batch_result: BatchItemResult) -> Self {
+        Self {
+            is_reserved: batch_result.is_reserved,
+            reservation_id: batch_result.reservation_id,
+            start_time: batch_result.start_time,
+            end_time: batch_result.end_time,
+            cost_microcredits: batch_result.cost_microcredits,
+            denial: batch_result.denial.map(proto::Denial::from),
+        }
+    }
+}
+
+impl From<Denial> for proto::Denial {
+    fn from(denial: Denial) -> Self {
+        let kind = match denial.kind {
+            DenialKind::InvalidRequest => proto::DenialKind::InvalidRequest,
+            DenialKind::PolicyViolation => proto::DenialKind::PolicyViolation,
+            DenialKind::NotEnoughCapacity => proto::DenialKind::NotEnoughCapacity,
+            DenialKind::BatchRefused => proto::DenialKind::BatchRefused,
+        };
+        Self {
+            kind: kind.into(),
+            user_message: denial.user_message,
+        }
+    }
+}
+
+impl From<AvailabilitySlot> for proto::AvailabilitySlot {
+    fn from(slot: AvailabilitySlot) -> Self {
+        Self {
+            start_time: slot.start_time,
+            end_time: slot.end_time,
+            scheduled_amount: slot.scheduled_amount,
+            maintenance_amount: slot.maintenance_amount,
+            reserved_amount: slot.reserved_amount,
+            available_amount: slot.available_amount,
+        }
+    }
+}
+
+impl From<proto::TimeMatch> for TimeMatch {
+    fn from(time_match: proto::TimeMatch) -> Self {
+        match time_match {
+            proto::TimeMatch::Overlapping => TimeMatch::Overlapping,
+            proto::TimeMatch::StartingIn => TimeMatch::StartingIn,
+            proto::TimeMatch::EndingIn => TimeMatch::EndingIn,
+        }
+    }
+}
+
+impl From<proto::ReservationSort> for ReservationSort {
+    fn from(sort: proto::ReservationSort) -> Self {
+        match sort {
+            proto::ReservationSort::Id => ReservationSort::Id,
+            proto::ReservationSort::StartTime => ReservationSort::StartTime,
+            proto::ReservationSort::EndTime => ReservationSort::EndTime,
+            proto::ReservationSort::CreatedAt => ReservationSort::CreatedAt,
+        }
+    }
+}
+
+impl From<proto::SortOrder> for SortOrder {
+    fn from(order: proto::SortOrder) -> Self {
+        match order {
+            proto::SortOrder::Asc => SortOrder::Asc,
+            proto::SortOrder::Desc => SortOrder::Desc,
+        }
+    }
+}
+
+// Read a requested time out of a message, where it's required.
+fn requested_time(
+    requested_time: Option<proto::RequestedTime>,
+    field_name: &str,
+) -> Result<RequestedTime, Status> {
+    match requested_time.and_then(|requested_time| requested_time.time) {
+        Some(proto::requested_time::Time::Epoch(epoch)) => Ok(RequestedTime::Epoch(epoch)),
+        Some(proto::requested_time::Time::Relative(relative_time)) => {
+            Ok(RequestedTime::Relative(relative_time))
+        }
+        None => Err(Status::invalid_argument(format!(
+            "`{field_name}` is required"
+        ))),
+    }
+}
+
+// Read a reservation status filter out of a message.
+fn reservation_status(status: i32) -> Result<ReservationStatus, Status> {
+    match proto::ReservationStatus::try_from(status) {
+        Ok(proto::ReservationStatus::Pending) => Ok(ReservationStatus::Pending),
+        Ok(proto::ReservationStatus::Active) => Ok(ReservationStatus::Active),
+        Ok(proto::ReservationStatus::Completed) => Ok(ReservationStatus::Completed),
+        Ok(proto::ReservationStatus::Cancelled) => Ok(ReservationStatus::Cancelled),
+        Ok(proto::ReservationStatus::Preempted) => Ok(ReservationStatus::Preempted),
+        Ok(proto::ReservationStatus::Expired) => Ok(ReservationStatus::Expired),
+        Ok(proto::ReservationStatus::Unspecified) | Err(_) => Err(Status::invalid_argument(
+            format!("Reservation status \"{status}\" doesn't exist"),
+        )),
+    }
+}
+
+// Tell a caller that was turned away why.
+fn auth_status(auth_error: AuthError) -> Status {
+    match auth_error {
+        AuthError::Unauthenticated(auth_message) => Status::unauthenticated(auth_message),
+        AuthError::Forbidden(auth_message) => Status::permission_denied(auth_message),
+    }
+}
+
+// Ask the hostess a question and answer with what she says.
+//
+// The DB driver blocks, so the hostess answers on a thread that's allowed to block. If she can't
+// answer, then the caller gets `INVALID_ARGUMENT` with why.
+async fn ask_hostess<Answer, Question>(
+    hostess: Arc<Hostess>,
+    question: Question,
+) -> Result<Answer, Status>
+where
+    Answer: Send + 'static,
+    Question: FnOnce(&Hostess) -> anyhow::Result<Answer> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(move || question(&hostess)).await {
+        Ok(Ok(answer)) => Ok(answer),
+        // Callers that turn out not to be allowed to ask are told so, not that they asked badly.
+        Ok(Err(error_message)) if error_message.is::<AuthError>() => {
+            Err(auth_status(error_message.downcast::<AuthError>().unwrap()))
+        }
+        Ok(Err(error_message)) => Err(Status::invalid_argument(error_message.to_string())),
+        Err(join_error) => {
+            error!("Hostess didn't finish answering: {}", join_error);
+            Err(Status::internal("request not evaluated"))
+        }
+    }
+}
+
+/// Serves the `Reservations` gRPC service with the hostess.
+pub struct ReservationService {
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+}
+
+impl ReservationService {
+    pub fn new(hostess: Arc<Hostess>, authenticator: Arc<Authenticator>) -> Self {
+        Self {
+            hostess,
+            authenticator,
+        }
+    }
+
+    // Work out who's calling, and turn them away unless they have at least the given role.
+    fn authorize<Message>(
+        &self,
+        request: &Request<Message>,
+        role: Role,
+    ) -> Result<Principal, Status> {
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .map(|authorization| authorization.to_str())
+            .transpose()
+            .map_err(|_| Status::unauthenticated("`authorization` metadata isn't readable"))?;
+        self.authenticator
+            .authorize(authorization, role)
+            .map_err(auth_status)
+    }
+}
+
+#[tonic::async_trait]
+impl Reservations for ReservationService {
+    async fn reserve(
+        &self,
+        request: Request<proto::ReserveRequest>,
+    ) -> Result<Response<proto::ReserveResponse>, Status> {
+        let principal = self.authorize(&request, Role::User)?;
+        let reserve_request = request.into_inner();
+        let reservation_params = ReservationParams {
+            start_time: requested_time(reserve_request.start_time, "start_time")?,
+            end_time: requested_time(reserve_request.end_time, "end_time")?,
+            capacity_amount: reserve_request.capacity_amount,
+            user_id: Some(
+                principal
+                    .act_for(reserve_request.user_id)
+                    .map_err(auth_status)?,
+            ),
+            pool: reserve_request.pool.unwrap_or_else(default_pool),
+            quote_token: reserve_request.quote_token,
+        };
+        let batch_result = ask_hostess(self.hostess.clone(), move |hostess| {
+            // One request is a batch of one, which already works out what kind of denial it got.
+            let mut batch_results = hostess.process_batch(
+                std::slice::from_ref(&reservation_params),
+                BatchOrdering::InOrder,
+                false,
+            )?;
+            Ok(batch_results.remove(0))
+        })
+        .await?;
+        Ok(Response::new(batch_result.into()))
+    }
+
+    async fn cancel_reservation(
+        &self,
+        request: Request<proto::CancelReservationRequest>,
+    ) -> Result<Response<proto::CancelReservationResponse>, Status> {
+        let principal = self.authorize(&request, Role::User)?;
+        let reservation_id = request.into_inner().reservation_id;
+        let cancelled_reservation = ask_hostess(self.hostess.clone(), move |hostess| {
+            // Users can only cancel their own reservations.
+            if let Some(reservation) = hostess.get_reservation(reservation_id)? {
+                principal.act_for(Some(reservation.user_id))?;
+            }
+            hostess.cancel_reservation(reservation_id)
+        })
+        .await?;
+        let user_message = match cancelled_reservation {
+            Some(_) => String::from("reservation cancelled"),
+            None => format!("reservation \"{reservation_id}\" doesn't exist or already ended"),
+        };
+        Ok(Response::new(proto::CancelReservationResponse {
+            is_cancelled: cancelled_reservation.is_some(),
+            user_message,
+            refund_microcredits: cancelled_reservation
+                .map(|cancelled_reservation| cancelled_reservation.refund_microcredits),
+        }))
+    }
+
+    async fn get_reservation(
+        &self,
+        request: Request<proto::GetReservationRequest>,
+    ) -> Result<Response<proto::Reservation>, Status> {
+        let principal = self.authorize(&request, Role::User)?;
+        let reservation_id = request.into_inner().reservation_id;
+        let reservation = ask_hostess(self.hostess.clone(), move |hostess| {
+            let reservation = hostess.get_reservation(reservation_id)?;
+            // Users can only see their own reservations.
+            if let Some(reservation) = &reservation {
+                principal.act_for(Some(reservation.user_id))?;
+            }
+            Ok(reservation)
+        })
+        .await?;
+        match reservation {
+            Some(reservation) => Ok(Response::new(reservation.into())),
+            None => Err(Status::not_found(format!(
+                "reservation \"{reservation_id}\" doesn't exist"
+            ))),
+        }
+    }
+
+    async fn list_reservations(
+        &self,
+        request: Request<proto::ListReservationsRequest>,
+    ) -> Result<Response<proto::ListReservationsResponse>, Status> {
+        let principal = self.authorize(&request, Role::User)?;
+        let list_request = request.into_inner();
+        let mut reservation_filter = ReservationFilter {
+            user_id: list_request.user_id,
+            pool: list_request.pool.clone(),
+            start_time: list_request.start_time,
+            end_time: list_request.end_time,
+            time_match: list_request.time_match().into(),
+            status: list_request.status.map(reservation_status).transpose()?,
+        };
+        // Operators can list everyone's reservations, but users only see their own.
+        if reservation_filter.user_id.is_some() || principal.role < Role::Operator {
+            reservation_filter.user_id = Some(
+                principal
+                    .act_for(list_request.user_id)
+                    .map_err(auth_status)?,
+            );
+        }
+        let sort = ReservationSort::from(list_request.sort());
+        let order = SortOrder::from(list_request.order());
+        let reservation_page = ask_hostess(self.hostess.clone(), move |hostess| {
+            hostess.list_reservations(
+                &reservation_filter,
+                sort,
+                order,
+                list_request.limit.unwrap_or(DEFAULT_PAGE_SIZE),
+                list_request.cursor.as_deref(),
+            )
+        })
+        .await?;
+        Ok(Response::new(proto::ListReservationsResponse {
+            reservations: reservation_page
+                .reservations
+                .into_iter()
+                .map(proto::Reservation::from)
+                .collect(),
+            next_cursor: reservation_page.next_cursor,
+        }))
+    }
+
+    async fn get_availability(
+        &self,
+        request: Request<proto::GetAvailabilityRequest>,
+    ) -> Result<Response<proto::GetAvailabilityResponse>, Status> {
+        self.authorize(&request, Role::User)?;
+        let availability_request = request.into_inner();
+        let pool = availability_request.pool.unwrap_or_else(default_pool);
+        let (start_time, end_time) = (
+            availability_request.start_time,
+            availability_request.end_time,
+        );
+        ask_hostess(self.hostess.clone(), move |hostess| {
+            let slots = hostess.check_availability(&pool, start_time, end_time)?;
+            let peak_reserved_amount = hostess.peak_reserved_amount(&pool, start_time, end_time)?;
+            Ok(Response::new(proto::GetAvailabilityResponse {
+                pool,
+                slots: slots
+                    .into_iter()
+                    .map(proto::AvailabilitySlot::from)
+                    .collect(),
+                peak_reserved_amount,
+            }))
+        })
+        .await
+    }
+}
+
+/// Serve the gRPC API until it stops.
+pub async fn start_grpc_api(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+    grpc_config: GrpcConfig,
+) -> Result<(), Box<dyn Error>> {
+    let address = SocketAddr::from(([127, 0, 0, 1], grpc_config.port));
+    let reservation_service = ReservationService::new(hostess, authenticator);
+
+    info!("Initializing gRPC API");
+    Server::builder()
+        .add_service(ReservationsServer::new(reservation_service))
+        .serve(address)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    // Standard library crates.
+    use std::sync::Arc;
+
+    // External crates.
+    use tokio::net::TcpListener;
+    use tonic::transport::server::TcpIncoming;
+    use tonic::transport::{Channel, Server};
+    use tonic::{Code, Request};
+
+    // Project crates.
+    use super::proto::reservations_client::ReservationsClient;
+    use super::proto::reservations_server::ReservationsServer;
+    use super::{proto, ReservationService};
+    use crate::auth::test_examples::{bearer, test_authenticator, OPERATOR_KEY, USER_KEY};
+    use crate::clock::test_examples::test_clock;
+    use crate::config::HostessConfig;
+    use crate::datastore::test_examples::{seed_test_pool, test_datastore};
+    use crate::hostess::Hostess;
+    use crate::logging::setup_native_logging;
+
+    /// Client of a gRPC API that's served in-process, on a port of its own.
+    ///
+    /// The test datastore is opened outside of the async runtime because its clients block.
+    async fn test_client() -> ReservationsClient<Channel> {
+        let datastore = tokio::task::spawn_blocking(test_datastore).await.unwrap();
+        let hostess = Arc::new(Hostess::new(
+            Arc::new(test_clock()),
+            datastore,
+            HostessConfig::default(),
+        ));
+        let reservation_service = ReservationService::new(hostess, test_authenticator());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(ReservationsServer::new(reservation_service))
+                .serve_with_incoming(incoming),
+        );
+        ReservationsClient::connect(format!("http://{address}"))
+            .await
+            .unwrap()
+    }
+
+    /// Request that's sent with an API key.
+    fn authorized<Message>(message: Message, api_key: &str) -> Request<Message> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert("authorization", bearer(api_key).parse().unwrap());
+        request
+    }
+
+    /// Request to reserve capacity from a pool for the hour after `start_time`.
+    fn reserve_request(pool: &str, start_time: u32, capacity_amount: u32) -> proto::ReserveRequest {
+        let requested_time = |time| proto::RequestedTime { time: Some(time) };
+        proto::ReserveRequest {
+            start_time: Some(requested_time(proto::requested_time::Time::Epoch(
+                start_time,
+            ))),
+            end_time: Some(requested_time(proto::requested_time::Time::Relative(
+                String::from("+1h"),
+            ))),
+            capacity_amount,
+            pool: Some(pool.to_string()),
+            ..Default::default()
+        }
+    }
+
+    // Test if reservations can be made and looked up, and callers are kept to their own.
+    #[tokio::test]
+    async fn test_grpc_reserve() {
+        let _ = setup_native_logging();
+        let test_pool = "test_grpc_reserve";
+        tokio::task::spawn_blocking(move || seed_test_pool(test_pool))
+            .await
+            .unwrap();
+        let mut client = test_client().await;
+
+        let reserve_response = client
+            .reserve(authorized(
+                reserve_request(test_pool, 1707165008, 48),
+                USER_KEY,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(reserve_response.is_reserved);
+        assert_eq!(reserve_response.end_time, Some(1707168608));
+        let reservation_id = reserve_response.reservation_id.unwrap();
+
+        let reservation = client
+            .get_reservation(authorized(
+                proto::GetReservationRequest { reservation_id },
+                USER_KEY,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reservation.user_id, 42);
+        assert_eq!(reservation.capacity_amount, 48);
+        assert_eq!(reservation.status(), proto::ReservationStatus::Pending);
+
+        // There's not enough capacity left for the operator's own reservation.
+        let reserve_response = client
+            .reserve(authorized(
+                reserve_request(test_pool, 1707165008, 32),
+                OPERATOR_KEY,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!reserve_response.is_reserved);
+        assert_eq!(
+            reserve_response.denial.unwrap().kind(),
+            proto::DenialKind::NotEnoughCapacity
+        );
+
+        // Users can't reserve for others, and callers have to say who they are.
+        let mut others_request = reserve_request(test_pool, 1707165008, 1);
+        others_request.user_id = Some(7);
+        let reserve_status = client
+            .reserve(authorized(others_request, USER_KEY))
+            .await
+            .unwrap_err();
+        assert_eq!(reserve_status.code(), Code::PermissionDenied);
+        let reserve_status = client
+            .reserve(Request::new(reserve_request(test_pool, 1707165008, 1)))
+            .await
+            .unwrap_err();
+        assert_eq!(reserve_status.code(), Code::Unauthenticated);
+
+        let get_status = client
+            .get_reservation(authorized(
+                proto::GetReservationRequest {
+                    reservation_id: u32::MAX,
+                },
+                USER_KEY,
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(get_status.code(), Code::NotFound);
+    }
+
+    // Test if reservations can be listed a page at a time, and cancelled.
+    #[tokio::test]
+    async fn test_grpc_list_and_cancel() {
+        let _ = setup_native_logging();
+        let test_pool = "test_grpc_list_and_cancel";
+        tokio::task::spawn_blocking(move || seed_test_pool(test_pool))
+            .await
+            .unwrap();
+        let mut client = test_client().await;
+        for start_time in [1707165008, 1707168608] {
+            let reserve_response = client
+                .reserve(authorized(
+                    reserve_request(test_pool, start_time, 8),
+                    USER_KEY,
+                ))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(reserve_response.is_reserved);
+        }
+
+        let list_request = proto::ListReservationsRequest {
+            pool: Some(test_pool.to_string()),
+            sort: proto::ReservationSort::StartTime.into(),
+            limit: Some(1),
+            ..Default::default()
+        };
+        let first_page = client
+            .list_reservations(authorized(list_request.clone(), USER_KEY))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(first_page.reservations.len(), 1);
+        assert_eq!(first_page.reservations[0].start_time, 1707165008);
+        let second_page = client
+            .list_reservations(authorized(
+                proto::ListReservationsRequest {
+                    cursor: first_page.next_cursor,
+                    ..list_request.clone()
+                },
+                USER_KEY,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(second_page.reservations.len(), 1);
+        assert_eq!(second_page.reservations[0].start_time, 1707168608);
+        assert!(second_page.next_cursor.is_none());
+
+        let reservation_id = second_page.reservations[0].reservation_id;
+        let cancel_response = client
+            .cancel_reservation(authorized(
+                proto::CancelReservationRequest { reservation_id },
+                USER_KEY,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(cancel_response.is_cancelled);
+        let cancelled_page = client
+            .list_reservations(authorized(
+                proto::ListReservationsRequest {
+                    status: Some(proto::ReservationStatus::Cancelled.into()),
+                    ..list_request
+                },
+                USER_KEY,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(cancelled_page.reservations.len(), 1);
+        assert_eq!(
+            cancelled_page.reservations[0].reservation_id,
+            reservation_id
+        );
+    }
+
+    // Test if availability accounts for reservations, and backwards timeframes are refused.
+    #[tokio::test]
+    async fn test_grpc_availability() {
+        let _ = setup_native_logging();
+        let test_pool = "test_grpc_availability";
+        tokio::task::spawn_blocking(move || seed_test_pool(test_pool))
+            .await
+            .unwrap();
+        let mut client = test_client().await;
+        client
+            .reserve(authorized(
+                reserve_request(test_pool, 1707165008, 16),
+                USER_KEY,
+            ))
+            .await
+            .unwrap();
+
+        let availability = client
+            .get_availability(authorized(
+                proto::GetAvailabilityRequest {
+                    pool: Some(test_pool.to_string()),
+                    start_time: 1707165008,
+                    end_time: 1707168608,
+                },
+                USER_KEY,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(availability.peak_reserved_amount, 16);
+        assert_eq!(availability.slots.len(), 1);
+        assert_eq!(availability.slots[0].available_amount, 48);
+
+        let availability_status = client
+            .get_availability(authorized(
+                proto::GetAvailabilityRequest {
+                    pool: Some(test_pool.to_string()),
+                    start_time: 1707168608,
+                    end_time: 1707165008,
+                },
+                USER_KEY,
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(availability_status.code(), Code::InvalidArgument);
+    }
+}
@@ -1,40 +1,1571 @@
 // Standard library crates.
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 // External crates.
 use anyhow::{anyhow, ensure, Result};
 #[allow(unused)]
 use log::{debug, error, info, trace, warn};
+use tokio::sync::watch;
 
 // Project crates.
-use crate::datastore::{add_user_reservation, get_schedule, get_user_reservation_schedule};
+use crate::batch::{batch_order, BatchItemResult, BatchOrdering, DenialKind, MAX_BATCH_SIZE};
+use crate::billing::{Invoice, InvoiceGrouping, PriceQuote, SegmentPricing, SurgePricing};
+use crate::clock::Clock;
+use crate::common::{
+    AvailabilitySlot, CapacityReduction, MaintenanceParams, MaintenanceWindow, Reservation,
+    ReservationStatus,
+};
+use crate::common::{RequestedTime, ReservationParams};
+use crate::config::HostessConfig;
+use crate::datastore::{Booking, Datastore};
+use crate::demand::{
+    unmet_demand, Bottleneck, DemandDimension, EvaluatedRequest, RequestEvaluation, RequestFilter,
+    RequestOutcome, UnmetDemand,
+};
+use crate::events::{
+    peak_utilization_percent, threshold_crossings, ChangeEvent, ChangeEventKind, ChangeEventParams,
+    ChangeFilter,
+};
+use crate::idempotency::{validate_key, IdempotencyClaim, IdempotencyConflict, IdempotencyRecord};
+use crate::ledger::LedgerEventKind;
+use crate::listing::{
+    PageCursor, PageRequest, ReservationFilter, ReservationPage, ReservationSort, SortOrder,
+    MAX_PAGE_SIZE,
+};
+use crate::metrics::{metrics, PoolGauges};
+use crate::reports::{
+    denial_report, load_report, usage_report, utilization_report, DenialRow, LoadRow,
+    ReportBuckets, UsageRow, UtilizationRow,
+};
+use crate::webhooks::{WebhookDelivery, WebhookDeliveryParams, WebhookParams, WebhookSubscription};
 use crate::CapacitySchedule;
 use crate::ReservationRequest;
 
-/// Convenience function for getting the active schedule in one place.
-pub fn process_reservation(reservation_request: &ReservationRequest) -> Result<bool> {
-    let active_schedule: CapacitySchedule = get_schedule().unwrap();
-    // See if we're able to meet the reservation request's requirements.
-    let is_reservable = evaluate_reservation_request(&reservation_request, &active_schedule);
-    match is_reservable {
-        Ok(true) => add_user_reservation(reservation_request)?,
-        _ => (),
-    };
-    is_reservable
+/// Greets reservation requests and decides whether they're seated.
+///
+/// The hostess asks its `Clock` what time it is so that time-sensitive rules (like "no
+/// reservations in the past") can be tested deterministically. She keeps the schedule and
+/// reservations in whichever `Datastore` she's given, and records a change event whenever they
+/// change.
+pub struct Hostess {
+    clock: Arc<dyn Clock>,
+    datastore: Arc<dyn Datastore>,
+    config: HostessConfig,
+    /// Latest change event's ID, so followers of the event stream wake up when there's news.
+    change_notifier: watch::Sender<u32>,
+}
+
+impl Hostess {
+    /// Create a new `Hostess`.
+    ///
+    /// # Arguments
+    /// - `clock`: Source of truth for "now".
+    /// - `datastore`: Where the schedule and reservations are kept.
+    /// - `config`: Settings that change how reservation requests are evaluated.
+    pub fn new(
+        clock: Arc<dyn Clock>,
+        datastore: Arc<dyn Datastore>,
+        config: HostessConfig,
+    ) -> Self {
+        Self {
+            clock,
+            datastore,
+            config,
+            change_notifier: watch::Sender::new(0),
+        }
+    }
+
+    /// Get the current time according to the hostess's clock.
+    pub fn now(&self) -> u32 {
+        self.clock.now()
+    }
+
+    /// Turn the user's reservation parameters into an exact reservation request.
+    ///
+    /// Relative start times (`"now"` or `"+30m"`) are resolved against the hostess's clock and
+    /// pushed back by the provisioning lead time if they'd start before capacity can spin up.
+    /// Relative end times (`"+2h"`) are measured from the resolved start time, so users get the
+    /// duration that they asked for. Exact Unix epochs are left alone. The reservation policy
+    /// isn't applied until the request is processed, so it's only enforced once.
+    pub fn resolve_reservation_params(
+        &self,
+        reservation_params: &ReservationParams,
+    ) -> Result<ReservationRequest> {
+        let epoch_now = self.now();
+        let start_time = match &reservation_params.start_time {
+            RequestedTime::Epoch(start_time) => *start_time,
+            RequestedTime::Relative(relative_time) => {
+                let start_offset = parse_relative_time(relative_time)?;
+                let lead_time = self.config.provisioning_lead_time;
+                add_duration(epoch_now, start_offset.max(lead_time))?
+            }
+        };
+        let end_time = match &reservation_params.end_time {
+            RequestedTime::Epoch(end_time) => *end_time,
+            RequestedTime::Relative(relative_time) => {
+                let end_offset = parse_relative_time(relative_time)?;
+                ensure!(
+                    !end_offset.is_zero(),
+                    format!("Reservation request `end_time` \"{relative_time}\" would end before it begins")
+                );
+                add_duration(start_time, end_offset)?
+            }
+        };
+        let reservation_request = ReservationRequest::new(
+            start_time,
+            end_time,
+            reservation_params.capacity_amount,
+            reservation_params
+                .user_id
+                .ok_or_else(|| anyhow!("Reservation request is missing its `user_id`"))?,
+        )
+        .in_pool(&reservation_params.pool);
+        debug!(
+            "Resolved \"{}\" to \"{}\" and \"{}\" to \"{}\"",
+            reservation_params.start_time, start_time, reservation_params.end_time, end_time
+        );
+        Ok(reservation_request)
+    }
+
+    /// Convenience function for getting the active schedule in one place.
+    ///
+    /// Reservation requests that break the reservation policy are refused with a
+    /// `PolicyViolation` before capacity is considered. Every request and its outcome are
+    /// recorded in the reservation ledger and the request history, unless its times aren't
+    /// valid Unix epochs that the datastore can keep. Granted reservations are
+    /// charged for the capacity segments that they use, according to the billing policy.
+    ///
+    /// # Returns
+    /// The granted reservation, or `None` if there isn't enough capacity for it.
+    pub fn process_reservation(
+        &self,
+        reservation_request: &ReservationRequest,
+    ) -> Result<Option<Reservation>> {
+        self.process_priced_reservation(reservation_request, None)
+    }
+
+    /// Reserve capacity at the price that it was quoted.
+    ///
+    /// The quote has to be for the request's exact terms, and it can only be redeemed once
+    /// before it expires. Requests that can't redeem their quote are refused. Quotes don't hold
+    /// capacity, so they're only redeemed if there's still enough capacity for the request.
+    ///
+    /// # Returns
+    /// The granted reservation, or `None` if there isn't enough capacity for it anymore.
+    pub fn process_quoted_reservation(
+        &self,
+        reservation_request: &ReservationRequest,
+        quote_token: &str,
+    ) -> Result<Option<Reservation>> {
+        self.process_priced_reservation(reservation_request, Some(quote_token))
+    }
+
+    /// Decide a reservation request and charge it the usual price, or the price it was quoted.
+    fn process_priced_reservation(
+        &self,
+        reservation_request: &ReservationRequest,
+        quote_token: Option<&str>,
+    ) -> Result<Option<Reservation>> {
+        let (decision, _request_evaluation) =
+            self.decide_reservation(reservation_request, quote_token)?;
+        decision
+    }
+
+    /// Decide a reservation request, and record the decision in the ledger and request history.
+    ///
+    /// # Returns
+    /// The decision, and how the request was evaluated (like why it was denied). Only failing
+    /// to record the decision is an outer error.
+    fn decide_reservation(
+        &self,
+        reservation_request: &ReservationRequest,
+        quote_token: Option<&str>,
+    ) -> Result<(Result<Option<Reservation>>, RequestEvaluation)> {
+        let evaluation_started = Instant::now();
+        let epoch_now = self.now();
+        let mut request_evaluation = RequestEvaluation::new(reservation_request, epoch_now);
+        let (decision, denial_kind) = match self.admit_request(reservation_request, epoch_now) {
+            Ok(reservable_request) => {
+                let booked = self.watching_utilization(
+                    &reservable_request.pool,
+                    (reservable_request.start_time, reservable_request.end_time),
+                    epoch_now,
+                    || {
+                        let mut booking =
+                            self.datastore.begin_booking(&[&reservable_request.pool])?;
+                        let booked = self.book_request(
+                            &mut *booking,
+                            &reservable_request,
+                            quote_token,
+                            epoch_now,
+                        )?;
+                        if let Booked::Granted(granted_reservation, _) = &booked {
+                            self.commit_granted(booking, &[granted_reservation], epoch_now)?;
+                        }
+                        Ok(booked)
+                    },
+                )?;
+                booked.decide(&mut request_evaluation)
+            }
+            Err(request_error) => {
+                Booked::Refused(request_error, None).decide(&mut request_evaluation)
+            }
+        };
+        self.record_decision(&request_evaluation, denial_kind, evaluation_started)?;
+        Ok((decision, request_evaluation))
+    }
+
+    /// Keep what a booking granted, then tell followers about the reservations that it created.
+    ///
+    /// The change events are recorded in the booking, so they're kept exactly when the
+    /// reservations are.
+    fn commit_granted(
+        &self,
+        mut booking: Box<dyn Booking + '_>,
+        granted_reservations: &[&Reservation],
+        epoch_now: u32,
+    ) -> Result<()> {
+        let mut change_events = Vec::new();
+        for granted_reservation in granted_reservations {
+            change_events.push(booking.record_change_event(&ChangeEventParams::reservation(
+                ChangeEventKind::ReservationCreated,
+                granted_reservation,
+                epoch_now,
+            )?)?);
+        }
+        booking.commit()?;
+        for change_event in &change_events {
+            self.announce_change(change_event);
+        }
+        Ok(())
+    }
+
+    /// Record how a request was decided in the ledger and request history, and count it.
+    ///
+    /// Requests with times that don't fit in the datastore can't be recorded, so they're only
+    /// counted.
+    fn record_decision(
+        &self,
+        request_evaluation: &RequestEvaluation,
+        denial_kind: Option<DenialKind>,
+        evaluation_started: Instant,
+    ) -> Result<()> {
+        let reservation_request = &request_evaluation.reservation_request;
+        let is_recordable = validate_unix_epoch(reservation_request.start_time)
+            .and_then(|()| validate_unix_epoch(reservation_request.end_time))
+            .is_ok();
+        if is_recordable {
+            self.datastore.record_request_event(
+                LedgerEventKind::Requested,
+                request_evaluation.reservation_id,
+                reservation_request,
+                request_evaluation.requested_at,
+                None,
+            )?;
+            if let Some(denial_reason) = &request_evaluation.denial_reason {
+                self.datastore.record_request_event(
+                    LedgerEventKind::Denied,
+                    None,
+                    reservation_request,
+                    request_evaluation.requested_at,
+                    Some(denial_reason),
+                )?;
+            }
+            self.datastore
+                .record_evaluated_request(request_evaluation)?;
+        }
+        metrics().count_decision(
+            &reservation_request.pool,
+            denial_kind,
+            evaluation_started.elapsed(),
+        );
+        Ok(())
+    }
+
+    /// Reserve capacity for a batch of reservation requests.
+    ///
+    /// Every request is decided and recorded like it would be on its own. Requests that can't
+    /// be resolved (like ones with an unknown relative time) are refused without being tried.
+    /// If the batch is atomic, then either every request is reserved or none of them are: the
+    /// whole batch is booked together, once a request isn't reserved the rest of the batch
+    /// isn't tried, and nothing that was booked for it is kept, including redeemed quotes.
+    ///
+    /// # Returns
+    /// What became of each request, in the order that they were sent.
+    pub fn process_batch(
+        &self,
+        batch: &[ReservationParams],
+        ordering: BatchOrdering,
+        is_atomic: bool,
+    ) -> Result<Vec<BatchItemResult>> {
+        ensure!(!batch.is_empty(), "Batch has no reservation requests");
+        ensure!(
+            batch.len() <= MAX_BATCH_SIZE,
+            format!("Batch has more than {MAX_BATCH_SIZE} reservation requests")
+        );
+        let mut batch_results: Vec<Option<BatchItemResult>> = vec![None; batch.len()];
+        let mut resolved_requests: Vec<(usize, ReservationRequest)> = Vec::new();
+        for (index, reservation_params) in batch.iter().enumerate() {
+            match self.resolve_reservation_params(reservation_params) {
+                Ok(reservation_request) => resolved_requests.push((index, reservation_request)),
+                Err(resolve_error) => {
+                    batch_results[index] = Some(BatchItemResult::denied(
+                        index,
+                        DenialKind::InvalidRequest,
+                        format!("{:#}", resolve_error),
+                    ));
+                }
+            }
+        }
+        let reservation_requests: Vec<&ReservationRequest> = resolved_requests
+            .iter()
+            .map(|(_, reservation_request)| reservation_request)
+            .collect();
+        let ordered_requests: Vec<&(usize, ReservationRequest)> =
+            batch_order(&reservation_requests, ordering)
+                .into_iter()
+                .map(|position| &resolved_requests[position])
+                .collect();
+        if is_atomic {
+            // Atomic batches with a request that can't be resolved can't all be reserved.
+            let refused_index = batch_results.iter().position(Option::is_some);
+            self.book_atomic_batch(batch, &ordered_requests, refused_index, &mut batch_results)?;
+        } else {
+            for (index, reservation_request) in ordered_requests {
+                let (decision, request_evaluation) = self.decide_reservation(
+                    reservation_request,
+                    batch[*index].quote_token.as_deref(),
+                )?;
+                batch_results[*index] = Some(batch_item_result(
+                    *index,
+                    reservation_request,
+                    decision,
+                    request_evaluation,
+                ));
+            }
+        }
+        Ok(batch_results.into_iter().flatten().collect())
+    }
+
+    /// Book every request in an atomic batch together, and only keep them if they're all granted.
+    ///
+    /// Requests are tried in order until one isn't granted. Requests that were tried are recorded
+    /// like they would be on their own, except that ones which were granted and then rolled back
+    /// are recorded as refused with the batch.
+    ///
+    /// # Arguments
+    /// - `ordered_requests`: Requests that were resolved with where they are in the batch, in
+    ///   the order that they're tried.
+    /// - `refused_index`: Request that was already refused, in which case nothing is tried.
+    /// - `batch_results`: What became of each request, which is filled in.
+    fn book_atomic_batch(
+        &self,
+        batch: &[ReservationParams],
+        ordered_requests: &[&(usize, ReservationRequest)],
+        refused_index: Option<usize>,
+        batch_results: &mut [Option<BatchItemResult>],
+    ) -> Result<()> {
+        let evaluation_started = Instant::now();
+        let epoch_now = self.now();
+        let mut tried_requests: Vec<Booked> = Vec::new();
+        if refused_index.is_none() {
+            // Requests are admitted before anything's booked, since that doesn't need the
+            // datastore.
+            let admitted_requests: Vec<Result<ReservationRequest>> = ordered_requests
+                .iter()
+                .map(|(_, reservation_request)| self.admit_request(reservation_request, epoch_now))
+                .collect();
+            let watched_timeframes: Vec<(String, (u32, u32))> = admitted_requests
+                .iter()
+                .flatten()
+                .map(|reservable_request| {
+                    (
+                        reservable_request.pool.clone(),
+                        (reservable_request.start_time, reservable_request.end_time),
+                    )
+                })
+                .collect();
+            let pools: Vec<&str> = watched_timeframes
+                .iter()
+                .map(|(pool, _)| pool.as_str())
+                .collect();
+            tried_requests = self.watching_utilizations(&watched_timeframes, epoch_now, || {
+                let mut booking = self.datastore.begin_booking(&pools)?;
+                let mut tried_requests = Vec::new();
+                for ((index, _), admitted_request) in ordered_requests.iter().zip(admitted_requests)
+                {
+                    let booked = match admitted_request {
+                        Ok(reservable_request) => self.book_request(
+                            &mut *booking,
+                            &reservable_request,
+                            batch[*index].quote_token.as_deref(),
+                            epoch_now,
+                        )?,
+                        Err(request_error) => Booked::Refused(request_error, None),
+                    };
+                    let is_granted = matches!(booked, Booked::Granted(..));
+                    tried_requests.push(booked);
+                    // Dropping the booking rolls back everything that it granted.
+                    if !is_granted {
+                        return Ok(tried_requests);
+                    }
+                }
+                let granted_reservations: Vec<&Reservation> = tried_requests
+                    .iter()
+                    .filter_map(|booked| match booked {
+                        Booked::Granted(granted_reservation, _) => Some(granted_reservation),
+                        _ => None,
+                    })
+                    .collect();
+                self.commit_granted(booking, &granted_reservations, epoch_now)?;
+                Ok(tried_requests)
+            })?;
+        }
+        let refused_index = refused_index.or_else(|| {
+            tried_requests
+                .iter()
+                .zip(ordered_requests)
+                .find(|(booked, _)| !matches!(booked, Booked::Granted(..)))
+                .map(|(_, (index, _))| *index)
+        });
+        let mut tried_requests = tried_requests.into_iter();
+        for (index, reservation_request) in ordered_requests {
+            let Some(booked) = tried_requests.next() else {
+                // Requests after the one that wasn't reserved aren't tried.
+                let refused_index = refused_index.unwrap_or_default();
+                batch_results[*index] = Some(
+                    refused_with_batch(*index, refused_index).with_timeframe(reservation_request),
+                );
+                continue;
+            };
+            let mut request_evaluation = RequestEvaluation::new(reservation_request, epoch_now);
+            if let (Booked::Granted(rolled_back_reservation, bottleneck), Some(refused_index)) =
+                (&booked, refused_index)
+            {
+                let mut batch_result = refused_with_batch(*index, refused_index);
+                batch_result.start_time = Some(rolled_back_reservation.start_time);
+                batch_result.end_time = Some(rolled_back_reservation.end_time);
+                request_evaluation.bottleneck = Some(Bottleneck::from(bottleneck));
+                request_evaluation.denial_reason = batch_result
+                    .denial
+                    .as_ref()
+                    .map(|denial| denial.user_message.clone());
+                self.record_decision(
+                    &request_evaluation,
+                    Some(DenialKind::BatchRefused),
+                    evaluation_started,
+                )?;
+                info!("Rolled back {}", rolled_back_reservation);
+                batch_results[*index] = Some(batch_result);
+                continue;
+            }
+            let (decision, denial_kind) = booked.decide(&mut request_evaluation);
+            self.record_decision(&request_evaluation, denial_kind, evaluation_started)?;
+            batch_results[*index] = Some(batch_item_result(
+                *index,
+                reservation_request,
+                decision,
+                request_evaluation,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Work out what a reservation that's about to be granted costs.
+    ///
+    /// Quoted requests cost what they were quoted, if their quote can still be redeemed.
+    /// Everything else costs the usual price of the capacity segments that it uses.
+    fn reservation_cost(
+        &self,
+        booking: &mut dyn Booking,
+        reservable_request: &ReservationRequest,
+        quote_token: Option<&str>,
+        epoch_now: u32,
+    ) -> Result<u64> {
+        let Some(quote_token) = quote_token else {
+            return Ok(self
+                .config
+                .billing
+                .reservation_cost(reservable_request, &booking.get_schedule()?.segments));
+        };
+        let price_quote = booking
+            .get_price_quote(quote_token)?
+            .ok_or_else(|| anyhow!("Quote \"{quote_token}\" doesn't exist"))?;
+        price_quote.ensure_redeemable(reservable_request, epoch_now)?;
+        Ok(price_quote.price_microcredits)
+    }
+
+    /// Add a reservation that was granted, redeeming its quote if it has one.
+    ///
+    /// The quote is redeemed together with the reservation, so it's only used up if the
+    /// reservation is added.
+    ///
+    /// # Returns
+    /// The granted reservation, or why it wasn't added (like its quote being redeemed by someone
+    /// else since it was checked). Only failing to add it is an outer error.
+    fn add_priced_reservation(
+        &self,
+        booking: &mut dyn Booking,
+        reservable_request: &ReservationRequest,
+        epoch_now: u32,
+        cost_microcredits: u64,
+        quote_token: Option<&str>,
+    ) -> Result<Result<Reservation>> {
+        let Some(quote_token) = quote_token else {
+            return Ok(Ok(booking.add_user_reservation(
+                reservable_request,
+                epoch_now,
+                cost_microcredits,
+            )?));
+        };
+        let Some(granted_reservation) = booking.add_quoted_reservation(
+            reservable_request,
+            epoch_now,
+            cost_microcredits,
+            quote_token,
+        )?
+        else {
+            return Ok(Err(anyhow!("Quote \"{quote_token}\" was already redeemed")));
+        };
+        info!("Redeemed quote \"{}\"", quote_token);
+        Ok(Ok(granted_reservation))
+    }
+
+    /// Price a reservation request by how scarce capacity is during its timeframe.
+    ///
+    /// Nothing is reserved. The quote can be redeemed with `process_quoted_reservation()` before
+    /// it expires.
+    ///
+    /// # Returns
+    /// The quote, which is refused like a reservation request would be if the request breaks
+    /// the policy or there isn't enough capacity for it.
+    pub fn quote_reservation(
+        &self,
+        reservation_request: &ReservationRequest,
+    ) -> Result<PriceQuote> {
+        let epoch_now = self.now();
+        let reservable_request = self.admit_request(reservation_request, epoch_now)?;
+        // Nothing's added, so the booking is dropped (and its pool let go) once it's priced.
+        let mut booking = self.datastore.begin_booking(&[&reservable_request.pool])?;
+        let (is_reservable, bottleneck) =
+            self.evaluate_request(&mut *booking, &reservable_request, epoch_now)?;
+        ensure!(is_reservable, not_enough_capacity(&bottleneck));
+        let billing = &self.config.billing;
+        let base_cost_microcredits =
+            billing.reservation_cost(&reservable_request, &booking.get_schedule()?.segments);
+        drop(booking);
+        let price_percent = billing
+            .surge
+            .price_percent(&bottleneck, reservable_request.capacity_amount);
+        let quote_lifetime = billing.surge.quote_lifetime.as_secs() as u32;
+        let price_quote = PriceQuote {
+            quote_token: PriceQuote::new_token(),
+            start_time: reservable_request.start_time,
+            end_time: reservable_request.end_time,
+            capacity_amount: reservable_request.capacity_amount,
+            user_id: reservable_request.user_id,
+            pool: reservable_request.pool.clone(),
+            base_cost_microcredits,
+            price_percent,
+            price_microcredits: SurgePricing::surged_cost(base_cost_microcredits, price_percent),
+            quoted_at: epoch_now,
+            expires_at: epoch_now.saturating_add(quote_lifetime),
+            redeemed_at: None,
+        };
+        self.datastore.add_price_quote(&price_quote)?;
+        info!(
+            "Quoted \"{}\" microcredits for {}",
+            price_quote.price_microcredits, reservable_request
+        );
+        Ok(price_quote)
+    }
+
+    /// Claim an idempotency key for a request, or get the response that it already got.
+    ///
+    /// # Arguments
+    /// - `idempotency_key`: Key that the user sent with the request.
+    /// - `user_id`: User that the request is for. Keys only have to be unique for each user.
+    /// - `request_hash`: Fingerprint of the request.
+    ///
+    /// # Returns
+    /// Whether to evaluate the request or give the response that it already got. Keys that were
+    /// used for a different request, or whose request is still being evaluated, are refused with
+    /// an `IdempotencyConflict`.
+    pub fn claim_idempotency_key(
+        &self,
+        idempotency_key: &str,
+        user_id: u32,
+        request_hash: &str,
+    ) -> Result<IdempotencyClaim> {
+        validate_key(idempotency_key)?;
+        let epoch_now = self.now();
+        let retention = self.config.idempotency_retention.as_secs() as u32;
+        let idempotency_record = IdempotencyRecord {
+            idempotency_key: idempotency_key.to_string(),
+            user_id,
+            request_hash: request_hash.to_string(),
+            response_body: None,
+            created_at: epoch_now,
+            expires_at: epoch_now.saturating_add(retention),
+        };
+        let Some(existing_record) = self
+            .datastore
+            .claim_idempotency_key(&idempotency_record, epoch_now)?
+        else {
+            return Ok(IdempotencyClaim::Claimed);
+        };
+        if existing_record.request_hash != request_hash {
+            return Err(IdempotencyConflict(format!(
+                "Idempotency key \"{idempotency_key}\" was already used for a different request"
+            ))
+            .into());
+        }
+        match existing_record.response_body {
+            Some(response_body) => {
+                info!(
+                    "Replaying response for idempotency key \"{}\"",
+                    idempotency_key
+                );
+                Ok(IdempotencyClaim::Answered(response_body))
+            }
+            None => Err(IdempotencyConflict(format!(
+                "Request with idempotency key \"{idempotency_key}\" is still being evaluated"
+            ))
+            .into()),
+        }
+    }
+
+    /// Keep the response to a request whose idempotency key was claimed, for its retries.
+    pub fn answer_idempotency_key(
+        &self,
+        idempotency_key: &str,
+        user_id: u32,
+        response_body: &str,
+    ) -> Result<()> {
+        self.datastore
+            .answer_idempotency_key(idempotency_key, user_id, response_body)
+    }
+
+    /// Let go of an idempotency key whose request couldn't be answered, so that it can be retried.
+    pub fn release_idempotency_key(&self, idempotency_key: &str, user_id: u32) -> Result<()> {
+        self.datastore
+            .release_idempotency_key(idempotency_key, user_id)
+    }
+
+    /// Apply the reservation policy to a request, and make sure that its timeframe can be
+    /// evaluated, before anything is queried.
+    ///
+    /// # Returns
+    /// The request as it'd be granted, like with its times snapped to the policy's grid.
+    fn admit_request(
+        &self,
+        reservation_request: &ReservationRequest,
+        epoch_now: u32,
+    ) -> Result<ReservationRequest> {
+        // Times that don't fit in the datastore can't be recorded, and requests that end before
+        // they begin can't be snapped into shape, so they're refused first.
+        validate_timeframe(reservation_request)?;
+        let reservable_request = self.config.policy.enforce(reservation_request, epoch_now)?;
+        // Timeframes that the database can't query are refused before it's asked.
+        validate_timeframe(&reservable_request)?;
+        Ok(reservable_request)
+    }
+
+    /// Decide a reservation request while its pool is booked, and add it to the booking if it's
+    /// granted.
+    ///
+    /// # Returns
+    /// What became of the request. Only failing to add a reservation that was granted is an
+    /// error.
+    fn book_request(
+        &self,
+        booking: &mut dyn Booking,
+        reservable_request: &ReservationRequest,
+        quote_token: Option<&str>,
+        epoch_now: u32,
+    ) -> Result<Booked> {
+        let bottleneck = match self.evaluate_request(booking, reservable_request, epoch_now) {
+            Ok((true, bottleneck)) => bottleneck,
+            Ok((false, bottleneck)) => return Ok(Booked::NotEnoughCapacity(bottleneck)),
+            Err(request_error) => return Ok(Booked::Refused(request_error, None)),
+        };
+        let cost_microcredits =
+            match self.reservation_cost(booking, reservable_request, quote_token, epoch_now) {
+                Ok(cost_microcredits) => cost_microcredits,
+                Err(quote_error) => return Ok(Booked::Refused(quote_error, Some(bottleneck))),
+            };
+        Ok(
+            match self.add_priced_reservation(
+                booking,
+                reservable_request,
+                epoch_now,
+                cost_microcredits,
+                quote_token,
+            )? {
+                Ok(granted_reservation) => Booked::Granted(granted_reservation, bottleneck),
+                Err(quote_error) => Booked::Refused(quote_error, Some(bottleneck)),
+            },
+        )
+    }
+
+    /// See if a request that was admitted can be granted, against what's booked.
+    ///
+    /// # Returns
+    /// Whether there's enough capacity for the request, and the slot with the least capacity
+    /// left during its timeframe.
+    fn evaluate_request(
+        &self,
+        booking: &mut dyn Booking,
+        reservable_request: &ReservationRequest,
+        epoch_now: u32,
+    ) -> Result<(bool, AvailabilitySlot)> {
+        let active_schedule: CapacitySchedule = booking.get_schedule()?;
+        // Only the maintenance and reservations that overlap the request can get in its way.
+        let maintenance_windows: Vec<MaintenanceWindow> = booking
+            .get_overlapping_maintenance_windows(
+                &reservable_request.pool,
+                reservable_request.start_time,
+                reservable_request.end_time,
+            )?;
+        let user_reservations: Vec<Reservation> = booking.get_overlapping_reservations(
+            &reservable_request.pool,
+            reservable_request.start_time,
+            reservable_request.end_time,
+        )?;
+        // See if we're able to meet the reservation request's requirements.
+        evaluate_reservation_request(
+            reservable_request,
+            &active_schedule,
+            &maintenance_windows,
+            &user_reservations,
+            epoch_now,
+        )
+    }
+
+    /// Get a reservation by its ID, whatever its status.
+    pub fn get_reservation(&self, reservation_id: u32) -> Result<Option<Reservation>> {
+        self.datastore.get_reservation(reservation_id)
+    }
+
+    /// List a page of reservations that match a filter.
+    ///
+    /// # Arguments
+    /// - `reservation_filter`: Which reservations to list.
+    /// - `sort`: What reservations are listed in order of.
+    /// - `order`: Whether they're listed smallest or biggest first.
+    /// - `page_size`: Most reservations to list, up to `MAX_PAGE_SIZE`.
+    /// - `cursor_token`: Where the page picks up, from the page before's `next_cursor`.
+    pub fn list_reservations(
+        &self,
+        reservation_filter: &ReservationFilter,
+        sort: ReservationSort,
+        order: SortOrder,
+        page_size: u32,
+        cursor_token: Option<&str>,
+    ) -> Result<ReservationPage> {
+        ensure!(
+            (1..=MAX_PAGE_SIZE).contains(&page_size),
+            format!("Page size \"{page_size}\" isn't between 1 and {MAX_PAGE_SIZE}")
+        );
+        let page_request = PageRequest {
+            sort,
+            order,
+            after: cursor_token
+                .map(|cursor_token| PageCursor::decode(cursor_token, sort, order))
+                .transpose()?,
+            // One more than the page holds tells whether there's a next page.
+            limit: page_size + 1,
+        };
+        let mut reservations = self
+            .datastore
+            .list_reservations(reservation_filter, &page_request)?;
+        let next_cursor = match reservations.len() > page_size as usize {
+            true => {
+                reservations.truncate(page_size as usize);
+                reservations.last().map(|last_reservation| {
+                    PageCursor::after(last_reservation, sort, order).encode()
+                })
+            }
+            false => None,
+        };
+        Ok(ReservationPage {
+            reservations,
+            next_cursor,
+        })
+    }
+
+    /// Give a reservation's capacity back before it ends.
+    ///
+    /// Some of what the reservation cost is refunded, according to the billing policy.
+    ///
+    /// # Returns
+    /// The cancelled reservation, or `None` if there wasn't a reservation with the given ID that
+    /// was still holding capacity.
+    pub fn cancel_reservation(&self, reservation_id: u32) -> Result<Option<Reservation>> {
+        let Some(reservation) = self.datastore.get_reservation(reservation_id)? else {
+            return Ok(None);
+        };
+        let epoch_now = self.now();
+        let refund_microcredits = self
+            .config
+            .billing
+            .cancellation_refund(&reservation, epoch_now);
+        let cancelled_reservation = self.end_reservation(
+            &reservation,
+            epoch_now,
+            "Cancelled by user",
+            refund_microcredits,
+        )?;
+        if let Some(cancelled_reservation) = &cancelled_reservation {
+            info!("Cancelled {}", cancelled_reservation);
+        }
+        Ok(cancelled_reservation)
+    }
+
+    /// Cancel a reservation that's still holding capacity, and tell followers that it was.
+    ///
+    /// # Returns
+    /// The cancelled reservation, or `None` if it wasn't holding capacity anymore.
+    fn end_reservation(
+        &self,
+        reservation: &Reservation,
+        epoch_now: u32,
+        reason: &str,
+        refund_microcredits: u64,
+    ) -> Result<Option<Reservation>> {
+        self.watching_utilization(
+            &reservation.pool,
+            (reservation.start_time, reservation.end_time),
+            epoch_now,
+            || {
+                let cancelled_reservation = self.datastore.end_reservation(
+                    reservation.reservation_id,
+                    LedgerEventKind::Cancelled,
+                    epoch_now,
+                    Some(reason),
+                    refund_microcredits,
+                )?;
+                if let Some(cancelled_reservation) = &cancelled_reservation {
+                    self.publish_change(&ChangeEventParams::reservation(
+                        ChangeEventKind::ReservationCancelled,
+                        cancelled_reservation,
+                        epoch_now,
+                    )?)?;
+                }
+                Ok(cancelled_reservation)
+            },
+        )
+    }
+
+    /// Add up what users or teams owe for the reservations that they made during a billing period.
+    ///
+    /// # Arguments
+    /// - `period_start`: Beginning of the billing period, represented by Unix epoch format.
+    /// - `period_end`: End of the billing period, represented by Unix epoch format.
+    /// - `grouping`: Whether invoices are for users or teams.
+    pub fn invoices(
+        &self,
+        period_start: u32,
+        period_end: u32,
+        grouping: InvoiceGrouping,
+    ) -> Result<Vec<Invoice>> {
+        ensure!(
+            period_start < period_end,
+            format!(
+                "Billing period from \"{period_start}\" to \"{period_end}\" begins before it ends"
+            )
+        );
+        let reservations = self
+            .datastore
+            .get_reservations_created_between(period_start, period_end)?;
+        Ok(self
+            .config
+            .billing
+            .invoices(&reservations, (period_start, period_end), grouping))
+    }
+
+    /// Change what a capacity segment costs from now on.
+    ///
+    /// Reservations that were already granted keep what they cost.
+    ///
+    /// # Returns
+    /// Whether there was a segment in the pool that starts at the given time.
+    pub fn price_segment(&self, segment_pricing: &SegmentPricing) -> Result<bool> {
+        let was_priced = self.datastore.set_segment_price(
+            &segment_pricing.pool,
+            segment_pricing.start_time,
+            segment_pricing.microcredits_per_unit_second,
+        )?;
+        if was_priced {
+            info!(
+                "Priced capacity segment in pool \"{}\" starting at \"{}\"",
+                segment_pricing.pool, segment_pricing.start_time
+            );
+            self.publish_change(&ChangeEventParams::schedule(
+                &segment_pricing.pool,
+                "segment_priced",
+                segment_pricing,
+                self.now(),
+            )?)?;
+        }
+        Ok(was_priced)
+    }
+
+    /// Get evaluated reservation requests that match a filter, in the order they were evaluated.
+    pub fn evaluated_requests(
+        &self,
+        request_filter: &RequestFilter,
+    ) -> Result<Vec<EvaluatedRequest>> {
+        self.datastore.get_evaluated_requests(request_filter)
+    }
+
+    /// Add up the capacity that denied reservation requests asked for.
+    ///
+    /// # Arguments
+    /// - `request_filter`: Which requests to add up. Only denied requests are counted, whatever
+    ///   outcome the filter asks for.
+    /// - `group_by`: Dimensions to group the unmet demand by, like pool and user.
+    pub fn unmet_demand(
+        &self,
+        request_filter: &RequestFilter,
+        group_by: &[DemandDimension],
+        bucket_length: Duration,
+    ) -> Result<Vec<UnmetDemand>> {
+        let denied_filter = RequestFilter {
+            outcome: Some(RequestOutcome::Denied),
+            ..request_filter.clone()
+        };
+        let denied_requests = self.datastore.get_evaluated_requests(&denied_filter)?;
+        unmet_demand(&denied_requests, group_by, bucket_length)
+    }
+
+    /// Find how much capacity is available in a pool during a timeframe.
+    ///
+    /// The timeframe's split wherever the schedule, maintenance windows, or user reservations
+    /// change, so every slot has a steady amount of available capacity.
+    pub fn check_availability(
+        &self,
+        pool: &str,
+        start_time: u32,
+        end_time: u32,
+    ) -> Result<Vec<AvailabilitySlot>> {
+        ensure!(
+            start_time < end_time,
+            format!("Availability timeframe from \"{start_time}\" to \"{end_time}\" begins before it ends")
+        );
+        let active_schedule: CapacitySchedule = self.datastore.get_schedule()?;
+        let maintenance_windows: Vec<MaintenanceWindow> = self
+            .datastore
+            .get_overlapping_maintenance_windows(pool, start_time, end_time)?;
+        let user_reservations: Vec<Reservation> = self
+            .datastore
+            .get_overlapping_reservations(pool, start_time, end_time)?;
+        let availability = capacity_timeline(
+            pool,
+            start_time,
+            end_time,
+            &active_schedule,
+            &maintenance_windows,
+            &user_reservations,
+        );
+        Ok(availability)
+    }
+
+    /// Work out how much of a pool's capacity segments were reserved during every bucket.
+    pub fn utilization_report(
+        &self,
+        pool: &str,
+        report_buckets: &ReportBuckets,
+    ) -> Result<Vec<UtilizationRow>> {
+        let pool_schedule = self.datastore.get_schedule()?.for_pool(pool);
+        let timeline =
+            self.check_availability(pool, report_buckets.start_time, report_buckets.end_time)?;
+        Ok(utilization_report(
+            report_buckets,
+            &pool_schedule.segments,
+            &timeline,
+        ))
+    }
+
+    /// Work out how much of a pool's capacity every user booked during every bucket.
+    pub fn usage_report(
+        &self,
+        pool: &str,
+        report_buckets: &ReportBuckets,
+    ) -> Result<Vec<UsageRow>> {
+        let user_reservations = self.datastore.get_overlapping_reservations(
+            pool,
+            report_buckets.start_time,
+            report_buckets.end_time,
+        )?;
+        Ok(usage_report(report_buckets, &user_reservations))
+    }
+
+    /// Work out the peak and average capacity that's reserved from a pool during every bucket.
+    pub fn load_report(&self, pool: &str, report_buckets: &ReportBuckets) -> Result<Vec<LoadRow>> {
+        let timeline =
+            self.check_availability(pool, report_buckets.start_time, report_buckets.end_time)?;
+        Ok(load_report(report_buckets, &timeline))
+    }
+
+    /// Work out how many of a pool's reservation requests were denied during every bucket.
+    pub fn denial_report(
+        &self,
+        pool: &str,
+        report_buckets: &ReportBuckets,
+    ) -> Result<Vec<DenialRow>> {
+        let pool_filter = RequestFilter {
+            pool: Some(String::from(pool)),
+            ..Default::default()
+        };
+        let evaluated_requests = self.datastore.get_evaluated_requests(&pool_filter)?;
+        Ok(denial_report(report_buckets, &evaluated_requests))
+    }
+
+    /// Find the most capacity that's reserved at once in a pool during a timeframe.
+    ///
+    /// The datastore works this out without sending every reservation back.
+    pub fn peak_reserved_amount(&self, pool: &str, start_time: u32, end_time: u32) -> Result<u32> {
+        ensure!(
+            start_time < end_time,
+            format!("Peak usage timeframe from \"{start_time}\" to \"{end_time}\" begins before it ends")
+        );
+        self.datastore
+            .get_peak_reserved_amount(pool, start_time, end_time)
+    }
+
+    /// Measure what every pool with a schedule looks like right now: how much of its capacity is
+    /// used, and how many of its reservations are pending or active.
+    pub fn pool_gauges(&self) -> Result<Vec<PoolGauges>> {
+        let epoch_now = self.now();
+        let pools: BTreeSet<String> = self
+            .datastore
+            .get_schedule()?
+            .segments
+            .into_iter()
+            .map(|capacity_segment| capacity_segment.pool)
+            .collect();
+        let reservation_counts = self.datastore.get_reservation_counts()?;
+        let count_of = |pool: &str, status: ReservationStatus| {
+            reservation_counts
+                .iter()
+                .find(|reservation_count| {
+                    reservation_count.pool == pool && reservation_count.status == status
+                })
+                .map_or(0, |reservation_count| reservation_count.count)
+        };
+        let mut pool_gauges = Vec::new();
+        for pool in pools {
+            let utilization = self
+                .check_availability(&pool, epoch_now, epoch_now + 1)?
+                .first()
+                .filter(|slot| slot.scheduled_amount > 0)
+                .map_or(0.0, |slot| {
+                    f64::from(slot.reserved_amount + slot.maintenance_amount)
+                        / f64::from(slot.scheduled_amount)
+                });
+            pool_gauges.push(PoolGauges {
+                pending_count: count_of(&pool, ReservationStatus::Pending),
+                active_count: count_of(&pool, ReservationStatus::Active),
+                pool,
+                utilization,
+            });
+        }
+        Ok(pool_gauges)
+    }
+
+    /// Take some (or all) of a pool's capacity offline for a while.
+    ///
+    /// Existing reservations aren't moved, so a maintenance window can overbook a pool. New
+    /// reservation requests are evaluated against what's left.
+    pub fn schedule_maintenance(
+        &self,
+        maintenance_params: &MaintenanceParams,
+    ) -> Result<MaintenanceWindow> {
+        validate_unix_epoch(maintenance_params.start_time)?;
+        validate_unix_epoch(maintenance_params.end_time)?;
+        ensure!(
+            maintenance_params.start_time < maintenance_params.end_time,
+            format!(
+                "Maintenance window from \"{}\" to \"{}\" begins before it ends",
+                maintenance_params.start_time, maintenance_params.end_time
+            )
+        );
+        ensure!(
+            maintenance_params.reduction != CapacityReduction::Amount(0),
+            "Maintenance window doesn't take any capacity offline"
+        );
+        ensure!(
+            !maintenance_params.reason.trim().is_empty(),
+            "Maintenance window needs a reason"
+        );
+        let epoch_now = self.now();
+        let maintenance_window = self.watching_utilization(
+            &maintenance_params.pool,
+            (maintenance_params.start_time, maintenance_params.end_time),
+            epoch_now,
+            || {
+                let maintenance_window =
+                    self.datastore.add_maintenance_window(maintenance_params)?;
+                self.publish_change(&ChangeEventParams::schedule(
+                    &maintenance_window.pool,
+                    "maintenance_scheduled",
+                    &maintenance_window,
+                    epoch_now,
+                )?)?;
+                Ok(maintenance_window)
+            },
+        )?;
+        info!("Scheduled {}", maintenance_window);
+        Ok(maintenance_window)
+    }
+
+    /// Get every maintenance window.
+    pub fn list_maintenance(&self) -> Result<Vec<MaintenanceWindow>> {
+        self.datastore.get_maintenance_windows()
+    }
+
+    /// Bring capacity back online by removing a maintenance window.
+    ///
+    /// # Returns
+    /// Whether there was a maintenance window with the given ID.
+    pub fn cancel_maintenance(&self, window_id: u32) -> Result<bool> {
+        let Some(maintenance_window) = self
+            .datastore
+            .get_maintenance_windows()?
+            .into_iter()
+            .find(|maintenance_window| maintenance_window.window_id == window_id)
+        else {
+            return Ok(false);
+        };
+        let epoch_now = self.now();
+        let was_cancelled = self.watching_utilization(
+            &maintenance_window.pool,
+            (maintenance_window.start_time, maintenance_window.end_time),
+            epoch_now,
+            || {
+                let was_cancelled = self.datastore.delete_maintenance_window(window_id)?;
+                if was_cancelled {
+                    self.publish_change(&ChangeEventParams::schedule(
+                        &maintenance_window.pool,
+                        "maintenance_cancelled",
+                        &maintenance_window,
+                        epoch_now,
+                    )?)?;
+                }
+                Ok(was_cancelled)
+            },
+        )?;
+        if was_cancelled {
+            info!("Cancelled maintenance window \"{}\"", window_id);
+        }
+        Ok(was_cancelled)
+    }
+
+    /// Move reservations along their lifecycle: pending ones that have started become active,
+    /// and active ones that have ended become completed.
+    ///
+    /// Transitions are recorded at the boundary they happened at, so ones that were missed while
+    /// nothing was running are caught up on with the right times. A reservation that another
+    /// server already moved along is skipped.
+    ///
+    /// # Returns
+    /// The reservations that were moved along, as they ended up.
+    pub fn advance_lifecycles(&self) -> Result<Vec<Reservation>> {
+        let epoch_now = self.now();
+        let mut advanced_reservations = Vec::new();
+        for mut reservation in self.datastore.get_due_reservations(epoch_now)? {
+            if reservation.status == ReservationStatus::Pending {
+                let Some(started_reservation) = self.advance_reservation(
+                    &reservation,
+                    LedgerEventKind::Started,
+                    ChangeEventKind::ReservationStarted,
+                    reservation.start_time,
+                )?
+                else {
+                    continue;
+                };
+                reservation = started_reservation;
+            }
+            if reservation.status == ReservationStatus::Active && reservation.end_time <= epoch_now
+            {
+                let Some(completed_reservation) = self.advance_reservation(
+                    &reservation,
+                    LedgerEventKind::Completed,
+                    ChangeEventKind::ReservationEnded,
+                    reservation.end_time,
+                )?
+                else {
+                    continue;
+                };
+                reservation = completed_reservation;
+            }
+            advanced_reservations.push(reservation);
+        }
+        Ok(advanced_reservations)
+    }
+
+    /// Get the next time that a reservation is due to move along its lifecycle, or `None` if no
+    /// reservation is waiting to start or end.
+    pub fn next_lifecycle_boundary(&self) -> Result<Option<u32>> {
+        self.datastore.get_next_lifecycle_boundary(self.now())
+    }
+
+    /// Take one step along a reservation's lifecycle, and tell followers that it was taken.
+    ///
+    /// # Returns
+    /// The reservation after the step, or `None` if it had already been taken.
+    fn advance_reservation(
+        &self,
+        reservation: &Reservation,
+        lifecycle_kind: LedgerEventKind,
+        change_kind: ChangeEventKind,
+        boundary_time: u32,
+    ) -> Result<Option<Reservation>> {
+        let advanced_reservation = self.datastore.advance_reservation(
+            reservation.reservation_id,
+            lifecycle_kind,
+            boundary_time,
+        )?;
+        if let Some(advanced_reservation) = &advanced_reservation {
+            self.publish_change(&ChangeEventParams::reservation(
+                change_kind,
+                advanced_reservation,
+                boundary_time,
+            )?)?;
+            info!("Moved along {}", advanced_reservation);
+        }
+        Ok(advanced_reservation)
+    }
+
+    /// Get notified whenever a change event is recorded.
+    ///
+    /// The notice is the latest event's ID. Followers should read what changed with
+    /// `changes_after()`, since notices can be skipped if changes come quickly.
+    pub fn subscribe_changes(&self) -> watch::Receiver<u32> {
+        self.change_notifier.subscribe()
+    }
+
+    /// Get changes that match a filter and were recorded after an event, oldest first.
+    ///
+    /// # Arguments
+    /// - `change_filter`: Which changes to get.
+    /// - `after_event_id`: Last event that the caller saw, or 0 for every change.
+    /// - `limit`: Most changes to get.
+    pub fn changes_after(
+        &self,
+        change_filter: &ChangeFilter,
+        after_event_id: u32,
+        limit: u32,
+    ) -> Result<Vec<ChangeEvent>> {
+        self.datastore
+            .get_change_events(change_filter, after_event_id, limit)
+    }
+
+    /// Get the latest change event's ID, or 0 if nothing's changed yet.
+    pub fn last_change_id(&self) -> Result<u32> {
+        self.datastore.get_last_change_event_id()
+    }
+
+    /// Subscribe a webhook to changes from now on.
+    pub fn register_webhook(&self, webhook_params: &WebhookParams) -> Result<WebhookSubscription> {
+        webhook_params.validate()?;
+        let last_event_id = self.datastore.get_last_change_event_id()?;
+        let subscription =
+            self.datastore
+                .add_webhook_subscription(webhook_params, last_event_id, self.now())?;
+        info!("Registered {}", subscription);
+        Ok(subscription)
+    }
+
+    /// Get every webhook subscription.
+    pub fn list_webhooks(&self) -> Result<Vec<WebhookSubscription>> {
+        self.datastore.get_webhook_subscriptions()
+    }
+
+    /// Stop sending changes to a webhook.
+    ///
+    /// # Returns
+    /// Whether there was a subscription with the given ID.
+    pub fn delete_webhook(&self, subscription_id: u32) -> Result<bool> {
+        let was_deleted = self
+            .datastore
+            .delete_webhook_subscription(subscription_id)?;
+        if was_deleted {
+            info!("Deleted webhook subscription \"{}\"", subscription_id);
+        }
+        Ok(was_deleted)
+    }
+
+    /// Get every attempt to deliver changes to a webhook, oldest first.
+    pub fn webhook_deliveries(&self, subscription_id: u32) -> Result<Vec<WebhookDelivery>> {
+        self.datastore.get_webhook_deliveries(subscription_id)
+    }
+
+    /// Log an attempt to deliver a change to a webhook.
+    pub fn record_webhook_delivery(
+        &self,
+        delivery_params: &WebhookDeliveryParams,
+    ) -> Result<WebhookDelivery> {
+        self.datastore.record_webhook_delivery(delivery_params)
+    }
+
+    /// Find the change that webhooks should be sent after, so that nothing is skipped after a
+    /// restart.
+    ///
+    /// # Returns
+    /// The last change that was handed out to every subscription, or `None` if there aren't any
+    /// subscriptions yet.
+    pub fn webhook_resume_id(&self) -> Result<Option<u32>> {
+        Ok(self
+            .datastore
+            .get_webhook_subscriptions()?
+            .iter()
+            .map(|subscription| subscription.last_event_id)
+            .min())
+    }
+
+    /// Find the webhooks that a change should be sent to, and mark it as handed out to every
+    /// webhook so it isn't sent again.
+    pub fn claim_webhook_subscriptions(
+        &self,
+        change_event: &ChangeEvent,
+    ) -> Result<Vec<WebhookSubscription>> {
+        let subscriptions = self
+            .datastore
+            .get_webhook_subscriptions()?
+            .into_iter()
+            .filter(|subscription| subscription.matches(change_event))
+            .collect();
+        self.datastore
+            .advance_webhook_subscriptions(change_event.event_id)?;
+        Ok(subscriptions)
+    }
+
+    /// Record a change event and wake up anyone that's following changes.
+    fn publish_change(&self, change_params: &ChangeEventParams) -> Result<ChangeEvent> {
+        let change_event = self.datastore.record_change_event(change_params)?;
+        self.announce_change(&change_event);
+        Ok(change_event)
+    }
+
+    /// Wake up anyone that's following changes to a change that's already been recorded.
+    fn announce_change(&self, change_event: &ChangeEvent) {
+        self.change_notifier.send_replace(change_event.event_id);
+        debug!("Published {}", change_event);
+    }
+
+    /// Change a pool's timeframe, then tell followers about utilization thresholds that the
+    /// change crossed.
+    ///
+    /// # Arguments
+    /// - `pool`: Pool that's changing.
+    /// - `timeframe`: Timeframe that's changing, represented by Unix epoch format.
+    /// - `epoch_now`: When the change happens, represented by Unix epoch format.
+    /// - `change`: What changes the pool.
+    fn watching_utilization<Changed>(
+        &self,
+        pool: &str,
+        timeframe: (u32, u32),
+        epoch_now: u32,
+        change: impl FnOnce() -> Result<Changed>,
+    ) -> Result<Changed> {
+        self.watching_utilizations(&[(pool.to_string(), timeframe)], epoch_now, change)
+    }
+
+    /// Change some pools' timeframes at once, then tell followers about utilization thresholds
+    /// that the change crossed.
+    ///
+    /// Utilization is only measured if there are thresholds to cross. Once the change is made it's
+    /// kept, so failing to tell followers about thresholds is logged instead of returned.
+    ///
+    /// # Arguments
+    /// - `watched_timeframes`: Pools and timeframes that might change, represented by Unix epoch
+    ///   format.
+    /// - `epoch_now`: When the change happens, represented by Unix epoch format.
+    /// - `change`: What changes the pools.
+    fn watching_utilizations<Changed>(
+        &self,
+        watched_timeframes: &[(String, (u32, u32))],
+        epoch_now: u32,
+        change: impl FnOnce() -> Result<Changed>,
+    ) -> Result<Changed> {
+        if self.config.utilization_thresholds.is_empty() {
+            return change();
+        }
+        let mut previous_utilization_percents = Vec::new();
+        for (pool, (start_time, end_time)) in watched_timeframes {
+            previous_utilization_percents.push(peak_utilization_percent(
+                &self.check_availability(pool, *start_time, *end_time)?,
+            ));
+        }
+        let changed = change()?;
+        for ((pool, timeframe), previous_utilization_percent) in
+            watched_timeframes.iter().zip(previous_utilization_percents)
+        {
+            if let Err(threshold_error) = self.publish_threshold_crossings(
+                pool,
+                *timeframe,
+                epoch_now,
+                previous_utilization_percent,
+            ) {
+                error!(
+                    "Failed to publish utilization thresholds crossed in pool \"{}\": {:#}",
+                    pool, threshold_error
+                );
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Tell followers about utilization thresholds that a pool's timeframe crossed since it was
+    /// last measured.
+    ///
+    /// # Arguments
+    /// - `previous_utilization_percent`: Busiest moment of the timeframe before it changed.
+    fn publish_threshold_crossings(
+        &self,
+        pool: &str,
+        timeframe: (u32, u32),
+        epoch_now: u32,
+        previous_utilization_percent: u32,
+    ) -> Result<()> {
+        let (start_time, end_time) = timeframe;
+        let utilization_percent =
+            peak_utilization_percent(&self.check_availability(pool, start_time, end_time)?);
+        for threshold_crossing in threshold_crossings(
+            &self.config.utilization_thresholds,
+            timeframe,
+            previous_utilization_percent,
+            utilization_percent,
+        ) {
+            self.publish_change(&ChangeEventParams::threshold_crossed(
+                pool,
+                &threshold_crossing,
+                epoch_now,
+            )?)?;
+        }
+        Ok(())
+    }
 }
 
-/// Ensure that reservation begin time is in the future.
+/// What became of a reservation request once its pool was booked.
+enum Booked {
+    /// Reservation was granted, and the slot with the least capacity left before it was.
+    Granted(Reservation, AvailabilitySlot),
+    /// There wasn't enough capacity, as shown by the slot with the least capacity left.
+    NotEnoughCapacity(AvailabilitySlot),
+    /// Request was refused, and the slot with the least capacity left if it got that far.
+    Refused(anyhow::Error, Option<AvailabilitySlot>),
+}
+
+impl Booked {
+    /// Fill in how the request was evaluated.
+    ///
+    /// # Returns
+    /// The decision, and why the request was denied if it was.
+    fn decide(
+        self,
+        request_evaluation: &mut RequestEvaluation,
+    ) -> (Result<Option<Reservation>>, Option<DenialKind>) {
+        match self {
+            Booked::Granted(granted_reservation, bottleneck) => {
+                request_evaluation.bottleneck = Some(Bottleneck::from(&bottleneck));
+                request_evaluation.outcome = RequestOutcome::Approved;
+                request_evaluation.reservation_id = Some(granted_reservation.reservation_id);
+                (Ok(Some(granted_reservation)), None)
+            }
+            Booked::NotEnoughCapacity(bottleneck) => {
+                request_evaluation.bottleneck = Some(Bottleneck::from(&bottleneck));
+                request_evaluation.denial_reason = Some(not_enough_capacity(&bottleneck));
+                (Ok(None), Some(DenialKind::NotEnoughCapacity))
+            }
+            Booked::Refused(request_error, bottleneck) => {
+                request_evaluation.bottleneck = bottleneck.as_ref().map(Bottleneck::from);
+                request_evaluation.denial_reason = Some(format!("{:#}", request_error));
+                let denial_kind = DenialKind::of_error(&request_error);
+                (Err(request_error), Some(denial_kind))
+            }
+        }
+    }
+}
+
+/// Report what became of a request in a batch that was decided on its own.
+fn batch_item_result(
+    index: usize,
+    reservation_request: &ReservationRequest,
+    decision: Result<Option<Reservation>>,
+    request_evaluation: RequestEvaluation,
+) -> BatchItemResult {
+    match decision {
+        Ok(Some(granted_reservation)) => BatchItemResult::reserved(index, &granted_reservation),
+        Ok(None) => BatchItemResult::denied(
+            index,
+            DenialKind::NotEnoughCapacity,
+            request_evaluation.denial_reason.unwrap_or_default(),
+        )
+        .with_timeframe(reservation_request),
+        Err(request_error) => BatchItemResult::denied(
+            index,
+            DenialKind::of_error(&request_error),
+            format!("{:#}", request_error),
+        )
+        .with_timeframe(reservation_request),
+    }
+}
+
+/// Explain that a request was refused because capacity ran out.
+fn not_enough_capacity(bottleneck: &AvailabilitySlot) -> String {
+    format!(
+        "Not enough capacity: only \"{}\" available from \"{}\" to \"{}\"",
+        bottleneck.available_amount, bottleneck.start_time, bottleneck.end_time
+    )
+}
+
+/// Explain that a request wasn't reserved because another request in its atomic batch wasn't.
+fn refused_with_batch(index: usize, refused_index: usize) -> BatchItemResult {
+    BatchItemResult::denied(
+        index,
+        DenialKind::BatchRefused,
+        format!(
+            "Refused with its atomic batch because request \"{refused_index}\" wasn't reserved"
+        ),
+    )
+}
+
+/// Read a relative time like `"now"` or `"+30m"` as an offset from now.
+fn parse_relative_time(relative_time: &str) -> Result<Duration> {
+    let relative_time = relative_time.trim();
+    if relative_time == "now" {
+        return Ok(Duration::ZERO);
+    }
+    let human_offset = relative_time.strip_prefix('+').ok_or_else(|| {
+        anyhow!("Time \"{relative_time}\" isn't a Unix epoch, \"now\", or an offset like \"+30m\"")
+    })?;
+    let offset = humantime::parse_duration(human_offset)
+        .map_err(|parse_error| anyhow!("Offset \"{relative_time}\" isn't valid: {parse_error}"))?;
+    Ok(offset)
+}
+
+/// Add a duration to a Unix epoch without overflowing it.
+fn add_duration(epoch: u32, offset: Duration) -> Result<u32> {
+    u32::try_from(offset.as_secs())
+        .ok()
+        .and_then(|offset_secs| epoch.checked_add(offset_secs))
+        .ok_or_else(|| anyhow!("Offset of {offset:?} from \"{epoch}\" isn't a valid Unix epoch"))
+}
+
+/// Ensure that reservation begin time is now or in the future.
 ///
-/// No one has a time machine for using caapacity reseved in the past.
-#[allow(unused)]
-fn starts_in_future(start_time: u32) -> Result<()> {
-    let system_now = SystemTime::now();
-    let time_since_epoch: Duration = system_now.duration_since(UNIX_EPOCH).unwrap();
-    let epoch_now = time_since_epoch.as_secs();
+/// No one has a time machine for using capacity reserved in the past. "Now" comes from the
+/// hostess's `Clock` so that the answer doesn't depend on when the tests happen to run.
+fn starts_in_future(start_time: u32, epoch_now: u32) -> Result<()> {
     ensure!(
-        start_time as u64 > epoch_now,
+        start_time >= epoch_now,
         format!(
-            "Reservation request with `start_time` \"{start_time}\" doesn't start in the future."
+            "Reservation request with `start_time` \"{start_time}\" starts in the past (now is \"{epoch_now}\")."
         )
     );
     Ok(())
@@ -43,20 +1574,19 @@ fn starts_in_future(start_time: u32) -> Result<()> {
 /// Ensure that start and end times are valid Unix epochs.
 ///
 /// Since there's no maximum or minimum number of seconds before or after Jan 1, 1970,
-/// we'll treat everything from Jan 1, 1970 to Jan 1, 2070 as a valid epoch date. Using 100 years
-/// (3,153,600,000 seconds) keeps us within a `u32` (max 4,294,967,295 seconds).
+/// we'll treat everything from Jan 1, 1970 to Jan 19, 2038 as a valid epoch date. Times are kept
+/// in the datastore's `INTEGER` columns, so the ceiling is the biggest signed 32-bit integer
+/// (2,147,483,647 seconds), even though a `u32` could go further.
 ///
 /// We can assume that no one wants to reserve capacity in the past, so time before the epcoch ("0")
 /// is ignored. The unsigned-ness of the integer type will bounce negative numbers at the API, but we
 /// check for them here, just to be certain.
 fn validate_unix_epoch(suspect_epoch: u32) -> Result<()> {
-    // 365 * 24 * 60 * 60 = 31536000 seconds in a year
-    // 315360000 * 100 = 3153600000 seconds in 100 years
-    // Set validity ceiling to 100 years in future from epoch.
-    let epoch_century: u32 = 3153600000;
-    // If it's a positive number corresponding to a date before 2870...
+    // Set validity ceiling to the last second that fits in an `INTEGER` column.
+    let epoch_ceiling: u32 = i32::MAX as u32;
+    // If it's a positive number corresponding to a date before 2038...
     ensure!(
-        suspect_epoch > 0 && suspect_epoch < epoch_century,
+        suspect_epoch > 0 && suspect_epoch <= epoch_ceiling,
         format!("Integer \"{suspect_epoch}\" isn't a valid Unix epoch")
     );
     debug!(
@@ -66,6 +1596,18 @@ fn validate_unix_epoch(suspect_epoch: u32) -> Result<()> {
     Ok(())
 }
 
+/// Ensure that a reservation request's times are valid Unix epochs and that it begins before it
+/// ends.
+fn validate_timeframe(reservation_request: &ReservationRequest) -> Result<()> {
+    validate_unix_epoch(reservation_request.start_time)?;
+    validate_unix_epoch(reservation_request.end_time)?;
+    ensure!(
+        reservation_request.start_time < reservation_request.end_time,
+        format!("Invalid reservation request begins before it ends: {reservation_request}")
+    );
+    Ok(())
+}
+
 /// Validate a capacity request as being in Arbiter's purview.
 ///
 /// Helper function for `evaluate_reservation_request()` that throws
@@ -78,18 +1620,18 @@ fn in_schedule_scope(
 ) -> Result<bool> {
     // todo: Optimize scope check iterators.
     let schedule_begin: u32 = capacity_schedule
-        .reservations
+        .segments
         .iter()
-        .min_by_key(|existing_reservation| existing_reservation.start_time)
-        .map(|existing_reservation| existing_reservation.start_time)
+        .min_by_key(|capacity_segment| capacity_segment.start_time)
+        .map(|capacity_segment| capacity_segment.start_time)
         .unwrap();
     debug!("Found capacity schedule's beginning: {}", schedule_begin);
     // todo: Optimize scope check iterators.
     let schedule_end: u32 = capacity_schedule
-        .reservations
+        .segments
         .iter()
-        .max_by_key(|existing_reservation| existing_reservation.end_time)
-        .map(|existing_reservation| existing_reservation.end_time)
+        .max_by_key(|capacity_segment| capacity_segment.end_time)
+        .map(|capacity_segment| capacity_segment.end_time)
         .unwrap();
     debug!("Found capacity schedule's ending: {}", schedule_end);
     let begins_in_scope: bool = reservation_request.start_time >= schedule_begin;
@@ -105,90 +1647,199 @@ fn in_schedule_scope(
     Ok(both_in_scope)
 }
 
+/// Split a pool's timeframe into slots with steady capacity.
+///
+/// Slot boundaries are wherever a capacity segment, maintenance window, or user reservation
+/// begins or ends. Time that's outside of the capacity schedule has no scheduled capacity.
+fn capacity_timeline(
+    pool: &str,
+    start_time: u32,
+    end_time: u32,
+    capacity_schedule: &CapacitySchedule,
+    maintenance_windows: &[MaintenanceWindow],
+    user_reservations: &[Reservation],
+) -> Vec<AvailabilitySlot> {
+    let overlaps = |other_start: u32, other_end: u32, slot_start: u32, slot_end: u32| {
+        other_start < slot_end && other_end > slot_start
+    };
+    let capacity_segments: Vec<_> = capacity_schedule
+        .segments
+        .iter()
+        .filter(|capacity_segment| capacity_segment.pool == pool)
+        .filter(|capacity_segment| {
+            overlaps(
+                capacity_segment.start_time,
+                capacity_segment.end_time,
+                start_time,
+                end_time,
+            )
+        })
+        .collect();
+    let maintenance_windows: Vec<_> = maintenance_windows
+        .iter()
+        .filter(|maintenance_window| maintenance_window.pool == pool)
+        .filter(|maintenance_window| {
+            overlaps(
+                maintenance_window.start_time,
+                maintenance_window.end_time,
+                start_time,
+                end_time,
+            )
+        })
+        .collect();
+    // Only count reservations that still hold capacity.
+    let user_reservations: Vec<_> = user_reservations
+        .iter()
+        .filter(|user_reservation| user_reservation.pool == pool)
+        .filter(|user_reservation| user_reservation.status.holds_capacity())
+        .filter(|user_reservation| {
+            overlaps(
+                user_reservation.start_time,
+                user_reservation.end_time,
+                start_time,
+                end_time,
+            )
+        })
+        .collect();
+
+    // Find every point in time where capacity could change.
+    let mut boundaries: Vec<u32> = vec![start_time, end_time];
+    boundaries.extend(
+        capacity_segments
+            .iter()
+            .flat_map(|capacity_segment| [capacity_segment.start_time, capacity_segment.end_time]),
+    );
+    boundaries.extend(maintenance_windows.iter().flat_map(|maintenance_window| {
+        [maintenance_window.start_time, maintenance_window.end_time]
+    }));
+    boundaries.extend(
+        user_reservations
+            .iter()
+            .flat_map(|user_reservation| [user_reservation.start_time, user_reservation.end_time]),
+    );
+    boundaries.retain(|boundary| (start_time..=end_time).contains(boundary));
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let mut timeline = Vec::new();
+    for slot in boundaries.windows(2) {
+        let (slot_start, slot_end) = (slot[0], slot[1]);
+        let scheduled_amount: u32 = capacity_segments
+            .iter()
+            .filter(|capacity_segment| {
+                overlaps(
+                    capacity_segment.start_time,
+                    capacity_segment.end_time,
+                    slot_start,
+                    slot_end,
+                )
+            })
+            .map(|capacity_segment| capacity_segment.capacity_amount)
+            .sum();
+        // Stack every maintenance window that's happening during the slot.
+        let online_amount = maintenance_windows
+            .iter()
+            .filter(|maintenance_window| {
+                overlaps(
+                    maintenance_window.start_time,
+                    maintenance_window.end_time,
+                    slot_start,
+                    slot_end,
+                )
+            })
+            .fold(scheduled_amount, |online_amount, maintenance_window| {
+                maintenance_window.reduction.apply(online_amount)
+            });
+        let reserved_amount: u32 = user_reservations
+            .iter()
+            .filter(|user_reservation| {
+                overlaps(
+                    user_reservation.start_time,
+                    user_reservation.end_time,
+                    slot_start,
+                    slot_end,
+                )
+            })
+            .map(|user_reservation| user_reservation.capacity_amount)
+            .sum();
+        timeline.push(AvailabilitySlot {
+            start_time: slot_start,
+            end_time: slot_end,
+            scheduled_amount,
+            maintenance_amount: scheduled_amount - online_amount,
+            reserved_amount,
+            available_amount: online_amount.saturating_sub(reserved_amount),
+        });
+    }
+    timeline
+}
+
 /// Decide if a user reservation request can be fulfilled.
 ///
 /// The given timeslot's checked against the capacity schedule to see if there's enough idle
-/// capacity's available during that timeframe.
+/// capacity's available during that timeframe. Capacity that's offline for maintenance isn't
+/// available.
 ///
 /// While there are more efficient algorithms for finding a timeslot, here we prioritize a
 /// solution that's easy to modify and reason about. We're not anticipating a ton of requests
 /// every second, so performance isn't the first concern. Rather, the most likely question
 /// to follow an allocation denial is "why not?" Followed shortly by "then when?"
+///
+/// # Returns
+/// Whether the request can be fulfilled, and the slot with the least capacity left during its
+/// timeframe (the answer to "why not?").
 fn evaluate_reservation_request(
     reservation_request: &ReservationRequest,
     capacity_schedule: &CapacitySchedule,
-) -> Result<bool> {
+    maintenance_windows: &[MaintenanceWindow],
+    user_reservations: &[Reservation],
+    epoch_now: u32,
+) -> Result<(bool, AvailabilitySlot)> {
+    // Only consider the capacity pool that's being reserved from.
+    let capacity_schedule = &capacity_schedule.for_pool(&reservation_request.pool);
+
     // Ensure the given schedule isn't empty.
     ensure!(
-        !capacity_schedule.reservations.is_empty(),
-        "Given Capacity Schedule has no reservations"
+        !capacity_schedule.segments.is_empty(),
+        format!(
+            "Capacity pool \"{}\" has no capacity schedule",
+            reservation_request.pool
+        )
     );
 
-    // Ensure start and stop times are valid unix epochs.
-    validate_unix_epoch(reservation_request.start_time)?;
-    validate_unix_epoch(reservation_request.end_time)?;
+    // Ensure start and stop times are valid unix epochs, and the request begins before it ends.
+    validate_timeframe(reservation_request)?;
 
-    // Ensure reservation request begins before it ends.
-    ensure!(
-        reservation_request.start_time < reservation_request.end_time,
-        format!("Invalid reservation request begins before it ends: {reservation_request}")
-    );
-
-    // Ensure reservation request starts in the future.
-    // temp: disable b/c it interferes with historiccal data.
-    //starts_in_future(reservation_request.start_time);
+    // Ensure reservation request starts now or in the future.
+    starts_in_future(reservation_request.start_time, epoch_now)?;
 
     // Ensure requested period is in scope of capacity schedule.
-    let _in_scope: bool = in_schedule_scope(&reservation_request, &capacity_schedule)?;
+    let _in_scope: bool = in_schedule_scope(reservation_request, capacity_schedule)?;
 
     debug!("Evaluating {}", reservation_request);
-    // Track the total capacity for each timeframe-compatible capacity reservation.
-    let mut reservation_capacities: Vec<u32> = Vec::new();
-
-    // Find where the request's period overlaps with existing reservations.
-    for existing_reservation in capacity_schedule.reservations.iter() {
-        let starts_during: bool = reservation_request.start_time < existing_reservation.end_time;
-        let ends_during: bool = reservation_request.end_time > existing_reservation.start_time;
-        // If requested timeframe overlaps with an existing reservation...
-        if starts_during && ends_during {
-            debug!("Found overlap with existing {}", existing_reservation);
-            // todo: ... find how much they overlap so we can suggest right/left alternative timeframes later.
-            reservation_capacities.push(existing_reservation.capacity_amount);
-        }
-    }
-    debug!("Competing capacity usages: {:?}", reservation_capacities);
-
-    // Find sum of user capacities so we can check against total capacity ceilings.
-    let mut total_user_reservations = 0;
-
-    let user_reservations: CapacitySchedule = get_user_reservation_schedule().unwrap();
-    // Find where the request's period overlaps with existing reservations.
-    for user_reservation in user_reservations.reservations.iter() {
-        let starts_during: bool = reservation_request.start_time < user_reservation.end_time;
-        let ends_during: bool = reservation_request.end_time > user_reservation.start_time;
-        // If requested timeframe overlaps with a user reservation...
-        if starts_during && ends_during {
-            debug!("Found overlap with existing {}", user_reservation);
-            // todo: ... find how much they overlap so we can suggest right/left alternative timeframes later.
-            total_user_reservations += user_reservation.capacity_amount;
-        }
-    }
-    debug!(
-        "Sum of competing user reservation usages: {:?}",
-        total_user_reservations
+    // Find how much capacity is left whenever capacity, maintenance, or reservations change.
+    let timeline = capacity_timeline(
+        &reservation_request.pool,
+        reservation_request.start_time,
+        reservation_request.end_time,
+        capacity_schedule,
+        maintenance_windows,
+        user_reservations,
     );
 
-    // Find most limiting resource capacity among existing reservations during request timeframe
-    let capacity_ceiling: u32 = match reservation_capacities.iter().min() {
-        Some(min_found) => *min_found,
-        // Throw a runtime error if no limiting factors were found b/c impossible inside schedule bounds
+    // Find the slot with the least capacity left during request timeframe.
+    let bottleneck: AvailabilitySlot = match timeline
+        .into_iter()
+        .min_by_key(|slot| slot.available_amount)
+    {
+        Some(min_found) => min_found,
+        // Throw a runtime error if no slots were found b/c impossible inside schedule bounds
         None => return Err(anyhow!("No applicable reservation capacities were found.")),
     };
-    debug!("Limiting factor: {}", capacity_ceiling);
+    debug!("Limiting factor: {:?}", bottleneck);
 
-    // Check if lowest available capacity across concurrent reservations can sate request.
-    let is_reservable: bool =
-        capacity_ceiling >= total_user_reservations + reservation_request.capacity_amount;
+    // Check if lowest available capacity across the timeframe can sate request.
+    let is_reservable: bool = bottleneck.available_amount >= reservation_request.capacity_amount;
 
     let verbal_decree: &str = if is_reservable { "Approved" } else { "Denied" };
     info!(
@@ -196,7 +1847,7 @@ fn evaluate_reservation_request(
         verbal_decree, reservation_request.user_id, reservation_request
     );
 
-    Ok(is_reservable)
+    Ok((is_reservable, bottleneck))
 }
 
 /// Test if schedules are being assessed correctly.
@@ -206,10 +1857,40 @@ mod tests {
     #[allow(unused)]
     use log::{debug, error, info, trace, warn};
 
+    // Standard library crates.
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
     // Project crates.
-    use super::process_reservation;
+    use super::{capacity_timeline, evaluate_reservation_request, Hostess};
+    use crate::batch::{BatchOrdering, DenialKind};
+    use crate::billing::{BillingPolicy, InvoiceGrouping, SegmentPricing, SurgePricing};
+    use crate::clock::test_examples::{test_clock, TEST_EPOCH_NOW};
+    use crate::clock::FixedClock;
     use crate::common::test_examples::test_reservation_alpha;
-    use crate::common::ReservationRequest;
+    use crate::common::{
+        AvailabilitySlot, CapacityReduction, MaintenanceParams, MaintenanceWindow, Reservation,
+        ReservationStatus,
+    };
+    use crate::common::{RequestedTime, ReservationParams, ReservationRequest};
+    use crate::config::HostessConfig;
+    use crate::datastore::test_examples::{schedule_one, seed_test_pool, test_datastore};
+    use crate::demand::{Bottleneck, DemandDimension, RequestFilter, RequestOutcome};
+    use crate::events::{ChangeEventKind, ChangeFilter};
+    use crate::idempotency::{IdempotencyClaim, IdempotencyConflict};
+    use crate::ledger::LedgerEventKind;
+    use crate::listing::{ReservationFilter, ReservationSort, SortOrder};
+    use crate::policy::{GranularityMode, PolicyViolation, ReservationPolicy};
+
+    /// Hostess whose clock is stopped one day before Schedule 1 begins.
+    fn test_hostess() -> Hostess {
+        Hostess::new(
+            Arc::new(test_clock()),
+            test_datastore(),
+            HostessConfig::default(),
+        )
+    }
 
     //
     // Edge Cases: Impossible requests that are more than malformed arguments (which would have
@@ -221,8 +1902,18 @@ mod tests {
     fn test_reject_impossible_timeframe() {
         // First reservation of schedule one with swapped start and end times.
         let impossible_time_reservation = ReservationRequest::new(1708374608, 1707165008, 65, 42);
-        let is_reservable = process_reservation(&impossible_time_reservation);
-        assert!(is_reservable.is_err());
+        let is_reservable = test_hostess().process_reservation(&impossible_time_reservation);
+        // It's refused before the datastore is asked about a timeframe that it can't query.
+        assert!(format!("{:#}", is_reservable.unwrap_err()).contains("begins before it ends"));
+    }
+
+    #[test]
+    // Request that ends after the last time that fits in the datastore.
+    fn test_reject_epoch_too_big_for_datastore() {
+        let far_future_reservation =
+            ReservationRequest::new(1707165008, i32::MAX as u32 + 1, 1, 42);
+        let is_reservable = test_hostess().process_reservation(&far_future_reservation);
+        assert!(format!("{:#}", is_reservable.unwrap_err()).contains("isn't a valid Unix epoch"));
     }
 
     #[test]
@@ -230,7 +1921,7 @@ mod tests {
     fn test_reject_before_schedule_scope() {
         // First reservation of schedule One that starts 42 seconds earlier.
         let too_early_reservation = ReservationRequest::new(1707164966, 1708374608, 64, 42);
-        let is_reservable = process_reservation(&too_early_reservation);
+        let is_reservable = test_hostess().process_reservation(&too_early_reservation);
         assert!(is_reservable.is_err());
     }
 
@@ -239,7 +1930,7 @@ mod tests {
     fn test_reject_after_schedule_scope() {
         // Last reservation of schedule One that ends 42 seconds later.
         let too_late_reservation = ReservationRequest::new(1711398608, 1713213050, 64, 42);
-        let is_reservable = process_reservation(&too_late_reservation);
+        let is_reservable = test_hostess().process_reservation(&too_late_reservation);
         assert!(is_reservable.is_err());
     }
 
@@ -256,8 +1947,10 @@ mod tests {
     // timeframe where it first becomes available.
     #[test]
     fn test_within_fences_with_capacity() {
-        let is_reservable = process_reservation(&test_reservation_alpha()).unwrap();
-        assert!(is_reservable);
+        let is_reservable = test_hostess()
+            .process_reservation(&test_reservation_alpha())
+            .unwrap();
+        assert!(is_reservable.is_some());
     }
 
     // Reservation request that fit neatly inside of a "schedule fence" with insufficient capacity.
@@ -265,8 +1958,10 @@ mod tests {
     fn test_within_fences_no_capacity() {
         // Exact match for slot, but exceeds total capacity by one.
         let too_big_reservation = ReservationRequest::new(1707165008, 1708374608, 65, 42);
-        let is_reservable = process_reservation(&too_big_reservation).unwrap();
-        assert!(!is_reservable);
+        let is_reservable = test_hostess()
+            .process_reservation(&too_big_reservation)
+            .unwrap();
+        assert!(is_reservable.is_none());
     }
 
     // Reservation request that crosses "schedule fences" that has capacity.
@@ -282,8 +1977,10 @@ mod tests {
         // Crosses schedule slots and within capacity.
         let interloper_sufficient_capacity =
             ReservationRequest::new(1708374650, 1711398566, 32, 42);
-        let is_reservable = process_reservation(&interloper_sufficient_capacity).unwrap();
-        assert!(is_reservable);
+        let is_reservable = test_hostess()
+            .process_reservation(&interloper_sufficient_capacity)
+            .unwrap();
+        assert!(is_reservable.is_some());
     }
 
     // Reservation request that crosses "schedule fences" with insufficient capacity.
@@ -297,7 +1994,1083 @@ mod tests {
         // Crosses schedule slots and within capacity.
         let interloper_insufficient_capacity =
             ReservationRequest::new(1708374650, 1711398566, 33, 42);
-        let is_reservable = process_reservation(&interloper_insufficient_capacity).unwrap();
-        assert!(!is_reservable);
+        let is_reservable = test_hostess()
+            .process_reservation(&interloper_insufficient_capacity)
+            .unwrap();
+        assert!(is_reservable.is_none());
+    }
+
+    //
+    // Clock: "Now" comes from the hostess's clock instead of the system's.
+    //
+
+    // Reservation request that starts before the hostess's "now".
+    #[test]
+    fn test_reject_start_in_past() {
+        // Stop the clock 42 seconds after Schedule 1 begins.
+        let late_hostess = Hostess::new(
+            Arc::new(FixedClock::new(1707165050)),
+            test_datastore(),
+            HostessConfig::default(),
+        );
+        let is_reservable = late_hostess.process_reservation(&test_reservation_alpha());
+        assert!(is_reservable.is_err());
+    }
+
+    // Reservation request that starts at exactly the hostess's "now".
+    #[test]
+    fn test_accept_start_now() {
+        // Stop the clock exactly when Schedule 1 begins.
+        let test_request = ReservationRequest::new(1707165008, 1708374608, 1, 42);
+        let is_reservable =
+            evaluate_reservation_request(&test_request, &schedule_one(), &[], &[], 1707165008);
+        assert!(is_reservable.is_ok());
+    }
+
+    //
+    // Relative Times: "now" and offsets like "+30m" are resolved against the hostess's clock.
+    //
+
+    /// Hostess that needs ten minutes to spin capacity up.
+    fn lead_time_hostess() -> Hostess {
+        let config = HostessConfig {
+            provisioning_lead_time: Duration::from_secs(600),
+            ..Default::default()
+        };
+        Hostess::new(Arc::new(test_clock()), test_datastore(), config)
+    }
+
+    // Reservation that starts "now" and lasts for an hour.
+    #[test]
+    fn test_resolve_now() {
+        let test_params = ReservationParams::new(
+            RequestedTime::Relative(String::from("now")),
+            RequestedTime::Relative(String::from("+1h")),
+            64,
+            42,
+        );
+        let test_hostess = test_hostess();
+        let reservation_request = test_hostess
+            .resolve_reservation_params(&test_params)
+            .unwrap();
+        assert_eq!(reservation_request.start_time, TEST_EPOCH_NOW);
+        assert_eq!(reservation_request.end_time, TEST_EPOCH_NOW + 3600);
+    }
+
+    // Reservation that starts "now" is pushed back by the provisioning lead time, but keeps its
+    // duration.
+    #[test]
+    fn test_resolve_now_with_lead_time() {
+        let test_params = ReservationParams::new(
+            RequestedTime::Relative(String::from("now")),
+            RequestedTime::Relative(String::from("+1h")),
+            64,
+            42,
+        );
+        let reservation_request = lead_time_hostess()
+            .resolve_reservation_params(&test_params)
+            .unwrap();
+        assert_eq!(reservation_request.start_time, TEST_EPOCH_NOW + 600);
+        assert_eq!(reservation_request.end_time, TEST_EPOCH_NOW + 600 + 3600);
+    }
+
+    // Reservation that starts further out than the provisioning lead time isn't pushed back.
+    #[test]
+    fn test_resolve_offset_beyond_lead_time() {
+        let test_params = ReservationParams::new(
+            RequestedTime::Relative(String::from("+30m")),
+            RequestedTime::Epoch(TEST_EPOCH_NOW + 7200),
+            64,
+            42,
+        );
+        let reservation_request = lead_time_hostess()
+            .resolve_reservation_params(&test_params)
+            .unwrap();
+        assert_eq!(reservation_request.start_time, TEST_EPOCH_NOW + 1800);
+        assert_eq!(reservation_request.end_time, TEST_EPOCH_NOW + 7200);
+    }
+
+    // Exact times aren't touched by the provisioning lead time.
+    #[test]
+    fn test_resolve_exact_times() {
+        let test_params = ReservationParams::new(
+            RequestedTime::Epoch(TEST_EPOCH_NOW),
+            RequestedTime::Epoch(TEST_EPOCH_NOW + 3600),
+            64,
+            42,
+        );
+        let reservation_request = lead_time_hostess()
+            .resolve_reservation_params(&test_params)
+            .unwrap();
+        assert_eq!(reservation_request.start_time, TEST_EPOCH_NOW);
+        assert_eq!(reservation_request.end_time, TEST_EPOCH_NOW + 3600);
+    }
+
+    // Relative times that are neither "now" nor an offset.
+    #[test]
+    fn test_reject_unknown_relative_time() {
+        for bad_time in ["tomorrow", "30m", "+soon", "-30m"] {
+            let test_params = ReservationParams::new(
+                RequestedTime::Relative(String::from(bad_time)),
+                RequestedTime::Relative(String::from("+1h")),
+                64,
+                42,
+            );
+            let reservation_request = lead_time_hostess().resolve_reservation_params(&test_params);
+            assert!(reservation_request.is_err(), "accepted \"{bad_time}\"");
+        }
+    }
+
+    // Reservation that ends "now" would end before it begins.
+    #[test]
+    fn test_reject_end_now() {
+        let test_params = ReservationParams::new(
+            RequestedTime::Relative(String::from("now")),
+            RequestedTime::Relative(String::from("now")),
+            64,
+            42,
+        );
+        let reservation_request = lead_time_hostess().resolve_reservation_params(&test_params);
+        assert!(reservation_request.is_err());
+    }
+
+    //
+    // Policy: Limits on the shape of reservations.
+    //
+
+    /// Hostess with every policy limit turned on.
+    ///
+    /// Reservations can last up to a day, start up to a week from now with at least an hour of
+    /// notice, and must fit on a 15 minute grid.
+    fn policy_hostess(granularity_mode: GranularityMode) -> Hostess {
+        let config = HostessConfig {
+            policy: ReservationPolicy {
+                max_duration: Some(Duration::from_secs(86400)),
+                max_horizon: Some(Duration::from_secs(604800)),
+                min_notice: Some(Duration::from_secs(3600)),
+                time_granularity: Some(Duration::from_secs(900)),
+                granularity_mode,
+            },
+            ..Default::default()
+        };
+        Hostess::new(Arc::new(test_clock()), test_datastore(), config)
+    }
+
+    /// Get the policy violation that a reservation request was refused for.
+    fn policy_violation(
+        test_request: &ReservationRequest,
+        test_hostess: &Hostess,
+    ) -> PolicyViolation {
+        let refusal = test_hostess.process_reservation(test_request).unwrap_err();
+        refusal.downcast::<PolicyViolation>().unwrap()
+    }
+
+    // Reservation request that lasts longer than a day.
+    #[test]
+    fn test_reject_too_long() {
+        // Start when Schedule 1 begins (rounded to the grid) and last a day and 15 minutes.
+        let test_request = ReservationRequest::new(1707165000, 1707252300, 1, 42);
+        let violation = policy_violation(&test_request, &policy_hostess(GranularityMode::Reject));
+        assert!(matches!(violation, PolicyViolation::TooLong { .. }));
+    }
+
+    // Reservation request that starts more than a week from now.
+    #[test]
+    fn test_reject_too_far_ahead() {
+        let test_request = ReservationRequest::new(1710793800, 1710797400, 1, 42);
+        let violation = policy_violation(&test_request, &policy_hostess(GranularityMode::Reject));
+        assert!(matches!(violation, PolicyViolation::TooFarAhead { .. }));
+    }
+
+    // Reservation request that starts in less than an hour.
+    #[test]
+    fn test_reject_too_little_notice() {
+        // Start 15 minutes from now (rounded to the grid).
+        let test_request = ReservationRequest::new(1707079500, 1707083100, 1, 42);
+        let violation = policy_violation(&test_request, &policy_hostess(GranularityMode::Reject));
+        assert!(matches!(violation, PolicyViolation::TooLittleNotice { .. }));
+    }
+
+    // Reservation request with a start time that's off of the 15 minute grid.
+    #[test]
+    fn test_reject_off_grid() {
+        let test_request = ReservationRequest::new(1707165008, 1707168600, 1, 42);
+        let violation = policy_violation(&test_request, &policy_hostess(GranularityMode::Reject));
+        assert_eq!(
+            violation,
+            PolicyViolation::OffGrid {
+                time: 1707165008,
+                granularity: 900
+            }
+        );
+    }
+
+    // Reservation request that's off of the 15 minute grid is stretched out to it.
+    #[test]
+    fn test_snap_to_grid() {
+        let test_request = ReservationRequest::new(1707165008, 1707168608, 1, 42);
+        let reservable_request = policy_hostess(GranularityMode::Snap)
+            .admit_request(&test_request, TEST_EPOCH_NOW)
+            .unwrap();
+        assert_eq!(reservable_request.start_time, 1707165000);
+        assert_eq!(reservable_request.end_time, 1707169500);
+    }
+
+    // Policy is only applied once the request is processed, so resolving leaves times alone.
+    #[test]
+    fn test_resolve_doesnt_snap() {
+        let test_params = ReservationParams::new(
+            RequestedTime::Epoch(1707165008),
+            RequestedTime::Epoch(1707168608),
+            1,
+            42,
+        );
+        let reservation_request = policy_hostess(GranularityMode::Snap)
+            .resolve_reservation_params(&test_params)
+            .unwrap();
+        assert_eq!(reservation_request.start_time, 1707165008);
+        assert_eq!(reservation_request.end_time, 1707168608);
+    }
+
+    // Snapping doesn't move a start time with enough notice to one without enough notice.
+    #[test]
+    fn test_snap_keeps_notice() {
+        // Ask for an hour that starts just over an hour from now.
+        let test_request =
+            ReservationRequest::new(TEST_EPOCH_NOW + 3601, TEST_EPOCH_NOW + 7201, 1, 42);
+        let reservable_request = policy_hostess(GranularityMode::Snap)
+            .admit_request(&test_request, TEST_EPOCH_NOW)
+            .unwrap();
+        assert!(reservable_request.start_time >= TEST_EPOCH_NOW + 3600);
+        assert_eq!(reservable_request.start_time % 900, 0);
+    }
+
+    // Snapping the start time later can leave a short reservation with no time at all.
+    #[test]
+    fn test_reject_empty_on_grid() {
+        // Ask for a second that starts just over an hour from now, in the same grid cell.
+        let test_request =
+            ReservationRequest::new(TEST_EPOCH_NOW + 3601, TEST_EPOCH_NOW + 3602, 1, 42);
+        let violation = policy_violation(&test_request, &policy_hostess(GranularityMode::Snap));
+        assert_eq!(
+            violation,
+            PolicyViolation::EmptyOnGrid {
+                start_time: 1707083100,
+                end_time: 1707083100,
+                granularity: 900
+            }
+        );
+    }
+
+    //
+    // Maintenance: Capacity that's offline isn't available for reservations.
+    //
+
+    // Maintenance windows and reservations split the timeline into slots with steady capacity.
+    //
+    // Schedule 1's first segment has 64 capacity. A 16 capacity maintenance window overlaps the
+    // first half of a 32 capacity reservation.
+    #[test]
+    fn test_capacity_timeline() {
+        let maintenance_window = MaintenanceWindow {
+            window_id: 1,
+            pool: String::from("default"),
+            start_time: 1707165008,
+            end_time: 1707166008,
+            reduction: CapacityReduction::Amount(16),
+            reason: String::from("reseating DIMMs"),
+        };
+        let user_reservation = Reservation {
+            reservation_id: 1,
+            start_time: 1707165508,
+            end_time: 1707166508,
+            capacity_amount: 32,
+            user_id: 42,
+            pool: String::from("default"),
+            status: ReservationStatus::Pending,
+            created_at: TEST_EPOCH_NOW,
+            updated_at: TEST_EPOCH_NOW,
+            cost_microcredits: 0,
+            refund_microcredits: 0,
+        };
+        let timeline = capacity_timeline(
+            "default",
+            1707165008,
+            1707167008,
+            &schedule_one(),
+            &[maintenance_window],
+            &[user_reservation],
+        );
+        let slot = |start_time, end_time, maintenance_amount, reserved_amount, available_amount| {
+            AvailabilitySlot {
+                start_time,
+                end_time,
+                scheduled_amount: 64,
+                maintenance_amount,
+                reserved_amount,
+                available_amount,
+            }
+        };
+        assert_eq!(
+            timeline,
+            vec![
+                slot(1707165008, 1707165508, 16, 0, 48),
+                slot(1707165508, 1707166008, 16, 32, 16),
+                slot(1707166008, 1707166508, 0, 32, 32),
+                slot(1707166508, 1707167008, 0, 0, 64),
+            ]
+        );
+    }
+
+    // Other pools' maintenance windows don't affect availability.
+    #[test]
+    fn test_capacity_timeline_other_pool() {
+        let maintenance_window = MaintenanceWindow {
+            window_id: 1,
+            pool: String::from("elsewhere"),
+            start_time: 1707165008,
+            end_time: 1707166008,
+            reduction: CapacityReduction::All,
+            reason: String::from("flooding"),
+        };
+        let timeline = capacity_timeline(
+            "default",
+            1707165008,
+            1707166008,
+            &schedule_one(),
+            &[maintenance_window],
+            &[],
+        );
+        assert_eq!(timeline.len(), 1);
+        assert_eq!(timeline[0].available_amount, 64);
+    }
+
+    // Reservation request that would fit if part of the pool weren't offline.
+    #[test]
+    fn test_maintenance_amount_reduces_capacity() {
+        let test_pool = "test_maintenance_amount";
+        seed_test_pool(test_pool);
+        let test_hostess = test_hostess();
+        test_hostess
+            .schedule_maintenance(&MaintenanceParams {
+                pool: String::from(test_pool),
+                start_time: 1707165008,
+                end_time: 1707251408,
+                reduction: CapacityReduction::Amount(16),
+                reason: String::from("replacing PDUs"),
+            })
+            .unwrap();
+        // Schedule 1's first segment has 64 capacity, but only 48 is online.
+        let too_big_reservation =
+            ReservationRequest::new(1707165008, 1708374608, 49, 42).in_pool(test_pool);
+        let is_reservable = test_hostess
+            .process_reservation(&too_big_reservation)
+            .unwrap();
+        assert!(is_reservable.is_none());
+        // What's left online can still be reserved.
+        let online_reservation =
+            ReservationRequest::new(1707165008, 1708374608, 48, 42).in_pool(test_pool);
+        let is_reservable = test_hostess
+            .process_reservation(&online_reservation)
+            .unwrap();
+        assert!(is_reservable.is_some());
+    }
+
+    // Reservation request during an outage that takes the whole pool offline.
+    #[test]
+    fn test_maintenance_all_blocks_capacity() {
+        let test_pool = "test_maintenance_all";
+        seed_test_pool(test_pool);
+        let test_hostess = test_hostess();
+        let maintenance_window = test_hostess
+            .schedule_maintenance(&MaintenanceParams {
+                pool: String::from(test_pool),
+                start_time: 1708374608,
+                end_time: 1708461008,
+                reduction: CapacityReduction::All,
+                reason: String::from("flooding"),
+            })
+            .unwrap();
+        let test_request =
+            ReservationRequest::new(1708374608, 1708461008, 1, 42).in_pool(test_pool);
+        let is_reservable = test_hostess.process_reservation(&test_request).unwrap();
+        assert!(is_reservable.is_none());
+        // Capacity comes back once the window's cancelled.
+        assert!(test_hostess
+            .cancel_maintenance(maintenance_window.window_id)
+            .unwrap());
+        let is_reservable = test_hostess.process_reservation(&test_request).unwrap();
+        assert!(is_reservable.is_some());
+    }
+
+    // Maintenance windows have to make sense.
+    #[test]
+    fn test_reject_invalid_maintenance() {
+        let test_hostess = test_hostess();
+        let backwards_window = MaintenanceParams {
+            pool: String::from("default"),
+            start_time: 1708461008,
+            end_time: 1708374608,
+            reduction: CapacityReduction::All,
+            reason: String::from("flooding"),
+        };
+        assert!(test_hostess
+            .schedule_maintenance(&backwards_window)
+            .is_err());
+        let reasonless_window = MaintenanceParams {
+            start_time: 1708374608,
+            end_time: 1708461008,
+            reason: String::from(" "),
+            ..backwards_window
+        };
+        assert!(test_hostess
+            .schedule_maintenance(&reasonless_window)
+            .is_err());
+    }
+
+    // Reservation request for a pool that has no capacity schedule.
+    #[test]
+    fn test_reject_unknown_pool() {
+        let test_request = test_reservation_alpha().in_pool("atlantis");
+        let is_reservable = test_hostess().process_reservation(&test_request);
+        assert!(is_reservable.is_err());
+    }
+
+    // Requests that are decided at the same time can't take the same capacity.
+    #[test]
+    fn test_concurrent_reservations_dont_double_book() {
+        let test_pool = "test_hostess_concurrent";
+        seed_test_pool(test_pool);
+        let hostess = test_hostess();
+        let granted_count = thread::scope(|scope| {
+            let deciders: Vec<_> = (0..8)
+                .map(|user_id| {
+                    let hostess = &hostess;
+                    scope.spawn(move || {
+                        let test_request =
+                            ReservationRequest::new(1707165008, 1707168608, 16, user_id)
+                                .in_pool(test_pool);
+                        hostess.process_reservation(&test_request).unwrap()
+                    })
+                })
+                .collect();
+            deciders
+                .into_iter()
+                .filter_map(|decider| decider.join().unwrap())
+                .count()
+        });
+        // The pool has 64 capacity, which is only enough for 4 of the 8 requests.
+        assert_eq!(granted_count, 4);
+        assert_eq!(
+            hostess
+                .peak_reserved_amount(test_pool, 1707165008, 1707168608)
+                .unwrap(),
+            64
+        );
+    }
+
+    // Peak usage that the database computes agrees with the capacity timeline.
+    #[test]
+    fn test_peak_reserved_amount() {
+        let test_pool = "test_peak_reserved_amount";
+        seed_test_pool(test_pool);
+        let test_hostess = test_hostess();
+        for (start_time, end_time, capacity_amount) in [
+            (1707165008, 1707251408, 10),
+            (1707208208, 1707294608, 20),
+            (1707294608, 1707381008, 5),
+        ] {
+            let test_request = ReservationRequest::new(start_time, end_time, capacity_amount, 42)
+                .in_pool(test_pool);
+            assert!(test_hostess
+                .process_reservation(&test_request)
+                .unwrap()
+                .is_some());
+        }
+        // First two reservations overlap.
+        let peak_reserved_amount = test_hostess
+            .peak_reserved_amount(test_pool, 1707165008, 1707381008)
+            .unwrap();
+        assert_eq!(peak_reserved_amount, 30);
+        let slots = test_hostess
+            .check_availability(test_pool, 1707165008, 1707381008)
+            .unwrap();
+        let timeline_peak = slots.iter().map(|slot| slot.reserved_amount).max();
+        assert_eq!(timeline_peak, Some(peak_reserved_amount));
+        // First reservation ends right as this timeframe begins.
+        let peak_reserved_amount = test_hostess
+            .peak_reserved_amount(test_pool, 1707251408, 1707381008)
+            .unwrap();
+        assert_eq!(peak_reserved_amount, 20);
+        // Nothing's reserved after the last reservation.
+        let peak_reserved_amount = test_hostess
+            .peak_reserved_amount(test_pool, 1707381008, 1707467408)
+            .unwrap();
+        assert_eq!(peak_reserved_amount, 0);
+    }
+
+    // Cancelling gives capacity back, and the ledger remembers why requests were denied.
+    #[test]
+    fn test_cancel_reservation() {
+        let test_pool = "test_cancel_reservation";
+        seed_test_pool(test_pool);
+        let test_hostess = test_hostess();
+        // Schedule 1 only has 64 capacity in its first segment.
+        let test_request =
+            ReservationRequest::new(1707165008, 1707251408, 64, 42).in_pool(test_pool);
+        let test_reservation = test_hostess
+            .process_reservation(&test_request)
+            .unwrap()
+            .unwrap();
+        assert!(test_hostess
+            .process_reservation(&test_request)
+            .unwrap()
+            .is_none());
+        let cancelled_reservation = test_hostess
+            .cancel_reservation(test_reservation.reservation_id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cancelled_reservation.status, ReservationStatus::Cancelled);
+        assert!(test_hostess
+            .cancel_reservation(test_reservation.reservation_id)
+            .unwrap()
+            .is_none());
+        assert!(test_hostess
+            .process_reservation(&test_request)
+            .unwrap()
+            .is_some());
+        let denial_reasons: Vec<Option<String>> = test_datastore()
+            .get_ledger_events()
+            .unwrap()
+            .into_iter()
+            .filter(|ledger_event| {
+                ledger_event.pool == test_pool && ledger_event.kind == LedgerEventKind::Denied
+            })
+            .map(|ledger_event| ledger_event.reason)
+            .collect();
+        assert_eq!(
+            denial_reasons,
+            vec![Some(String::from(
+                "Not enough capacity: only \"0\" available from \"1707165008\" to \"1707251408\""
+            ))]
+        );
+    }
+
+    // Reservations are charged for their segments and get some of it back when they're cancelled.
+    #[test]
+    fn test_charge_reservations() {
+        let test_pool = "test_charge_reservations";
+        seed_test_pool(test_pool);
+        test_datastore()
+            .set_segment_price(test_pool, 1707165008, 2)
+            .unwrap();
+        let config = HostessConfig {
+            billing: BillingPolicy {
+                full_refund_notice: Duration::from_secs(2 * 86400),
+                late_refund_percent: 50,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let test_hostess = Hostess::new(Arc::new(test_clock()), test_datastore(), config);
+        // User 4242 only makes reservations here, so their invoice is only for this test.
+        let test_request =
+            ReservationRequest::new(1707165008, 1707168608, 8, 4242).in_pool(test_pool);
+        let test_reservation = test_hostess
+            .process_reservation(&test_request)
+            .unwrap()
+            .unwrap();
+        assert_eq!(test_reservation.cost_microcredits, 8 * 3600 * 2);
+        // A day's notice is less than the policy asks for, so half is refunded.
+        let cancelled_reservation = test_hostess
+            .cancel_reservation(test_reservation.reservation_id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cancelled_reservation.refund_microcredits, 8 * 3600);
+        let invoices = test_hostess
+            .invoices(TEST_EPOCH_NOW, TEST_EPOCH_NOW + 1, InvoiceGrouping::User)
+            .unwrap();
+        let user_invoice = invoices
+            .iter()
+            .find(|invoice| invoice.user_id == Some(4242))
+            .unwrap();
+        assert_eq!(user_invoice.reservations, 1);
+        assert_eq!(user_invoice.total_microcredits, 8 * 3600);
+        assert!(test_hostess
+            .invoices(TEST_EPOCH_NOW, TEST_EPOCH_NOW, InvoiceGrouping::User)
+            .is_err());
+    }
+
+    // Idempotency keys are claimed once, answered, and then replayed for the same request only.
+    #[test]
+    fn test_idempotency_keys() {
+        let test_hostess = Hostess::new(
+            Arc::new(test_clock()),
+            test_datastore(),
+            HostessConfig::default(),
+        );
+        assert_eq!(
+            test_hostess
+                .claim_idempotency_key("test-hostess-retry", 4646, "first-hash")
+                .unwrap(),
+            IdempotencyClaim::Claimed
+        );
+        // Retries that arrive before the first request is answered have to wait.
+        let in_progress = test_hostess
+            .claim_idempotency_key("test-hostess-retry", 4646, "first-hash")
+            .unwrap_err();
+        assert!(in_progress.is::<IdempotencyConflict>());
+        test_hostess
+            .answer_idempotency_key("test-hostess-retry", 4646, "{\"is_reserved\":true}")
+            .unwrap();
+        assert_eq!(
+            test_hostess
+                .claim_idempotency_key("test-hostess-retry", 4646, "first-hash")
+                .unwrap(),
+            IdempotencyClaim::Answered(String::from("{\"is_reserved\":true}"))
+        );
+        let reused_key = test_hostess
+            .claim_idempotency_key("test-hostess-retry", 4646, "second-hash")
+            .unwrap_err();
+        assert!(reused_key.is::<IdempotencyConflict>());
+        assert!(test_hostess
+            .claim_idempotency_key("", 4646, "first-hash")
+            .is_err());
+    }
+
+    // Batches report what became of each request, in the order that they were sent.
+    #[test]
+    fn test_batch_reservations() {
+        let test_pool = "test_batch_reservations";
+        seed_test_pool(test_pool);
+        let test_hostess = test_hostess();
+        let batch_params = |start_time: u32, end_time: u32, capacity_amount: u32| {
+            let mut reservation_params = ReservationParams::new(
+                RequestedTime::Epoch(start_time),
+                RequestedTime::Epoch(end_time),
+                capacity_amount,
+                42,
+            );
+            reservation_params.pool = test_pool.to_string();
+            reservation_params
+        };
+        // Schedule 1's first segment has 64 capacity, so only one of each pair fits.
+        let mut unresolvable_params = batch_params(1707165008, 1707168608, 1);
+        unresolvable_params.end_time = RequestedTime::Relative(String::from("+soon"));
+        let in_order_batch = [
+            batch_params(1707165008, 1707168608, 8),
+            batch_params(1707165008, 1707168608, 64),
+            unresolvable_params,
+        ];
+        let in_order_results = test_hostess
+            .process_batch(&in_order_batch, BatchOrdering::InOrder, false)
+            .unwrap();
+        assert_eq!(in_order_results.len(), 3);
+        assert!(in_order_results[0].is_reserved);
+        assert!(in_order_results[0].reservation_id.is_some());
+        assert_eq!(
+            in_order_results[1].denial.as_ref().unwrap().kind,
+            DenialKind::NotEnoughCapacity
+        );
+        assert_eq!(in_order_results[1].start_time, Some(1707165008));
+        assert_eq!(
+            in_order_results[2].denial.as_ref().unwrap().kind,
+            DenialKind::InvalidRequest
+        );
+        // Packing tries the biggest request first, so it's the small one that doesn't fit.
+        let packed_batch = [
+            batch_params(1707168608, 1707172208, 8),
+            batch_params(1707168608, 1707172208, 64),
+        ];
+        let packed_results = test_hostess
+            .process_batch(&packed_batch, BatchOrdering::OptimizePacking, false)
+            .unwrap();
+        assert_eq!(packed_results[0].index, 0);
+        assert!(!packed_results[0].is_reserved);
+        assert!(packed_results[1].is_reserved);
+        assert!(test_hostess
+            .process_batch(&[], BatchOrdering::InOrder, false)
+            .is_err());
+    }
+
+    // Atomic batches roll back what they reserved when any of their requests isn't reserved.
+    #[test]
+    fn test_atomic_batch_reservations() {
+        let test_pool = "test_atomic_batch_reservations";
+        seed_test_pool(test_pool);
+        let test_hostess = test_hostess();
+        let batch_params = |capacity_amount: u32| {
+            let mut reservation_params = ReservationParams::new(
+                RequestedTime::Epoch(1707165008),
+                RequestedTime::Epoch(1707168608),
+                capacity_amount,
+                42,
+            );
+            reservation_params.pool = test_pool.to_string();
+            reservation_params
+        };
+        let refused_batch = [batch_params(8), batch_params(64), batch_params(1)];
+        let refused_results = test_hostess
+            .process_batch(&refused_batch, BatchOrdering::InOrder, true)
+            .unwrap();
+        let denial_kinds: Vec<_> = refused_results
+            .iter()
+            .map(|batch_result| batch_result.denial.as_ref().unwrap().kind)
+            .collect();
+        assert_eq!(
+            denial_kinds,
+            vec![
+                DenialKind::BatchRefused,
+                DenialKind::NotEnoughCapacity,
+                DenialKind::BatchRefused
+            ]
+        );
+        // The first request was booked, then rolled back with the rest of the batch.
+        assert!(test_datastore()
+            .get_overlapping_reservations(test_pool, 1707165008, 1707168608)
+            .unwrap()
+            .is_empty());
+        // Nothing is held, so the whole batch fits once the big request is smaller.
+        let granted_batch = [batch_params(8), batch_params(56)];
+        let granted_results = test_hostess
+            .process_batch(&granted_batch, BatchOrdering::InOrder, true)
+            .unwrap();
+        assert!(granted_results
+            .iter()
+            .all(|batch_result| batch_result.is_reserved));
+    }
+
+    // Atomic batches keep nothing, not even redeemed quotes, when booking fails partway through.
+    #[test]
+    fn test_atomic_batch_failure_rolls_back() {
+        let test_pool = "test_atomic_batch_failure";
+        let overpriced_pool = "test_atomic_batch_failure_overpriced";
+        seed_test_pool(test_pool);
+        seed_test_pool(overpriced_pool);
+        let test_hostess = test_hostess();
+        // Reservations in the overpriced pool cost more than the datastore can keep, so adding
+        // one fails.
+        assert!(test_hostess
+            .price_segment(&SegmentPricing {
+                pool: overpriced_pool.to_string(),
+                start_time: 1707165008,
+                microcredits_per_unit_second: i64::MAX as u64,
+            })
+            .unwrap());
+        let test_request =
+            ReservationRequest::new(1707165008, 1707168608, 8, 42).in_pool(test_pool);
+        let price_quote = test_hostess.quote_reservation(&test_request).unwrap();
+        let batch_params = |pool: &str, quote_token: Option<&str>| {
+            let mut reservation_params = ReservationParams::new(
+                RequestedTime::Epoch(1707165008),
+                RequestedTime::Epoch(1707168608),
+                8,
+                42,
+            );
+            reservation_params.pool = pool.to_string();
+            reservation_params.quote_token = quote_token.map(String::from);
+            reservation_params
+        };
+        let failing_batch = [
+            batch_params(test_pool, Some(&price_quote.quote_token)),
+            batch_params(overpriced_pool, None),
+        ];
+        assert!(test_hostess
+            .process_batch(&failing_batch, BatchOrdering::InOrder, true)
+            .is_err());
+        assert_eq!(
+            test_hostess
+                .peak_reserved_amount(test_pool, 1707165008, 1707168608)
+                .unwrap(),
+            0
+        );
+        // The quote wasn't used up, and the pool isn't left booked.
+        assert!(test_hostess
+            .process_quoted_reservation(&test_request, &price_quote.quote_token)
+            .unwrap()
+            .is_some());
+    }
+
+    // Pages of reservations end with a cursor for the next page, until the last one.
+    #[test]
+    fn test_list_reservations() {
+        let test_pool = "test_list_reservations";
+        seed_test_pool(test_pool);
+        let test_hostess = test_hostess();
+        for start_time in [1707165008, 1707168608, 1707172208] {
+            let test_request =
+                ReservationRequest::new(start_time, start_time + 3600, 1, 42).in_pool(test_pool);
+            test_hostess.process_reservation(&test_request).unwrap();
+        }
+        let pool_filter = ReservationFilter {
+            pool: Some(test_pool.to_string()),
+            ..Default::default()
+        };
+        let first_page = test_hostess
+            .list_reservations(
+                &pool_filter,
+                ReservationSort::EndTime,
+                SortOrder::Asc,
+                2,
+                None,
+            )
+            .unwrap();
+        assert_eq!(first_page.reservations.len(), 2);
+        assert_eq!(first_page.reservations[0].start_time, 1707165008);
+        let last_page = test_hostess
+            .list_reservations(
+                &pool_filter,
+                ReservationSort::EndTime,
+                SortOrder::Asc,
+                2,
+                first_page.next_cursor.as_deref(),
+            )
+            .unwrap();
+        assert_eq!(last_page.reservations.len(), 1);
+        assert_eq!(last_page.reservations[0].start_time, 1707172208);
+        assert_eq!(last_page.next_cursor, None);
+        // Cursors only work for the order that they came from, and pages can't be empty.
+        assert!(test_hostess
+            .list_reservations(
+                &pool_filter,
+                ReservationSort::Id,
+                SortOrder::Asc,
+                2,
+                first_page.next_cursor.as_deref(),
+            )
+            .is_err());
+        assert!(test_hostess
+            .list_reservations(&pool_filter, ReservationSort::Id, SortOrder::Asc, 0, None)
+            .is_err());
+    }
+
+    // Quotes cost more as capacity runs out, and can only be redeemed once for their own terms.
+    #[test]
+    fn test_quote_reservations() {
+        let test_pool = "test_quote_reservations";
+        seed_test_pool(test_pool);
+        test_datastore()
+            .set_segment_price(test_pool, 1707165008, 1)
+            .unwrap();
+        let config = HostessConfig {
+            billing: BillingPolicy {
+                surge: SurgePricing {
+                    threshold_percent: 50,
+                    max_price_percent: 300,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let test_hostess = Hostess::new(Arc::new(test_clock()), test_datastore(), config);
+        // Half of Schedule 1's first segment is idle, so it's quoted at the usual price.
+        let idle_request =
+            ReservationRequest::new(1707165008, 1707168608, 32, 42).in_pool(test_pool);
+        let idle_quote = test_hostess.quote_reservation(&idle_request).unwrap();
+        assert_eq!(idle_quote.price_percent, 100);
+        assert_eq!(idle_quote.price_microcredits, 32 * 3600);
+        assert_eq!(idle_quote.expires_at, TEST_EPOCH_NOW + 300);
+        // Quotes don't hold capacity.
+        let busy_request =
+            ReservationRequest::new(1707165008, 1707168608, 48, 43).in_pool(test_pool);
+        let busy_quote = test_hostess.quote_reservation(&busy_request).unwrap();
+        assert_eq!(busy_quote.price_percent, 200);
+        assert_eq!(busy_quote.price_microcredits, 48 * 3600 * 2);
+        // The quote only redeems its own terms.
+        assert!(test_hostess
+            .process_quoted_reservation(&idle_request, &busy_quote.quote_token)
+            .is_err());
+        let busy_reservation = test_hostess
+            .process_quoted_reservation(&busy_request, &busy_quote.quote_token)
+            .unwrap()
+            .unwrap();
+        assert_eq!(busy_reservation.cost_microcredits, 48 * 3600 * 2);
+        // Each quote is only redeemed once, even after the capacity it bought is given back.
+        test_hostess
+            .cancel_reservation(busy_reservation.reservation_id)
+            .unwrap();
+        assert!(test_hostess
+            .process_quoted_reservation(&busy_request, &busy_quote.quote_token)
+            .is_err());
+        assert!(test_hostess
+            .process_quoted_reservation(&idle_request, "no such quote")
+            .is_err());
+        // Requests that don't fit aren't quoted.
+        let huge_request =
+            ReservationRequest::new(1707165008, 1707168608, 65, 42).in_pool(test_pool);
+        assert!(test_hostess.quote_reservation(&huge_request).is_err());
+    }
+
+    //
+    // Request History: Every evaluated request is kept for capacity planning.
+    //
+
+    // Approved and denied requests are both recorded, with why denied ones were refused.
+    #[test]
+    fn test_record_evaluated_requests() {
+        let test_pool = "test_record_evaluated_requests";
+        seed_test_pool(test_pool);
+        let test_hostess = policy_hostess(GranularityMode::Reject);
+        // Schedule 1's first segment has 64 capacity, so the second request doesn't fit.
+        let approved_request =
+            ReservationRequest::new(1707165900, 1707169500, 48, 42).in_pool(test_pool);
+        let denied_request =
+            ReservationRequest::new(1707165900, 1707169500, 32, 43).in_pool(test_pool);
+        let off_grid_request =
+            ReservationRequest::new(1707165908, 1707169500, 1, 44).in_pool(test_pool);
+        let approved_reservation = test_hostess
+            .process_reservation(&approved_request)
+            .unwrap()
+            .unwrap();
+        assert!(test_hostess
+            .process_reservation(&denied_request)
+            .unwrap()
+            .is_none());
+        assert!(test_hostess.process_reservation(&off_grid_request).is_err());
+
+        let request_filter = RequestFilter {
+            pool: Some(String::from(test_pool)),
+            ..Default::default()
+        };
+        let evaluated_requests = test_hostess.evaluated_requests(&request_filter).unwrap();
+        assert_eq!(evaluated_requests.len(), 3);
+        assert_eq!(evaluated_requests[0].outcome, RequestOutcome::Approved);
+        assert_eq!(
+            evaluated_requests[0].reservation_id,
+            Some(approved_reservation.reservation_id)
+        );
+        // Denied for capacity: the bottleneck shows how much was left.
+        assert_eq!(evaluated_requests[1].outcome, RequestOutcome::Denied);
+        assert_eq!(evaluated_requests[1].user_id, 43);
+        assert_eq!(
+            evaluated_requests[1].bottleneck,
+            Some(Bottleneck {
+                start_time: 1707165900,
+                end_time: 1707169500,
+                available_amount: 16
+            })
+        );
+        // Denied for breaking the policy: capacity was never considered.
+        assert_eq!(evaluated_requests[2].outcome, RequestOutcome::Denied);
+        assert!(evaluated_requests[2]
+            .denial_reason
+            .as_ref()
+            .unwrap()
+            .contains("isn't a multiple"));
+        assert_eq!(evaluated_requests[2].bottleneck, None);
+
+        let unmet_demand = test_hostess
+            .unmet_demand(
+                &request_filter,
+                &[DemandDimension::User],
+                Duration::from_secs(86400),
+            )
+            .unwrap();
+        assert_eq!(unmet_demand.len(), 2);
+        assert_eq!(unmet_demand[0].user_id, Some(43));
+        assert_eq!(unmet_demand[0].denied_capacity_amount, 32);
+        assert_eq!(unmet_demand[0].denied_unit_seconds, 32 * 3600);
+    }
+
+    // Reservation and schedule changes are recorded for followers, along with the utilization
+    // thresholds that they cross.
+    #[test]
+    fn test_publish_changes() {
+        let test_pool = "test_publish_changes";
+        seed_test_pool(test_pool);
+        let test_hostess = test_hostess();
+        let change_notices = test_hostess.subscribe_changes();
+        let first_event_id = test_hostess.last_change_id().unwrap();
+        // Schedule 1's first segment has 64 capacity, so 52 of it is over 80%.
+        let test_request =
+            ReservationRequest::new(1707165008, 1707251408, 52, 42).in_pool(test_pool);
+        let test_reservation = test_hostess
+            .process_reservation(&test_request)
+            .unwrap()
+            .unwrap();
+        // Taking the rest offline fills the pool.
+        let maintenance_window = test_hostess
+            .schedule_maintenance(&MaintenanceParams {
+                pool: String::from(test_pool),
+                start_time: 1707165008,
+                end_time: 1707251408,
+                reduction: CapacityReduction::Amount(12),
+                reason: String::from("replacing PDUs"),
+            })
+            .unwrap();
+        assert!(test_hostess
+            .cancel_maintenance(maintenance_window.window_id)
+            .unwrap());
+        test_hostess
+            .cancel_reservation(test_reservation.reservation_id)
+            .unwrap()
+            .unwrap();
+
+        let pool_filter = ChangeFilter {
+            pool: Some(String::from(test_pool)),
+            user_id: None,
+        };
+        let change_events = test_hostess
+            .changes_after(&pool_filter, first_event_id, 100)
+            .unwrap();
+        let changes: Vec<(ChangeEventKind, String)> = change_events
+            .iter()
+            .map(|change_event| {
+                let detail = match change_event.kind {
+                    ChangeEventKind::ScheduleChanged => &change_event.payload["change"],
+                    ChangeEventKind::UtilizationThresholdCrossed => {
+                        &change_event.payload["threshold_percent"]
+                    }
+                    _ => &change_event.payload["status"],
+                };
+                (change_event.kind, detail.to_string())
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    ChangeEventKind::ReservationCreated,
+                    String::from("\"pending\"")
+                ),
+                (
+                    ChangeEventKind::UtilizationThresholdCrossed,
+                    String::from("80")
+                ),
+                (
+                    ChangeEventKind::ScheduleChanged,
+                    String::from("\"maintenance_scheduled\"")
+                ),
+                (
+                    ChangeEventKind::UtilizationThresholdCrossed,
+                    String::from("100")
+                ),
+                (
+                    ChangeEventKind::ScheduleChanged,
+                    String::from("\"maintenance_cancelled\"")
+                ),
+                (
+                    ChangeEventKind::UtilizationThresholdCrossed,
+                    String::from("100")
+                ),
+                (
+                    ChangeEventKind::ReservationCancelled,
+                    String::from("\"cancelled\"")
+                ),
+                (
+                    ChangeEventKind::UtilizationThresholdCrossed,
+                    String::from("80")
+                ),
+            ]
+        );
+        assert_eq!(change_events[0].user_id, Some(42));
+        assert_eq!(change_events[1].payload["direction"], "rising");
+        assert_eq!(change_events[7].payload["direction"], "falling");
+        // Followers were told about the latest change.
+        assert_eq!(
+            *change_notices.borrow(),
+            change_events.last().unwrap().event_id
+        );
     }
 }
@@ -0,0 +1,121 @@
+//! Idempotency
+//!
+//! `idempotency` keeps requests that clients retry from being carried out twice.
+//!
+//! Clients send an `Idempotency-Key` header with requests that they might retry, like after a
+//! timeout. The first response to a key is kept, and retries with the same key and the same
+//! request get that response back instead of being evaluated again. Reusing a key for a different
+//! request is a conflict. Keys are forgotten once they're older than the retention period.
+
+// Standard library crates.
+use std::fmt;
+
+// External crates.
+use anyhow::{ensure, Result};
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Longest idempotency key that's accepted.
+pub const MAX_KEY_LENGTH: usize = 255;
+
+/// Request that was made with an idempotency key, and the response it got.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdempotencyRecord {
+    pub idempotency_key: String,
+    /// User that the request was for. Keys only have to be unique for each user.
+    pub user_id: u32,
+    /// Fingerprint of the request, so that keys can't be reused for other requests.
+    pub request_hash: String,
+    /// Response that was given, as JSON, or `None` while the request is being evaluated.
+    pub response_body: Option<String>,
+    /// When the key was first used, represented by Unix epoch format.
+    pub created_at: u32,
+    /// When the key is forgotten, represented by Unix epoch format.
+    pub expires_at: u32,
+}
+
+/// What to do with a request that came with an idempotency key.
+#[derive(Debug, PartialEq)]
+pub enum IdempotencyClaim {
+    /// The key is new, so evaluate the request and keep its response.
+    Claimed,
+    /// The request was already answered, so give the same response, as JSON.
+    Answered(String),
+}
+
+/// Idempotency key that can't be used for a request right now.
+#[derive(Debug, PartialEq)]
+pub struct IdempotencyConflict(pub String);
+
+impl fmt::Display for IdempotencyConflict {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.0)
+    }
+}
+
+impl std::error::Error for IdempotencyConflict {}
+
+/// Ensure an idempotency key is usable.
+pub fn validate_key(idempotency_key: &str) -> Result<()> {
+    ensure!(
+        !idempotency_key.trim().is_empty(),
+        "`Idempotency-Key` header is empty"
+    );
+    ensure!(
+        idempotency_key.len() <= MAX_KEY_LENGTH,
+        format!("`Idempotency-Key` header is longer than {MAX_KEY_LENGTH} characters")
+    );
+    Ok(())
+}
+
+/// Fingerprint a request, so that retries can be told apart from other requests.
+pub fn request_hash(request: &impl Serialize) -> Result<String> {
+    let request_json = serde_json::to_vec(request)?;
+    let digest = Sha256::digest(&request_json);
+    Ok(digest
+        .iter()
+        .map(|digest_byte| format!("{digest_byte:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    // Project crates.
+    use super::{request_hash, validate_key, MAX_KEY_LENGTH};
+    use crate::common::{RequestedTime, ReservationParams};
+
+    #[test]
+    fn test_request_hash() {
+        let test_params = ReservationParams::new(
+            RequestedTime::Epoch(1707165008),
+            RequestedTime::Relative(String::from("+1h")),
+            8,
+            42,
+        );
+        let same_params = ReservationParams::new(
+            RequestedTime::Epoch(1707165008),
+            RequestedTime::Relative(String::from("+1h")),
+            8,
+            42,
+        );
+        let other_params = ReservationParams::new(
+            RequestedTime::Epoch(1707165008),
+            RequestedTime::Relative(String::from("+1h")),
+            16,
+            42,
+        );
+        let test_hash = request_hash(&test_params).unwrap();
+        assert_eq!(test_hash.len(), 64);
+        assert_eq!(test_hash, request_hash(&same_params).unwrap());
+        assert_ne!(test_hash, request_hash(&other_params).unwrap());
+    }
+
+    #[test]
+    fn test_validate_key() {
+        assert!(validate_key("retry-7f3c").is_ok());
+        assert!(validate_key(" ").is_err());
+        assert!(validate_key(&"k".repeat(MAX_KEY_LENGTH + 1)).is_err());
+    }
+}
@@ -0,0 +1,384 @@
+//! Reservation Ledger
+//!
+//! `ledger` answers "who had what, when, and why did it change?"
+//!
+//! Every change to a reservation request or reservation is appended to the immutable
+//! `reservation_ledger` table in the same transaction as the change itself. The
+//! `user_reservations` table is only a shortcut: replaying the ledger from the beginning gives
+//! the same reservations, and replaying it up to a past time gives the reservations as they were
+//! then.
+
+// Standard library crates.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+// External crates.
+use anyhow::{anyhow, bail, Error, Result};
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use serde_derive::{Deserialize, Serialize};
+
+// Project crates.
+use crate::common::{Reservation, ReservationStatus};
+use crate::datastore::Datastore;
+
+/// Kinds of changes that are recorded in the ledger.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerEventKind {
+    /// User asked for capacity.
+    Requested,
+    /// Request was granted and became a reservation.
+    Approved,
+    /// Request was refused.
+    Denied,
+    /// Reservation's start time arrived.
+    Started,
+    /// Reservation's end time arrived.
+    Completed,
+    /// Reservation was given back before it ended.
+    Cancelled,
+    /// Reservation was taken away to make room for something more important.
+    Preempted,
+    /// Reservation ran out of time before it was used.
+    Expired,
+}
+
+impl LedgerEventKind {
+    /// Name of the event kind as it's stored in the datastore.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LedgerEventKind::Requested => "requested",
+            LedgerEventKind::Approved => "approved",
+            LedgerEventKind::Denied => "denied",
+            LedgerEventKind::Started => "started",
+            LedgerEventKind::Completed => "completed",
+            LedgerEventKind::Cancelled => "cancelled",
+            LedgerEventKind::Preempted => "preempted",
+            LedgerEventKind::Expired => "expired",
+        }
+    }
+
+    /// Status that a reservation ends up in after an event of this kind ends it.
+    ///
+    /// Events that don't end reservations don't have one.
+    pub fn ending_status(&self) -> Option<ReservationStatus> {
+        match self {
+            LedgerEventKind::Cancelled => Some(ReservationStatus::Cancelled),
+            LedgerEventKind::Preempted => Some(ReservationStatus::Preempted),
+            LedgerEventKind::Expired => Some(ReservationStatus::Expired),
+            LedgerEventKind::Requested
+            | LedgerEventKind::Approved
+            | LedgerEventKind::Denied
+            | LedgerEventKind::Started
+            | LedgerEventKind::Completed => None,
+        }
+    }
+
+    /// Status that a reservation has to be in for an event of this kind to move it along, and
+    /// the status that it moves to.
+    ///
+    /// Events that aren't part of a reservation running its course don't have one.
+    pub fn lifecycle_step(&self) -> Option<(ReservationStatus, ReservationStatus)> {
+        match self {
+            LedgerEventKind::Started => {
+                Some((ReservationStatus::Pending, ReservationStatus::Active))
+            }
+            LedgerEventKind::Completed => {
+                Some((ReservationStatus::Active, ReservationStatus::Completed))
+            }
+            LedgerEventKind::Requested
+            | LedgerEventKind::Approved
+            | LedgerEventKind::Denied
+            | LedgerEventKind::Cancelled
+            | LedgerEventKind::Preempted
+            | LedgerEventKind::Expired => None,
+        }
+    }
+}
+
+// Read event kinds back out of the datastore.
+impl FromStr for LedgerEventKind {
+    type Err = Error;
+
+    fn from_str(kind_name: &str) -> Result<Self, Self::Err> {
+        match kind_name {
+            "requested" => Ok(LedgerEventKind::Requested),
+            "approved" => Ok(LedgerEventKind::Approved),
+            "denied" => Ok(LedgerEventKind::Denied),
+            "started" => Ok(LedgerEventKind::Started),
+            "completed" => Ok(LedgerEventKind::Completed),
+            "cancelled" => Ok(LedgerEventKind::Cancelled),
+            "preempted" => Ok(LedgerEventKind::Preempted),
+            "expired" => Ok(LedgerEventKind::Expired),
+            unknown_kind => Err(anyhow!("Unknown ledger event kind \"{unknown_kind}\"")),
+        }
+    }
+}
+
+// Print instantiated enum nicely.
+impl fmt::Display for LedgerEventKind {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+/// One change that's been recorded in the ledger.
+///
+/// Every event carries the reservation's terms as they were after the change, so replaying
+/// doesn't need anything but the ledger.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LedgerEvent {
+    /// Order that the event was recorded in.
+    pub event_id: u32,
+    /// Reservation that changed, if the request became one.
+    pub reservation_id: Option<u32>,
+    pub kind: LedgerEventKind,
+    /// When the change happened, represented by Unix epoch format.
+    pub recorded_at: u32,
+    pub start_time: u32,
+    pub end_time: u32,
+    pub capacity_amount: u32,
+    pub user_id: u32,
+    pub pool: String,
+    /// Why the change happened, like a denial reason.
+    pub reason: Option<String>,
+    /// What the reservation cost after the change, in microcredits.
+    pub cost_microcredits: u64,
+    /// What the reservation got back after the change, in microcredits.
+    pub refund_microcredits: u64,
+}
+
+// Print instantiated struct nicely.
+impl fmt::Display for LedgerEvent {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "ledger event \"{}\" ({}) on \"{}\" for user ID \"{}\" \
+            of \"{}\" capacity \
+            in pool \"{}\" \
+            from \"{}\" to \"{}\"",
+            self.event_id,
+            self.kind,
+            self.recorded_at,
+            self.user_id,
+            self.capacity_amount,
+            self.pool,
+            self.start_time,
+            self.end_time
+        )
+    }
+}
+
+/// Work out reservations by replaying ledger events in the order they were recorded.
+///
+/// # Arguments
+/// - `ledger_events`: Every ledger event, in any order.
+/// - `as_of`: Only replay events recorded at or before this time, represented by Unix epoch
+///   format. Every event is replayed if it's missing.
+///
+/// # Returns
+/// The reservations, ordered by ID.
+pub fn replay(ledger_events: &[LedgerEvent], as_of: Option<u32>) -> Result<Vec<Reservation>> {
+    let mut ordered_events: Vec<&LedgerEvent> = ledger_events
+        .iter()
+        .filter(|ledger_event| as_of.is_none_or(|as_of| ledger_event.recorded_at <= as_of))
+        .collect();
+    ordered_events.sort_by_key(|ledger_event| ledger_event.event_id);
+
+    let mut reservations: BTreeMap<u32, Reservation> = BTreeMap::new();
+    for ledger_event in ordered_events {
+        // Requests that never became reservations don't change any reservation.
+        let Some(reservation_id) = ledger_event.reservation_id else {
+            continue;
+        };
+        match ledger_event.kind {
+            LedgerEventKind::Requested | LedgerEventKind::Denied => {}
+            LedgerEventKind::Approved => {
+                if reservations.contains_key(&reservation_id) {
+                    bail!("Reservation \"{reservation_id}\" was approved twice in {ledger_event}");
+                }
+                let approved_reservation = Reservation {
+                    reservation_id,
+                    start_time: ledger_event.start_time,
+                    end_time: ledger_event.end_time,
+                    capacity_amount: ledger_event.capacity_amount,
+                    user_id: ledger_event.user_id,
+                    pool: ledger_event.pool.clone(),
+                    status: ReservationStatus::Pending,
+                    created_at: ledger_event.recorded_at,
+                    updated_at: ledger_event.recorded_at,
+                    cost_microcredits: ledger_event.cost_microcredits,
+                    refund_microcredits: 0,
+                };
+                reservations.insert(reservation_id, approved_reservation);
+            }
+            LedgerEventKind::Started | LedgerEventKind::Completed => {
+                let advanced_reservation = replayed_reservation(&mut reservations, ledger_event)?;
+                if let Some((_, next_status)) = ledger_event.kind.lifecycle_step() {
+                    advanced_reservation.status = next_status;
+                }
+                advanced_reservation.updated_at = ledger_event.recorded_at;
+            }
+            LedgerEventKind::Cancelled | LedgerEventKind::Preempted | LedgerEventKind::Expired => {
+                let ended_reservation = replayed_reservation(&mut reservations, ledger_event)?;
+                if let Some(ending_status) = ledger_event.kind.ending_status() {
+                    ended_reservation.status = ending_status;
+                }
+                ended_reservation.refund_microcredits = ledger_event.refund_microcredits;
+                ended_reservation.updated_at = ledger_event.recorded_at;
+            }
+        }
+    }
+    Ok(reservations.into_values().collect())
+}
+
+/// Find the reservation that a ledger event changes.
+fn replayed_reservation<'a>(
+    reservations: &'a mut BTreeMap<u32, Reservation>,
+    ledger_event: &LedgerEvent,
+) -> Result<&'a mut Reservation> {
+    let reservation_id = ledger_event.reservation_id.unwrap_or_default();
+    reservations.get_mut(&reservation_id).ok_or_else(|| {
+        anyhow!("Reservation \"{reservation_id}\" wasn't approved before {ledger_event}")
+    })
+}
+
+/// Work out reservations from a datastore's ledger, as they are now or as they were in the past.
+pub fn reservations_as_of(
+    datastore: &dyn Datastore,
+    as_of: Option<u32>,
+) -> Result<Vec<Reservation>> {
+    let ledger_events = datastore.get_ledger_events()?;
+    replay(&ledger_events, as_of)
+}
+
+/// Rebuild a datastore's reservations from its ledger.
+///
+/// # Returns
+/// The number of reservations that were rebuilt.
+pub fn rebuild_reservations(datastore: &dyn Datastore) -> Result<usize> {
+    let reservations = reservations_as_of(datastore, None)?;
+    datastore.replace_reservations(&reservations)?;
+    info!(
+        "Rebuilt \"{}\" reservations from ledger",
+        reservations.len()
+    );
+    Ok(reservations.len())
+}
+
+#[cfg(test)]
+mod tests {
+    // Project crates.
+    use super::{replay, LedgerEvent, LedgerEventKind};
+    use crate::common::ReservationStatus;
+
+    /// Ledger event for Schedule 1's first segment.
+    fn test_event(
+        event_id: u32,
+        reservation_id: Option<u32>,
+        kind: LedgerEventKind,
+        recorded_at: u32,
+    ) -> LedgerEvent {
+        LedgerEvent {
+            event_id,
+            reservation_id,
+            kind,
+            recorded_at,
+            start_time: 1707165008,
+            end_time: 1708374608,
+            capacity_amount: 8,
+            user_id: 42,
+            pool: String::from("default"),
+            reason: None,
+            cost_microcredits: 0,
+            refund_microcredits: 0,
+        }
+    }
+
+    #[test]
+    fn test_replay_lifecycle() {
+        let ledger_events = vec![
+            test_event(1, Some(7), LedgerEventKind::Requested, 1707078608),
+            LedgerEvent {
+                cost_microcredits: 1000,
+                ..test_event(2, Some(7), LedgerEventKind::Approved, 1707078608)
+            },
+            test_event(3, None, LedgerEventKind::Requested, 1707078708),
+            test_event(4, None, LedgerEventKind::Denied, 1707078708),
+            LedgerEvent {
+                cost_microcredits: 1000,
+                refund_microcredits: 400,
+                ..test_event(5, Some(7), LedgerEventKind::Cancelled, 1707078908)
+            },
+        ];
+        let reservations = replay(&ledger_events, None).unwrap();
+        assert_eq!(reservations.len(), 1);
+        assert_eq!(reservations[0].reservation_id, 7);
+        assert_eq!(reservations[0].capacity_amount, 8);
+        assert_eq!(reservations[0].cost_microcredits, 1000);
+        assert_eq!(reservations[0].refund_microcredits, 400);
+        assert_eq!(reservations[0].status, ReservationStatus::Cancelled);
+        assert_eq!(reservations[0].created_at, 1707078608);
+        assert_eq!(reservations[0].updated_at, 1707078908);
+    }
+
+    #[test]
+    fn test_replay_as_of() {
+        let ledger_events = vec![
+            test_event(1, Some(7), LedgerEventKind::Approved, 1707078608),
+            test_event(2, Some(8), LedgerEventKind::Approved, 1707078708),
+            test_event(3, Some(7), LedgerEventKind::Preempted, 1707078808),
+        ];
+        // Nothing had happened yet.
+        assert!(replay(&ledger_events, Some(1707078607)).unwrap().is_empty());
+        // Only the first reservation existed, and it hadn't been preempted.
+        let reservations = replay(&ledger_events, Some(1707078608)).unwrap();
+        assert_eq!(reservations.len(), 1);
+        assert_eq!(reservations[0].status, ReservationStatus::Pending);
+        let reservations = replay(&ledger_events, Some(1707078808)).unwrap();
+        assert_eq!(reservations[0].status, ReservationStatus::Preempted);
+        assert_eq!(reservations[1].status, ReservationStatus::Pending);
+    }
+
+    #[test]
+    fn test_replay_runs_course() {
+        let ledger_events = vec![
+            test_event(1, Some(7), LedgerEventKind::Approved, 1707078608),
+            test_event(2, Some(7), LedgerEventKind::Started, 1707165008),
+            test_event(3, Some(7), LedgerEventKind::Completed, 1708374608),
+        ];
+        let reservations = replay(&ledger_events, Some(1707165008)).unwrap();
+        assert_eq!(reservations[0].status, ReservationStatus::Active);
+        let reservations = replay(&ledger_events, None).unwrap();
+        assert_eq!(reservations[0].status, ReservationStatus::Completed);
+        assert_eq!(reservations[0].updated_at, 1708374608);
+    }
+
+    #[test]
+    fn test_replay_in_recorded_order() {
+        let ledger_events = vec![
+            test_event(2, Some(7), LedgerEventKind::Expired, 1707078608),
+            test_event(1, Some(7), LedgerEventKind::Approved, 1707078608),
+        ];
+        let reservations = replay(&ledger_events, None).unwrap();
+        assert_eq!(reservations[0].status, ReservationStatus::Expired);
+    }
+
+    #[test]
+    fn test_replay_refuses_impossible_history() {
+        let unapproved_change = vec![test_event(
+            1,
+            Some(7),
+            LedgerEventKind::Cancelled,
+            1707078608,
+        )];
+        assert!(replay(&unapproved_change, None).is_err());
+        let approved_twice = vec![
+            test_event(1, Some(7), LedgerEventKind::Approved, 1707078608),
+            test_event(2, Some(7), LedgerEventKind::Approved, 1707078608),
+        ];
+        assert!(replay(&approved_twice, None).is_err());
+    }
+}
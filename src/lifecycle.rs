@@ -0,0 +1,235 @@
+//! Lifecycle
+//!
+//! `lifecycle` moves reservations along as time passes: a pending reservation becomes active when
+//! it starts, and an active reservation becomes completed when it ends.
+//!
+//! The engine wakes up at the next start or end time, or whenever something changes (since a new
+//! reservation might start sooner than anything it knew about), and moves along every reservation
+//! whose boundary has passed. Each step is recorded in the ledger and published as a change event
+//! at the boundary it happened at. Since the steps that are due are read from the datastore,
+//! boundaries that passed while Arbiter wasn't running are caught up on when it starts again.
+
+// Standard library crates.
+use std::sync::Arc;
+use std::time::Duration;
+
+// External crates.
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+
+// Project crates.
+use crate::hostess::Hostess;
+
+/// Longest time that the engine sleeps before looking for due reservations again, in case a
+/// boundary was missed, like when another server added a reservation.
+pub const LIFECYCLE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Keep moving reservations along their lifecycle until the server stops.
+pub async fn run_lifecycle_engine(hostess: Arc<Hostess>) {
+    let mut changes = hostess.subscribe_changes();
+    info!("Running reservation lifecycle engine");
+    loop {
+        // Take note of changes from here on, including the engine's own, so that anything that
+        // changes while it's busy wakes it up again.
+        changes.borrow_and_update();
+        let next_wakeup = advance_lifecycles(hostess.clone()).await;
+        tokio::select! {
+            _ = tokio::time::sleep(next_wakeup) => {}
+            Ok(()) = changes.changed() => {}
+        }
+    }
+}
+
+/// Move along every reservation that's due.
+///
+/// # Returns
+/// How long to sleep before the next reservation is due.
+async fn advance_lifecycles(hostess: Arc<Hostess>) -> Duration {
+    let advance_result = tokio::task::spawn_blocking(move || {
+        let advanced_reservations = hostess.advance_lifecycles()?;
+        let next_boundary = hostess.next_lifecycle_boundary()?;
+        anyhow::Ok((advanced_reservations.len(), next_boundary, hostess.now()))
+    })
+    .await;
+    match advance_result {
+        Ok(Ok((advanced_count, next_boundary, epoch_now))) => {
+            if advanced_count > 0 {
+                debug!("Moved along {} reservations", advanced_count);
+            }
+            next_boundary
+                .map(|next_boundary| {
+                    Duration::from_secs(next_boundary.saturating_sub(epoch_now).into())
+                })
+                .map_or(LIFECYCLE_POLL_INTERVAL, |until_boundary| {
+                    until_boundary.min(LIFECYCLE_POLL_INTERVAL)
+                })
+        }
+        Ok(Err(error_message)) => {
+            error!("Couldn't move reservations along: {:#}", error_message);
+            LIFECYCLE_POLL_INTERVAL
+        }
+        Err(join_error) => {
+            error!(
+                "Hostess didn't finish moving reservations along: {}",
+                join_error
+            );
+            LIFECYCLE_POLL_INTERVAL
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Standard library crates.
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // Project crates.
+    use super::run_lifecycle_engine;
+    use crate::clock::test_examples::TEST_EPOCH_NOW;
+    use crate::clock::FixedClock;
+    use crate::common::{Reservation, ReservationStatus};
+    use crate::config::{DatastoreBackend, DatastoreConfig, HostessConfig};
+    use crate::datastore::open_datastore;
+    use crate::datastore::test_examples::{seed_backend_pool, test_sqlite_path};
+    use crate::events::{ChangeEventKind, ChangeFilter};
+    use crate::hostess::Hostess;
+    use crate::logging::setup_native_logging;
+    use crate::ReservationRequest;
+
+    /// Start a hostess with a datastore of its own, since the engine moves along every
+    /// reservation that's due and shouldn't touch other tests' reservations.
+    fn lifecycle_hostess(test_name: &str, clock: Arc<FixedClock>) -> Arc<Hostess> {
+        let config = DatastoreConfig {
+            backend: DatastoreBackend::Sqlite,
+            sqlite_path: test_sqlite_path(test_name),
+            ..Default::default()
+        };
+        let datastore = open_datastore(&config).expect("Failed to open lifecycle datastore");
+        datastore
+            .reset()
+            .expect("Failed to initialize lifecycle datastore");
+        seed_backend_pool(&*datastore, "test_lifecycle");
+        Arc::new(Hostess::new(clock, datastore, HostessConfig::default()))
+    }
+
+    fn reserve(hostess: &Hostess) -> Reservation {
+        hostess
+            .process_reservation(
+                &ReservationRequest::new(1707165008, 1707168608, 10, 42).in_pool("test_lifecycle"),
+            )
+            .unwrap()
+            .unwrap()
+    }
+
+    fn change_kinds(hostess: &Hostess) -> Vec<ChangeEventKind> {
+        hostess
+            .changes_after(&ChangeFilter::default(), 0, 100)
+            .unwrap()
+            .into_iter()
+            .map(|change_event| change_event.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_advance_lifecycles() {
+        let _ = setup_native_logging();
+        let clock = Arc::new(FixedClock::new(TEST_EPOCH_NOW));
+        let hostess = lifecycle_hostess("test-advance-lifecycles", clock.clone());
+        let reservation = reserve(&hostess);
+        assert_eq!(hostess.advance_lifecycles().unwrap(), vec![]);
+        assert_eq!(
+            hostess.next_lifecycle_boundary().unwrap(),
+            Some(reservation.start_time)
+        );
+
+        clock.set(reservation.start_time + 60);
+        let started_reservations = hostess.advance_lifecycles().unwrap();
+        assert_eq!(started_reservations.len(), 1);
+        assert_eq!(started_reservations[0].status, ReservationStatus::Active);
+        // Steps are recorded when they were due, not when they were noticed.
+        assert_eq!(started_reservations[0].updated_at, reservation.start_time);
+        assert_eq!(
+            hostess.next_lifecycle_boundary().unwrap(),
+            Some(reservation.end_time)
+        );
+        // Nothing's due again until the reservation ends.
+        assert_eq!(hostess.advance_lifecycles().unwrap(), vec![]);
+
+        clock.set(reservation.end_time);
+        let completed_reservations = hostess.advance_lifecycles().unwrap();
+        assert_eq!(completed_reservations.len(), 1);
+        assert_eq!(
+            completed_reservations[0].status,
+            ReservationStatus::Completed
+        );
+        assert_eq!(hostess.next_lifecycle_boundary().unwrap(), None);
+        // Completed reservations can't be cancelled.
+        assert_eq!(
+            hostess
+                .cancel_reservation(reservation.reservation_id)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            change_kinds(&hostess),
+            vec![
+                ChangeEventKind::ReservationCreated,
+                ChangeEventKind::ReservationStarted,
+                ChangeEventKind::ReservationEnded,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_engine_catches_up() {
+        let _ = setup_native_logging();
+        let clock = Arc::new(FixedClock::new(TEST_EPOCH_NOW));
+        let hostess = lifecycle_hostess("test-lifecycle-engine-catches-up", clock.clone());
+        let reservation = {
+            let hostess = hostess.clone();
+            tokio::task::spawn_blocking(move || reserve(&hostess))
+                .await
+                .unwrap()
+        };
+        // The reservation started and ended while the engine wasn't running.
+        clock.set(reservation.end_time + 3600);
+        let mut changes = hostess.subscribe_changes();
+        changes.borrow_and_update();
+        let engine = tokio::spawn(run_lifecycle_engine(hostess.clone()));
+        // Reservations are moved along before followers are told, so the engine has caught up
+        // once it's announced that the reservation ended.
+        let change_kinds = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                changes.changed().await.unwrap();
+                let hostess = hostess.clone();
+                let change_kinds = tokio::task::spawn_blocking(move || change_kinds(&hostess))
+                    .await
+                    .unwrap();
+                if change_kinds.contains(&ChangeEventKind::ReservationEnded) {
+                    break change_kinds;
+                }
+            }
+        })
+        .await
+        .expect("Lifecycle engine didn't catch up");
+        engine.abort();
+        let reservation_id = reservation.reservation_id;
+        let caught_up =
+            tokio::task::spawn_blocking(move || hostess.get_reservation(reservation_id))
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+        assert_eq!(caught_up.status, ReservationStatus::Completed);
+        assert_eq!(caught_up.updated_at, reservation.end_time);
+        assert_eq!(
+            change_kinds,
+            vec![
+                ChangeEventKind::ReservationCreated,
+                ChangeEventKind::ReservationStarted,
+                ChangeEventKind::ReservationEnded,
+            ]
+        );
+    }
+}
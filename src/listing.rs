@@ -0,0 +1,335 @@
+//! Listing
+//!
+//! `listing` describes which reservations to list, what order to list them in, and where a page
+//! of them picks up.
+//!
+//! Pages are found with keyset pagination: each page ends with a cursor that holds the sort value
+//! and ID of its last reservation, and the next page starts right after them. Unlike offsets,
+//! cursors don't skip or repeat reservations when others are made or cancelled between pages, and
+//! the datastore can find them with its indexes instead of counting past every earlier page.
+
+// External crates.
+use anyhow::{anyhow, ensure, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use serde_derive::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Project crates.
+use crate::common::{Reservation, ReservationStatus};
+
+/// Reservations that are listed when a page size isn't given.
+pub const DEFAULT_PAGE_SIZE: u32 = 50;
+
+/// Most reservations that one page can have.
+pub const MAX_PAGE_SIZE: u32 = 500;
+
+/// How a reservation's timeframe has to line up with the filter's timeframe.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeMatch {
+    /// Any part of the reservation is during the timeframe.
+    #[default]
+    Overlapping,
+    /// The reservation starts during the timeframe.
+    StartingIn,
+    /// The reservation ends during the timeframe.
+    EndingIn,
+}
+
+impl TimeMatch {
+    /// SQL condition that matches reservations to the timeframe's start.
+    ///
+    /// Overlapping reservations only have to end after the timeframe starts, but ones that start
+    /// or end in it can do so at its very first second.
+    pub fn sql_start_condition(&self, start_param: &str) -> String {
+        match self {
+            TimeMatch::Overlapping => format!("end_time > {start_param}"),
+            TimeMatch::StartingIn => format!("start_time >= {start_param}"),
+            TimeMatch::EndingIn => format!("end_time >= {start_param}"),
+        }
+    }
+
+    /// SQL condition that matches reservations to the timeframe's end.
+    pub fn sql_end_condition(&self, end_param: &str) -> String {
+        match self {
+            TimeMatch::Overlapping | TimeMatch::StartingIn => format!("start_time < {end_param}"),
+            TimeMatch::EndingIn => format!("end_time < {end_param}"),
+        }
+    }
+}
+
+/// Which reservations to list. Every filter is optional.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReservationFilter {
+    pub user_id: Option<u32>,
+    pub pool: Option<String>,
+    /// Start of the timeframe, represented by Unix epoch format.
+    pub start_time: Option<u32>,
+    /// End of the timeframe, represented by Unix epoch format.
+    pub end_time: Option<u32>,
+    pub time_match: TimeMatch,
+    pub status: Option<ReservationStatus>,
+}
+
+/// What reservations are listed in order of.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReservationSort {
+    /// When reservations were granted, since IDs are handed out in order.
+    #[default]
+    Id,
+    StartTime,
+    EndTime,
+    CreatedAt,
+}
+
+impl ReservationSort {
+    /// Name of the sort as it's written in cursors.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReservationSort::Id => "id",
+            ReservationSort::StartTime => "start_time",
+            ReservationSort::EndTime => "end_time",
+            ReservationSort::CreatedAt => "created_at",
+        }
+    }
+
+    /// Column that the datastore sorts by.
+    pub fn column(&self) -> &'static str {
+        self.as_str()
+    }
+
+    /// Value of a reservation that it's sorted by.
+    pub fn sort_value(&self, reservation: &Reservation) -> u32 {
+        match self {
+            ReservationSort::Id => reservation.reservation_id,
+            ReservationSort::StartTime => reservation.start_time,
+            ReservationSort::EndTime => reservation.end_time,
+            ReservationSort::CreatedAt => reservation.created_at,
+        }
+    }
+}
+
+/// Whether reservations are listed smallest or biggest first.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    /// Name of the order as it's written in cursors.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        }
+    }
+
+    /// SQL keyword for the order.
+    pub fn sql_keyword(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+
+    /// SQL operator that finds what comes after a value in this order.
+    pub fn sql_after(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => ">",
+            SortOrder::Desc => "<",
+        }
+    }
+}
+
+/// Where a page of reservations picks up: right after the last reservation of the page before.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageCursor {
+    pub sort: ReservationSort,
+    pub order: SortOrder,
+    /// Value that the last reservation was sorted by.
+    pub sort_value: u32,
+    /// ID of the last reservation, which breaks ties between reservations with the same value.
+    pub reservation_id: u32,
+}
+
+impl PageCursor {
+    /// Cursor that picks up after the given reservation.
+    pub fn after(reservation: &Reservation, sort: ReservationSort, order: SortOrder) -> Self {
+        Self {
+            sort,
+            order,
+            sort_value: sort.sort_value(reservation),
+            reservation_id: reservation.reservation_id,
+        }
+    }
+
+    /// Write the cursor as an opaque token that's safe to put in a URL.
+    pub fn encode(&self) -> String {
+        let raw_cursor = format!(
+            "{}.{}.{}.{}",
+            self.sort.as_str(),
+            self.order.as_str(),
+            self.sort_value,
+            self.reservation_id
+        );
+        URL_SAFE_NO_PAD.encode(raw_cursor)
+    }
+
+    /// Read a cursor back out of its token.
+    ///
+    /// The cursor has to be for the same sort and order as the page that's being asked for.
+    pub fn decode(cursor_token: &str, sort: ReservationSort, order: SortOrder) -> Result<Self> {
+        let invalid_cursor = || anyhow!("Invalid cursor \"{cursor_token}\"");
+        let raw_cursor = URL_SAFE_NO_PAD
+            .decode(cursor_token)
+            .ok()
+            .and_then(|raw_cursor| String::from_utf8(raw_cursor).ok())
+            .ok_or_else(invalid_cursor)?;
+        let cursor_parts: Vec<&str> = raw_cursor.split('.').collect();
+        let [sort_name, order_name, sort_value, reservation_id] = cursor_parts[..] else {
+            return Err(invalid_cursor());
+        };
+        ensure!(
+            sort_name == sort.as_str() && order_name == order.as_str(),
+            format!(
+                "Cursor \"{cursor_token}\" is for reservations sorted by \"{sort_name}\" \"{order_name}\", not \"{}\" \"{}\"",
+                sort.as_str(),
+                order.as_str()
+            )
+        );
+        Ok(Self {
+            sort,
+            order,
+            sort_value: sort_value.parse().map_err(|_| invalid_cursor())?,
+            reservation_id: reservation_id.parse().map_err(|_| invalid_cursor())?,
+        })
+    }
+}
+
+/// Which page of reservations to get from the datastore.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageRequest {
+    pub sort: ReservationSort,
+    pub order: SortOrder,
+    /// Where the page picks up, or `None` for the first page.
+    pub after: Option<PageCursor>,
+    /// Most reservations to get.
+    pub limit: u32,
+}
+
+impl PageRequest {
+    /// SQL condition that skips the pages before this one, which is only needed if it has a
+    /// cursor.
+    ///
+    /// # Arguments
+    /// - `value_param`: Placeholder for the cursor's sort value.
+    /// - `id_param`: Placeholder for the cursor's reservation ID.
+    pub fn sql_after(&self, value_param: &str, id_param: &str) -> String {
+        let sort_column = self.sort.column();
+        let after = self.order.sql_after();
+        format!(
+            "({sort_column} {after} {value_param} \
+             OR ({sort_column} = {value_param} AND id {after} {id_param}))"
+        )
+    }
+
+    /// SQL that sorts the page, with IDs breaking ties.
+    pub fn sql_order_by(&self) -> String {
+        let order = self.order.sql_keyword();
+        format!("ORDER BY {} {order}, id {order}", self.sort.column())
+    }
+}
+
+/// SQL WHERE clause that matches every condition, or nothing if there aren't any.
+///
+/// Only the filters that are present are put in the query, so the datastore can plan it with the
+/// indexes that fit them.
+pub fn sql_where(conditions: &[String]) -> String {
+    match conditions.is_empty() {
+        true => String::new(),
+        false => format!("WHERE {}", conditions.join(" AND ")),
+    }
+}
+
+/// One page of reservations.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct ReservationPage {
+    pub reservations: Vec<Reservation>,
+    /// Cursor for the next page, or `None` if this is the last one.
+    pub next_cursor: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    // External crates.
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+
+    // Project crates.
+    use super::{sql_where, PageCursor, PageRequest, ReservationSort, SortOrder, TimeMatch};
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let test_cursor = PageCursor {
+            sort: ReservationSort::StartTime,
+            order: SortOrder::Desc,
+            sort_value: 1707165008,
+            reservation_id: 42,
+        };
+        let cursor_token = test_cursor.encode();
+        assert_eq!(
+            PageCursor::decode(&cursor_token, ReservationSort::StartTime, SortOrder::Desc).unwrap(),
+            test_cursor
+        );
+        // Cursors only pick up where pages in the same order left off.
+        assert!(
+            PageCursor::decode(&cursor_token, ReservationSort::StartTime, SortOrder::Asc).is_err()
+        );
+        assert!(PageCursor::decode(&cursor_token, ReservationSort::Id, SortOrder::Desc).is_err());
+        assert!(PageCursor::decode("not a cursor", ReservationSort::Id, SortOrder::Asc).is_err());
+        let bad_token = URL_SAFE_NO_PAD.encode("id.asc.soon.42");
+        assert!(PageCursor::decode(&bad_token, ReservationSort::Id, SortOrder::Asc).is_err());
+    }
+
+    #[test]
+    fn test_sql_conditions() {
+        assert_eq!(
+            TimeMatch::StartingIn.sql_start_condition("?4"),
+            "start_time >= ?4"
+        );
+        assert_eq!(
+            TimeMatch::StartingIn.sql_end_condition("?5"),
+            "start_time < ?5"
+        );
+        assert_eq!(
+            TimeMatch::Overlapping.sql_start_condition("?4"),
+            "end_time > ?4"
+        );
+        let page_request = PageRequest {
+            sort: ReservationSort::EndTime,
+            order: SortOrder::Desc,
+            after: None,
+            limit: 10,
+        };
+        assert_eq!(
+            page_request.sql_after("?6", "?7"),
+            "(end_time < ?6 OR (end_time = ?6 AND id < ?7))"
+        );
+        assert_eq!(
+            sql_where(&[String::from("user_id = ?1"), String::from("pool = ?2")]),
+            "WHERE user_id = ?1 AND pool = ?2"
+        );
+        assert_eq!(sql_where(&[]), "");
+        assert_eq!(
+            page_request.sql_order_by(),
+            "ORDER BY end_time DESC, id DESC"
+        );
+    }
+}
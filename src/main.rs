@@ -1,28 +1,175 @@
 // Standard library crates.
+use std::path::Path;
+use std::sync::Arc;
 
 // External crates.
+use clap::{Parser, Subcommand};
 #[allow(unused)]
 use log::{debug, error, info, trace, warn};
+use serde_json::json;
 
 // Project modules
+mod auth;
+use auth::Authenticator;
+mod batch;
+mod billing;
+mod clock;
+use clock::SystemClock;
 mod common;
+mod config;
+use config::{load_config, DEFAULT_CONFIG_PATH};
 // Make reservation abstractions available everywhere via re-export b/c used often.
 pub use common::CapacitySchedule;
 pub use common::ReservationRequest;
 mod datastore;
-use datastore::initialize_database;
+use datastore::open_datastore;
+mod demand;
+mod events;
+mod grpc_api;
+use grpc_api::{start_grpc_api, GrpcConfig};
 mod hostess;
+mod idempotency;
+use hostess::Hostess;
+mod ledger;
+use ledger::{rebuild_reservations, reservations_as_of};
+mod lifecycle;
+use lifecycle::run_lifecycle_engine;
+mod listing;
 mod logging;
 use logging::setup_native_logging;
+mod metrics;
+mod migrations;
+mod policy;
+mod reports;
 mod restful_api;
 use restful_api::start_restful_api;
+mod versioning;
+use versioning::ApiConfig;
+mod webhooks;
+use webhooks::{dispatch_webhooks, WebhookConfig};
+
+/// Arbiter is a simple resource scheduler.
+#[derive(Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Delete every table (and every reservation!) before starting. Only for development builds.
+    #[arg(long, global = true)]
+    dev_reset_database: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Apply pending database schema migrations, then exit.
+    Migrate,
+    /// Apply pending database schema migrations, then start the RESTful and gRPC APIs (default).
+    Serve,
+    /// Work out reservations from the reservation ledger and print them as JSON.
+    Replay {
+        /// Show reservations as they were at this Unix epoch instead of now.
+        #[arg(long)]
+        as_of: Option<u32>,
+        /// Replace the stored reservations with the replayed ones. Stop Arbiter first.
+        #[arg(long, conflicts_with = "as_of")]
+        rebuild: bool,
+    },
+}
 
 fn main() {
+    let cli = Cli::parse();
+
     let _ = setup_native_logging();
 
-    let _ = initialize_database();
+    let config = match load_config(Path::new(DEFAULT_CONFIG_PATH)) {
+        Ok(config) => config,
+        Err(config_error) => {
+            error!("{:#}", config_error);
+            return;
+        }
+    };
 
-    let _ = start_restful_api();
+    // Only let development builds throw away the database.
+    if cli.dev_reset_database && !cfg!(debug_assertions) {
+        error!("`--dev-reset-database` is only available in development builds");
+        return;
+    }
+    let datastore = match open_datastore(&config.datastore) {
+        Ok(datastore) => datastore,
+        Err(datastore_error) => {
+            error!("Failed to open datastore: {:#}", datastore_error);
+            return;
+        }
+    };
+    let database = match cli.dev_reset_database {
+        true => datastore.reset(),
+        false => datastore.initialize(),
+    };
+    if let Err(database_error) = database {
+        error!("Failed to initialize database: {:#}", database_error);
+        return;
+    }
+    match cli.command {
+        Some(Command::Migrate) => {
+            info!("Done");
+            return;
+        }
+        Some(Command::Replay { as_of, rebuild }) => {
+            if rebuild {
+                if let Err(ledger_error) = rebuild_reservations(&*datastore) {
+                    error!("Failed to rebuild reservations: {:#}", ledger_error);
+                    return;
+                }
+            }
+            match reservations_as_of(&*datastore, as_of) {
+                Ok(reservations) => println!("{}", json!(reservations)),
+                Err(ledger_error) => error!("Failed to replay ledger: {:#}", ledger_error),
+            }
+            return;
+        }
+        Some(Command::Serve) | None => {}
+    }
+
+    // Decide what "now" is with the host's clock.
+    let clock = Arc::new(SystemClock);
+    let hostess = Arc::new(Hostess::new(clock.clone(), datastore, config.hostess));
+    let authenticator = Arc::new(Authenticator::new(clock, config.auth));
+
+    serve_apis(
+        hostess,
+        authenticator,
+        config.api,
+        config.grpc,
+        config.webhooks,
+    );
 
     info!("Done");
 }
+
+/// Serve the RESTful and gRPC APIs side by side, with the same hostess, and send webhooks in the
+/// background.
+#[tokio::main]
+async fn serve_apis(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+    api_config: ApiConfig,
+    grpc_config: GrpcConfig,
+    webhook_config: WebhookConfig,
+) {
+    tokio::spawn(run_lifecycle_engine(hostess.clone()));
+    tokio::spawn(dispatch_webhooks(hostess.clone(), webhook_config));
+    let restful_api = async {
+        if let Err(restful_error) =
+            start_restful_api(hostess.clone(), authenticator.clone(), api_config).await
+        {
+            error!("RESTful API stopped: {}", restful_error);
+        }
+    };
+    let grpc_api = async {
+        if let Err(grpc_error) =
+            start_grpc_api(hostess.clone(), authenticator.clone(), grpc_config).await
+        {
+            error!("gRPC API stopped: {}", grpc_error);
+        }
+    };
+    tokio::join!(restful_api, grpc_api);
+}
@@ -0,0 +1,262 @@
+//! Metrics
+//!
+//! `metrics` tells operators how Arbiter is doing in Prometheus' text format, so it can be
+//! scraped and graphed instead of read out of the log file.
+//!
+//! Counters and histograms are kept up as things happen: requests to the RESTful API by route
+//! and status, reservation requests that were approved or denied (and why), how long deciding a
+//! request took, and how long each datastore operation took. Gauges that describe the schedule,
+//! like each pool's utilization and how many reservations are pending or active, are measured
+//! from the datastore whenever `/metrics` is scraped, so every Arbiter that shares a database
+//! reports the same thing.
+
+// Standard library crates.
+use std::sync::LazyLock;
+use std::time::Duration;
+
+// External crates.
+use anyhow::Result;
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+// Project crates.
+use crate::batch::DenialKind;
+use crate::common::ReservationStatus;
+
+/// Metrics that are shared by the whole server.
+static METRICS: LazyLock<Metrics> =
+    LazyLock::new(|| Metrics::new().expect("Failed to register metrics"));
+
+/// Get the metrics that are shared by the whole server.
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// How many reservations in a pool have a status.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReservationCount {
+    pub pool: String,
+    pub status: ReservationStatus,
+    pub count: u32,
+}
+
+/// What a pool looks like right now.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoolGauges {
+    pub pool: String,
+    /// Fraction of the pool's scheduled capacity that's reserved or offline, which can be more
+    /// than 1 if maintenance takes capacity that was already reserved.
+    pub utilization: f64,
+    pub pending_count: u32,
+    pub active_count: u32,
+}
+
+/// Every metric that Arbiter reports.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    approved_reservations: IntCounterVec,
+    denied_reservations: IntCounterVec,
+    evaluation_seconds: HistogramVec,
+    query_seconds: HistogramVec,
+    pool_utilization: GaugeVec,
+    reservations: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Result<Self> {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new(
+                "arbiter_http_requests_total",
+                "Requests to the RESTful API, by route and response status.",
+            ),
+            &["method", "route", "status"],
+        )?;
+        let approved_reservations = IntCounterVec::new(
+            Opts::new(
+                "arbiter_reservations_approved_total",
+                "Reservation requests that were approved, by pool.",
+            ),
+            &["pool"],
+        )?;
+        let denied_reservations = IntCounterVec::new(
+            Opts::new(
+                "arbiter_reservations_denied_total",
+                "Reservation requests that were denied, by pool and reason.",
+            ),
+            &["pool", "reason"],
+        )?;
+        let evaluation_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "arbiter_evaluation_duration_seconds",
+                "Time taken to decide and record reservation requests, by outcome.",
+            ),
+            &["outcome"],
+        )?;
+        let query_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "arbiter_db_query_duration_seconds",
+                "Time taken by datastore operations, by operation.",
+            ),
+            &["operation"],
+        )?;
+        let pool_utilization = GaugeVec::new(
+            Opts::new(
+                "arbiter_pool_utilization_ratio",
+                "Fraction of each pool's scheduled capacity that's reserved or offline right now.",
+            ),
+            &["pool"],
+        )?;
+        let reservations = IntGaugeVec::new(
+            Opts::new(
+                "arbiter_reservations",
+                "Reservations that are pending or active, by pool and status.",
+            ),
+            &["pool", "status"],
+        )?;
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(approved_reservations.clone()))?;
+        registry.register(Box::new(denied_reservations.clone()))?;
+        registry.register(Box::new(evaluation_seconds.clone()))?;
+        registry.register(Box::new(query_seconds.clone()))?;
+        registry.register(Box::new(pool_utilization.clone()))?;
+        registry.register(Box::new(reservations.clone()))?;
+        Ok(Self {
+            registry,
+            http_requests,
+            approved_reservations,
+            denied_reservations,
+            evaluation_seconds,
+            query_seconds,
+            pool_utilization,
+            reservations,
+        })
+    }
+
+    /// Count a request to the RESTful API.
+    ///
+    /// # Arguments
+    /// - `route`: Route's path template, like `"/v1/reservations/{reservation_id}"`, so that
+    ///   requests for different reservations are counted together.
+    pub fn count_http_request(&self, method: &str, route: &str, status: u16) {
+        self.http_requests
+            .with_label_values(&[method, route, &status.to_string()])
+            .inc();
+    }
+
+    /// Count how a reservation request was decided, and how long deciding it took.
+    ///
+    /// # Arguments
+    /// - `denial_kind`: Why the request was denied, or `None` if it was approved.
+    pub fn count_decision(&self, pool: &str, denial_kind: Option<DenialKind>, elapsed: Duration) {
+        let outcome = match denial_kind {
+            Some(denial_kind) => {
+                self.denied_reservations
+                    .with_label_values(&[pool, denial_kind.as_str()])
+                    .inc();
+                "denied"
+            }
+            None => {
+                self.approved_reservations.with_label_values(&[pool]).inc();
+                "approved"
+            }
+        };
+        self.evaluation_seconds
+            .with_label_values(&[outcome])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Time a datastore operation.
+    pub fn observe_query(&self, operation: &str, elapsed: Duration) {
+        self.query_seconds
+            .with_label_values(&[operation])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Replace what pools look like with what they look like now.
+    ///
+    /// Pools that aren't measured anymore are forgotten, so they don't report stale values.
+    pub fn set_pool_gauges(&self, pool_gauges: &[PoolGauges]) {
+        self.pool_utilization.reset();
+        self.reservations.reset();
+        for pool_gauge in pool_gauges {
+            self.pool_utilization
+                .with_label_values(&[&pool_gauge.pool])
+                .set(pool_gauge.utilization);
+            for (status, count) in [
+                (ReservationStatus::Pending, pool_gauge.pending_count),
+                (ReservationStatus::Active, pool_gauge.active_count),
+            ] {
+                self.reservations
+                    .with_label_values(&[&pool_gauge.pool, status.as_str()])
+                    .set(count.into());
+            }
+        }
+    }
+
+    /// Write every metric in Prometheus' text format.
+    pub fn render(&self) -> Result<String> {
+        let mut rendered = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut rendered)?;
+        Ok(String::from_utf8(rendered)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Standard library crates.
+    use std::time::Duration;
+
+    // Project crates.
+    use super::{Metrics, PoolGauges};
+    use crate::batch::DenialKind;
+
+    // Test if metrics are rendered in Prometheus' text format.
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new().unwrap();
+        metrics.count_http_request("GET", "/v1/reservations/{reservation_id}", 200);
+        metrics.count_http_request("GET", "/v1/reservations/{reservation_id}", 200);
+        metrics.count_decision("default", None, Duration::from_millis(3));
+        metrics.count_decision(
+            "default",
+            Some(DenialKind::NotEnoughCapacity),
+            Duration::from_millis(2),
+        );
+        metrics.observe_query("get_schedule", Duration::from_millis(1));
+        metrics.set_pool_gauges(&[PoolGauges {
+            pool: String::from("default"),
+            utilization: 0.25,
+            pending_count: 2,
+            active_count: 1,
+        }]);
+        let rendered = metrics.render().unwrap();
+        for expected_line in [
+            "# TYPE arbiter_http_requests_total counter",
+            "arbiter_http_requests_total{method=\"GET\",route=\"/v1/reservations/{reservation_id}\",status=\"200\"} 2",
+            "arbiter_reservations_approved_total{pool=\"default\"} 1",
+            "arbiter_reservations_denied_total{pool=\"default\",reason=\"not_enough_capacity\"} 1",
+            "# TYPE arbiter_evaluation_duration_seconds histogram",
+            "arbiter_evaluation_duration_seconds_count{outcome=\"approved\"} 1",
+            "arbiter_db_query_duration_seconds_count{operation=\"get_schedule\"} 1",
+            "arbiter_pool_utilization_ratio{pool=\"default\"} 0.25",
+            "arbiter_reservations{pool=\"default\",status=\"pending\"} 2",
+            "arbiter_reservations{pool=\"default\",status=\"active\"} 1",
+        ] {
+            assert!(
+                rendered.lines().any(|line| line == expected_line),
+                "Metrics are missing \"{expected_line}\":\n{rendered}"
+            );
+        }
+
+        // Pools that aren't measured anymore are forgotten.
+        metrics.set_pool_gauges(&[]);
+        let rendered = metrics.render().unwrap();
+        assert!(!rendered.contains("arbiter_pool_utilization_ratio{"));
+    }
+}
@@ -0,0 +1,525 @@
+//! Schema Migrations
+//!
+//! `migrations` keeps Arbiter's database schema up to date without throwing away data.
+//!
+//! Each migration is a SQL file in `migrations/<backend>/` that's embedded in the binary. Applied
+//! migrations are recorded in the `schema_version` table, so every migration runs exactly once,
+//! in order, whether it's applied on startup or with `arbiter migrate`. Migrations are never
+//! edited after they're released. Schema changes get a new migration instead.
+//!
+//! Every datastore backend has its own list of migrations because their SQL dialects differ.
+//!
+//! PostgreSQL databases that were created before migrations existed are adopted at version 1,
+//! but only if their tables are exactly what the first migration creates. Anything else is
+//! refused instead of being guessed at.
+
+// External crates.
+use anyhow::{bail, ensure, Context, Result};
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+
+/// A versioned change to the database schema.
+pub struct Migration {
+    /// Order that the migration is applied in, starting from 1.
+    pub version: i32,
+    /// Short description of what the migration changes.
+    pub name: &'static str,
+    /// SQL that makes the change.
+    pub sql: &'static str,
+}
+
+/// Every PostgreSQL migration, in the order that they're applied.
+pub const POSTGRES_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: include_str!("../migrations/postgres/0001_initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "time_ranges",
+        sql: include_str!("../migrations/postgres/0002_time_ranges.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "schedule_checks",
+        sql: include_str!("../migrations/postgres/0003_schedule_checks.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "reservation_ledger",
+        sql: include_str!("../migrations/postgres/0004_reservation_ledger.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "request_history",
+        sql: include_str!("../migrations/postgres/0005_request_history.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "chargeback",
+        sql: include_str!("../migrations/postgres/0006_chargeback.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "price_quotes",
+        sql: include_str!("../migrations/postgres/0007_price_quotes.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "idempotency_keys",
+        sql: include_str!("../migrations/postgres/0008_idempotency_keys.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "reservation_listing",
+        sql: include_str!("../migrations/postgres/0009_reservation_listing.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "change_events",
+        sql: include_str!("../migrations/postgres/0010_change_events.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "webhooks",
+        sql: include_str!("../migrations/postgres/0011_webhooks.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "reservation_lifecycle",
+        sql: include_str!("../migrations/postgres/0012_reservation_lifecycle.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "user_listing",
+        sql: include_str!("../migrations/postgres/0013_user_listing.sql"),
+    },
+];
+
+/// Every SQLite migration, in the order that they're applied.
+pub const SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: include_str!("../migrations/sqlite/0001_initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "schedule_checks",
+        sql: include_str!("../migrations/sqlite/0002_schedule_checks.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "reservation_ledger",
+        sql: include_str!("../migrations/sqlite/0003_reservation_ledger.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "request_history",
+        sql: include_str!("../migrations/sqlite/0004_request_history.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "chargeback",
+        sql: include_str!("../migrations/sqlite/0005_chargeback.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "price_quotes",
+        sql: include_str!("../migrations/sqlite/0006_price_quotes.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "idempotency_keys",
+        sql: include_str!("../migrations/sqlite/0007_idempotency_keys.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "reservation_listing",
+        sql: include_str!("../migrations/sqlite/0008_reservation_listing.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "change_events",
+        sql: include_str!("../migrations/sqlite/0009_change_events.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "webhooks",
+        sql: include_str!("../migrations/sqlite/0010_webhooks.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "reservation_lifecycle",
+        sql: include_str!("../migrations/sqlite/0011_reservation_lifecycle.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "user_listing",
+        sql: include_str!("../migrations/sqlite/0012_user_listing.sql"),
+    },
+];
+
+/// SQL that creates the table that records which migrations have been applied.
+///
+/// It's plain enough for every backend to understand.
+const CREATE_VERSION_TABLE: &str = "CREATE TABLE IF NOT EXISTS schema_version (
+     version    INTEGER PRIMARY KEY,
+     name       TEXT NOT NULL,
+     applied_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+ )";
+
+/// Tables that Arbiter created before it had migrations, with each column's name and type in
+/// order. They're the same tables that the first PostgreSQL migration creates.
+const BASELINE_TABLES: &[(&str, &[(&str, &str)])] = &[
+    (
+        "capacity_schedule",
+        &[
+            ("id", "integer"),
+            ("start_time", "integer"),
+            ("end_time", "integer"),
+            ("capacity_amount", "integer"),
+            ("pool", "text"),
+        ],
+    ),
+    (
+        "user_reservations",
+        &[
+            ("id", "integer"),
+            ("start_time", "integer"),
+            ("end_time", "integer"),
+            ("reservation_amount", "integer"),
+            ("user_id", "integer"),
+            ("pool", "text"),
+            ("status", "text"),
+            ("created_at", "integer"),
+            ("updated_at", "integer"),
+        ],
+    ),
+    (
+        "maintenance_windows",
+        &[
+            ("id", "integer"),
+            ("pool", "text"),
+            ("start_time", "integer"),
+            ("end_time", "integer"),
+            ("reduction_amount", "integer"),
+            ("reason", "text"),
+        ],
+    ),
+];
+
+/// Make every other Arbiter that migrates the same PostgreSQL database wait its turn.
+///
+/// The two-key form of the lock keeps it apart from the locks that bookings take on pools.
+const LOCK_POSTGRES_MIGRATIONS: &str = "SELECT pg_advisory_lock(hashtext('schema_version'), 0)";
+
+/// Let the next Arbiter that's waiting to migrate the PostgreSQL database have its turn.
+const UNLOCK_POSTGRES_MIGRATIONS: &str = "SELECT pg_advisory_unlock(hashtext('schema_version'), 0)";
+
+/// Get the version of the newest migration.
+pub fn latest_version(migrations: &[Migration]) -> i32 {
+    migrations
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Get the version of the newest migration that's been applied to a PostgreSQL database.
+///
+/// Databases that haven't been migrated yet are at version 0.
+pub fn current_postgres_version(db_client: &mut postgres::Client) -> Result<i32> {
+    db_client.batch_execute(CREATE_VERSION_TABLE)?;
+    let version_row =
+        db_client.query_one("SELECT COALESCE(MAX(version), 0) FROM schema_version", &[])?;
+    Ok(version_row.get(0))
+}
+
+/// Apply every PostgreSQL migration that hasn't been applied to the database yet.
+///
+/// Each migration runs in its own transaction along with its `schema_version` record, so a
+/// failed migration doesn't leave the schema half changed. Arbiters that share the database
+/// take turns migrating it, so each migration is only applied once.
+///
+/// # Returns
+/// The database's schema version after migrating.
+pub fn run_postgres_migrations(db_client: &mut postgres::Client) -> Result<i32> {
+    db_client.batch_execute(LOCK_POSTGRES_MIGRATIONS)?;
+    let migrated_version = apply_postgres_migrations(db_client);
+    let unlocked = db_client.batch_execute(UNLOCK_POSTGRES_MIGRATIONS);
+    let migrated_version = migrated_version?;
+    unlocked?;
+    Ok(migrated_version)
+}
+
+/// Apply PostgreSQL migrations while no one else is migrating the database.
+fn apply_postgres_migrations(db_client: &mut postgres::Client) -> Result<i32> {
+    let mut starting_version = current_postgres_version(db_client)?;
+    if starting_version == 0 && adopt_postgres_baseline(db_client)? {
+        starting_version = 1;
+    }
+    for migration in pending_migrations(POSTGRES_MIGRATIONS, starting_version)? {
+        let mut transaction = db_client.transaction()?;
+        transaction
+            .batch_execute(migration.sql)
+            .with_context(|| failed_migration_message(migration))?;
+        transaction.execute(
+            "INSERT INTO schema_version (version, name) VALUES ($1, $2)",
+            &[&migration.version, &migration.name],
+        )?;
+        transaction.commit()?;
+        info!(
+            "Applied migration \"{}\" ({})",
+            migration.version, migration.name
+        );
+    }
+    let migrated_version = current_postgres_version(db_client)?;
+    info!("Database schema is at version \"{}\"", migrated_version);
+    Ok(migrated_version)
+}
+
+/// Adopt the tables of a PostgreSQL database that was created before migrations existed, as if
+/// the first migration had created them.
+///
+/// # Returns
+/// Whether there were tables to adopt. Databases with some of the tables, or with tables that
+/// aren't exactly what the first migration creates, are refused.
+fn adopt_postgres_baseline(db_client: &mut postgres::Client) -> Result<bool> {
+    let mut adopted_count = 0;
+    for (table_name, baseline_columns) in BASELINE_TABLES {
+        let mut columns: Vec<(String, String)> = Vec::new();
+        for column_row in db_client.query(
+            "SELECT column_name::TEXT, data_type::TEXT
+             FROM information_schema.columns
+             WHERE table_schema = current_schema() AND table_name = $1
+             ORDER BY ordinal_position",
+            &[table_name],
+        )? {
+            columns.push((column_row.get(0), column_row.get(1)));
+        }
+        if columns.is_empty() {
+            continue;
+        }
+        ensure!(
+            columns
+                .iter()
+                .map(|(name, data_type)| (name.as_str(), data_type.as_str()))
+                .eq(baseline_columns.iter().copied()),
+            "Table \"{table_name}\" was created before migrations existed, but it isn't what \
+             Arbiter created then, so it can't be migrated"
+        );
+        adopted_count += 1;
+    }
+    if adopted_count == 0 {
+        return Ok(false);
+    }
+    ensure!(
+        adopted_count == BASELINE_TABLES.len(),
+        "Database only has some of the tables that Arbiter created before migrations existed, \
+         so it can't be migrated"
+    );
+    let first_migration = &POSTGRES_MIGRATIONS[0];
+    db_client.execute(
+        "INSERT INTO schema_version (version, name) VALUES ($1, $2)",
+        &[&first_migration.version, &first_migration.name],
+    )?;
+    warn!(
+        "Adopted tables that were created before migrations existed as migration \"{}\" ({})",
+        first_migration.version, first_migration.name
+    );
+    Ok(true)
+}
+
+/// Get the version of the newest migration that's been applied to a SQLite database.
+///
+/// Databases that haven't been migrated yet are at version 0.
+pub fn current_sqlite_version(db_connection: &rusqlite::Connection) -> Result<i32> {
+    db_connection.execute_batch(CREATE_VERSION_TABLE)?;
+    let version = db_connection.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+        [],
+        |version_row| version_row.get(0),
+    )?;
+    Ok(version)
+}
+
+/// Apply every SQLite migration that hasn't been applied to the database yet.
+///
+/// Each migration runs in its own transaction along with its `schema_version` record, so a
+/// failed migration doesn't leave the schema half changed.
+///
+/// # Returns
+/// The database's schema version after migrating.
+pub fn run_sqlite_migrations(db_connection: &mut rusqlite::Connection) -> Result<i32> {
+    let starting_version = current_sqlite_version(db_connection)?;
+    for migration in pending_migrations(SQLITE_MIGRATIONS, starting_version)? {
+        let transaction = db_connection.transaction()?;
+        transaction
+            .execute_batch(migration.sql)
+            .with_context(|| failed_migration_message(migration))?;
+        transaction.execute(
+            "INSERT INTO schema_version (version, name) VALUES (?1, ?2)",
+            rusqlite::params![migration.version, migration.name],
+        )?;
+        transaction.commit()?;
+        info!(
+            "Applied migration \"{}\" ({})",
+            migration.version, migration.name
+        );
+    }
+    let migrated_version = current_sqlite_version(db_connection)?;
+    info!("Database schema is at version \"{}\"", migrated_version);
+    Ok(migrated_version)
+}
+
+/// Find the migrations that a database at the given version still needs.
+fn pending_migrations(
+    migrations: &'static [Migration],
+    current_version: i32,
+) -> Result<impl Iterator<Item = &'static Migration>> {
+    // Don't let an older Arbiter use a schema it doesn't understand.
+    if current_version > latest_version(migrations) {
+        bail!(
+            "Database schema is at version \"{}\", but this Arbiter only knows up to version \"{}\"",
+            current_version,
+            latest_version(migrations)
+        );
+    }
+    Ok(migrations
+        .iter()
+        .filter(move |migration| migration.version > current_version))
+}
+
+/// Describe a migration that couldn't be applied.
+fn failed_migration_message(migration: &Migration) -> String {
+    format!(
+        "Failed to apply migration \"{}\" ({})",
+        migration.version, migration.name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    // Standard library crates.
+    use std::thread;
+
+    // External crates.
+    use postgres::{Client, NoTls};
+
+    // Project crates.
+    use super::{
+        latest_version, pending_migrations, run_postgres_migrations, POSTGRES_MIGRATIONS,
+        SQLITE_MIGRATIONS,
+    };
+    use crate::config::DatastoreConfig;
+    use crate::datastore::test_examples::test_datastore;
+
+    /// Connect to the test PostgreSQL server with a schema of its own, so that the migrations
+    /// being tested don't touch the tables that the rest of the test suite uses.
+    fn schema_client(schema: &str) -> Client {
+        let mut db_client = Client::connect(&DatastoreConfig::default().postgres_connection, NoTls)
+            .expect("Failed to connect to test database");
+        db_client
+            .batch_execute(&format!(
+                "CREATE SCHEMA IF NOT EXISTS {schema};
+                 SET search_path TO {schema}, public;"
+            ))
+            .expect("Failed to use test schema");
+        db_client
+    }
+
+    /// Start a schema over from nothing.
+    fn reset_schema(schema: &str) -> Client {
+        let mut db_client = schema_client(schema);
+        db_client
+            .batch_execute(&format!(
+                "DROP SCHEMA {schema} CASCADE;
+                 CREATE SCHEMA {schema};"
+            ))
+            .expect("Failed to reset test schema");
+        db_client
+    }
+
+    #[test]
+    fn test_migrations_are_in_order() {
+        for migrations in [POSTGRES_MIGRATIONS, SQLITE_MIGRATIONS] {
+            for (index, migration) in migrations.iter().enumerate() {
+                assert_eq!(migration.version, index as i32 + 1);
+            }
+            assert_eq!(latest_version(migrations), migrations.len() as i32);
+        }
+    }
+
+    #[test]
+    fn test_migrating_twice_changes_nothing() {
+        let test_datastore = test_datastore();
+        test_datastore.initialize().unwrap();
+        test_datastore.initialize().unwrap();
+    }
+
+    #[test]
+    fn test_refuse_schema_from_the_future() {
+        assert!(pending_migrations(SQLITE_MIGRATIONS, 0).is_ok());
+        assert!(pending_migrations(SQLITE_MIGRATIONS, latest_version(SQLITE_MIGRATIONS)).is_ok());
+        assert!(pending_migrations(SQLITE_MIGRATIONS, 42).is_err());
+    }
+
+    // Arbiters that start at the same time take turns migrating.
+    #[test]
+    fn test_concurrent_postgres_migrations() {
+        let schema = "arbiter_concurrent_migrations";
+        drop(reset_schema(schema));
+        let migrated_versions: Vec<i32> = thread::scope(|scope| {
+            let migrators: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| run_postgres_migrations(&mut schema_client(schema))))
+                .collect();
+            migrators
+                .into_iter()
+                .map(|migrator| migrator.join().unwrap().unwrap())
+                .collect()
+        });
+        assert_eq!(
+            migrated_versions,
+            vec![latest_version(POSTGRES_MIGRATIONS); 4]
+        );
+    }
+
+    // Databases from before migrations are adopted if their tables are exactly the first
+    // migration's, and refused otherwise.
+    #[test]
+    fn test_adopt_postgres_baseline() {
+        let schema = "arbiter_baseline_migrations";
+        let mut db_client = reset_schema(schema);
+        db_client.batch_execute(POSTGRES_MIGRATIONS[0].sql).unwrap();
+        db_client
+            .batch_execute(
+                "INSERT INTO user_reservations (start_time, end_time, reservation_amount, user_id,
+                     pool, status, created_at, updated_at)
+                 VALUES (1707165008, 1707168608, 8, 42, 'default', 'pending', 1707078608,
+                     1707078608)",
+            )
+            .unwrap();
+        assert_eq!(
+            run_postgres_migrations(&mut db_client).unwrap(),
+            latest_version(POSTGRES_MIGRATIONS)
+        );
+        let kept_row = db_client
+            .query_one("SELECT COUNT(*) FROM user_reservations", &[])
+            .unwrap();
+        assert_eq!(kept_row.get::<_, i64>(0), 1);
+
+        // Tables that Arbiter didn't create aren't guessed at.
+        let mut db_client = reset_schema(schema);
+        db_client
+            .batch_execute("CREATE TABLE capacity_schedule (id SERIAL PRIMARY KEY, size TEXT)")
+            .unwrap();
+        let refusal = run_postgres_migrations(&mut db_client).unwrap_err();
+        assert!(format!("{refusal:#}").contains("capacity_schedule"));
+        // The lock isn't held after a refusal.
+        drop(reset_schema(schema));
+        assert!(run_postgres_migrations(&mut schema_client(schema)).is_ok());
+        drop(reset_schema(schema));
+    }
+}
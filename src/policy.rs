@@ -0,0 +1,239 @@
+//! Reservation Policy
+//!
+//! `policy` limits the shape of reservations so that no one can book the whole cluster for the
+//! whole schedule or fragment capacity with odd, second-level boundaries.
+//!
+//! Every limit is optional and disabled by default. The hostess enforces the policy before she
+//! checks whether there's enough capacity, so users find out about shape problems first.
+
+// Standard library crates.
+use std::fmt;
+use std::time::Duration;
+
+// External crates.
+use anyhow::Result;
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use serde::de::Deserialize as _;
+use serde::Deserializer;
+use serde_derive::Deserialize;
+
+// Project crates.
+use crate::ReservationRequest;
+
+/// What to do with reservation times that aren't on the time grid.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GranularityMode {
+    /// Refuse reservations with times that aren't on the grid.
+    #[default]
+    Reject,
+    /// Stretch reservations outward to the nearest grid lines.
+    Snap,
+}
+
+/// Limits on the shape of reservations.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReservationPolicy {
+    /// Longest that a single reservation can last.
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    pub max_duration: Option<Duration>,
+    /// Furthest in advance that a reservation can start.
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    pub max_horizon: Option<Duration>,
+    /// Least amount of warning that's needed before a reservation starts.
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    pub min_notice: Option<Duration>,
+    /// Spacing of the grid that reservation times must fall on, like every 15 minutes.
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    pub time_granularity: Option<Duration>,
+    /// What to do with reservation times that aren't on the grid.
+    pub granularity_mode: GranularityMode,
+}
+
+/// Reasons that a reservation request breaks the reservation policy.
+#[derive(Debug, PartialEq)]
+pub enum PolicyViolation {
+    /// Reservation lasts longer than the maximum duration.
+    TooLong { duration: u32, max_duration: u32 },
+    /// Reservation starts further in advance than the booking horizon.
+    TooFarAhead { start_time: u32, latest_start: u32 },
+    /// Reservation starts without enough notice.
+    TooLittleNotice {
+        start_time: u32,
+        earliest_start: u32,
+    },
+    /// Reservation time doesn't fall on the time grid.
+    OffGrid { time: u32, granularity: u32 },
+    /// Reservation has no time left once it's snapped to the time grid.
+    EmptyOnGrid {
+        start_time: u32,
+        end_time: u32,
+        granularity: u32,
+    },
+}
+
+// Print violations nicely so they can be shown to users.
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolicyViolation::TooLong {
+                duration,
+                max_duration,
+            } => write!(
+                formatter,
+                "Reservation lasts \"{duration}\" seconds, but can't last longer than \"{max_duration}\" seconds"
+            ),
+            PolicyViolation::TooFarAhead {
+                start_time,
+                latest_start,
+            } => write!(
+                formatter,
+                "Reservation starts on \"{start_time}\", but can't start later than \"{latest_start}\""
+            ),
+            PolicyViolation::TooLittleNotice {
+                start_time,
+                earliest_start,
+            } => write!(
+                formatter,
+                "Reservation starts on \"{start_time}\", but can't start earlier than \"{earliest_start}\""
+            ),
+            PolicyViolation::OffGrid { time, granularity } => write!(
+                formatter,
+                "Reservation time \"{time}\" isn't a multiple of \"{granularity}\" seconds"
+            ),
+            PolicyViolation::EmptyOnGrid {
+                start_time,
+                end_time,
+                granularity,
+            } => write!(
+                formatter,
+                "Reservation snapped to the \"{granularity}\" second grid starts on \"{start_time}\", but ends on \"{end_time}\""
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PolicyViolation {}
+
+impl ReservationPolicy {
+    /// Ensure that a reservation request follows the policy.
+    ///
+    /// If the policy snaps times to the grid, then the returned request has the snapped times.
+    /// Start times are moved earlier and end times are moved later so that users get at least
+    /// what they asked for, unless moving the start time earlier would break the minimum notice
+    /// (or start in the past). Then it's moved later instead.
+    ///
+    /// # Arguments
+    /// - `reservation_request`: Reservation request with exact times.
+    /// - `epoch_now`: Current time, represented by Unix epoch format.
+    pub fn enforce(
+        &self,
+        reservation_request: &ReservationRequest,
+        epoch_now: u32,
+    ) -> Result<ReservationRequest, PolicyViolation> {
+        let min_notice = self.min_notice.map(duration_secs).unwrap_or(0);
+        let earliest_start = epoch_now.saturating_add(min_notice);
+        let mut start_time = reservation_request.start_time;
+        let mut end_time = reservation_request.end_time;
+
+        // Put reservation times on the grid.
+        let granularity = self.time_granularity.map(duration_secs).unwrap_or(0);
+        if granularity > 0 {
+            match self.granularity_mode {
+                GranularityMode::Reject => {
+                    for time in [start_time, end_time] {
+                        if time % granularity != 0 {
+                            return Err(PolicyViolation::OffGrid { time, granularity });
+                        }
+                    }
+                }
+                GranularityMode::Snap => {
+                    let snapped_start = start_time - start_time % granularity;
+                    // Don't let snapping push an otherwise timely start into the past.
+                    start_time = if snapped_start < earliest_start && start_time >= earliest_start {
+                        round_up(start_time, granularity)
+                    } else {
+                        snapped_start
+                    };
+                    end_time = round_up(end_time, granularity);
+                    // Moving the start time later can leave nothing between it and the end.
+                    if start_time >= end_time {
+                        return Err(PolicyViolation::EmptyOnGrid {
+                            start_time,
+                            end_time,
+                            granularity,
+                        });
+                    }
+                    debug!(
+                        "Snapped reservation to grid: \"{}\" to \"{}\"",
+                        start_time, end_time
+                    );
+                }
+            }
+        }
+
+        // Ensure reservation isn't too long.
+        if let Some(max_duration) = self.max_duration.map(duration_secs) {
+            let duration = end_time.saturating_sub(start_time);
+            if duration > max_duration {
+                return Err(PolicyViolation::TooLong {
+                    duration,
+                    max_duration,
+                });
+            }
+        }
+
+        // Ensure there's enough notice before the reservation starts.
+        if min_notice > 0 && start_time < earliest_start {
+            return Err(PolicyViolation::TooLittleNotice {
+                start_time,
+                earliest_start,
+            });
+        }
+
+        // Ensure reservation doesn't start too far in advance.
+        if let Some(max_horizon) = self.max_horizon.map(duration_secs) {
+            let latest_start = epoch_now.saturating_add(max_horizon);
+            if start_time > latest_start {
+                return Err(PolicyViolation::TooFarAhead {
+                    start_time,
+                    latest_start,
+                });
+            }
+        }
+
+        Ok(ReservationRequest::new(
+            start_time,
+            end_time,
+            reservation_request.capacity_amount,
+            reservation_request.user_id,
+        )
+        .in_pool(&reservation_request.pool))
+    }
+}
+
+/// Get a duration in whole seconds that fits alongside Unix epochs.
+fn duration_secs(duration: Duration) -> u32 {
+    u32::try_from(duration.as_secs()).unwrap_or(u32::MAX)
+}
+
+/// Move a time later to the next grid line, unless it's already on one.
+fn round_up(time: u32, granularity: u32) -> u32 {
+    match time % granularity {
+        0 => time,
+        remainder => time.saturating_add(granularity - remainder),
+    }
+}
+
+/// Read an optional "human time" duration like `"15m"` from the config file.
+fn deserialize_optional_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let human_duration = String::deserialize(deserializer)?;
+    humantime::parse_duration(&human_duration)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
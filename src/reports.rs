@@ -0,0 +1,576 @@
+//! Reports
+//!
+//! `reports` turns the capacity schedule, user reservations, and request history into numbers
+//! for finance and planning folks: how much of the schedule is used, who's using it, how spiky
+//! the load is, and how often requests are denied.
+//!
+//! Every report splits its timeframe into buckets of the same length (except maybe the last one,
+//! which ends with the timeframe) and reports on every bucket. Rows can be sent as JSON or CSV.
+
+// Standard library crates.
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// External crates.
+use anyhow::{ensure, Result};
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use serde_derive::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Project crates.
+use crate::common::{AvailabilitySlot, CapacitySegment, Reservation};
+use crate::demand::{EvaluatedRequest, RequestOutcome};
+
+/// Most buckets that one report can be split into, so no one asks for a row per second.
+const MAX_BUCKETS: u32 = 10000;
+
+/// Seconds in an hour, for reporting unit-hours.
+const SECONDS_PER_HOUR: f64 = 3600.0;
+
+/// Timeframe that a report covers, split into buckets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReportBuckets {
+    pub start_time: u32,
+    pub end_time: u32,
+    /// Length of every bucket in seconds.
+    pub bucket_length: u32,
+}
+
+impl ReportBuckets {
+    /// Split a timeframe into buckets.
+    ///
+    /// # Arguments
+    /// - `start_time`: Beginning of the timeframe, represented by Unix epoch format.
+    /// - `end_time`: End of the timeframe, represented by Unix epoch format.
+    /// - `bucket_length`: How long every bucket is, like a day.
+    pub fn new(start_time: u32, end_time: u32, bucket_length: Duration) -> Result<Self> {
+        ensure!(
+            start_time < end_time,
+            format!(
+                "Report timeframe from \"{start_time}\" to \"{end_time}\" begins before it ends"
+            )
+        );
+        let bucket_length = u32::try_from(bucket_length.as_secs()).unwrap_or(u32::MAX);
+        ensure!(
+            bucket_length > 0,
+            "Report buckets must be at least a second long"
+        );
+        let bucket_count = (end_time - start_time).div_ceil(bucket_length);
+        ensure!(
+            bucket_count <= MAX_BUCKETS,
+            format!(
+                "Report would have \"{bucket_count}\" buckets, but can't have more than \"{MAX_BUCKETS}\""
+            )
+        );
+        Ok(Self {
+            start_time,
+            end_time,
+            bucket_length,
+        })
+    }
+
+    /// Get the start and end time of every bucket, earliest first.
+    pub fn buckets(&self) -> Vec<(u32, u32)> {
+        (self.start_time..self.end_time)
+            .step_by(self.bucket_length as usize)
+            .map(|bucket_start| {
+                let bucket_end = bucket_start
+                    .saturating_add(self.bucket_length)
+                    .min(self.end_time);
+                (bucket_start, bucket_end)
+            })
+            .collect()
+    }
+}
+
+/// Report rows that can be written as CSV.
+///
+/// Rows only have numbers in them, so nothing needs quoting.
+pub trait CsvRow {
+    /// Column names, separated by commas.
+    fn csv_header() -> &'static str;
+
+    /// Row's values, separated by commas and in the same order as the header.
+    fn csv_row(&self) -> String;
+}
+
+/// Write report rows as CSV with a header line.
+pub fn to_csv<Row: CsvRow>(report_rows: &[Row]) -> String {
+    let mut csv = format!("{}\n", Row::csv_header());
+    for report_row in report_rows {
+        csv.push_str(&report_row.csv_row());
+        csv.push('\n');
+    }
+    csv
+}
+
+/// How much of a capacity segment was reserved during a bucket.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct UtilizationRow {
+    pub bucket_start: u32,
+    pub bucket_end: u32,
+    pub segment_start: u32,
+    pub segment_end: u32,
+    /// Capacity that the segment provides.
+    pub scheduled_amount: u32,
+    /// Capacity that the segment provided during the bucket, multiplied by hours.
+    pub scheduled_unit_hours: f64,
+    /// Capacity that was reserved from the segment during the bucket, multiplied by hours.
+    pub reserved_unit_hours: f64,
+    /// Share of the scheduled unit-hours that were reserved.
+    pub utilization_percent: f64,
+}
+
+impl CsvRow for UtilizationRow {
+    fn csv_header() -> &'static str {
+        "bucket_start,bucket_end,segment_start,segment_end,scheduled_amount,scheduled_unit_hours,reserved_unit_hours,utilization_percent"
+    }
+
+    fn csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{}",
+            self.bucket_start,
+            self.bucket_end,
+            self.segment_start,
+            self.segment_end,
+            self.scheduled_amount,
+            self.scheduled_unit_hours,
+            self.reserved_unit_hours,
+            self.utilization_percent
+        )
+    }
+}
+
+/// How much capacity a user booked during a bucket.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct UsageRow {
+    pub bucket_start: u32,
+    pub bucket_end: u32,
+    pub user_id: u32,
+    /// Number of the user's reservations that overlap the bucket.
+    pub reservations: u32,
+    /// Capacity that the user reserved during the bucket, multiplied by hours.
+    pub unit_hours: f64,
+}
+
+impl CsvRow for UsageRow {
+    fn csv_header() -> &'static str {
+        "bucket_start,bucket_end,user_id,reservations,unit_hours"
+    }
+
+    fn csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{}",
+            self.bucket_start, self.bucket_end, self.user_id, self.reservations, self.unit_hours
+        )
+    }
+}
+
+/// Peak and average reserved capacity during a bucket.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct LoadRow {
+    pub bucket_start: u32,
+    pub bucket_end: u32,
+    /// Most capacity that was reserved at once.
+    pub peak_reserved_amount: u32,
+    /// Capacity that was reserved on average over the whole bucket.
+    pub average_reserved_amount: f64,
+}
+
+impl CsvRow for LoadRow {
+    fn csv_header() -> &'static str {
+        "bucket_start,bucket_end,peak_reserved_amount,average_reserved_amount"
+    }
+
+    fn csv_row(&self) -> String {
+        format!(
+            "{},{},{},{}",
+            self.bucket_start,
+            self.bucket_end,
+            self.peak_reserved_amount,
+            self.average_reserved_amount
+        )
+    }
+}
+
+/// How many reservation requests were denied during a bucket.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct DenialRow {
+    pub bucket_start: u32,
+    pub bucket_end: u32,
+    /// Number of requests that were evaluated during the bucket.
+    pub requests: u32,
+    pub denied_requests: u32,
+    /// Share of the requests that were denied.
+    pub denial_rate_percent: f64,
+}
+
+impl CsvRow for DenialRow {
+    fn csv_header() -> &'static str {
+        "bucket_start,bucket_end,requests,denied_requests,denial_rate_percent"
+    }
+
+    fn csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{}",
+            self.bucket_start,
+            self.bucket_end,
+            self.requests,
+            self.denied_requests,
+            self.denial_rate_percent
+        )
+    }
+}
+
+/// Find how many seconds two timeframes share.
+fn overlap_seconds(first: (u32, u32), second: (u32, u32)) -> u32 {
+    first.1.min(second.1).saturating_sub(first.0.max(second.0))
+}
+
+/// Find what percent a part is of a whole, or 0 if there's no whole.
+fn percent(part: f64, whole: f64) -> f64 {
+    match whole > 0.0 {
+        true => part / whole * 100.0,
+        false => 0.0,
+    }
+}
+
+/// Work out how much of every capacity segment was reserved during every bucket.
+///
+/// # Arguments
+/// - `report_buckets`: Timeframe that the report covers.
+/// - `capacity_segments`: Pool's capacity segments.
+/// - `timeline`: Pool's availability during the timeframe, like from `check_availability()`.
+///
+/// # Returns
+/// One row for every bucket and segment that overlap, earliest first.
+pub fn utilization_report(
+    report_buckets: &ReportBuckets,
+    capacity_segments: &[CapacitySegment],
+    timeline: &[AvailabilitySlot],
+) -> Vec<UtilizationRow> {
+    let mut capacity_segments: Vec<&CapacitySegment> = capacity_segments.iter().collect();
+    capacity_segments.sort_by_key(|capacity_segment| capacity_segment.start_time);
+    let mut report_rows = Vec::new();
+    for bucket in report_buckets.buckets() {
+        for capacity_segment in &capacity_segments {
+            let segment = (capacity_segment.start_time, capacity_segment.end_time);
+            let scheduled_seconds = overlap_seconds(bucket, segment);
+            if scheduled_seconds == 0 {
+                continue;
+            }
+            // Segments don't overlap, so every slot inside of this one is reserved from it.
+            let reserved_unit_seconds: u64 = timeline
+                .iter()
+                .map(|slot| {
+                    let slot_seconds = overlap_seconds(
+                        (slot.start_time, slot.end_time),
+                        (bucket.0.max(segment.0), bucket.1.min(segment.1)),
+                    );
+                    u64::from(slot.reserved_amount) * u64::from(slot_seconds)
+                })
+                .sum();
+            let scheduled_unit_seconds =
+                u64::from(capacity_segment.capacity_amount) * u64::from(scheduled_seconds);
+            report_rows.push(UtilizationRow {
+                bucket_start: bucket.0,
+                bucket_end: bucket.1,
+                segment_start: capacity_segment.start_time,
+                segment_end: capacity_segment.end_time,
+                scheduled_amount: capacity_segment.capacity_amount,
+                scheduled_unit_hours: scheduled_unit_seconds as f64 / SECONDS_PER_HOUR,
+                reserved_unit_hours: reserved_unit_seconds as f64 / SECONDS_PER_HOUR,
+                utilization_percent: percent(
+                    reserved_unit_seconds as f64,
+                    scheduled_unit_seconds as f64,
+                ),
+            });
+        }
+    }
+    report_rows
+}
+
+/// Work out how much capacity every user booked during every bucket.
+///
+/// Only reservations that hold capacity are counted.
+///
+/// # Returns
+/// One row for every bucket and user with a reservation in it, earliest first, then by user.
+pub fn usage_report(report_buckets: &ReportBuckets, reservations: &[Reservation]) -> Vec<UsageRow> {
+    let mut report_rows = Vec::new();
+    for bucket in report_buckets.buckets() {
+        let mut bucket_usage: BTreeMap<u32, (u32, u64)> = BTreeMap::new();
+        for reservation in reservations
+            .iter()
+            .filter(|reservation| reservation.status.holds_capacity())
+        {
+            let reserved_seconds =
+                overlap_seconds(bucket, (reservation.start_time, reservation.end_time));
+            if reserved_seconds == 0 {
+                continue;
+            }
+            let user_usage = bucket_usage.entry(reservation.user_id).or_default();
+            user_usage.0 += 1;
+            user_usage.1 += u64::from(reservation.capacity_amount) * u64::from(reserved_seconds);
+        }
+        for (user_id, (reservation_count, unit_seconds)) in bucket_usage {
+            report_rows.push(UsageRow {
+                bucket_start: bucket.0,
+                bucket_end: bucket.1,
+                user_id,
+                reservations: reservation_count,
+                unit_hours: unit_seconds as f64 / SECONDS_PER_HOUR,
+            });
+        }
+    }
+    report_rows
+}
+
+/// Work out the peak and average reserved capacity during every bucket.
+///
+/// # Arguments
+/// - `report_buckets`: Timeframe that the report covers.
+/// - `timeline`: Pool's availability during the timeframe, like from `check_availability()`.
+pub fn load_report(report_buckets: &ReportBuckets, timeline: &[AvailabilitySlot]) -> Vec<LoadRow> {
+    report_buckets
+        .buckets()
+        .into_iter()
+        .map(|bucket| {
+            let overlapping_slots: Vec<(&AvailabilitySlot, u32)> = timeline
+                .iter()
+                .map(|slot| {
+                    (
+                        slot,
+                        overlap_seconds(bucket, (slot.start_time, slot.end_time)),
+                    )
+                })
+                .filter(|(_, slot_seconds)| *slot_seconds > 0)
+                .collect();
+            let reserved_unit_seconds: u64 = overlapping_slots
+                .iter()
+                .map(|(slot, slot_seconds)| {
+                    u64::from(slot.reserved_amount) * u64::from(*slot_seconds)
+                })
+                .sum();
+            LoadRow {
+                bucket_start: bucket.0,
+                bucket_end: bucket.1,
+                peak_reserved_amount: overlapping_slots
+                    .iter()
+                    .map(|(slot, _)| slot.reserved_amount)
+                    .max()
+                    .unwrap_or(0),
+                average_reserved_amount: reserved_unit_seconds as f64
+                    / f64::from(bucket.1 - bucket.0),
+            }
+        })
+        .collect()
+}
+
+/// Work out how many reservation requests were denied during every bucket.
+///
+/// Requests are counted in the bucket that they were evaluated in, not the one they asked for.
+pub fn denial_report(
+    report_buckets: &ReportBuckets,
+    evaluated_requests: &[EvaluatedRequest],
+) -> Vec<DenialRow> {
+    report_buckets
+        .buckets()
+        .into_iter()
+        .map(|bucket| {
+            let bucket_requests: Vec<&EvaluatedRequest> = evaluated_requests
+                .iter()
+                .filter(|evaluated_request| {
+                    (bucket.0..bucket.1).contains(&evaluated_request.requested_at)
+                })
+                .collect();
+            let denied_requests = bucket_requests
+                .iter()
+                .filter(|evaluated_request| evaluated_request.outcome == RequestOutcome::Denied)
+                .count() as u32;
+            DenialRow {
+                bucket_start: bucket.0,
+                bucket_end: bucket.1,
+                requests: bucket_requests.len() as u32,
+                denied_requests,
+                denial_rate_percent: percent(
+                    f64::from(denied_requests),
+                    bucket_requests.len() as f64,
+                ),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    // Standard library crates.
+    use std::time::Duration;
+
+    // Project crates.
+    use super::{
+        denial_report, load_report, to_csv, usage_report, utilization_report, LoadRow,
+        ReportBuckets,
+    };
+    use crate::common::{AvailabilitySlot, CapacitySegment, Reservation, ReservationStatus};
+    use crate::demand::{EvaluatedRequest, RequestOutcome};
+
+    /// Two day report with one day buckets, starting when Schedule 1 begins.
+    fn test_buckets() -> ReportBuckets {
+        ReportBuckets::new(1707165008, 1707337808, Duration::from_secs(86400)).unwrap()
+    }
+
+    /// Slot with 64 scheduled capacity and some of it reserved.
+    fn test_slot(start_time: u32, end_time: u32, reserved_amount: u32) -> AvailabilitySlot {
+        AvailabilitySlot {
+            start_time,
+            end_time,
+            scheduled_amount: 64,
+            maintenance_amount: 0,
+            reserved_amount,
+            available_amount: 64 - reserved_amount,
+        }
+    }
+
+    /// Reservation that holds capacity.
+    fn test_reservation(
+        start_time: u32,
+        end_time: u32,
+        capacity_amount: u32,
+        user_id: u32,
+    ) -> Reservation {
+        Reservation {
+            reservation_id: 1,
+            start_time,
+            end_time,
+            capacity_amount,
+            user_id,
+            pool: String::from("default"),
+            status: ReservationStatus::Pending,
+            created_at: 1707078608,
+            updated_at: 1707078608,
+            cost_microcredits: 0,
+            refund_microcredits: 0,
+        }
+    }
+
+    #[test]
+    fn test_buckets_end_with_timeframe() {
+        let report_buckets =
+            ReportBuckets::new(1707165008, 1707251409, Duration::from_secs(43200)).unwrap();
+        assert_eq!(
+            report_buckets.buckets(),
+            vec![
+                (1707165008, 1707208208),
+                (1707208208, 1707251408),
+                (1707251408, 1707251409)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_refuse_bad_buckets() {
+        assert!(ReportBuckets::new(1707337808, 1707165008, Duration::from_secs(86400)).is_err());
+        assert!(ReportBuckets::new(1707165008, 1707337808, Duration::ZERO).is_err());
+        // One second buckets for two days is too many rows.
+        assert!(ReportBuckets::new(1707165008, 1707337808, Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn test_utilization_report() {
+        let capacity_segments = vec![CapacitySegment::new(1707165008, 1708374608, 64)];
+        // Half of the capacity is reserved for half of the first day.
+        let timeline = vec![
+            test_slot(1707165008, 1707208208, 32),
+            test_slot(1707208208, 1707337808, 0),
+        ];
+        let report_rows = utilization_report(&test_buckets(), &capacity_segments, &timeline);
+        assert_eq!(report_rows.len(), 2);
+        assert_eq!(report_rows[0].scheduled_unit_hours, 64.0 * 24.0);
+        assert_eq!(report_rows[0].reserved_unit_hours, 32.0 * 12.0);
+        assert_eq!(report_rows[0].utilization_percent, 25.0);
+        assert_eq!(report_rows[1].utilization_percent, 0.0);
+    }
+
+    #[test]
+    fn test_usage_report() {
+        let mut cancelled_reservation = test_reservation(1707165008, 1707337808, 64, 44);
+        cancelled_reservation.status = ReservationStatus::Cancelled;
+        let reservations = vec![
+            test_reservation(1707165008, 1707208208, 8, 42),
+            test_reservation(1707208208, 1707294608, 4, 42),
+            test_reservation(1707165008, 1707168608, 1, 43),
+            cancelled_reservation,
+        ];
+        let report_rows = usage_report(&test_buckets(), &reservations);
+        assert_eq!(report_rows.len(), 3);
+        assert_eq!(report_rows[0].user_id, 42);
+        assert_eq!(report_rows[0].reservations, 2);
+        assert_eq!(report_rows[0].unit_hours, 8.0 * 12.0 + 4.0 * 12.0);
+        assert_eq!(report_rows[1].user_id, 43);
+        assert_eq!(report_rows[1].unit_hours, 1.0);
+        // Second day only has the end of the second reservation.
+        assert_eq!(report_rows[2].bucket_start, 1707251408);
+        assert_eq!(report_rows[2].unit_hours, 4.0 * 12.0);
+    }
+
+    #[test]
+    fn test_load_report() {
+        let timeline = vec![
+            test_slot(1707165008, 1707208208, 32),
+            test_slot(1707208208, 1707251408, 16),
+            test_slot(1707251408, 1707337808, 0),
+        ];
+        let report_rows = load_report(&test_buckets(), &timeline);
+        assert_eq!(report_rows[0].peak_reserved_amount, 32);
+        assert_eq!(report_rows[0].average_reserved_amount, 24.0);
+        assert_eq!(report_rows[1].peak_reserved_amount, 0);
+        assert_eq!(report_rows[1].average_reserved_amount, 0.0);
+    }
+
+    #[test]
+    fn test_denial_report() {
+        let evaluated_request = |requested_at: u32, outcome: RequestOutcome| EvaluatedRequest {
+            request_id: 1,
+            requested_at,
+            start_time: 1707337808,
+            end_time: 1707424208,
+            capacity_amount: 8,
+            user_id: 42,
+            pool: String::from("default"),
+            outcome,
+            denial_reason: None,
+            reservation_id: None,
+            bottleneck: None,
+        };
+        let evaluated_requests = vec![
+            evaluated_request(1707165008, RequestOutcome::Approved),
+            evaluated_request(1707165009, RequestOutcome::Denied),
+            evaluated_request(1707165010, RequestOutcome::Denied),
+            evaluated_request(1707165011, RequestOutcome::Approved),
+        ];
+        let report_rows = denial_report(&test_buckets(), &evaluated_requests);
+        assert_eq!(report_rows[0].requests, 4);
+        assert_eq!(report_rows[0].denied_requests, 2);
+        assert_eq!(report_rows[0].denial_rate_percent, 50.0);
+        // Buckets without requests don't divide by zero.
+        assert_eq!(report_rows[1].requests, 0);
+        assert_eq!(report_rows[1].denial_rate_percent, 0.0);
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let report_rows = vec![LoadRow {
+            bucket_start: 1707165008,
+            bucket_end: 1707251408,
+            peak_reserved_amount: 32,
+            average_reserved_amount: 24.5,
+        }];
+        assert_eq!(
+            to_csv(&report_rows),
+            "bucket_start,bucket_end,peak_reserved_amount,average_reserved_amount\n\
+             1707165008,1707251408,32,24.5\n"
+        );
+    }
+}
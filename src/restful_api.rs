@@ -1,23 +1,66 @@
 // Standard library crates.
+use std::convert::Infallible;
 use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
 
 // External crates.
+use anyhow::anyhow;
+use futures_util::StreamExt;
 #[allow(unused)]
 use log::{debug, error, info, trace, warn};
+use serde::de::DeserializeOwned;
+use serde::Serialize as SerializeJson;
 use serde_derive::{Deserialize, Serialize};
-use warp::Filter;
+use utoipa::openapi::path::{Operation, PathItem};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::Deprecated;
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+use warp::http::{HeaderValue, StatusCode};
+use warp::path::FullPath;
+use warp::{Filter, Reply};
 
 // Project crates.
-use crate::hostess::process_reservation;
+use crate::auth::{AuthError, Authenticator, Principal, Role};
+use crate::batch::{BatchItemResult, BatchOrdering, Denial, DenialKind};
+use crate::billing::{Invoice, InvoiceGrouping, PriceQuote, SegmentPricing};
+use crate::common::{
+    AvailabilitySlot, MaintenanceParams, MaintenanceWindow, ReservationParams, ReservationStatus,
+    DEFAULT_POOL,
+};
+use crate::demand::{DemandDimension, EvaluatedRequest, RequestFilter, UnmetDemand};
+use crate::events::{follow_changes, ChangeEvent, ChangeEventKind, ChangeFilter};
+use crate::hostess::Hostess;
+use crate::idempotency::{request_hash, IdempotencyClaim, IdempotencyConflict};
+use crate::listing::{
+    ReservationFilter, ReservationPage, ReservationSort, SortOrder, TimeMatch, DEFAULT_PAGE_SIZE,
+};
+use crate::metrics::metrics;
+use crate::policy::PolicyViolation;
+use crate::reports::{to_csv, CsvRow, DenialRow, LoadRow, ReportBuckets, UsageRow, UtilizationRow};
+use crate::versioning::{ApiConfig, ApiVersion};
+use crate::webhooks::{WebhookDelivery, WebhookParams, WebhookSubscription};
 use crate::ReservationRequest;
 
+/// Port that the RESTful API listens on.
+pub const RESTFUL_API_PORT: u16 = 4242;
+
+/// Routes that are only served at the root, since they describe every version at once.
+const UNVERSIONED_PATHS: [&str; 2] = ["/openapi.json", "/metrics"];
+
 /// RESTful API JSON response concerning reservation attempt.
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, ToSchema)]
 struct ReservationResponse {
     is_reserved: bool,
     user_message: String,
-    // todo: Add unique IDs to reservations.
-    // "reservation_id": u32
+    /// Unique ID of the reservation that was created.
+    reservation_id: Option<u32>,
+    /// Start time that was evaluated after resolving relative times like `"now"`.
+    start_time: Option<u32>,
+    /// End time that was evaluated after resolving relative times like `"+1h"`.
+    end_time: Option<u32>,
+    /// What the reservation that was created costs, in microcredits.
+    cost_microcredits: Option<u64>,
 }
 
 impl ReservationResponse {
@@ -25,122 +68,3694 @@ impl ReservationResponse {
         Self {
             is_reserved,
             user_message,
+            reservation_id: None,
+            start_time: None,
+            end_time: None,
+            cost_microcredits: None,
         }
     }
+
+    /// Report the exact timeframe that was asked for.
+    fn with_timeframe(mut self, reservation_request: &ReservationRequest) -> Self {
+        self.start_time = Some(reservation_request.start_time);
+        self.end_time = Some(reservation_request.end_time);
+        self
+    }
 }
 
-// Greet the user by name.
-//
-// "Hello" will be prepended to the name provided in the URL and returned in the HTML body.
+/// RESTful API v2 JSON response concerning reservation attempt.
+///
+/// Unlike v1's, the HTTP status says whether the reservation was made, and denials say what kind
+/// they are.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct ReservationResponseV2 {
+    is_reserved: bool,
+    /// Unique ID of the reservation that was created.
+    reservation_id: Option<u32>,
+    /// Start time that was evaluated after resolving relative times like `"now"`.
+    start_time: Option<u32>,
+    /// End time that was evaluated after resolving relative times like `"+1h"`.
+    end_time: Option<u32>,
+    /// What the reservation that was created costs, in microcredits.
+    cost_microcredits: Option<u64>,
+    /// Why the reservation wasn't made, if it wasn't.
+    denial: Option<Denial>,
+}
+
+impl From<BatchItemResult> for ReservationResponseV2 {
+    fn from(batch_result: BatchItemResult) -> Self {
+        Self {
+            is_reserved: batch_result.is_reserved,
+            reservation_id: batch_result.reservation_id,
+            start_time: batch_result.start_time,
+            end_time: batch_result.end_time,
+            cost_microcredits: batch_result.cost_microcredits,
+            denial: batch_result.denial,
+        }
+    }
+}
+
+/// Response to a reservation request, which each version of the API shapes its own way.
+trait ReservationReply: SerializeJson + DeserializeOwned + Send + 'static {
+    /// Version of the API that answers with this response.
+    const API_VERSION: ApiVersion;
+
+    /// Resolve the user's reservation parameters and see if they can be seated.
+    ///
+    /// # Returns
+    /// The response if the request was granted, or refused by the policy or for lack of capacity.
+    /// Requests that couldn't be evaluated, like ones with parameters that can't be resolved or
+    /// that hit a datastore error, are errors instead, since retrying them could go differently.
+    fn respond(reservation_params: &ReservationParams, hostess: &Hostess) -> anyhow::Result<Self>;
+
+    /// Response to a request that couldn't be evaluated.
+    fn refused(user_message: String) -> Self;
+
+    /// HTTP status that the response is sent with.
+    fn status(&self) -> StatusCode;
+}
+
+impl ReservationReply for ReservationResponse {
+    const API_VERSION: ApiVersion = ApiVersion::V1;
+
+    fn respond(reservation_params: &ReservationParams, hostess: &Hostess) -> anyhow::Result<Self> {
+        respond_to_reservation(reservation_params, hostess)
+    }
+
+    fn refused(user_message: String) -> Self {
+        ReservationResponse::new(false, user_message)
+    }
+
+    // Whether the reservation was made is only in the body.
+    fn status(&self) -> StatusCode {
+        StatusCode::OK
+    }
+}
+
+impl ReservationReply for ReservationResponseV2 {
+    const API_VERSION: ApiVersion = ApiVersion::V2;
+
+    fn respond(reservation_params: &ReservationParams, hostess: &Hostess) -> anyhow::Result<Self> {
+        // One request is a batch of one, which already works out what kind of denial it got.
+        let mut batch_results = hostess
+            .process_batch(
+                std::slice::from_ref(reservation_params),
+                BatchOrdering::InOrder,
+                false,
+            )
+            .map_err(|error_message| anyhow!("{:#}", error_message))?;
+        let batch_result = batch_results.remove(0);
+        match batch_result.denial {
+            Some(Denial {
+                kind: DenialKind::InvalidRequest,
+                user_message,
+            }) => Err(anyhow!(user_message)),
+            _ => Ok(batch_result.into()),
+        }
+    }
+
+    fn refused(user_message: String) -> Self {
+        BatchItemResult::denied(0, DenialKind::InvalidRequest, user_message).into()
+    }
+
+    fn status(&self) -> StatusCode {
+        match self.denial.as_ref().map(|denial| denial.kind) {
+            None => StatusCode::CREATED,
+            Some(DenialKind::InvalidRequest) => StatusCode::BAD_REQUEST,
+            Some(DenialKind::PolicyViolation) => StatusCode::UNPROCESSABLE_ENTITY,
+            Some(DenialKind::NotEnoughCapacity | DenialKind::BatchRefused) => StatusCode::CONFLICT,
+        }
+    }
+}
+
+/// RESTful API JSON body for reserving a batch of requests at once.
+#[derive(Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct BatchRequest {
+    requests: Vec<ReservationParams>,
+    #[serde(default)]
+    ordering: BatchOrdering,
+    /// Whether every request has to be reserved, or none of them are.
+    #[serde(default)]
+    atomic: bool,
+}
+
+/// RESTful API JSON response concerning a batch of reservation attempts.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct BatchResponse {
+    /// Whether every request in the batch was reserved.
+    is_reserved: bool,
+    /// What became of each request, in the order that they were sent.
+    results: Vec<BatchItemResult>,
+}
+
+/// RESTful API JSON response for requests that couldn't be answered.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct ErrorResponse {
+    user_message: String,
+}
+
+/// RESTful API query parameters for checking availability.
+#[derive(Deserialize, IntoParams, Serialize)]
+#[serde(deny_unknown_fields)]
+#[into_params(parameter_in = Query)]
+struct AvailabilityQuery {
+    #[serde(default = "default_pool")]
+    pool: String,
+    start_time: u32,
+    end_time: u32,
+}
+
+/// RESTful API JSON response concerning available capacity.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct AvailabilityResponse {
+    pool: String,
+    slots: Vec<AvailabilitySlot>,
+    /// Most capacity that's reserved at once during the timeframe.
+    peak_reserved_amount: u32,
+}
+
+/// RESTful API query parameters for grouping unmet demand.
+#[derive(Deserialize, IntoParams, Serialize)]
+#[serde(deny_unknown_fields)]
+#[into_params(parameter_in = Query)]
+struct UnmetDemandQuery {
+    pool: Option<String>,
+    user_id: Option<u32>,
+    start_time: Option<u32>,
+    end_time: Option<u32>,
+    /// Comma separated dimensions to group by, like `"time,pool"`.
+    #[serde(default = "default_demand_grouping")]
+    group_by: String,
+    /// How long the buckets of time are that requests are grouped into, like `"1h"` or `"7d"`.
+    #[serde(default = "default_report_bucket")]
+    bucket: String,
+}
+
+impl UnmetDemandQuery {
+    /// Which denied requests to add up.
+    fn request_filter(&self) -> RequestFilter {
+        RequestFilter {
+            pool: self.pool.clone(),
+            user_id: self.user_id,
+            start_time: self.start_time,
+            end_time: self.end_time,
+            outcome: None,
+        }
+    }
+
+    /// Dimensions to group unmet demand by.
+    fn dimensions(&self) -> anyhow::Result<Vec<DemandDimension>> {
+        self.group_by
+            .split(',')
+            .map(str::trim)
+            .filter(|dimension_name| !dimension_name.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+
+    /// How long the buckets of time are that requests are grouped into.
+    fn bucket_length(&self) -> anyhow::Result<Duration> {
+        humantime::parse_duration(&self.bucket).map_err(|parse_error| {
+            anyhow!("Invalid demand bucket \"{}\": {}", self.bucket, parse_error)
+        })
+    }
+}
+
+/// Ways that reports can be sent back.
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum ReportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+/// RESTful API query parameters for reports.
+#[derive(Deserialize, IntoParams, Serialize)]
+#[serde(deny_unknown_fields)]
+#[into_params(parameter_in = Query)]
+struct ReportQuery {
+    #[serde(default = "default_pool")]
+    pool: String,
+    start_time: u32,
+    end_time: u32,
+    /// How long every bucket is, like `"1h"` or `"1d"`.
+    #[serde(default = "default_report_bucket")]
+    bucket: String,
+    #[serde(default)]
+    format: ReportFormat,
+}
+
+impl ReportQuery {
+    /// Split the report's timeframe into buckets.
+    fn report_buckets(&self) -> anyhow::Result<ReportBuckets> {
+        let bucket_length = humantime::parse_duration(&self.bucket).map_err(|parse_error| {
+            anyhow!("Invalid report bucket \"{}\": {}", self.bucket, parse_error)
+        })?;
+        ReportBuckets::new(self.start_time, self.end_time, bucket_length)
+    }
+}
+
+/// RESTful API query parameters for listing reservations.
+#[derive(Deserialize, IntoParams, Serialize)]
+#[serde(deny_unknown_fields)]
+#[into_params(parameter_in = Query)]
+struct ReservationListQuery {
+    user_id: Option<u32>,
+    pool: Option<String>,
+    start_time: Option<u32>,
+    end_time: Option<u32>,
+    /// How reservations have to line up with the timeframe.
+    #[serde(default)]
+    time_match: TimeMatch,
+    status: Option<ReservationStatus>,
+    #[serde(default)]
+    sort: ReservationSort,
+    #[serde(default)]
+    order: SortOrder,
+    #[serde(default = "default_page_size")]
+    limit: u32,
+    /// Where to pick up, from the last page's `next_cursor`.
+    cursor: Option<String>,
+}
+
+impl ReservationListQuery {
+    /// Which reservations to list.
+    fn reservation_filter(&self) -> ReservationFilter {
+        ReservationFilter {
+            user_id: self.user_id,
+            pool: self.pool.clone(),
+            start_time: self.start_time,
+            end_time: self.end_time,
+            time_match: self.time_match,
+            status: self.status,
+        }
+    }
+}
+
+/// RESTful API query parameters for following changes.
+#[derive(Deserialize, IntoParams, Serialize)]
+#[serde(deny_unknown_fields)]
+#[into_params(parameter_in = Query)]
+struct ChangeStreamQuery {
+    /// Only follow changes to this pool.
+    pool: Option<String>,
+    /// Only follow changes to this user's reservations, and changes that aren't to anyone's.
+    user_id: Option<u32>,
+    /// Start after this event. `Last-Event-ID` takes its place when followers reconnect.
+    after: Option<u32>,
+}
+
+impl ChangeStreamQuery {
+    /// Which changes to follow.
+    fn change_filter(&self) -> ChangeFilter {
+        ChangeFilter {
+            pool: self.pool.clone(),
+            user_id: self.user_id,
+        }
+    }
+}
+
+/// RESTful API JSON response concerning reservation cancellation.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct ReservationCancelResponse {
+    is_cancelled: bool,
+    user_message: String,
+    /// What was given back for the cancelled reservation, in microcredits.
+    refund_microcredits: Option<u64>,
+}
+
+/// RESTful API query parameters for invoices.
+#[derive(Deserialize, IntoParams, Serialize)]
+#[serde(deny_unknown_fields)]
+#[into_params(parameter_in = Query)]
+struct InvoiceQuery {
+    /// Beginning of the billing period, represented by Unix epoch format.
+    start_time: u32,
+    /// End of the billing period, represented by Unix epoch format.
+    end_time: u32,
+    #[serde(default)]
+    group_by: InvoiceGrouping,
+}
+
+/// RESTful API JSON response concerning segment pricing.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct PricingResponse {
+    is_priced: bool,
+    user_message: String,
+}
+
+/// RESTful API JSON response concerning maintenance window cancellation.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct MaintenanceCancelResponse {
+    is_cancelled: bool,
+    user_message: String,
+}
+
+/// RESTful API JSON response concerning webhook subscription deletion.
+#[derive(Deserialize, Serialize, ToSchema)]
+struct WebhookDeleteResponse {
+    is_deleted: bool,
+    user_message: String,
+}
+
+// Get the number of reservations that are listed at once for query parameter defaults.
+fn default_page_size() -> u32 {
+    DEFAULT_PAGE_SIZE
+}
+
+// Get the name of the default capacity pool for query parameter defaults.
+fn default_pool() -> String {
+    String::from(DEFAULT_POOL)
+}
+
+// Split reports into days unless the user asks for something else.
+fn default_report_bucket() -> String {
+    String::from("1d")
+}
+
+// Group unmet demand by every dimension unless the user asks for fewer.
+fn default_demand_grouping() -> String {
+    String::from("time,pool,user")
+}
+
+/// OpenAPI document for every route, without version prefixes.
+///
+/// It's generated from the routes' `#[utoipa::path]`s and the types that they take and give back,
+/// so clients that are generated from it stay in step with the server. `api_document()` puts the
+/// routes under every version that serves them.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Arbiter",
+        description = "Reserves capacity from pools with scheduled capacity."
+    ),
+    paths(
+        openapi_route,
+        metrics_route,
+        greeting_route,
+        reservation_route,
+        batch_reservation_route,
+        list_reservations_route,
+        cancel_reservation_route,
+        availability_route,
+        evaluated_requests_route,
+        unmet_demand_route,
+        utilization_report_route,
+        usage_report_route,
+        load_report_route,
+        denial_report_route,
+        quote_route,
+        invoices_route,
+        pricing_route,
+        schedule_maintenance_route,
+        list_maintenance_route,
+        cancel_maintenance_route,
+        change_stream_route,
+        register_webhook_route,
+        list_webhooks_route,
+        delete_webhook_route,
+        webhook_deliveries_route,
+    ),
+    // Query parameters only refer to their types' schemas, so those are listed here.
+    components(schemas(
+        ReservationRequest,
+        ChangeEventKind,
+        ReportFormat,
+        TimeMatch,
+        ReservationSort,
+        SortOrder,
+        InvoiceGrouping,
+    )),
+    modifiers(&BearerAuth),
+    tags(
+        (name = "reservations", description = "Reserve, list, and cancel capacity"),
+        (name = "availability", description = "Capacity that's left to reserve"),
+        (name = "billing", description = "Quotes, prices, and invoices"),
+        (name = "demand", description = "Requests that were evaluated, and demand that wasn't met"),
+        (name = "reports", description = "Utilization, usage, load, and denials over time"),
+        (name = "maintenance", description = "Capacity that's offline"),
+        (name = "events", description = "Changes as they happen"),
+        (name = "webhooks", description = "Changes sent to other systems"),
+        (name = "meta", description = "The API itself"),
+    )
+)]
+struct ApiDoc;
+
+/// OpenAPI document for the routes that `/v2/` answers differently than `/v1/`.
+#[derive(OpenApi)]
+#[openapi(paths(reservation_route_v2))]
+struct ApiDocV2;
+
+/// OpenAPI document for every version of the API.
+///
+/// Routes at the root aren't in it, since clients should use a version instead.
+fn api_document(api_config: &ApiConfig) -> utoipa::openapi::OpenApi {
+    let mut api_document = ApiDoc::openapi();
+    let mut v2_document = ApiDocV2::openapi();
+    let v2_paths = std::mem::take(&mut v2_document.paths.paths);
+    api_document.merge(v2_document);
+    let unversioned_paths = std::mem::take(&mut api_document.paths.paths);
+    for (path, path_item) in unversioned_paths {
+        // The document and metrics cover every version.
+        if UNVERSIONED_PATHS.contains(&path.as_str()) {
+            api_document.paths.paths.insert(path, path_item);
+            continue;
+        }
+        for api_version in ApiVersion::ALL {
+            let version_path_item = match api_version {
+                ApiVersion::V1 => None,
+                ApiVersion::V2 => v2_paths.get(&path),
+            };
+            let mut versioned_path_item = match version_path_item {
+                Some(version_path_item) => {
+                    let mut versioned_path_item = version_path_item.clone();
+                    versioned_path_item.merge_operations(path_item.clone());
+                    versioned_path_item
+                }
+                None => path_item.clone(),
+            };
+            let is_deprecated = api_config
+                .deprecations
+                .iter()
+                .any(|deprecation| deprecation.version == api_version);
+            for operation in operations_mut(&mut versioned_path_item) {
+                // Operation IDs have to be unique across the whole document.
+                operation.operation_id = operation
+                    .operation_id
+                    .take()
+                    .map(|operation_id| format!("{}_{operation_id}", api_version.as_str()));
+                if is_deprecated {
+                    operation.deprecated = Some(Deprecated::True);
+                }
+            }
+            api_document.paths.paths.insert(
+                format!("/{}{path}", api_version.as_str()),
+                versioned_path_item,
+            );
+        }
+    }
+    api_document
+}
+
+// Every operation of a documented path.
+fn operations_mut(path_item: &mut PathItem) -> impl Iterator<Item = &mut Operation> {
+    [
+        &mut path_item.get,
+        &mut path_item.post,
+        &mut path_item.put,
+        &mut path_item.patch,
+        &mut path_item.delete,
+    ]
+    .into_iter()
+    .flatten()
+}
+
+/// Describes the `Authorization: Bearer` header that callers authenticate with.
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_auth",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("API key, or JWT that's signed with HS256"))
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// Describe the API as an OpenAPI 3 document.
+///
+/// Anyone can read it, so that clients can be generated before they have credentials.
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "meta",
+    responses(
+        (status = 200, description = "OpenAPI 3 document", body = Object),
+    ),
+)]
+fn openapi_route(
+    api_config: &ApiConfig,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let openapi = Arc::new(api_document(api_config));
+    warp::path!("openapi.json")
+        .and(warp::get())
+        .map(move || warp::reply::json(&*openapi))
+}
+
+/// Report how Arbiter is doing in Prometheus' text format.
+///
+/// Gauges that describe pools, like their utilization and how many reservations are pending or
+/// active, are measured from the datastore whenever this is scraped.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "meta",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Metrics in Prometheus' text format", body = String, content_type = "text/plain; version=0.0.4"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+        (status = 500, description = "Metrics couldn't be written", body = ErrorResponse),
+    ),
+)]
+fn metrics_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Routes at the root don't share the versioned routes' rejection handling, so callers that
+    // are turned away are told why here.
+    warp::path!("metrics").and(warp::get()).and(
+        require_role(authenticator, Role::Operator)
+            .and(with_hostess(hostess))
+            .and_then(render_metrics)
+            .recover(handle_rejection),
+    )
+}
+
+// Measure what pools look like now, then write every metric.
 //
-// # Returns
-// HTML body with `"Hello, <given_name>!"`.
+// Pools that can't be measured are left out instead of reporting stale values, but the rest of
+// the metrics are still served.
+async fn render_metrics(hostess: Arc<Hostess>) -> Result<warp::reply::Response, Infallible> {
+    let pool_gauges = tokio::task::spawn_blocking(move || hostess.pool_gauges()).await;
+    match pool_gauges {
+        Ok(Ok(pool_gauges)) => metrics().set_pool_gauges(&pool_gauges),
+        Ok(Err(error_message)) => {
+            error!("Couldn't measure pools for metrics: {:#}", error_message);
+            metrics().set_pool_gauges(&[]);
+        }
+        Err(join_error) => {
+            error!("Hostess didn't finish measuring pools: {}", join_error);
+            metrics().set_pool_gauges(&[]);
+        }
+    }
+    let metrics_reply = match metrics().render() {
+        Ok(rendered_metrics) => warp::reply::with_header(
+            rendered_metrics,
+            "content-type",
+            "text/plain; version=0.0.4",
+        )
+        .into_response(),
+        Err(error_message) => {
+            error!("Couldn't write metrics: {:#}", error_message);
+            let error_response = ErrorResponse {
+                user_message: String::from("metrics not written"),
+            };
+            warp::reply::with_status(
+                warp::reply::json(&error_response),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response()
+        }
+    };
+    Ok(metrics_reply)
+}
+
+/// Greet the user by name.
+///
+/// "Hello" will be prepended to the name provided in the URL and returned in the HTML body.
+///
+/// # Returns
+/// HTML body with `"Hello, <given_name>!"`.
+#[utoipa::path(
+    get,
+    path = "/hello/{name}",
+    tag = "meta",
+    params(("name" = String, Path, description = "Name to greet")),
+    responses(
+        (status = 200, description = "Greeting", body = String, content_type = "text/plain"),
+    ),
+)]
 fn greeting_route() -> impl Filter<Extract = (String,), Error = warp::Rejection> + Copy {
     warp::path!("hello" / String).map(|name: String| format!("Hello, {}!", name))
 }
 
-// Reserve some resource capacity within a timeframe.
+// Share one hostess among all of the routes that need her.
+fn with_hostess(
+    hostess: Arc<Hostess>,
+) -> impl Filter<Extract = (Arc<Hostess>,), Error = Infallible> + Clone {
+    warp::any().map(move || hostess.clone())
+}
+
+// Work out who's calling, and turn them away unless they have at least the given role.
+//
+// Routes check credentials after they've matched the path and method, so callers that can't use
+// a route are told why instead of getting a "404 Not Found".
+fn with_principal(
+    authenticator: Arc<Authenticator>,
+    role: Role,
+) -> impl Filter<Extract = (Principal,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization").and_then(
+        move |authorization: Option<String>| {
+            let principal = authenticator.authorize(authorization.as_deref(), role);
+            async move { principal.map_err(warp::reject::custom) }
+        },
+    )
+}
+
+// Turn callers away unless they have at least the given role.
+fn require_role(
+    authenticator: Arc<Authenticator>,
+    role: Role,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    with_principal(authenticator, role)
+        .map(|_principal: Principal| ())
+        .untuple_one()
+}
+
+impl warp::reject::Reject for AuthError {}
+
+// Tell callers that were turned away why, as JSON.
 //
-// # Parameters
-// - `start_time`: Reservation start time, represented unix epoch format.
-// - `end_time`: Reservation end time, represented by unix epoch format.
-// - `capacity_amount`: Amount of resource you'd like to have allocated.
-// - `user_id`: Your unique identifier.
-fn reservation_route() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Copy {
+// Other rejections (like "404 Not Found") are left to warp.
+async fn handle_rejection(
+    rejection: warp::Rejection,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(auth_error) = rejection.find::<AuthError>() else {
+        return Err(rejection);
+    };
+    Ok(auth_error_reply(auth_error))
+}
+
+// Reply to a caller that was turned away.
+fn auth_error_reply(auth_error: &AuthError) -> warp::reply::Response {
+    let error_response = ErrorResponse {
+        user_message: auth_error.to_string(),
+    };
+    let json_reply = warp::reply::json(&error_response);
+    match auth_error {
+        AuthError::Unauthenticated(_) => warp::reply::with_header(
+            warp::reply::with_status(json_reply, StatusCode::UNAUTHORIZED),
+            "www-authenticate",
+            "Bearer",
+        )
+        .into_response(),
+        AuthError::Forbidden(_) => {
+            warp::reply::with_status(json_reply, StatusCode::FORBIDDEN).into_response()
+        }
+    }
+}
+
+/// Reserve some resource capacity within a timeframe.
+///
+/// # Parameters
+/// - `start_time`: Reservation start time, represented unix epoch format, `"now"`, or an offset from
+///   now like `"+30m"`.
+/// - `end_time`: Reservation end time, represented by unix epoch format or an offset from the start
+///   time like `"+2h"`.
+/// - `capacity_amount`: Amount of resource you'd like to have allocated.
+/// - `user_id`: Optional user to reserve for. Defaults to you, and only operators can name anyone
+///   else.
+/// - `quote_token`: Optional quote from `/quote` to reserve at the quoted price.
+///
+/// Requests that might be retried can send an `Idempotency-Key` header. Retries with the same key
+/// and parameters get the first response back (with an `Idempotent-Replayed` header) instead of
+/// being reserved again.
+#[utoipa::path(
+    post,
+    path = "/reserve",
+    tag = "reservations",
+    request_body = ReservationParams,
+    params(
+        ("idempotency-key" = Option<String>, Header, description = "Key that makes retries of this request safe"),
+    ),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Whether the reservation was made", body = ReservationResponse, headers(
+            ("idempotent-replayed" = String, description = "`true` if this is the response to an earlier request with the same key"),
+        )),
+        (status = 409, description = "Idempotency key was used for another request, or its request is still being evaluated", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn reservation_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     info!("Received reservation request");
     warp::path!("reserve")
         // Only POST requests can ferry JSON bodies (*usually*).
         .and(warp::post())
+        .and(with_principal(authenticator, Role::User))
         // Expect JSON body format to follow our definition.
-        .and(warp::body::json::<ReservationRequest>())
-        .map(|reservation_request: ReservationRequest| {
-            // Check if the
-            let json_response = match process_reservation(&reservation_request) {
-                Ok(is_reserved) => {
-                    let evaluation_message = match is_reserved {
-                        true => String::from("reservation created"),
-                        false => String::from("reservation not created"),
-                    };
-                    ReservationResponse::new(is_reserved, evaluation_message)
+        .and(warp::body::json::<ReservationParams>())
+        .and_then(act_for_principal)
+        .and(warp::header::optional::<String>("idempotency-key"))
+        .and(with_hostess(hostess))
+        .and_then(evaluate_reservation::<ReservationResponse>)
+}
+
+/// Reserve some resource capacity within a timeframe, and say how it went with the HTTP status.
+///
+/// Takes the same parameters and `Idempotency-Key` header as v1's `/reserve`, but reservations
+/// that are made get a "201 Created", and ones that aren't get a typed denial like
+/// `"not_enough_capacity"` with a status to match.
+#[utoipa::path(
+    post,
+    path = "/reserve",
+    operation_id = "reservation_route",
+    tag = "reservations",
+    request_body = ReservationParams,
+    params(
+        ("idempotency-key" = Option<String>, Header, description = "Key that makes retries of this request safe"),
+    ),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 201, description = "Reservation was made", body = ReservationResponseV2, headers(
+            ("idempotent-replayed" = String, description = "`true` if this is the response to an earlier request with the same key"),
+        )),
+        (status = 400, description = "Request doesn't make sense", body = ReservationResponseV2),
+        (status = 409, description = "Not enough capacity is left, or the idempotency key was used for another request or is still being evaluated", body = ReservationResponseV2),
+        (status = 422, description = "Request breaks the reservation policy", body = ReservationResponseV2),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn reservation_route_v2(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("reserve")
+        .and(warp::post())
+        .and(with_principal(authenticator, Role::User))
+        .and(warp::body::json::<ReservationParams>())
+        .and_then(act_for_principal)
+        .and(warp::header::optional::<String>("idempotency-key"))
+        .and(with_hostess(hostess))
+        .and_then(evaluate_reservation::<ReservationResponseV2>)
+}
+
+/// Reserve capacity for a batch of requests at once.
+///
+/// # Parameters
+/// - `requests`: Reservation requests, each with the same parameters as `/reserve`.
+/// - `ordering`: Optional order to try the requests in. `"in_order"` (the default) tries them as
+///   they were sent, and `"optimize_packing"` tries the biggest ones first.
+/// - `atomic`: Optional. If `true`, then either every request is reserved or none of them are.
+///
+/// # Returns
+/// Whether every request was reserved, and what became of each one: its reservation, or a typed
+/// denial like `"not_enough_capacity"`.
+#[utoipa::path(
+    post,
+    path = "/reserve/batch",
+    tag = "reservations",
+    request_body = BatchRequest,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "What became of each request", body = BatchResponse),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn batch_reservation_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("reserve" / "batch")
+        .and(warp::post())
+        .and(with_principal(authenticator, Role::User))
+        .and(warp::body::json::<BatchRequest>())
+        .and(with_hostess(hostess))
+        .and_then(
+            |principal: Principal, mut batch_request: BatchRequest, hostess| {
+                ask_hostess(hostess, StatusCode::OK, move |hostess| {
+                    for reservation_params in &mut batch_request.requests {
+                        reservation_params.user_id =
+                            Some(principal.act_for(reservation_params.user_id)?);
+                    }
+                    let results = hostess.process_batch(
+                        &batch_request.requests,
+                        batch_request.ordering,
+                        batch_request.atomic,
+                    )?;
+                    Ok(BatchResponse {
+                        is_reserved: results.iter().all(|result| result.is_reserved),
+                        results,
+                    })
+                })
+            },
+        )
+}
+
+// Decide which user reservation parameters are for, based on who's asking.
+async fn act_for_principal(
+    principal: Principal,
+    mut reservation_params: ReservationParams,
+) -> Result<ReservationParams, warp::Rejection> {
+    let user_id = principal
+        .act_for(reservation_params.user_id)
+        .map_err(warp::reject::custom)?;
+    reservation_params.user_id = Some(user_id);
+    Ok(reservation_params)
+}
+
+// Ask the hostess to evaluate a reservation request and tell the user how it went.
+//
+// The DB driver blocks, so evaluation happens on a thread that's allowed to block instead of
+// the one that's serving requests.
+async fn evaluate_reservation<Response: ReservationReply>(
+    reservation_params: ReservationParams,
+    idempotency_key: Option<String>,
+    hostess: Arc<Hostess>,
+) -> Result<warp::reply::Response, Infallible> {
+    let evaluation = tokio::task::spawn_blocking(move || match &idempotency_key {
+        Some(idempotency_key) => {
+            respond_idempotently::<Response>(&reservation_params, idempotency_key, &hostess)
+        }
+        None => Response::respond(&reservation_params, &hostess)
+            .map(|json_response| (json_response, false)),
+    })
+    .await;
+    let reply = match evaluation {
+        Ok(Ok((json_response, false))) => {
+            let status = json_response.status();
+            warp::reply::with_status(warp::reply::json(&json_response), status).into_response()
+        }
+        Ok(Ok((json_response, true))) => {
+            let status = json_response.status();
+            warp::reply::with_header(
+                warp::reply::with_status(warp::reply::json(&json_response), status),
+                "idempotent-replayed",
+                "true",
+            )
+            .into_response()
+        }
+        Ok(Err(error_message)) if error_message.is::<IdempotencyConflict>() => {
+            let error_response = ErrorResponse {
+                user_message: error_message.to_string(),
+            };
+            warp::reply::with_status(warp::reply::json(&error_response), StatusCode::CONFLICT)
+                .into_response()
+        }
+        Ok(Err(error_message)) => {
+            let json_response = Response::refused(error_message.to_string());
+            let status = json_response.status();
+            warp::reply::with_status(warp::reply::json(&json_response), status).into_response()
+        }
+        Err(join_error) => {
+            error!("Reservation evaluation didn't finish: {}", join_error);
+            let json_response = Response::refused(String::from("reservation not evaluated"));
+            // v1 only ever said what went wrong in the body.
+            let status = match Response::API_VERSION {
+                ApiVersion::V1 => StatusCode::OK,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            warp::reply::with_status(warp::reply::json(&json_response), status).into_response()
+        }
+    };
+    Ok(reply)
+}
+
+// Evaluate a reservation request at most once for its idempotency key.
+//
+// # Returns
+// The response, and whether it's being replayed from an earlier request with the same key.
+fn respond_idempotently<Response: ReservationReply>(
+    reservation_params: &ReservationParams,
+    idempotency_key: &str,
+    hostess: &Hostess,
+) -> anyhow::Result<(Response, bool)> {
+    let user_id = reservation_params
+        .user_id
+        .ok_or_else(|| anyhow!("Reservation request is missing its `user_id`"))?;
+    // Responses are shaped by their version, so a key that was used with one version is a
+    // conflict in another instead of a replay.
+    let request_hash = request_hash(&(Response::API_VERSION, reservation_params))?;
+    match hostess.claim_idempotency_key(idempotency_key, user_id, &request_hash)? {
+        IdempotencyClaim::Answered(response_body) => {
+            Ok((serde_json::from_str(&response_body)?, true))
+        }
+        IdempotencyClaim::Claimed => {
+            // Requests that couldn't be evaluated aren't answered, so they can be retried.
+            let json_response = match Response::respond(reservation_params, hostess) {
+                Ok(json_response) => json_response,
+                Err(error_message) => {
+                    release_idempotency_key(idempotency_key, user_id, hostess);
+                    return Err(error_message);
                 }
-                Err(error_message) => ReservationResponse::new(false, error_message.to_string()),
             };
-            warp::reply::json(&json_response)
+            // The request was already evaluated, so the user still hears about it even if its
+            // response can't be kept. The key is released instead of looking like its request
+            // is still being evaluated until it expires.
+            let answer = serde_json::to_string(&json_response).map_err(anyhow::Error::from);
+            if let Err(answer_error) = answer.and_then(|response_body| {
+                hostess.answer_idempotency_key(idempotency_key, user_id, &response_body)
+            }) {
+                error!(
+                    "Couldn't keep response for idempotency key \"{}\": {:#}",
+                    idempotency_key, answer_error
+                );
+                release_idempotency_key(idempotency_key, user_id, hostess);
+            }
+            Ok((json_response, false))
+        }
+    }
+}
+
+// Let go of an idempotency key whose request wasn't answered, so that its retries are evaluated.
+fn release_idempotency_key(idempotency_key: &str, user_id: u32, hostess: &Hostess) {
+    if let Err(release_error) = hostess.release_idempotency_key(idempotency_key, user_id) {
+        error!(
+            "Couldn't release idempotency key \"{}\": {:#}",
+            idempotency_key, release_error
+        );
+    }
+}
+
+// Resolve the user's reservation parameters and see if they can be seated.
+fn respond_to_reservation(
+    reservation_params: &ReservationParams,
+    hostess: &Hostess,
+) -> anyhow::Result<ReservationResponse> {
+    let reservation_request = hostess.resolve_reservation_params(reservation_params)?;
+    let processed_reservation = match &reservation_params.quote_token {
+        Some(quote_token) => hostess.process_quoted_reservation(&reservation_request, quote_token),
+        None => hostess.process_reservation(&reservation_request),
+    };
+    match processed_reservation {
+        Ok(Some(granted_reservation)) => {
+            let mut json_response =
+                ReservationResponse::new(true, String::from("reservation created"));
+            json_response.reservation_id = Some(granted_reservation.reservation_id);
+            json_response.cost_microcredits = Some(granted_reservation.cost_microcredits);
+            // Granted times are the ones that were evaluated, like after snapping to a grid.
+            json_response.start_time = Some(granted_reservation.start_time);
+            json_response.end_time = Some(granted_reservation.end_time);
+            Ok(json_response)
+        }
+        Ok(None) => Ok(
+            ReservationResponse::new(false, String::from("reservation not created"))
+                .with_timeframe(&reservation_request),
+        ),
+        Err(error_message) if error_message.is::<PolicyViolation>() => {
+            Ok(ReservationResponse::new(false, error_message.to_string())
+                .with_timeframe(&reservation_request))
+        }
+        Err(error_message) => Err(error_message),
+    }
+}
+
+// Ask the hostess a question and reply with her answer as JSON.
+//
+// The DB driver blocks, so the hostess answers on a thread that's allowed to block. If she can't
+// answer, then the user gets a "400 Bad Request" that explains why.
+async fn ask_hostess<Answer, Question>(
+    hostess: Arc<Hostess>,
+    success_status: StatusCode,
+    question: Question,
+) -> Result<warp::reply::Response, Infallible>
+where
+    Answer: SerializeJson + Send + 'static,
+    Question: FnOnce(&Hostess) -> anyhow::Result<Answer> + Send + 'static,
+{
+    let answer = tokio::task::spawn_blocking(move || question(&hostess)).await;
+    let json_reply = match answer {
+        Ok(Ok(answer)) => {
+            warp::reply::with_status(warp::reply::json(&answer), success_status).into_response()
+        }
+        // Callers that turn out not to be allowed to ask are told so, not that they asked badly.
+        Ok(Err(error_message)) if error_message.is::<AuthError>() => {
+            auth_error_reply(error_message.downcast_ref::<AuthError>().unwrap())
+        }
+        Ok(Err(error_message)) => {
+            let error_response = ErrorResponse {
+                user_message: error_message.to_string(),
+            };
+            warp::reply::with_status(warp::reply::json(&error_response), StatusCode::BAD_REQUEST)
+                .into_response()
+        }
+        Err(join_error) => {
+            error!("Hostess didn't finish answering: {}", join_error);
+            let error_response = ErrorResponse {
+                user_message: String::from("request not evaluated"),
+            };
+            warp::reply::with_status(
+                warp::reply::json(&error_response),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response()
+        }
+    };
+    Ok(json_reply)
+}
+
+// Ask the hostess for a report and reply with it as JSON or CSV.
+//
+// Like `ask_hostess()`, reports are worked out on a thread that's allowed to block, and reports
+// that can't be worked out get a "400 Bad Request" that explains why.
+async fn ask_hostess_for_report<Row, Question>(
+    hostess: Arc<Hostess>,
+    report_query: ReportQuery,
+    question: Question,
+) -> Result<warp::reply::Response, Infallible>
+where
+    Row: CsvRow + SerializeJson + Send + 'static,
+    Question: FnOnce(&Hostess, &str, &ReportBuckets) -> anyhow::Result<Vec<Row>> + Send + 'static,
+{
+    let report_format = report_query.format;
+    let answer = tokio::task::spawn_blocking(move || {
+        let report_buckets = report_query.report_buckets()?;
+        question(&hostess, &report_query.pool, &report_buckets)
+    })
+    .await;
+    let reply = match answer {
+        Ok(Ok(report_rows)) => match report_format {
+            ReportFormat::Json => warp::reply::json(&report_rows).into_response(),
+            ReportFormat::Csv => {
+                warp::reply::with_header(to_csv(&report_rows), "content-type", "text/csv")
+                    .into_response()
+            }
+        },
+        Ok(Err(error_message)) => {
+            let error_response = ErrorResponse {
+                user_message: error_message.to_string(),
+            };
+            warp::reply::with_status(warp::reply::json(&error_response), StatusCode::BAD_REQUEST)
+                .into_response()
+        }
+        Err(join_error) => {
+            error!("Hostess didn't finish the report: {}", join_error);
+            let error_response = ErrorResponse {
+                user_message: String::from("report not evaluated"),
+            };
+            warp::reply::with_status(
+                warp::reply::json(&error_response),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response()
+        }
+    };
+    Ok(reply)
+}
+
+/// Report how much of every capacity segment was reserved.
+///
+/// # Parameters
+/// - `pool`: Capacity pool to report on. Defaults to `"default"`.
+/// - `start_time`: Beginning of the report, represented by unix epoch format.
+/// - `end_time`: End of the report, represented by unix epoch format.
+/// - `bucket`: How long every row's bucket is, like `"1h"`. Defaults to `"1d"`.
+/// - `format`: `"json"` (default) or `"csv"`.
+#[utoipa::path(
+    get,
+    path = "/reports/utilization",
+    tag = "reports",
+    params(ReportQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Report rows as JSON, or CSV if `format=csv`", content(
+            (Vec<UtilizationRow> = "application/json"),
+            (String = "text/csv"),
+        )),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn utilization_report_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("reports" / "utilization")
+        .and(warp::get())
+        .and(require_role(authenticator, Role::Operator))
+        .and(warp::query::<ReportQuery>())
+        .and(with_hostess(hostess))
+        .and_then(|report_query: ReportQuery, hostess| {
+            ask_hostess_for_report(hostess, report_query, |hostess, pool, report_buckets| {
+                hostess.utilization_report(pool, report_buckets)
+            })
         })
-    //.map(|data: ReservationRequest| warp::reply::json(&data))
 }
 
-#[tokio::main]
-pub async fn start_restful_api() -> Result<(), Box<dyn Error>> {
-    // Combine routes so we can feed them to the server enmass.
-    let all_routes = greeting_route().or(reservation_route());
+/// Report how many unit-hours every user booked.
+///
+/// Takes the same parameters as the utilization report.
+#[utoipa::path(
+    get,
+    path = "/reports/usage",
+    tag = "reports",
+    params(ReportQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Report rows as JSON, or CSV if `format=csv`", content(
+            (Vec<UsageRow> = "application/json"),
+            (String = "text/csv"),
+        )),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn usage_report_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("reports" / "usage")
+        .and(warp::get())
+        .and(require_role(authenticator, Role::Operator))
+        .and(warp::query::<ReportQuery>())
+        .and(with_hostess(hostess))
+        .and_then(|report_query: ReportQuery, hostess| {
+            ask_hostess_for_report(hostess, report_query, |hostess, pool, report_buckets| {
+                hostess.usage_report(pool, report_buckets)
+            })
+        })
+}
 
-    // Start RESTful API.
-    info!("Initializing RESTful API");
-    Ok(warp::serve(all_routes).run(([127, 0, 0, 1], 4242)).await)
+/// Report peak versus average reserved capacity.
+///
+/// Takes the same parameters as the utilization report.
+#[utoipa::path(
+    get,
+    path = "/reports/load",
+    tag = "reports",
+    params(ReportQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Report rows as JSON, or CSV if `format=csv`", content(
+            (Vec<LoadRow> = "application/json"),
+            (String = "text/csv"),
+        )),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn load_report_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("reports" / "load")
+        .and(warp::get())
+        .and(require_role(authenticator, Role::Operator))
+        .and(warp::query::<ReportQuery>())
+        .and(with_hostess(hostess))
+        .and_then(|report_query: ReportQuery, hostess| {
+            ask_hostess_for_report(hostess, report_query, |hostess, pool, report_buckets| {
+                hostess.load_report(pool, report_buckets)
+            })
+        })
 }
 
-#[cfg(test)]
-mod tests {
-    // External crates.
-    use serde_json::from_slice;
+/// Report how often reservation requests were denied.
+///
+/// Takes the same parameters as the utilization report, but requests are bucketed by when they
+/// were made.
+#[utoipa::path(
+    get,
+    path = "/reports/denials",
+    tag = "reports",
+    params(ReportQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Report rows as JSON, or CSV if `format=csv`", content(
+            (Vec<DenialRow> = "application/json"),
+            (String = "text/csv"),
+        )),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn denial_report_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("reports" / "denials")
+        .and(warp::get())
+        .and(require_role(authenticator, Role::Operator))
+        .and(warp::query::<ReportQuery>())
+        .and(with_hostess(hostess))
+        .and_then(|report_query: ReportQuery, hostess| {
+            ask_hostess_for_report(hostess, report_query, |hostess, pool, report_buckets| {
+                hostess.denial_report(pool, report_buckets)
+            })
+        })
+}
 
-    // Project crates.
-    use super::ReservationResponse;
-    use crate::common::test_examples::test_reservation_alpha;
-    use crate::logging::setup_native_logging;
-    use crate::restful_api::greeting_route;
-    use crate::restful_api::reservation_route;
-    use crate::ReservationRequest;
-    // Test if the greeting route works correctly.
-    //
-    // This is the equivalent of:
-    // `user@host: wget -qO- localhost:4242/hello/Eisenhorn`
-    // `Hello, Eisenhorn`
-    #[tokio::test]
-    async fn test_greeting_route() {
-        let _ = setup_native_logging();
-        let route_filter = greeting_route();
+/// Check how much capacity is available in a pool during a timeframe.
+///
+/// Capacity that's offline for maintenance or held by reservations isn't available.
+///
+/// # Parameters
+/// - `pool`: Capacity pool to check. Defaults to `"default"`.
+/// - `start_time`: Beginning of the timeframe, represented by unix epoch format.
+/// - `end_time`: End of the timeframe, represented by unix epoch format.
+#[utoipa::path(
+    get,
+    path = "/availability",
+    tag = "availability",
+    params(AvailabilityQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Available capacity during the timeframe", body = AvailabilityResponse),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn availability_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("availability")
+        .and(warp::get())
+        .and(require_role(authenticator, Role::User))
+        .and(warp::query::<AvailabilityQuery>())
+        .and(with_hostess(hostess))
+        .and_then(|availability_query: AvailabilityQuery, hostess| {
+            ask_hostess(hostess, StatusCode::OK, move |hostess| {
+                let slots = hostess.check_availability(
+                    &availability_query.pool,
+                    availability_query.start_time,
+                    availability_query.end_time,
+                )?;
+                let peak_reserved_amount = hostess.peak_reserved_amount(
+                    &availability_query.pool,
+                    availability_query.start_time,
+                    availability_query.end_time,
+                )?;
+                Ok(AvailabilityResponse {
+                    pool: availability_query.pool,
+                    slots,
+                    peak_reserved_amount,
+                })
+            })
+        })
+}
 
-        let api_response = warp::test::request()
-            .method("GET")
-            .path("/hello/Eisenhorn")
-            .reply(&route_filter)
-            .await;
-        assert_eq!(api_response.status(), 200);
-        assert_eq!(api_response.body(), "Hello, Eisenhorn!");
-    }
+/// List reservation requests that were evaluated, approved or denied.
+///
+/// # Parameters
+/// - `pool`: Only list requests for this capacity pool.
+/// - `user_id`: Only list requests by this user.
+/// - `start_time`, `end_time`: Only list requests for time that overlaps this timeframe,
+///   represented by unix epoch format.
+/// - `outcome`: Only list `"approved"` or `"denied"` requests.
+#[utoipa::path(
+    get,
+    path = "/demand/requests",
+    tag = "demand",
+    params(RequestFilter),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Evaluated requests, in the order they were evaluated", body = Vec<EvaluatedRequest>),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn evaluated_requests_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("demand" / "requests")
+        .and(warp::get())
+        .and(require_role(authenticator, Role::Operator))
+        .and(warp::query::<RequestFilter>())
+        .and(with_hostess(hostess))
+        .and_then(|request_filter: RequestFilter, hostess| {
+            ask_hostess(hostess, StatusCode::OK, move |hostess| {
+                hostess.evaluated_requests(&request_filter)
+            })
+        })
+}
 
-    // Disabled b/c last minute tokio concurrency confict with DB driver. Sad.
-    ////// Test if the reservation route works correctly.
-    //////
-    ////// This is the equivalent of:
-    ////// `wget --method=POST -O- -q --body-data='{"start_time": 1707165008, "end_time": 1708374608, "capacity_amount": 64, "user_id": 42}' --header=Content-Type:application/json localhost:4242/reserve`
-    ////// {"start_time":1707165008,"end_time":1708374608,"capacity_amount":64,"user_id":42}
-    ////#[tokio::test]
-    ////async fn test_reservation_route() {
-    ////    let _ = setup_native_logging();
-    ////    let route_filter = reservation_route();
-
-    ////    // Define JSON parameters for theoretical reservation REST request.
-    ////    let test_reservation = test_reservation_alpha();
-
-    ////    let api_response = warp::test::request()
-    ////        .path("/reserve")
-    ////        // POST is required for sending RESTful (JSON) requests.
-    ////        .method("POST")
-    ////        .json(&test_reservation)
-    ////        .reply(&route_filter)
-    ////        .await;
-    ////    assert_eq!(api_response.status(), 200);
-
-    ////    let rest_response = api_response.body();
-    ////    // Deserialize JSON from HTML body.
-    ////    let jsonified_body: ReservationResponse = from_slice(rest_response).unwrap();
-    ////    assert_eq!(jsonified_body.is_reserved, true);
-    ////    assert_eq!(jsonified_body.user_message, "reservation created");
-    ////}
-    // Future: Test that requests with unknown fields are rejected by serde's unknown fields
-    // rejection.
-    // wget --method=POST -O- -q --body-data='{"start_time": 1707165008, "end_time": 1708374608, "capacity_amount": 64, "user_id": 42, "memes": "lol"}' --header=Content-Type:application/json localhost:4242/reserve
+/// Add up the capacity that denied reservation requests asked for.
+///
+/// # Parameters
+/// - `pool`, `user_id`, `start_time`, `end_time`: Same filters as listing requests.
+/// - `group_by`: Comma separated dimensions to group by: `"time"` (bucket that the requested time
+///   starts in), `"pool"`, and `"user"`. Defaults to all of them.
+/// - `bucket`: How long every `"time"` bucket is, like `"1h"`. Defaults to `"1d"`.
+#[utoipa::path(
+    get,
+    path = "/demand/unmet",
+    tag = "demand",
+    params(UnmetDemandQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Denied demand, grouped", body = Vec<UnmetDemand>),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn unmet_demand_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("demand" / "unmet")
+        .and(warp::get())
+        .and(require_role(authenticator, Role::Operator))
+        .and(warp::query::<UnmetDemandQuery>())
+        .and(with_hostess(hostess))
+        .and_then(|unmet_demand_query: UnmetDemandQuery, hostess| {
+            ask_hostess(hostess, StatusCode::OK, move |hostess| {
+                hostess.unmet_demand(
+                    &unmet_demand_query.request_filter(),
+                    &unmet_demand_query.dimensions()?,
+                    unmet_demand_query.bucket_length()?,
+                )
+            })
+        })
+}
+
+/// Add up what users or teams owe for the reservations that they made during a billing period.
+///
+/// # Parameters
+/// - `start_time`, `end_time`: Billing period, represented by unix epoch format. Reservations
+///   that were made during it are billed, along with any refunds that they've gotten.
+/// - `group_by`: `"user"` (default) or `"team"`. Teams are the ones listed in
+///   `[hostess.billing.teams]`, and users that aren't listed there are grouped without a team.
+#[utoipa::path(
+    get,
+    path = "/invoices",
+    tag = "billing",
+    params(InvoiceQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Invoices for the billing period", body = Vec<Invoice>),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn invoices_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("invoices")
+        .and(warp::get())
+        .and(require_role(authenticator, Role::Operator))
+        .and(warp::query::<InvoiceQuery>())
+        .and(with_hostess(hostess))
+        .and_then(|invoice_query: InvoiceQuery, hostess| {
+            ask_hostess(hostess, StatusCode::OK, move |hostess| {
+                hostess.invoices(
+                    invoice_query.start_time,
+                    invoice_query.end_time,
+                    invoice_query.group_by,
+                )
+            })
+        })
+}
+
+/// Price a reservation request by how scarce capacity is during its timeframe, without reserving.
+///
+/// # Parameters
+/// Same as `/reserve`, except `quote_token`.
+///
+/// # Returns
+/// The price and a quote token that `/reserve` can redeem at that price until the quote expires.
+#[utoipa::path(
+    post,
+    path = "/quote",
+    tag = "billing",
+    request_body = ReservationParams,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Quote that `/reserve` can redeem", body = PriceQuote),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn quote_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("quote")
+        .and(warp::post())
+        .and(with_principal(authenticator, Role::User))
+        .and(warp::body::json::<ReservationParams>())
+        .and_then(act_for_principal)
+        .and(with_hostess(hostess))
+        .and_then(|reservation_params: ReservationParams, hostess| {
+            ask_hostess(hostess, StatusCode::OK, move |hostess| {
+                let reservation_request =
+                    hostess.resolve_reservation_params(&reservation_params)?;
+                hostess.quote_reservation(&reservation_request)
+            })
+        })
+}
+
+/// Change what a capacity segment costs for reservations that are made from now on.
+///
+/// # Parameters
+/// - `pool`: Capacity pool that the segment belongs to. Defaults to `"default"`.
+/// - `start_time`: When the segment starts, represented by unix epoch format.
+/// - `microcredits_per_unit_second`: What one unit of capacity costs for one second.
+#[utoipa::path(
+    post,
+    path = "/admin/pricing",
+    tag = "billing",
+    request_body = SegmentPricing,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Whether the segment was priced", body = PricingResponse),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn pricing_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "pricing")
+        .and(warp::post())
+        .and(require_role(authenticator, Role::Admin))
+        .and(warp::body::json::<SegmentPricing>())
+        .and(with_hostess(hostess))
+        .and_then(|segment_pricing: SegmentPricing, hostess| {
+            ask_hostess(hostess, StatusCode::OK, move |hostess| {
+                let is_priced = hostess.price_segment(&segment_pricing)?;
+                let user_message = match is_priced {
+                    true => String::from("capacity segment priced"),
+                    false => format!(
+                        "pool \"{}\" doesn't have a capacity segment starting at \"{}\"",
+                        segment_pricing.pool, segment_pricing.start_time
+                    ),
+                };
+                Ok(PricingResponse {
+                    is_priced,
+                    user_message,
+                })
+            })
+        })
+}
+
+/// Take some (or all) of a pool's capacity offline for maintenance.
+///
+/// # Parameters
+/// - `pool`: Capacity pool to take capacity out of. Defaults to `"default"`.
+/// - `start_time`: Maintenance start time, represented by unix epoch format.
+/// - `end_time`: Maintenance end time, represented by unix epoch format.
+/// - `reduction`: `"all"` or an amount of capacity, like `{"amount": 16}`.
+/// - `reason`: Why capacity is offline.
+#[utoipa::path(
+    post,
+    path = "/admin/maintenance",
+    tag = "maintenance",
+    request_body = MaintenanceParams,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 201, description = "Scheduled maintenance window", body = MaintenanceWindow),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn schedule_maintenance_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "maintenance")
+        .and(warp::post())
+        .and(require_role(authenticator, Role::Operator))
+        .and(warp::body::json::<MaintenanceParams>())
+        .and(with_hostess(hostess))
+        .and_then(|maintenance_params: MaintenanceParams, hostess| {
+            ask_hostess(hostess, StatusCode::CREATED, move |hostess| {
+                hostess.schedule_maintenance(&maintenance_params)
+            })
+        })
+}
+
+/// List every maintenance window.
+#[utoipa::path(
+    get,
+    path = "/admin/maintenance",
+    tag = "maintenance",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Every maintenance window", body = Vec<MaintenanceWindow>),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn list_maintenance_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "maintenance")
+        .and(warp::get())
+        .and(require_role(authenticator, Role::Operator))
+        .and(with_hostess(hostess))
+        .and_then(|hostess| {
+            ask_hostess(hostess, StatusCode::OK, |hostess| {
+                hostess.list_maintenance()
+            })
+        })
+}
+
+/// Give a reservation's capacity back before it ends.
+#[utoipa::path(
+    delete,
+    path = "/reservations/{reservation_id}",
+    tag = "reservations",
+    params(("reservation_id" = u32, Path, description = "Reservation to cancel")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Whether the reservation was cancelled", body = ReservationCancelResponse),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn cancel_reservation_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("reservations" / u32)
+        .and(warp::delete())
+        .and(with_principal(authenticator, Role::User))
+        .and(with_hostess(hostess))
+        .and_then(|reservation_id: u32, principal: Principal, hostess| {
+            ask_hostess(hostess, StatusCode::OK, move |hostess| {
+                // Users can only cancel their own reservations.
+                if let Some(reservation) = hostess.get_reservation(reservation_id)? {
+                    principal.act_for(Some(reservation.user_id))?;
+                }
+                let cancelled_reservation = hostess.cancel_reservation(reservation_id)?;
+                let user_message = match cancelled_reservation {
+                    Some(_) => String::from("reservation cancelled"),
+                    None => {
+                        format!("reservation \"{reservation_id}\" doesn't exist or already ended")
+                    }
+                };
+                Ok(ReservationCancelResponse {
+                    is_cancelled: cancelled_reservation.is_some(),
+                    user_message,
+                    refund_microcredits: cancelled_reservation
+                        .map(|cancelled_reservation| cancelled_reservation.refund_microcredits),
+                })
+            })
+        })
+}
+
+/// List reservations, a page at a time.
+///
+/// # Parameters
+/// - `user_id`: Optional user whose reservations to list. Defaults to you, and only operators can
+///   name anyone else or leave it out to list everyone's.
+/// - `pool`, `status`: Optional filters.
+/// - `start_time`, `end_time`: Optional timeframe, represented by unix epoch format.
+/// - `time_match`: How reservations have to line up with the timeframe: `"overlapping"` (the
+///   default), `"starting_in"`, or `"ending_in"`.
+/// - `sort`: `"id"` (the default), `"start_time"`, `"end_time"`, or `"created_at"`.
+/// - `order`: `"asc"` (the default) or `"desc"`.
+/// - `limit`: Most reservations to list at once, up to 500. Defaults to 50.
+/// - `cursor`: The last page's `next_cursor`, to get the page after it.
+#[utoipa::path(
+    get,
+    path = "/reservations",
+    tag = "reservations",
+    params(ReservationListQuery),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Page of reservations", body = ReservationPage),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn list_reservations_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("reservations")
+        .and(warp::get())
+        .and(with_principal(authenticator, Role::User))
+        .and(warp::query::<ReservationListQuery>())
+        .and(with_hostess(hostess))
+        .and_then(
+            |principal: Principal, list_query: ReservationListQuery, hostess| {
+                ask_hostess(hostess, StatusCode::OK, move |hostess| {
+                    let mut reservation_filter = list_query.reservation_filter();
+                    // Operators can list everyone's reservations, but users only see their own.
+                    if reservation_filter.user_id.is_some() || principal.role < Role::Operator {
+                        reservation_filter.user_id = Some(principal.act_for(list_query.user_id)?);
+                    }
+                    hostess.list_reservations(
+                        &reservation_filter,
+                        list_query.sort,
+                        list_query.order,
+                        list_query.limit,
+                        list_query.cursor.as_deref(),
+                    )
+                })
+            },
+        )
+}
+
+/// Bring capacity back online by cancelling a maintenance window.
+#[utoipa::path(
+    delete,
+    path = "/admin/maintenance/{window_id}",
+    tag = "maintenance",
+    params(("window_id" = u32, Path, description = "Maintenance window to cancel")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Whether the maintenance window was cancelled", body = MaintenanceCancelResponse),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn cancel_maintenance_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "maintenance" / u32)
+        .and(warp::delete())
+        .and(require_role(authenticator, Role::Operator))
+        .and(with_hostess(hostess))
+        .and_then(|window_id: u32, hostess| {
+            ask_hostess(hostess, StatusCode::OK, move |hostess| {
+                let is_cancelled = hostess.cancel_maintenance(window_id)?;
+                let user_message = match is_cancelled {
+                    true => String::from("maintenance window cancelled"),
+                    false => format!("maintenance window \"{window_id}\" doesn't exist"),
+                };
+                Ok(MaintenanceCancelResponse {
+                    is_cancelled,
+                    user_message,
+                })
+            })
+        })
+}
+
+/// Send changes to a URL as they happen.
+///
+/// Deliveries are signed with the subscription's secret and retried until the receiver accepts
+/// them. The subscription only hears about changes from now on.
+///
+/// # Parameters
+/// - `url`: Where changes are `POST`ed. Only `http://` URLs are supported.
+/// - `event_kinds`: Kinds of changes to send, like `["reservation_started", "reservation_ended"]`.
+/// - `pool`: Only send changes to this capacity pool.
+/// - `user_id`: Only send changes to this user's reservations, and changes that aren't to
+///   anyone's.
+/// - `secret`: Key that deliveries are signed with, at least 16 characters.
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks",
+    tag = "webhooks",
+    request_body = WebhookParams,
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 201, description = "Registered webhook subscription", body = WebhookSubscription),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn register_webhook_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "webhooks")
+        .and(warp::post())
+        .and(require_role(authenticator, Role::Operator))
+        .and(warp::body::json::<WebhookParams>())
+        .and(with_hostess(hostess))
+        .and_then(|webhook_params: WebhookParams, hostess| {
+            ask_hostess(hostess, StatusCode::CREATED, move |hostess| {
+                hostess.register_webhook(&webhook_params)
+            })
+        })
+}
+
+/// List every webhook subscription, without their secrets.
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks",
+    tag = "webhooks",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Every webhook subscription", body = Vec<WebhookSubscription>),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn list_webhooks_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "webhooks")
+        .and(warp::get())
+        .and(require_role(authenticator, Role::Operator))
+        .and(with_hostess(hostess))
+        .and_then(|hostess| ask_hostess(hostess, StatusCode::OK, |hostess| hostess.list_webhooks()))
+}
+
+/// Stop sending changes to a webhook. Its delivery log is kept.
+#[utoipa::path(
+    delete,
+    path = "/admin/webhooks/{subscription_id}",
+    tag = "webhooks",
+    params(("subscription_id" = u32, Path, description = "Webhook subscription to delete")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Whether the webhook subscription was deleted", body = WebhookDeleteResponse),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn delete_webhook_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "webhooks" / u32)
+        .and(warp::delete())
+        .and(require_role(authenticator, Role::Operator))
+        .and(with_hostess(hostess))
+        .and_then(|subscription_id: u32, hostess| {
+            ask_hostess(hostess, StatusCode::OK, move |hostess| {
+                let is_deleted = hostess.delete_webhook(subscription_id)?;
+                let user_message = match is_deleted {
+                    true => String::from("webhook subscription deleted"),
+                    false => format!("webhook subscription \"{subscription_id}\" doesn't exist"),
+                };
+                Ok(WebhookDeleteResponse {
+                    is_deleted,
+                    user_message,
+                })
+            })
+        })
+}
+
+/// List every attempt to deliver changes to a webhook, oldest first.
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks/{subscription_id}/deliveries",
+    tag = "webhooks",
+    params(("subscription_id" = u32, Path, description = "Webhook subscription that changes were sent to")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Every delivery attempt", body = Vec<WebhookDelivery>),
+        (status = 400, description = "Request couldn't be answered", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn webhook_deliveries_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "webhooks" / u32 / "deliveries")
+        .and(warp::get())
+        .and(require_role(authenticator, Role::Operator))
+        .and(with_hostess(hostess))
+        .and_then(|subscription_id: u32, hostess| {
+            ask_hostess(hostess, StatusCode::OK, move |hostess| {
+                hostess.webhook_deliveries(subscription_id)
+            })
+        })
+}
+
+/// Follow reservation and capacity changes as they happen, as server-sent events.
+///
+/// Every event's `event` is its kind and its `data` is the change as JSON. Followers that
+/// reconnect with `Last-Event-ID` (or `after`) get the changes that they missed first. Followers
+/// that don't name an event only hear about changes from now on.
+///
+/// # Parameters
+/// - `pool`: Only follow changes to this capacity pool.
+/// - `user_id`: Only follow changes to this user's reservations, and changes that aren't to
+///   anyone's (like schedule changes). Users only follow their own, and only operators can name
+///   anyone else.
+/// - `after`: Start after this event ID.
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "events",
+    params(
+        ChangeStreamQuery,
+        ("last-event-id" = Option<u32>, Header, description = "Last event that was seen, to resume after it"),
+    ),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Stream of changes", body = ChangeEvent, content_type = "text/event-stream"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Caller's role can't do this", body = ErrorResponse),
+    ),
+)]
+fn change_stream_route(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("events")
+        .and(warp::get())
+        .and(with_principal(authenticator, Role::User))
+        .and(warp::query::<ChangeStreamQuery>())
+        .and(warp::sse::last_event_id::<u32>())
+        .and(with_hostess(hostess))
+        .and_then(
+            |principal: Principal,
+             stream_query: ChangeStreamQuery,
+             last_event_id: Option<u32>,
+             hostess: Arc<Hostess>| async move {
+                let mut change_filter = stream_query.change_filter();
+                // Operators can follow everyone's changes, but users only follow their own.
+                if change_filter.user_id.is_some() || principal.role < Role::Operator {
+                    change_filter.user_id = Some(
+                        principal
+                            .act_for(stream_query.user_id)
+                            .map_err(warp::reject::custom)?,
+                    );
+                }
+                let after_event_id = last_event_id.or(stream_query.after);
+                let change_stream =
+                    follow_changes(hostess, change_filter, after_event_id).map(|change_event| {
+                        warp::sse::Event::default()
+                            .id(change_event.event_id.to_string())
+                            .event(change_event.kind.as_str())
+                            .json_data(&change_event)
+                    });
+                Ok::<_, warp::Rejection>(warp::sse::reply(
+                    warp::sse::keep_alive().stream(change_stream),
+                ))
+            },
+        )
+}
+
+// Combine the routes that every version answers the same way.
+fn shared_routes(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    greeting_route()
+        .or(batch_reservation_route(
+            hostess.clone(),
+            authenticator.clone(),
+        ))
+        .or(cancel_reservation_route(
+            hostess.clone(),
+            authenticator.clone(),
+        ))
+        .or(list_reservations_route(
+            hostess.clone(),
+            authenticator.clone(),
+        ))
+        .or(availability_route(hostess.clone(), authenticator.clone()))
+        .or(evaluated_requests_route(
+            hostess.clone(),
+            authenticator.clone(),
+        ))
+        .or(unmet_demand_route(hostess.clone(), authenticator.clone()))
+        .or(utilization_report_route(
+            hostess.clone(),
+            authenticator.clone(),
+        ))
+        .or(usage_report_route(hostess.clone(), authenticator.clone()))
+        .or(load_report_route(hostess.clone(), authenticator.clone()))
+        .or(denial_report_route(hostess.clone(), authenticator.clone()))
+        .or(quote_route(hostess.clone(), authenticator.clone()))
+        .or(invoices_route(hostess.clone(), authenticator.clone()))
+        .or(pricing_route(hostess.clone(), authenticator.clone()))
+        .or(schedule_maintenance_route(
+            hostess.clone(),
+            authenticator.clone(),
+        ))
+        .or(list_maintenance_route(
+            hostess.clone(),
+            authenticator.clone(),
+        ))
+        .or(cancel_maintenance_route(
+            hostess.clone(),
+            authenticator.clone(),
+        ))
+        .or(change_stream_route(hostess.clone(), authenticator.clone()))
+        .or(register_webhook_route(
+            hostess.clone(),
+            authenticator.clone(),
+        ))
+        .or(list_webhooks_route(hostess.clone(), authenticator.clone()))
+        .or(delete_webhook_route(hostess.clone(), authenticator.clone()))
+        .or(webhook_deliveries_route(hostess, authenticator))
+}
+
+// Serve the routes under every version, and at the root for clients that predate versions.
+//
+// Responses from deprecated versions (and from the root) say so in their headers.
+fn versioned_routes(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+    api_config: Arc<ApiConfig>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let v1_routes = reservation_route(hostess.clone(), authenticator.clone())
+        .or(shared_routes(hostess.clone(), authenticator.clone()));
+    let v2_routes = reservation_route_v2(hostess.clone(), authenticator.clone())
+        .or(shared_routes(hostess.clone(), authenticator.clone()));
+    let unversioned_routes = reservation_route(hostess.clone(), authenticator.clone())
+        .or(shared_routes(hostess, authenticator));
+    let is_unversioned_served = api_config.unversioned_routes;
+    warp::path(ApiVersion::V1.as_str())
+        .and(v1_routes)
+        .or(warp::path(ApiVersion::V2.as_str()).and(v2_routes))
+        .or(warp::any()
+            .and_then(move || async move {
+                match is_unversioned_served {
+                    true => Ok(()),
+                    false => Err(warp::reject::not_found()),
+                }
+            })
+            .untuple_one()
+            .and(unversioned_routes))
+        // Callers that are turned away from deprecated routes should hear that they're deprecated
+        // too, so they're turned away before the headers are added.
+        .recover(handle_rejection)
+        .and(warp::path::full())
+        .map(move |reply, full_path: FullPath| {
+            with_deprecation_headers(reply, full_path.as_str(), &api_config)
+        })
+}
+
+// Tell the caller if the route they asked for is deprecated, and what replaces it.
+fn with_deprecation_headers(
+    reply: impl Reply,
+    request_path: &str,
+    api_config: &ApiConfig,
+) -> warp::reply::Response {
+    let mut response = reply.into_response();
+    for (header_name, header_value) in api_config.deprecation_headers(request_path) {
+        if let Ok(header_value) = HeaderValue::from_str(&header_value) {
+            response.headers_mut().insert(header_name, header_value);
+        }
+    }
+    response
+}
+
+// Combine every route so they can be served together, and count every request to them.
+fn all_routes(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+    api_config: ApiConfig,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let route_templates = route_templates(&api_document(&api_config));
+    openapi_route(&api_config)
+        .or(metrics_route(hostess.clone(), authenticator.clone()))
+        .or(versioned_routes(
+            hostess,
+            authenticator,
+            Arc::new(api_config),
+        ))
+        .with(warp::log::custom(move |request_info| {
+            let route = route_template(&route_templates, request_info.path());
+            metrics().count_http_request(
+                request_info.method().as_str(),
+                route.unwrap_or("unmatched"),
+                request_info.status().as_u16(),
+            );
+        }))
+}
+
+// Every path that's routed, like `"/v1/reservations/{reservation_id}"`, including the versioned
+// routes that are also served at the root.
+fn route_templates(api_document: &utoipa::openapi::OpenApi) -> Arc<Vec<String>> {
+    let mut route_templates: Vec<String> = api_document.paths.paths.keys().cloned().collect();
+    let unversioned_templates: Vec<String> = route_templates
+        .iter()
+        .filter_map(|route_template| ApiVersion::of_path(route_template))
+        .filter(|(api_version, _)| *api_version == ApiVersion::V1)
+        .map(|(_, unprefixed_template)| unprefixed_template.to_string())
+        .collect();
+    route_templates.extend(unversioned_templates);
+    Arc::new(route_templates)
+}
+
+// Find the route that a request's path was for, so that requests for different reservations
+// (or greetings) are counted together.
+fn route_template<'a>(route_templates: &'a [String], request_path: &str) -> Option<&'a str> {
+    let request_segments: Vec<&str> = request_path.split('/').collect();
+    route_templates
+        .iter()
+        .find(|route_template| {
+            let template_segments: Vec<&str> = route_template.split('/').collect();
+            template_segments.len() == request_segments.len()
+                && template_segments.iter().zip(&request_segments).all(
+                    |(template_segment, request_segment)| {
+                        template_segment == request_segment || template_segment.starts_with('{')
+                    },
+                )
+        })
+        .map(String::as_str)
+}
+
+/// Serve the RESTful API until it stops.
+pub async fn start_restful_api(
+    hostess: Arc<Hostess>,
+    authenticator: Arc<Authenticator>,
+    api_config: ApiConfig,
+) -> Result<(), Box<dyn Error>> {
+    // Combine routes so we can feed them to the server enmass.
+    let all_routes = all_routes(hostess, authenticator, api_config);
+
+    // Start RESTful API.
+    info!("Initializing RESTful API");
+    warp::serve(all_routes)
+        .run(([127, 0, 0, 1], RESTFUL_API_PORT))
+        .await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    // Standard library crates.
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // External crates.
+    use serde_json::from_slice;
+    use warp::hyper::body::HttpBody;
+    use warp::{Filter, Reply};
+
+    // Project crates.
+    use super::{
+        all_routes, api_document, batch_reservation_route, handle_rejection, metrics_route,
+        openapi_route, reservation_route_v2, route_template, route_templates, AvailabilityResponse,
+        BatchResponse, ErrorResponse, MaintenanceCancelResponse, PricingResponse,
+        ReservationCancelResponse, ReservationResponse, ReservationResponseV2,
+        WebhookDeleteResponse, UNVERSIONED_PATHS,
+    };
+    use crate::auth::test_examples::{
+        bearer, test_authenticator, ADMIN_KEY, JWT_SECRET, OPERATOR_KEY, USER_KEY,
+    };
+    use crate::auth::{sign_jwt, JwtClaims, Role};
+    use crate::batch::DenialKind;
+    use crate::billing::{Invoice, PriceQuote};
+    use crate::clock::test_examples::test_clock;
+    use crate::clock::test_examples::TEST_EPOCH_NOW;
+    use crate::clock::{Clock, FixedClock};
+    use crate::common::MaintenanceWindow;
+    use crate::config::HostessConfig;
+    use crate::datastore::failing::FailingDatastore;
+    use crate::datastore::test_examples::{seed_test_pool, test_datastore};
+    use crate::demand::{EvaluatedRequest, UnmetDemand};
+    use crate::events::ChangeEventKind;
+    use crate::hostess::Hostess;
+    use crate::listing::ReservationPage;
+    use crate::logging::setup_native_logging;
+    use crate::policy::ReservationPolicy;
+    use crate::reports::{DenialRow, LoadRow, UsageRow, UtilizationRow};
+    use crate::restful_api::greeting_route;
+    use crate::restful_api::reservation_route;
+    use crate::restful_api::{
+        availability_route, cancel_maintenance_route, cancel_reservation_route,
+        change_stream_route, denial_report_route, evaluated_requests_route, invoices_route,
+        list_reservations_route, load_report_route, pricing_route, quote_route,
+        schedule_maintenance_route, unmet_demand_route, usage_report_route,
+        utilization_report_route,
+    };
+    use crate::restful_api::{
+        delete_webhook_route, list_webhooks_route, register_webhook_route, webhook_deliveries_route,
+    };
+    use crate::versioning::{ApiConfig, ApiVersion, VersionDeprecation};
+    use crate::webhooks::{WebhookDelivery, WebhookSubscription};
+    use crate::ReservationRequest;
+
+    /// Hostess that keeps reservations in the test datastore.
+    ///
+    /// The test datastore is opened outside of the async runtime because its clients block.
+    async fn test_hostess(clock: impl Clock + 'static, config: HostessConfig) -> Arc<Hostess> {
+        let datastore = tokio::task::spawn_blocking(test_datastore).await.unwrap();
+        Arc::new(Hostess::new(Arc::new(clock), datastore, config))
+    }
+
+    // Test if the greeting route works correctly.
+    //
+    // This is the equivalent of:
+    // `user@host: wget -qO- localhost:4242/hello/Eisenhorn`
+    // `Hello, Eisenhorn`
+    #[tokio::test]
+    async fn test_greeting_route() {
+        let _ = setup_native_logging();
+        let route_filter = greeting_route();
+
+        let api_response = warp::test::request()
+            .method("GET")
+            .path("/hello/Eisenhorn")
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 200);
+        assert_eq!(api_response.body(), "Hello, Eisenhorn!");
+    }
+
+    // Test if the reservation route works correctly.
+    //
+    // This is the equivalent of:
+    // `wget --method=POST -O- -q --body-data='{"start_time": 1711398608, "end_time": 1713213008, "capacity_amount": 64, "user_id": 42}' --header=Content-Type:application/json localhost:4242/reserve`
+    // {"is_reserved":true,"user_message":"reservation created","reservation_id":1,"start_time":1711398608,"end_time":1713213008}
+    #[tokio::test]
+    async fn test_reservation_route() {
+        let _ = setup_native_logging();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+        let route_filter = reservation_route(hostess, test_authenticator());
+
+        // Define JSON parameters for theoretical reservation REST request. This uses Schedule 1's
+        // last timeframe so that it doesn't collide with the hostess's tests.
+        let test_reservation = ReservationRequest::new(1711398608, 1713213008, 64, 42);
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/reserve")
+            // POST is required for sending RESTful (JSON) requests.
+            .method("POST")
+            .json(&test_reservation)
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 200);
+
+        let rest_response = api_response.body();
+        // Deserialize JSON from HTML body.
+        let jsonified_body: ReservationResponse = from_slice(rest_response).unwrap();
+        assert!(jsonified_body.is_reserved);
+        assert_eq!(jsonified_body.user_message, "reservation created");
+        assert!(jsonified_body.reservation_id.is_some());
+        assert_eq!(jsonified_body.start_time, Some(1711398608));
+        assert_eq!(jsonified_body.end_time, Some(1713213008));
+    }
+
+    // Test if the reservation route resolves relative times with the server's clock.
+    //
+    // This is the equivalent of:
+    // `wget --method=POST -O- -q --body-data='{"start_time": "now", "end_time": "+1h", "capacity_amount": 1, "user_id": 42}' --header=Content-Type:application/json localhost:4242/reserve`
+    #[tokio::test]
+    async fn test_reservation_route_now() {
+        let _ = setup_native_logging();
+        // Stop the clock a day into Schedule 1's last timeframe and take ten minutes to spin up.
+        let epoch_now = 1711485008;
+        let config = HostessConfig {
+            provisioning_lead_time: Duration::from_secs(600),
+            ..Default::default()
+        };
+        let hostess = test_hostess(FixedClock::new(epoch_now), config).await;
+        let route_filter = reservation_route(hostess, test_authenticator());
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/reserve")
+            .method("POST")
+            .body(
+                r#"{"start_time": "now", "end_time": "+1h", "capacity_amount": 1, "user_id": 42}"#,
+            )
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 200);
+
+        let jsonified_body: ReservationResponse = from_slice(api_response.body()).unwrap();
+        assert!(jsonified_body.is_reserved);
+        assert_eq!(jsonified_body.start_time, Some(epoch_now + 600));
+        assert_eq!(jsonified_body.end_time, Some(epoch_now + 600 + 3600));
+    }
+    // Test if maintenance windows show up in availability checks until they're cancelled.
+    //
+    // This is the equivalent of:
+    // `wget --method=POST -O- -q --body-data='{"pool": "test_maintenance_route", "start_time": 1707165008, "end_time": 1707251408, "reduction": {"amount": 16}, "reason": "replacing PDUs"}' --header=Content-Type:application/json localhost:4242/admin/maintenance`
+    // `wget -O- -q 'localhost:4242/availability?pool=test_maintenance_route&start_time=1707165008&end_time=1707251408'`
+    // `wget --method=DELETE -O- -q localhost:4242/admin/maintenance/1`
+    #[tokio::test]
+    async fn test_maintenance_routes() {
+        let _ = setup_native_logging();
+        let test_pool = "test_maintenance_route";
+        tokio::task::spawn_blocking(move || seed_test_pool(test_pool))
+            .await
+            .unwrap();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/admin/maintenance")
+            .method("POST")
+            .body(r#"{"pool": "test_maintenance_route", "start_time": 1707165008, "end_time": 1707251408, "reduction": {"amount": 16}, "reason": "replacing PDUs"}"#)
+            .reply(&schedule_maintenance_route(hostess.clone(), test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 201);
+        let maintenance_window: MaintenanceWindow = from_slice(api_response.body()).unwrap();
+
+        let availability_path =
+            "/availability?pool=test_maintenance_route&start_time=1707165008&end_time=1707251408";
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path(availability_path)
+            .reply(&availability_route(hostess.clone(), test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 200);
+        let availability: AvailabilityResponse = from_slice(api_response.body()).unwrap();
+        assert_eq!(availability.slots.len(), 1);
+        assert_eq!(availability.slots[0].maintenance_amount, 16);
+        assert_eq!(availability.slots[0].available_amount, 48);
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path(&format!(
+                "/admin/maintenance/{}",
+                maintenance_window.window_id
+            ))
+            .method("DELETE")
+            .reply(&cancel_maintenance_route(
+                hostess.clone(),
+                test_authenticator(),
+            ))
+            .await;
+        let cancellation: MaintenanceCancelResponse = from_slice(api_response.body()).unwrap();
+        assert!(cancellation.is_cancelled);
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path(availability_path)
+            .reply(&availability_route(hostess, test_authenticator()))
+            .await;
+        let availability: AvailabilityResponse = from_slice(api_response.body()).unwrap();
+        assert_eq!(availability.slots[0].available_amount, 64);
+    }
+
+    // Test if webhooks can be registered, listed without their secrets, and deleted by operators.
+    //
+    // This is the equivalent of:
+    // `wget --method=POST -O- -q --body-data='{"url": "http://127.0.0.1:9/arbiter", "event_kinds": ["reservation_started"], "secret": "correct horse battery staple"}' --header=Content-Type:application/json localhost:4242/v1/admin/webhooks`
+    // `wget -O- -q localhost:4242/v1/admin/webhooks/1/deliveries`
+    // `wget --method=DELETE -O- -q localhost:4242/v1/admin/webhooks/1`
+    #[tokio::test]
+    async fn test_webhook_routes() {
+        let _ = setup_native_logging();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+        let webhook_body = r#"{"url": "http://127.0.0.1:9/arbiter", "event_kinds": ["reservation_started", "reservation_ended"], "pool": "test_webhook_routes", "secret": "correct horse battery staple"}"#;
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path("/admin/webhooks")
+            .method("POST")
+            .body(webhook_body)
+            .reply(
+                &register_webhook_route(hostess.clone(), test_authenticator())
+                    .recover(handle_rejection),
+            )
+            .await;
+        assert_eq!(api_response.status(), 403);
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(OPERATOR_KEY))
+            .path("/admin/webhooks")
+            .method("POST")
+            .body(r#"{"url": "http://127.0.0.1:9/arbiter", "event_kinds": ["reservation_started"], "secret": "hunter2"}"#)
+            .reply(&register_webhook_route(hostess.clone(), test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 400);
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(OPERATOR_KEY))
+            .path("/admin/webhooks")
+            .method("POST")
+            .body(webhook_body)
+            .reply(&register_webhook_route(
+                hostess.clone(),
+                test_authenticator(),
+            ))
+            .await;
+        assert_eq!(api_response.status(), 201);
+        assert!(!String::from_utf8_lossy(api_response.body()).contains("secret"));
+        let subscription: WebhookSubscription = from_slice(api_response.body()).unwrap();
+        assert_eq!(
+            subscription.event_kinds,
+            vec![
+                ChangeEventKind::ReservationStarted,
+                ChangeEventKind::ReservationEnded
+            ]
+        );
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(OPERATOR_KEY))
+            .path("/admin/webhooks")
+            .reply(&list_webhooks_route(hostess.clone(), test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 200);
+        assert!(!String::from_utf8_lossy(api_response.body()).contains("correct horse"));
+        let subscriptions: Vec<WebhookSubscription> = from_slice(api_response.body()).unwrap();
+        assert!(subscriptions
+            .iter()
+            .any(|listed| listed.subscription_id == subscription.subscription_id));
+
+        let deliveries_path = format!(
+            "/admin/webhooks/{}/deliveries",
+            subscription.subscription_id
+        );
+        let api_response = warp::test::request()
+            .header("authorization", bearer(OPERATOR_KEY))
+            .path(&deliveries_path)
+            .reply(&webhook_deliveries_route(
+                hostess.clone(),
+                test_authenticator(),
+            ))
+            .await;
+        assert_eq!(api_response.status(), 200);
+        let deliveries: Vec<WebhookDelivery> = from_slice(api_response.body()).unwrap();
+        assert!(deliveries.is_empty());
+
+        let delete_path = format!("/admin/webhooks/{}", subscription.subscription_id);
+        for is_deleted in [true, false] {
+            let api_response = warp::test::request()
+                .header("authorization", bearer(OPERATOR_KEY))
+                .path(&delete_path)
+                .method("DELETE")
+                .reply(&delete_webhook_route(hostess.clone(), test_authenticator()))
+                .await;
+            let deletion: WebhookDeleteResponse = from_slice(api_response.body()).unwrap();
+            assert_eq!(deletion.is_deleted, is_deleted);
+        }
+    }
+
+    // Test if availability checks with backwards timeframes are refused.
+    #[tokio::test]
+    async fn test_availability_route_backwards() {
+        let _ = setup_native_logging();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/availability?start_time=1708374608&end_time=1707165008")
+            .reply(&availability_route(hostess, test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 400);
+    }
+
+    // Future: Test that requests with unknown fields are rejected by serde's unknown fields
+    // rejection.
+    // wget --method=POST -O- -q --body-data='{"start_time": 1707165008, "end_time": 1708374608, "capacity_amount": 64, "user_id": 42, "memes": "lol"}' --header=Content-Type:application/json localhost:4242/reserve
+
+    // Test if reservations can be cancelled once.
+    //
+    // This is the equivalent of:
+    // `wget --method=DELETE -O- -q localhost:4242/reservations/1`
+    // {"is_cancelled":true,"user_message":"reservation cancelled"}
+    #[tokio::test]
+    async fn test_cancel_reservation_route() {
+        let _ = setup_native_logging();
+        let test_pool = "test_cancel_reservation_route";
+        tokio::task::spawn_blocking(move || seed_test_pool(test_pool))
+            .await
+            .unwrap();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+        let reservation_hostess = hostess.clone();
+        let reservation = tokio::task::spawn_blocking(move || {
+            let test_request =
+                ReservationRequest::new(1707165008, 1708374608, 64, 42).in_pool(test_pool);
+            reservation_hostess.process_reservation(&test_request)
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+        let route_filter = cancel_reservation_route(hostess, test_authenticator());
+        let cancellation_path = format!("/reservations/{}", reservation.reservation_id);
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path(&cancellation_path)
+            .method("DELETE")
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 200);
+        let cancellation: ReservationCancelResponse = from_slice(api_response.body()).unwrap();
+        assert!(cancellation.is_cancelled);
+
+        // Reservations can't be cancelled twice.
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path(&cancellation_path)
+            .method("DELETE")
+            .reply(&route_filter)
+            .await;
+        let cancellation: ReservationCancelResponse = from_slice(api_response.body()).unwrap();
+        assert!(!cancellation.is_cancelled);
+    }
+
+    // Test if denied requests show up as unmet demand.
+    //
+    // This is the equivalent of:
+    // `wget -O- -q 'localhost:4242/demand/unmet?pool=test_unmet_demand_route&group_by=pool,user'`
+    // [{"pool":"test_unmet_demand_route","user_id":43,"denied_requests":1,"denied_capacity_amount":64,"denied_unit_seconds":5529600}]
+    #[tokio::test]
+    async fn test_unmet_demand_route() {
+        let _ = setup_native_logging();
+        let test_pool = "test_unmet_demand_route";
+        tokio::task::spawn_blocking(move || seed_test_pool(test_pool))
+            .await
+            .unwrap();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+        let reservation_hostess = hostess.clone();
+        tokio::task::spawn_blocking(move || {
+            // Second user asks for the capacity that the first user already has.
+            for user_id in [42, 43] {
+                let test_request =
+                    ReservationRequest::new(1707165008, 1707251408, 64, user_id).in_pool(test_pool);
+                reservation_hostess
+                    .process_reservation(&test_request)
+                    .unwrap();
+            }
+        })
+        .await
+        .unwrap();
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/demand/unmet?pool=test_unmet_demand_route&group_by=pool,user")
+            .reply(&unmet_demand_route(hostess.clone(), test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 200);
+        let unmet_demand: Vec<UnmetDemand> = from_slice(api_response.body()).unwrap();
+        assert_eq!(unmet_demand.len(), 1);
+        assert_eq!(unmet_demand[0].pool.as_deref(), Some(test_pool));
+        assert_eq!(unmet_demand[0].user_id, Some(43));
+        assert_eq!(unmet_demand[0].period_start, None);
+        assert_eq!(unmet_demand[0].denied_capacity_amount, 64);
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/demand/requests?pool=test_unmet_demand_route&outcome=approved")
+            .reply(&evaluated_requests_route(
+                hostess.clone(),
+                test_authenticator(),
+            ))
+            .await;
+        assert_eq!(api_response.status(), 200);
+        let evaluated_requests: Vec<EvaluatedRequest> = from_slice(api_response.body()).unwrap();
+        assert_eq!(evaluated_requests.len(), 1);
+        assert_eq!(evaluated_requests[0].user_id, 42);
+
+        // Requests can be grouped into buckets that are longer than a day.
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/demand/unmet?pool=test_unmet_demand_route&group_by=time&bucket=7d")
+            .reply(&unmet_demand_route(hostess.clone(), test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 200);
+        let unmet_demand: Vec<UnmetDemand> = from_slice(api_response.body()).unwrap();
+        assert_eq!(unmet_demand.len(), 1);
+        assert_eq!(unmet_demand[0].period_start, Some(1706745600));
+
+        // Unknown groupings and buckets that aren't durations are refused.
+        for bad_query in ["group_by=planet", "group_by=time&bucket=weekly"] {
+            let api_response = warp::test::request()
+                .header("authorization", bearer(ADMIN_KEY))
+                .path(&format!("/demand/unmet?{bad_query}"))
+                .reply(&unmet_demand_route(hostess.clone(), test_authenticator()))
+                .await;
+            assert_eq!(api_response.status(), 400, "accepted \"{bad_query}\"");
+        }
+    }
+
+    // Test if reports come back as JSON or CSV.
+    //
+    // This is the equivalent of:
+    // `wget -O- -q 'localhost:4242/reports/load?pool=test_report_routes&start_time=1707165008&end_time=1707337808&format=csv'`
+    // bucket_start,bucket_end,peak_reserved_amount,average_reserved_amount
+    // 1707165008,1707251408,16,8
+    // 1707251408,1707337808,0,0
+    #[tokio::test]
+    async fn test_report_routes() {
+        let _ = setup_native_logging();
+        let test_pool = "test_report_routes";
+        tokio::task::spawn_blocking(move || seed_test_pool(test_pool))
+            .await
+            .unwrap();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+        let reservation_hostess = hostess.clone();
+        tokio::task::spawn_blocking(move || {
+            // Reserve 16 capacity for the first half of the first day.
+            let test_request =
+                ReservationRequest::new(1707165008, 1707208208, 16, 42).in_pool(test_pool);
+            reservation_hostess
+                .process_reservation(&test_request)
+                .unwrap();
+        })
+        .await
+        .unwrap();
+
+        let report_path =
+            "/reports/load?pool=test_report_routes&start_time=1707165008&end_time=1707337808";
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path(report_path)
+            .reply(&load_report_route(hostess.clone(), test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 200);
+        let load_rows: Vec<LoadRow> = from_slice(api_response.body()).unwrap();
+        assert_eq!(load_rows.len(), 2);
+        assert_eq!(load_rows[0].peak_reserved_amount, 16);
+        assert_eq!(load_rows[0].average_reserved_amount, 8.0);
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path(&format!("{report_path}&format=csv"))
+            .reply(&load_report_route(hostess.clone(), test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 200);
+        assert_eq!(api_response.headers()["content-type"], "text/csv");
+        assert_eq!(
+            api_response.body(),
+            "bucket_start,bucket_end,peak_reserved_amount,average_reserved_amount\n\
+             1707165008,1707251408,16,8\n\
+             1707251408,1707337808,0,0\n"
+        );
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/reports/usage?pool=test_report_routes&start_time=1707165008&end_time=1707337808&bucket=12h")
+            .reply(&usage_report_route(hostess.clone(), test_authenticator()))
+            .await;
+        let usage_rows: Vec<UsageRow> = from_slice(api_response.body()).unwrap();
+        assert_eq!(usage_rows.len(), 1);
+        assert_eq!(usage_rows[0].unit_hours, 16.0 * 12.0);
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/reports/utilization?pool=test_report_routes&start_time=1707165008&end_time=1707337808")
+            .reply(&utilization_report_route(hostess.clone(), test_authenticator()))
+            .await;
+        let utilization_rows: Vec<UtilizationRow> = from_slice(api_response.body()).unwrap();
+        assert_eq!(utilization_rows[0].utilization_percent, 12.5);
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/reports/denials?pool=test_report_routes&start_time=1707078608&end_time=1707165008")
+            .reply(&denial_report_route(hostess.clone(), test_authenticator()))
+            .await;
+        let denial_rows: Vec<DenialRow> = from_slice(api_response.body()).unwrap();
+        assert_eq!(denial_rows[0].requests, 1);
+        assert_eq!(denial_rows[0].denied_requests, 0);
+
+        // Buckets have to be real durations.
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path(&format!("{report_path}&bucket=fortnightish"))
+            .reply(&load_report_route(hostess, test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 400);
+    }
+
+    // Test if priced segments show up on invoices.
+    //
+    // This is the equivalent of:
+    // `wget --post-data='{"pool":"test_billing_routes","start_time":1707165008,"microcredits_per_unit_second":1}' -O- -q localhost:4242/admin/pricing`
+    // {"is_priced":true,"user_message":"capacity segment priced"}
+    // `wget -O- -q 'localhost:4242/invoices?start_time=1707078608&end_time=1707078609'`
+    // [{"user_id":4343,"period_start":1707078608,"period_end":1707078609,"reservations":1,"cost_microcredits":28800,"refund_microcredits":0,"total_microcredits":28800}]
+    #[tokio::test]
+    async fn test_billing_routes() {
+        let _ = setup_native_logging();
+        let test_pool = "test_billing_routes";
+        tokio::task::spawn_blocking(move || seed_test_pool(test_pool))
+            .await
+            .unwrap();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/admin/pricing")
+            .method("POST")
+            .json(&serde_json::json!({
+                "pool": test_pool,
+                "start_time": 1707165008,
+                "microcredits_per_unit_second": 1,
+            }))
+            .reply(&pricing_route(hostess.clone(), test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 200);
+        let pricing: PricingResponse = from_slice(api_response.body()).unwrap();
+        assert!(pricing.is_priced);
+
+        // Segments have to exist to be priced.
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/admin/pricing")
+            .method("POST")
+            .json(&serde_json::json!({
+                "pool": test_pool,
+                "start_time": 1707165009,
+                "microcredits_per_unit_second": 1,
+            }))
+            .reply(&pricing_route(hostess.clone(), test_authenticator()))
+            .await;
+        let pricing: PricingResponse = from_slice(api_response.body()).unwrap();
+        assert!(!pricing.is_priced);
+
+        // User 4343 only makes reservations here, so their invoice is only for this test.
+        let reservation_hostess = hostess.clone();
+        tokio::task::spawn_blocking(move || {
+            let test_request =
+                ReservationRequest::new(1707165008, 1707168608, 8, 4343).in_pool(test_pool);
+            reservation_hostess.process_reservation(&test_request)
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/invoices?start_time=1707078608&end_time=1707078609")
+            .reply(&invoices_route(hostess.clone(), test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 200);
+        let invoices: Vec<Invoice> = from_slice(api_response.body()).unwrap();
+        let user_invoice = invoices
+            .iter()
+            .find(|invoice| invoice.user_id == Some(4343))
+            .unwrap();
+        assert_eq!(user_invoice.total_microcredits, 8 * 3600);
+
+        // Invoices can only be grouped by user or team.
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/invoices?start_time=1707078608&end_time=1707078609&group_by=planet")
+            .reply(&invoices_route(hostess, test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 400);
+    }
+
+    // Quotes are made without reserving, then redeemed by the reservation route.
+    #[tokio::test]
+    async fn test_quote_route() {
+        let _ = setup_native_logging();
+        let test_pool = "test_quote_route";
+        tokio::task::spawn_blocking(move || {
+            seed_test_pool(test_pool);
+            test_datastore()
+                .set_segment_price(test_pool, 1707165008, 1)
+                .unwrap();
+        })
+        .await
+        .unwrap();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+        let test_params = serde_json::json!({
+            "start_time": 1707165008,
+            "end_time": "+1h",
+            "capacity_amount": 8,
+            "user_id": 42,
+            "pool": test_pool,
+        });
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/quote")
+            .method("POST")
+            .json(&test_params)
+            .reply(&quote_route(hostess.clone(), test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 200);
+        let price_quote: PriceQuote = from_slice(api_response.body()).unwrap();
+        assert_eq!(price_quote.end_time, 1707168608);
+        assert_eq!(price_quote.price_microcredits, 8 * 3600);
+
+        let mut quoted_params = test_params.clone();
+        quoted_params["quote_token"] = serde_json::json!(price_quote.quote_token);
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/reserve")
+            .method("POST")
+            .json(&quoted_params)
+            .reply(&reservation_route(hostess.clone(), test_authenticator()))
+            .await;
+        let reservation: ReservationResponse = from_slice(api_response.body()).unwrap();
+        assert!(reservation.is_reserved);
+        assert_eq!(reservation.cost_microcredits, Some(8 * 3600));
+
+        // The quote was used up.
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/reserve")
+            .method("POST")
+            .json(&quoted_params)
+            .reply(&reservation_route(hostess.clone(), test_authenticator()))
+            .await;
+        let reservation: ReservationResponse = from_slice(api_response.body()).unwrap();
+        assert!(!reservation.is_reserved);
+        assert!(reservation.user_message.contains("already redeemed"));
+
+        // Requests that couldn't be reserved can't be quoted either.
+        let mut huge_params = test_params;
+        huge_params["capacity_amount"] = serde_json::json!(1_000_000);
+        let api_response = warp::test::request()
+            .header("authorization", bearer(ADMIN_KEY))
+            .path("/quote")
+            .method("POST")
+            .json(&huge_params)
+            .reply(&quote_route(hostess, test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 400);
+    }
+
+    // Callers have to prove who they are, and can only act for themselves unless they're
+    // operators.
+    #[tokio::test]
+    async fn test_route_auth() {
+        let _ = setup_native_logging();
+        let test_pool = "test_route_auth";
+        tokio::task::spawn_blocking(move || seed_test_pool(test_pool))
+            .await
+            .unwrap();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+        let route_filter =
+            reservation_route(hostess.clone(), test_authenticator()).recover(handle_rejection);
+        let test_params = serde_json::json!({
+            "start_time": 1707165008,
+            "end_time": "+1h",
+            "capacity_amount": 1,
+            "pool": test_pool,
+        });
+
+        let api_response = warp::test::request()
+            .path("/reserve")
+            .method("POST")
+            .json(&test_params)
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 401);
+        assert_eq!(api_response.headers()["www-authenticate"], "Bearer");
+
+        // Users reserve for themselves.
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path("/reserve")
+            .method("POST")
+            .json(&test_params)
+            .reply(&route_filter)
+            .await;
+        let reservation: ReservationResponse = from_slice(api_response.body()).unwrap();
+        let reservation_id = reservation.reservation_id.unwrap();
+        let reservation_hostess = hostess.clone();
+        let user_reservation = tokio::task::spawn_blocking(move || {
+            reservation_hostess.get_reservation(reservation_id)
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+        assert_eq!(user_reservation.user_id, 42);
+
+        // Only operators can reserve for other users.
+        let mut other_user_params = test_params.clone();
+        other_user_params["user_id"] = serde_json::json!(43);
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path("/reserve")
+            .method("POST")
+            .json(&other_user_params)
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 403);
+        let api_response = warp::test::request()
+            .header("authorization", bearer(OPERATOR_KEY))
+            .path("/reserve")
+            .method("POST")
+            .json(&other_user_params)
+            .reply(&route_filter)
+            .await;
+        let reservation: ReservationResponse = from_slice(api_response.body()).unwrap();
+        assert!(reservation.is_reserved);
+        let other_reservation_id = reservation.reservation_id.unwrap();
+
+        // Users can't cancel other users' reservations.
+        let route_filter = cancel_reservation_route(hostess.clone(), test_authenticator());
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path(&format!("/reservations/{other_reservation_id}"))
+            .method("DELETE")
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 403);
+        let error_response: ErrorResponse = from_slice(api_response.body()).unwrap();
+        assert!(error_response.user_message.contains("can't act for"));
+
+        // Reports are only for operators.
+        let route_filter =
+            load_report_route(hostess.clone(), test_authenticator()).recover(handle_rejection);
+        let report_path =
+            format!("/reports/load?pool={test_pool}&start_time=1707165008&end_time=1707251408");
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path(&report_path)
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 403);
+
+        // Signed JWTs work like API keys.
+        let operator_jwt = sign_jwt(
+            &JwtClaims {
+                sub: String::from("7"),
+                role: Role::Operator,
+                exp: u64::from(TEST_EPOCH_NOW) + 3600,
+                nbf: None,
+                iss: None,
+            },
+            JWT_SECRET,
+        )
+        .unwrap();
+        let api_response = warp::test::request()
+            .header("authorization", bearer(&operator_jwt))
+            .path(&report_path)
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 200);
+    }
+
+    // Retried reservation requests with the same idempotency key are only reserved once.
+    #[tokio::test]
+    async fn test_idempotent_reservations() {
+        let _ = setup_native_logging();
+        let test_pool = "test_idempotent_reservations";
+        tokio::task::spawn_blocking(move || seed_test_pool(test_pool))
+            .await
+            .unwrap();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+        let route_filter = reservation_route(hostess, test_authenticator());
+        let test_params = serde_json::json!({
+            "start_time": 1707165008,
+            "end_time": "+1h",
+            "capacity_amount": 1,
+            "pool": test_pool,
+        });
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .header("idempotency-key", "test-restful-retry")
+            .path("/reserve")
+            .method("POST")
+            .json(&test_params)
+            .reply(&route_filter)
+            .await;
+        assert!(!api_response.headers().contains_key("idempotent-replayed"));
+        let first_reservation: ReservationResponse = from_slice(api_response.body()).unwrap();
+        assert!(first_reservation.is_reserved);
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .header("idempotency-key", "test-restful-retry")
+            .path("/reserve")
+            .method("POST")
+            .json(&test_params)
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.headers()["idempotent-replayed"], "true");
+        let retried_reservation: ReservationResponse = from_slice(api_response.body()).unwrap();
+        assert_eq!(
+            retried_reservation.reservation_id,
+            first_reservation.reservation_id
+        );
+
+        // Reusing the key for something else is a conflict.
+        let mut other_params = test_params.clone();
+        other_params["capacity_amount"] = serde_json::json!(2);
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .header("idempotency-key", "test-restful-retry")
+            .path("/reserve")
+            .method("POST")
+            .json(&other_params)
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 409);
+
+        // Requests without a key are evaluated every time.
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path("/reserve")
+            .method("POST")
+            .json(&test_params)
+            .reply(&route_filter)
+            .await;
+        let another_reservation: ReservationResponse = from_slice(api_response.body()).unwrap();
+        assert_ne!(
+            another_reservation.reservation_id,
+            first_reservation.reservation_id
+        );
+    }
+
+    // Idempotency keys whose requests weren't answered are released, so their retries are
+    // evaluated instead of being refused until the key expires.
+    #[tokio::test]
+    async fn test_idempotent_reservations_released() {
+        let _ = setup_native_logging();
+        let test_pool = "test_idempotent_reservations_released";
+        let failing_datastore = tokio::task::spawn_blocking(move || {
+            seed_test_pool(test_pool);
+            Arc::new(FailingDatastore::new(test_datastore()))
+        })
+        .await
+        .unwrap();
+        let hostess = Arc::new(Hostess::new(
+            Arc::new(test_clock()),
+            failing_datastore.clone(),
+            HostessConfig::default(),
+        ));
+        let route_filter = reservation_route(hostess, test_authenticator());
+        let test_params = serde_json::json!({
+            "start_time": 1707165008,
+            "end_time": "+1h",
+            "capacity_amount": 1,
+            "pool": test_pool,
+        });
+        let reserve = |idempotency_key: &'static str| {
+            warp::test::request()
+                .header("authorization", bearer(USER_KEY))
+                .header("idempotency-key", idempotency_key)
+                .path("/reserve")
+                .method("POST")
+                .json(&test_params)
+                .reply(&route_filter)
+        };
+
+        // Requests that hit an error aren't answered for good.
+        failing_datastore.fail_next("begin_booking");
+        let api_response = reserve("test-restful-release-error").await;
+        let failed_reservation: ReservationResponse = from_slice(api_response.body()).unwrap();
+        assert!(!failed_reservation.is_reserved);
+        let api_response = reserve("test-restful-release-error").await;
+        assert!(!api_response.headers().contains_key("idempotent-replayed"));
+        let retried_reservation: ReservationResponse = from_slice(api_response.body()).unwrap();
+        assert!(retried_reservation.is_reserved);
+
+        // Neither are ones whose response couldn't be kept.
+        failing_datastore.fail_next("answer_idempotency_key");
+        let api_response = reserve("test-restful-release-answer").await;
+        let first_reservation: ReservationResponse = from_slice(api_response.body()).unwrap();
+        assert!(first_reservation.is_reserved);
+        let api_response = reserve("test-restful-release-answer").await;
+        assert_eq!(api_response.status(), 200);
+        assert!(!api_response.headers().contains_key("idempotent-replayed"));
+        let retried_reservation: ReservationResponse = from_slice(api_response.body()).unwrap();
+        assert!(retried_reservation.is_reserved);
+        assert_ne!(
+            retried_reservation.reservation_id,
+            first_reservation.reservation_id
+        );
+    }
+
+    // Test if the batch reservation route reports what became of each request.
+    //
+    // This is the equivalent of:
+    // `wget --method=POST -O- -q --body-data='{"requests": [{"start_time": 1707165008, "end_time": "+1h", "capacity_amount": 8}, {"start_time": 1707165008, "end_time": "+1h", "capacity_amount": 64}], "ordering": "optimize_packing"}' --header=Content-Type:application/json --header='Authorization: Bearer ...' localhost:4242/reserve/batch`
+    #[tokio::test]
+    async fn test_batch_reservation_route() {
+        let _ = setup_native_logging();
+        let test_pool = "test_batch_reservation_route";
+        tokio::task::spawn_blocking(move || seed_test_pool(test_pool))
+            .await
+            .unwrap();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+        let route_filter = batch_reservation_route(hostess, test_authenticator());
+        let small_params = serde_json::json!({
+            "start_time": 1707165008,
+            "end_time": "+1h",
+            "capacity_amount": 8,
+            "pool": test_pool,
+        });
+        let mut big_params = small_params.clone();
+        big_params["capacity_amount"] = serde_json::json!(64);
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path("/reserve/batch")
+            .method("POST")
+            .json(&serde_json::json!({
+                "requests": [small_params, big_params],
+                "ordering": "optimize_packing",
+            }))
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 200);
+        let batch_response: BatchResponse = from_slice(api_response.body()).unwrap();
+        assert!(!batch_response.is_reserved);
+        assert_eq!(batch_response.results[0].index, 0);
+        assert_eq!(
+            batch_response.results[0].denial.as_ref().unwrap().kind,
+            DenialKind::NotEnoughCapacity
+        );
+        assert!(batch_response.results[1].is_reserved);
+        assert_eq!(batch_response.results[1].end_time, Some(1707168608));
+
+        // Users can't slip someone else's request into their batch.
+        let mut other_params = small_params.clone();
+        other_params["user_id"] = serde_json::json!(7);
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path("/reserve/batch")
+            .method("POST")
+            .json(&serde_json::json!({ "requests": [small_params, other_params] }))
+            .reply(&route_filter.clone().recover(handle_rejection))
+            .await;
+        assert_eq!(api_response.status(), 403);
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path("/reserve/batch")
+            .method("POST")
+            .json(&serde_json::json!({ "requests": [] }))
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 400);
+    }
+
+    // Test if the reservation listing route pages through reservations, and keeps users to their
+    // own.
+    //
+    // This is the equivalent of:
+    // `wget --header='Authorization: Bearer ...' -O- -q 'localhost:4242/reservations?pool=default&sort=start_time&limit=1'`
+    #[tokio::test]
+    async fn test_list_reservations_route() {
+        let _ = setup_native_logging();
+        let test_pool = "test_list_reservations_route";
+        tokio::task::spawn_blocking(move || seed_test_pool(test_pool))
+            .await
+            .unwrap();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+        let route_filter = list_reservations_route(hostess.clone(), test_authenticator())
+            .recover(handle_rejection);
+        tokio::task::spawn_blocking(move || {
+            // User 42 holds the first and last hour, and user 7 holds the one between.
+            for (start_time, user_id) in [(1707165008, 42), (1707168608, 7), (1707172208, 42)] {
+                let test_request =
+                    ReservationRequest::new(start_time, start_time + 3600, 1, user_id)
+                        .in_pool(test_pool);
+                hostess.process_reservation(&test_request).unwrap();
+            }
+        })
+        .await
+        .unwrap();
+
+        let list_path = format!("/reservations?pool={test_pool}&sort=start_time&limit=1");
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path(&list_path)
+            .method("GET")
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 200);
+        let first_page: ReservationPage = from_slice(api_response.body()).unwrap();
+        assert_eq!(first_page.reservations.len(), 1);
+        assert_eq!(first_page.reservations[0].user_id, 42);
+        let next_cursor = first_page.next_cursor.unwrap();
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path(&format!("{list_path}&cursor={next_cursor}"))
+            .method("GET")
+            .reply(&route_filter)
+            .await;
+        let last_page: ReservationPage = from_slice(api_response.body()).unwrap();
+        assert_eq!(last_page.reservations[0].start_time, 1707172208);
+        assert_eq!(last_page.next_cursor, None);
+
+        // Operators see everyone's reservations, but users can't ask for someone else's.
+        let api_response = warp::test::request()
+            .header("authorization", bearer(OPERATOR_KEY))
+            .path(&format!("/reservations?pool={test_pool}"))
+            .method("GET")
+            .reply(&route_filter)
+            .await;
+        let everyones_page: ReservationPage = from_slice(api_response.body()).unwrap();
+        assert_eq!(everyones_page.reservations.len(), 3);
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path(&format!("/reservations?pool={test_pool}&user_id=7"))
+            .method("GET")
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 403);
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path(&format!("/reservations?pool={test_pool}&cursor=bogus"))
+            .method("GET")
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 400);
+    }
+
+    /// Read the next server-sent event from a streaming response.
+    async fn next_server_event(event_stream: &mut warp::hyper::Body) -> String {
+        let event_chunk = tokio::time::timeout(Duration::from_secs(10), event_stream.data())
+            .await
+            .expect("No server-sent event arrived")
+            .unwrap()
+            .unwrap();
+        String::from_utf8(event_chunk.to_vec()).unwrap()
+    }
+
+    // Test if followers get the changes they missed, then new ones as they happen, and only
+    // their own.
+    //
+    // This is the equivalent of:
+    // `wget -O- -q --header="Last-Event-ID: 41" 'localhost:4242/v1/events?pool=test_change_stream_route'`
+    #[tokio::test]
+    async fn test_change_stream_route() {
+        let _ = setup_native_logging();
+        let test_pool = "test_change_stream_route";
+        tokio::task::spawn_blocking(move || seed_test_pool(test_pool))
+            .await
+            .unwrap();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+        let route_filter =
+            change_stream_route(hostess.clone(), test_authenticator()).recover(handle_rejection);
+        let reserve_hour = |start_time: u32, user_id: u32| {
+            let hostess = hostess.clone();
+            tokio::task::spawn_blocking(move || {
+                let test_request =
+                    ReservationRequest::new(start_time, start_time + 3600, 1, user_id)
+                        .in_pool(test_pool);
+                hostess.process_reservation(&test_request).unwrap().unwrap()
+            })
+        };
+        let first_event_id = {
+            let hostess = hostess.clone();
+            tokio::task::spawn_blocking(move || hostess.last_change_id())
+                .await
+                .unwrap()
+                .unwrap()
+        };
+        let missed_reservation = reserve_hour(1707165008, 42).await.unwrap();
+
+        // Followers that reconnect get what they missed.
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .header("last-event-id", first_event_id.to_string())
+            .path(&format!("/events?pool={test_pool}"))
+            .method("GET")
+            .filter(&route_filter)
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(api_response.status(), 200);
+        let mut event_stream = api_response.into_body();
+        let missed_event = next_server_event(&mut event_stream).await;
+        assert!(missed_event.contains("event:reservation_created"));
+        assert!(missed_event.contains(&format!(
+            "\"reservation_id\":{}",
+            missed_reservation.reservation_id
+        )));
+        let missed_event_id: u32 = missed_event
+            .lines()
+            .find_map(|line| line.strip_prefix("id:"))
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        // New changes arrive as they happen, but users only hear about their own.
+        let follower = tokio::spawn(async move {
+            let api_response = warp::test::request()
+                .header("authorization", bearer(USER_KEY))
+                .path(&format!("/events?pool={test_pool}&after={missed_event_id}"))
+                .method("GET")
+                .filter(&route_filter)
+                .await
+                .unwrap()
+                .into_response();
+            next_server_event(&mut api_response.into_body()).await
+        });
+        reserve_hour(1707168608, 7).await.unwrap();
+        let new_reservation = reserve_hour(1707172208, 42).await.unwrap();
+        let new_event = follower.await.unwrap();
+        assert!(new_event.contains(&format!(
+            "\"reservation_id\":{}",
+            new_reservation.reservation_id
+        )));
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path(&format!("/events?pool={test_pool}&user_id=7"))
+            .method("GET")
+            .reply(&change_stream_route(hostess, test_authenticator()).recover(handle_rejection))
+            .await;
+        assert_eq!(api_response.status(), 403);
+    }
+
+    // Test if the OpenAPI document is served to anyone.
+    //
+    // This is the equivalent of:
+    // `wget -O- -q localhost:4242/openapi.json`
+    #[tokio::test]
+    async fn test_openapi_route() {
+        let _ = setup_native_logging();
+        let api_response = warp::test::request()
+            .path("/openapi.json")
+            .method("GET")
+            .reply(&openapi_route(&ApiConfig::default()))
+            .await;
+        assert_eq!(api_response.status(), 200);
+        let openapi: serde_json::Value = from_slice(api_response.body()).unwrap();
+        assert!(openapi["openapi"].as_str().unwrap().starts_with("3."));
+        // Each version documents its own response to reservations.
+        assert_eq!(
+            openapi["paths"]["/v1/reserve"]["post"]["responses"]["200"]["content"]
+                ["application/json"]["schema"]["$ref"],
+            "#/components/schemas/ReservationResponse"
+        );
+        assert_eq!(
+            openapi["paths"]["/v2/reserve"]["post"]["responses"]["201"]["content"]
+                ["application/json"]["schema"]["$ref"],
+            "#/components/schemas/ReservationResponseV2"
+        );
+        assert!(openapi["paths"]["/v2/reservations"]["get"].is_object());
+        assert!(openapi["paths"]["/reserve"].is_null());
+        for schema_name in [
+            "ReservationParams",
+            "ReservationResponse",
+            "ReservationResponseV2",
+            "ReservationRequest",
+        ] {
+            assert!(
+                openapi["components"]["schemas"][schema_name].is_object(),
+                "OpenAPI document is missing the \"{schema_name}\" schema"
+            );
+        }
+        // Every schema that's referred to has to be in the document, or clients can't be
+        // generated from it.
+        let mut unvisited = vec![&openapi];
+        while let Some(json_value) = unvisited.pop() {
+            match json_value {
+                serde_json::Value::Object(json_object) => {
+                    if let Some(schema_ref) = json_object.get("$ref").and_then(|r| r.as_str()) {
+                        let schema_name = schema_ref.trim_start_matches("#/components/schemas/");
+                        assert!(
+                            openapi["components"]["schemas"][schema_name].is_object(),
+                            "OpenAPI document refers to the missing \"{schema_name}\" schema"
+                        );
+                    }
+                    unvisited.extend(json_object.values());
+                }
+                serde_json::Value::Array(json_array) => unvisited.extend(json_array),
+                _ => {}
+            }
+        }
+        // Generated clients name their methods after operation IDs, so they can't repeat.
+        let mut operation_ids = BTreeSet::new();
+        for path_item in openapi["paths"].as_object().unwrap().values() {
+            for operation in path_item.as_object().unwrap().values() {
+                let operation_id = operation["operationId"].as_str().unwrap();
+                assert!(
+                    operation_ids.insert(operation_id),
+                    "Operation ID \"{operation_id}\" is used more than once"
+                );
+            }
+        }
+
+        // Operations of deprecated versions are marked as deprecated.
+        let api_config = ApiConfig {
+            deprecations: vec![VersionDeprecation {
+                version: ApiVersion::V1,
+                deprecated_at: 1795046400,
+                sunset_at: None,
+            }],
+            ..Default::default()
+        };
+        let openapi = api_document(&api_config);
+        assert!(openapi.paths.paths["/v1/reserve"]
+            .post
+            .as_ref()
+            .unwrap()
+            .deprecated
+            .is_some());
+        assert!(openapi.paths.paths["/v2/reserve"]
+            .post
+            .as_ref()
+            .unwrap()
+            .deprecated
+            .is_none());
+    }
+
+    // Test if every documented operation is routed the way it's documented, and every version
+    // documents the same routes.
+    //
+    // Warp's filters can't be listed, so routes are found by driving each documented operation
+    // through every route.
+    #[tokio::test]
+    async fn test_openapi_matches_routes() {
+        let _ = setup_native_logging();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+        let route_filter = all_routes(hostess, test_authenticator(), ApiConfig::default());
+        let openapi = api_document(&ApiConfig::default());
+        let mut version_routes = BTreeSet::new();
+        for (path, path_item) in &openapi.paths.paths {
+            let operations = [
+                ("GET", &path_item.get),
+                ("POST", &path_item.post),
+                ("DELETE", &path_item.delete),
+                ("PUT", &path_item.put),
+                ("PATCH", &path_item.patch),
+            ];
+            for (method, operation) in operations {
+                let Some(operation) = operation else {
+                    continue;
+                };
+                let generic_path: String = path
+                    .split('/')
+                    .skip(1)
+                    .map(|segment| match segment.starts_with('{') {
+                        true => String::from("/{}"),
+                        false => format!("/{segment}"),
+                    })
+                    .collect();
+                // Versions are compared by their routes without the version prefix.
+                match ApiVersion::of_path(&generic_path) {
+                    Some((api_version, unprefixed_path)) => {
+                        version_routes.insert((
+                            api_version.as_str(),
+                            method.to_string(),
+                            unprefixed_path.to_string(),
+                        ));
+                    }
+                    None => assert!(
+                        UNVERSIONED_PATHS.contains(&generic_path.as_str()),
+                        "{method} {path} is documented without a version"
+                    ),
+                }
+                // Documented operations have to be routed, and ask for credentials if they're
+                // documented as needing them.
+                let api_response = warp::test::request()
+                    .path(&generic_path.replace("{}", "1"))
+                    .method(method)
+                    .reply(&route_filter)
+                    .await;
+                let expected_status = match operation.security.is_some() {
+                    true => 401,
+                    false => 200,
+                };
+                assert_eq!(
+                    api_response.status(),
+                    expected_status,
+                    "{method} {path} is documented, but isn't routed the way it's documented"
+                );
+            }
+        }
+        // Every version serves every route, even if it answers some of them differently.
+        let versioned_routes: BTreeSet<(String, String)> = version_routes
+            .iter()
+            .map(|(_, method, path)| (method.clone(), path.clone()))
+            .collect();
+        for api_version in ApiVersion::ALL {
+            let served_routes: BTreeSet<(String, String)> = version_routes
+                .iter()
+                .filter(|(version_name, _, _)| *version_name == api_version.as_str())
+                .map(|(_, method, path)| (method.clone(), path.clone()))
+                .collect();
+            assert_eq!(
+                served_routes,
+                versioned_routes,
+                "Version \"{}\" doesn't document every route",
+                api_version.as_str()
+            );
+        }
+    }
+
+    // Test if operators can scrape metrics, and requests to every route are counted.
+    //
+    // This is the equivalent of:
+    // `wget --header="Authorization: Bearer $ARBITER_TOKEN" -O- -q localhost:4242/metrics`
+    #[tokio::test]
+    async fn test_metrics_route() {
+        let _ = setup_native_logging();
+        let test_pool = "test_metrics_route";
+        tokio::task::spawn_blocking(move || seed_test_pool(test_pool))
+            .await
+            .unwrap();
+        let epoch_now = 1707165008 + 3600;
+        let hostess = test_hostess(FixedClock::new(epoch_now), HostessConfig::default()).await;
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path("/metrics")
+            .reply(&metrics_route(hostess.clone(), test_authenticator()))
+            .await;
+        assert_eq!(api_response.status(), 403);
+
+        let route_filter = all_routes(hostess, test_authenticator(), ApiConfig::default());
+        let test_params = serde_json::json!({
+            "start_time": epoch_now,
+            "end_time": "+1h",
+            "capacity_amount": 16,
+            "pool": test_pool,
+        });
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path("/v1/reserve")
+            .method("POST")
+            .json(&test_params)
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 200);
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path("/v1/reservations/4294967295")
+            .method("DELETE")
+            .reply(&route_filter)
+            .await;
+        let cancel_status = api_response.status().as_u16();
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(OPERATOR_KEY))
+            .path("/metrics")
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 200);
+        assert!(api_response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let rendered_metrics = String::from_utf8_lossy(api_response.body());
+        // Other tests count requests and decisions too, so only these ones are certain.
+        for expected_prefix in [
+            "arbiter_http_requests_total{method=\"POST\",route=\"/v1/reserve\",status=\"200\"} ",
+            &format!("arbiter_http_requests_total{{method=\"DELETE\",route=\"/v1/reservations/{{reservation_id}}\",status=\"{cancel_status}\"}} "),
+            "arbiter_reservations_approved_total{pool=\"test_metrics_route\"} 1",
+            "arbiter_evaluation_duration_seconds_count{outcome=\"approved\"} ",
+            "arbiter_db_query_duration_seconds_count{operation=\"begin_booking\"} ",
+            "arbiter_pool_utilization_ratio{pool=\"test_metrics_route\"} 0.25",
+            "arbiter_reservations{pool=\"test_metrics_route\",status=\"pending\"} 1",
+            "arbiter_reservations{pool=\"test_metrics_route\",status=\"active\"} 0",
+        ] {
+            assert!(
+                rendered_metrics
+                    .lines()
+                    .any(|line| line.starts_with(expected_prefix)),
+                "Metrics are missing \"{expected_prefix}\":\n{rendered_metrics}"
+            );
+        }
+    }
+
+    // Test if requests are counted by the route that they were for, not their exact path.
+    #[test]
+    fn test_route_template() {
+        let route_templates = route_templates(&api_document(&ApiConfig::default()));
+        assert_eq!(
+            route_template(&route_templates, "/v2/reservations/4242"),
+            Some("/v2/reservations/{reservation_id}")
+        );
+        assert_eq!(
+            route_template(&route_templates, "/reserve"),
+            Some("/reserve")
+        );
+        assert_eq!(
+            route_template(&route_templates, "/metrics"),
+            Some("/metrics")
+        );
+        assert_eq!(route_template(&route_templates, "/v1/nowhere"), None);
+    }
+
+    // Test if v2's reservation route says how reservations went with HTTP statuses and typed
+    // denials.
+    //
+    // This is the equivalent of:
+    // `wget --method=POST -O- -q --body-data='{"start_time": 1707165008, "end_time": "+1h", "capacity_amount": 64, "pool": "test_reservation_route_v2"}' --header=Content-Type:application/json localhost:4242/v2/reserve`
+    #[tokio::test]
+    async fn test_reservation_route_v2() {
+        let _ = setup_native_logging();
+        let test_pool = "test_reservation_route_v2";
+        tokio::task::spawn_blocking(move || seed_test_pool(test_pool))
+            .await
+            .unwrap();
+        let config = HostessConfig {
+            policy: ReservationPolicy {
+                max_duration: Some(Duration::from_secs(24 * 60 * 60)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let hostess = test_hostess(test_clock(), config).await;
+        let route_filter = reservation_route_v2(hostess, test_authenticator());
+        let test_params = serde_json::json!({
+            "start_time": 1707165008,
+            "end_time": "+1h",
+            "capacity_amount": 64,
+            "pool": test_pool,
+        });
+
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .header("idempotency-key", "test-restful-v2-retry")
+            .path("/reserve")
+            .method("POST")
+            .json(&test_params)
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 201);
+        let reservation: ReservationResponseV2 = from_slice(api_response.body()).unwrap();
+        assert!(reservation.is_reserved);
+        assert!(reservation.reservation_id.is_some());
+        assert!(reservation.denial.is_none());
+        assert_eq!(reservation.end_time, Some(1707168608));
+
+        // Retries get the same status back, not just the same body.
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .header("idempotency-key", "test-restful-v2-retry")
+            .path("/reserve")
+            .method("POST")
+            .json(&test_params)
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 201);
+        assert_eq!(api_response.headers()["idempotent-replayed"], "true");
+
+        // The pool is full now.
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path("/reserve")
+            .method("POST")
+            .json(&test_params)
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 409);
+        let denied_reservation: ReservationResponseV2 = from_slice(api_response.body()).unwrap();
+        assert!(!denied_reservation.is_reserved);
+        assert_eq!(
+            denied_reservation.denial.unwrap().kind,
+            DenialKind::NotEnoughCapacity
+        );
+
+        let mut long_params = test_params.clone();
+        long_params["end_time"] = serde_json::json!("+2days");
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path("/reserve")
+            .method("POST")
+            .json(&long_params)
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 422);
+        let denied_reservation: ReservationResponseV2 = from_slice(api_response.body()).unwrap();
+        assert_eq!(
+            denied_reservation.denial.unwrap().kind,
+            DenialKind::PolicyViolation
+        );
+
+        let mut unknown_pool_params = test_params.clone();
+        unknown_pool_params["pool"] = serde_json::json!("test_reservation_route_v2_unknown");
+        let api_response = warp::test::request()
+            .header("authorization", bearer(USER_KEY))
+            .path("/reserve")
+            .method("POST")
+            .json(&unknown_pool_params)
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 400);
+        let denied_reservation: ReservationResponseV2 = from_slice(api_response.body()).unwrap();
+        assert_eq!(
+            denied_reservation.denial.unwrap().kind,
+            DenialKind::InvalidRequest
+        );
+    }
+
+    // Test if routes are served under every version and at the root, and deprecated ones say so.
+    //
+    // This is the equivalent of:
+    // `wget -S -O- -q localhost:4242/hello/Eisenhorn`
+    // `Deprecation: @1792368000`
+    // `Link: </v1/hello/Eisenhorn>; rel="successor-version"`
+    #[tokio::test]
+    async fn test_versioned_routes() {
+        let _ = setup_native_logging();
+        let hostess = test_hostess(test_clock(), HostessConfig::default()).await;
+        let route_filter = all_routes(hostess.clone(), test_authenticator(), ApiConfig::default());
+
+        for path in ["/v1/hello/Eisenhorn", "/v2/hello/Eisenhorn"] {
+            let api_response = warp::test::request().path(path).reply(&route_filter).await;
+            assert_eq!(api_response.status(), 200);
+            assert_eq!(api_response.body(), "Hello, Eisenhorn!");
+            assert!(!api_response.headers().contains_key("deprecation"));
+        }
+
+        // Clients that predate versions still get v1's answers, and hear where to go instead.
+        let api_response = warp::test::request()
+            .path("/hello/Eisenhorn")
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 200);
+        assert_eq!(api_response.headers()["deprecation"], "@1792368000");
+        assert_eq!(
+            api_response.headers()["link"],
+            "</v1/hello/Eisenhorn>; rel=\"successor-version\""
+        );
+        assert!(!api_response.headers().contains_key("sunset"));
+        // Even when they're turned away.
+        let api_response = warp::test::request()
+            .path("/reservations")
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 401);
+        assert!(api_response.headers().contains_key("deprecation"));
+        let api_response = warp::test::request()
+            .path("/openapi.json")
+            .reply(&route_filter)
+            .await;
+        assert!(!api_response.headers().contains_key("deprecation"));
+
+        // Deprecated versions say when they'll be gone.
+        let api_config = ApiConfig {
+            unversioned_routes: false,
+            deprecations: vec![VersionDeprecation {
+                version: ApiVersion::V1,
+                deprecated_at: 1795046400,
+                sunset_at: Some(1798761600),
+            }],
+            ..Default::default()
+        };
+        let route_filter = all_routes(hostess, test_authenticator(), api_config);
+        let api_response = warp::test::request()
+            .path("/v1/hello/Eisenhorn")
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 200);
+        assert_eq!(api_response.headers()["deprecation"], "@1795046400");
+        assert_eq!(
+            api_response.headers()["sunset"],
+            "Fri, 01 Jan 2027 00:00:00 GMT"
+        );
+        assert_eq!(
+            api_response.headers()["link"],
+            "</v2/hello/Eisenhorn>; rel=\"successor-version\""
+        );
+        let api_response = warp::test::request()
+            .path("/hello/Eisenhorn")
+            .reply(&route_filter)
+            .await;
+        assert_eq!(api_response.status(), 404);
+    }
 }
@@ -0,0 +1,285 @@
+//! Versioning
+//!
+//! `versioning` describes the versions of the RESTful API, and tells clients which ones they
+//! should move off of.
+//!
+//! Every route is served under a version prefix like `/v1/`, so a version's responses can change
+//! shape without breaking clients of the versions before it. Routes are also served at the root,
+//! like `/reserve`, for clients that predate versioning; those answer the way `/v1/` does.
+//!
+//! Versions that are on their way out say so in every response, with the `Deprecation` header
+//! (RFC 9745), the `Sunset` header (RFC 8594) once there's a date they'll be removed, and a `Link`
+//! to the same route in the version that replaces them.
+//!
+//! ```toml
+//! [api]
+//! unversioned_sunset_at = 1798761600
+//!
+//! [[api.deprecations]]
+//! version = "v1"
+//! deprecated_at = 1795046400
+//! ```
+
+// Standard library crates.
+use std::collections::HashSet;
+
+// External crates.
+use anyhow::{ensure, Result};
+use chrono::DateTime;
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use serde_derive::{Deserialize, Serialize};
+
+/// When routes at the root were deprecated in favor of `/v1/`, represented by Unix epoch format.
+pub const UNVERSIONED_DEPRECATED_AT: u32 = 1792368000;
+
+/// Version of the RESTful API, which is the first segment of its routes' paths.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiVersion {
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    /// Every version, oldest first.
+    pub const ALL: [ApiVersion; 2] = [ApiVersion::V1, ApiVersion::V2];
+
+    /// Path segment that the version's routes are under.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "v1",
+            ApiVersion::V2 => "v2",
+        }
+    }
+
+    /// Version that replaces this one, if there is one yet.
+    pub fn successor(&self) -> Option<ApiVersion> {
+        match self {
+            ApiVersion::V1 => Some(ApiVersion::V2),
+            ApiVersion::V2 => None,
+        }
+    }
+
+    /// Work out which version a request's path is for.
+    ///
+    /// # Returns
+    /// The version, and the rest of the path after its prefix. Paths without a version prefix
+    /// give back `None`.
+    pub fn of_path(request_path: &str) -> Option<(ApiVersion, &str)> {
+        ApiVersion::ALL.into_iter().find_map(|api_version| {
+            let unprefixed_path = request_path
+                .strip_prefix('/')?
+                .strip_prefix(api_version.as_str())?;
+            match unprefixed_path.is_empty() || unprefixed_path.starts_with('/') {
+                true => Some((api_version, unprefixed_path)),
+                false => None,
+            }
+        })
+    }
+}
+
+/// Version that clients should move off of.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct VersionDeprecation {
+    pub version: ApiVersion,
+    /// When the version was deprecated, represented by Unix epoch format.
+    pub deprecated_at: u32,
+    /// When the version will stop being served, represented by Unix epoch format.
+    pub sunset_at: Option<u32>,
+}
+
+/// Settings for the RESTful API's versions.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Whether routes are still served at the root, like `/reserve`, for clients that predate
+    /// versioning.
+    pub unversioned_routes: bool,
+    /// When routes at the root will stop being served, represented by Unix epoch format.
+    pub unversioned_sunset_at: Option<u32>,
+    /// Versions that clients should move off of.
+    pub deprecations: Vec<VersionDeprecation>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            unversioned_routes: true,
+            unversioned_sunset_at: None,
+            deprecations: Vec::new(),
+        }
+    }
+}
+
+impl ApiConfig {
+    /// Ensure the deprecations make sense.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(unversioned_sunset_at) = self.unversioned_sunset_at {
+            ensure!(
+                unversioned_sunset_at > UNVERSIONED_DEPRECATED_AT,
+                "`unversioned_sunset_at` must be after routes at the root were deprecated"
+            );
+        }
+        let mut seen_versions = HashSet::new();
+        for deprecation in &self.deprecations {
+            let version_name = deprecation.version.as_str();
+            ensure!(
+                deprecation.version.successor().is_some(),
+                format!(
+                    "Version \"{version_name}\" can't be deprecated before there's a newer one"
+                )
+            );
+            ensure!(
+                seen_versions.insert(deprecation.version),
+                format!("Version \"{version_name}\" is deprecated more than once")
+            );
+            if let Some(sunset_at) = deprecation.sunset_at {
+                ensure!(
+                    sunset_at > deprecation.deprecated_at,
+                    format!("Version \"{version_name}\" must be deprecated before its sunset")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Deprecation of the version that a request's path is for, if it's deprecated.
+    ///
+    /// # Returns
+    /// The deprecation, and the same path in the version that replaces it.
+    pub fn deprecation_of(&self, request_path: &str) -> Option<(VersionDeprecation, String)> {
+        let Some((api_version, unprefixed_path)) = ApiVersion::of_path(request_path) else {
+            // Routes at the root are `/v1/`'s, so `/v1/` is what replaces them.
+            let unversioned_deprecation = VersionDeprecation {
+                version: ApiVersion::V1,
+                deprecated_at: UNVERSIONED_DEPRECATED_AT,
+                sunset_at: self.unversioned_sunset_at,
+            };
+            return Some((unversioned_deprecation, format!("/v1{request_path}")));
+        };
+        let deprecation = self
+            .deprecations
+            .iter()
+            .find(|deprecation| deprecation.version == api_version)?;
+        let successor = api_version.successor()?;
+        Some((
+            deprecation.clone(),
+            format!("/{}{unprefixed_path}", successor.as_str()),
+        ))
+    }
+
+    /// Headers that tell clients a request's path is deprecated, if it is.
+    pub fn deprecation_headers(&self, request_path: &str) -> Vec<(&'static str, String)> {
+        let Some((deprecation, successor_path)) = self.deprecation_of(request_path) else {
+            return Vec::new();
+        };
+        let mut deprecation_headers = vec![
+            ("deprecation", format!("@{}", deprecation.deprecated_at)),
+            (
+                "link",
+                format!("<{successor_path}>; rel=\"successor-version\""),
+            ),
+        ];
+        if let Some(sunset_at) = deprecation.sunset_at {
+            deprecation_headers.push(("sunset", http_date(sunset_at)));
+        }
+        deprecation_headers
+    }
+}
+
+/// Write a time the way HTTP headers do, like `"Sun, 06 Nov 1994 08:49:37 GMT"`.
+fn http_date(epoch_time: u32) -> String {
+    DateTime::from_timestamp(i64::from(epoch_time), 0)
+        .unwrap_or_default()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    // Project crates.
+    use super::{ApiConfig, ApiVersion, VersionDeprecation};
+
+    #[test]
+    fn test_version_of_path() {
+        assert_eq!(
+            ApiVersion::of_path("/v1/reserve"),
+            Some((ApiVersion::V1, "/reserve"))
+        );
+        assert_eq!(
+            ApiVersion::of_path("/v2/admin/maintenance/1"),
+            Some((ApiVersion::V2, "/admin/maintenance/1"))
+        );
+        assert_eq!(ApiVersion::of_path("/reserve"), None);
+        assert_eq!(ApiVersion::of_path("/v10/reserve"), None);
+        assert_eq!(ApiVersion::of_path("/hello/v1"), None);
+    }
+
+    #[test]
+    fn test_deprecation_headers() {
+        let api_config = ApiConfig {
+            unversioned_sunset_at: Some(1798761600),
+            deprecations: vec![VersionDeprecation {
+                version: ApiVersion::V1,
+                deprecated_at: 1795046400,
+                sunset_at: None,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            api_config.deprecation_headers("/reserve"),
+            vec![
+                ("deprecation", String::from("@1792368000")),
+                (
+                    "link",
+                    String::from("</v1/reserve>; rel=\"successor-version\"")
+                ),
+                ("sunset", String::from("Fri, 01 Jan 2027 00:00:00 GMT")),
+            ]
+        );
+        assert_eq!(
+            api_config.deprecation_headers("/v1/reservations"),
+            vec![
+                ("deprecation", String::from("@1795046400")),
+                (
+                    "link",
+                    String::from("</v2/reservations>; rel=\"successor-version\"")
+                ),
+            ]
+        );
+        assert!(api_config.deprecation_headers("/v2/reserve").is_empty());
+        assert!(ApiConfig::default()
+            .deprecation_headers("/v1/reserve")
+            .is_empty());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(ApiConfig::default().validate().is_ok());
+        let newest_deprecated = ApiConfig {
+            deprecations: vec![VersionDeprecation {
+                version: ApiVersion::V2,
+                deprecated_at: 1795046400,
+                sunset_at: None,
+            }],
+            ..Default::default()
+        };
+        assert!(newest_deprecated.validate().is_err());
+        let sunset_first = ApiConfig {
+            deprecations: vec![VersionDeprecation {
+                version: ApiVersion::V1,
+                deprecated_at: 1795046400,
+                sunset_at: Some(1792368000),
+            }],
+            ..Default::default()
+        };
+        assert!(sunset_first.validate().is_err());
+        let early_sunset = ApiConfig {
+            unversioned_sunset_at: Some(1707165008),
+            ..Default::default()
+        };
+        assert!(early_sunset.validate().is_err());
+    }
+}
@@ -0,0 +1,790 @@
+//! Webhooks
+//!
+//! `webhooks` tells other systems about changes by calling them, so they don't have to follow the
+//! event stream.
+//!
+//! Operators subscribe a URL to the kinds of change events that it cares about, like reservations
+//! starting, ending, or being cancelled, optionally narrowed down to a pool or a user. Every
+//! matching change is `POST`ed to the URL as JSON, signed with the subscription's secret, and
+//! retried with exponential backoff until the receiver answers with a 2xx status. Every attempt is
+//! kept in the `webhook_deliveries` table so operators can see what was sent and what happened.
+//!
+//! Receivers check that a delivery came from Arbiter by working out the HMAC-SHA256 of
+//! `"{X-Arbiter-Timestamp}.{body}"` with the secret, and comparing it to the
+//! `X-Arbiter-Signature` header, which is `sha256=` followed by the HMAC in hex.
+//!
+//! Deliveries are plain HTTP, so receivers that only speak HTTPS need a proxy in front of them.
+
+// Standard library crates.
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+// External crates.
+use anyhow::{ensure, Context, Result};
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use hyper::client::HttpConnector;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Client, Request, StatusCode, Uri};
+#[allow(unused)]
+use log::{debug, error, info, trace, warn};
+use serde_derive::{Deserialize, Serialize};
+use sha2::Sha256;
+use utoipa::ToSchema;
+
+// Project crates.
+use crate::config::deserialize_duration;
+use crate::events::{follow_changes, ChangeEvent, ChangeEventKind, ChangeFilter};
+use crate::hostess::Hostess;
+
+/// Shortest secret that a subscription can be signed with.
+pub const MIN_SECRET_LENGTH: usize = 16;
+
+/// Kinds of changes that Arbiter doesn't record yet, since it doesn't preempt reservations or keep
+/// a waitlist. Subscriptions to them would never hear anything, so they're refused.
+const UNRECORDED_EVENT_KINDS: &[ChangeEventKind] = &[
+    ChangeEventKind::ReservationPreempted,
+    ChangeEventKind::WaitlistBooked,
+];
+
+/// How long the dispatcher waits before following changes again after it lost track of them.
+const DISPATCH_RESUME_DELAY: Duration = Duration::from_secs(5);
+
+/// Settings that change how webhooks are delivered.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// Most times that a change is sent to a receiver before it's given up on.
+    pub max_attempts: u32,
+    /// Wait before the first retry. It doubles after every failed attempt.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub initial_backoff: Duration,
+    /// Longest that a receiver has to answer before the attempt counts as failed.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub delivery_timeout: Duration,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            delivery_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl WebhookConfig {
+    /// Ensure every change gets at least one attempt.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(self.max_attempts > 0, "`max_attempts` must be at least 1");
+        ensure!(
+            !self.delivery_timeout.is_zero(),
+            "`delivery_timeout` must be longer than zero"
+        );
+        Ok(())
+    }
+}
+
+/// Webhook subscription as it arrives from an operator.
+#[derive(Deserialize, Serialize, ToSchema)]
+// Reject unknown REST JSON params with descriptive message.
+#[serde(deny_unknown_fields)]
+pub struct WebhookParams {
+    /// Where changes are `POST`ed, like `"http://orchestrator.internal/arbiter"`.
+    pub url: String,
+    /// Kinds of changes that the receiver is told about.
+    pub event_kinds: Vec<ChangeEventKind>,
+    /// Only send changes to this capacity pool.
+    pub pool: Option<String>,
+    /// Only send changes to this user's reservations, and changes that aren't to anyone's.
+    pub user_id: Option<u32>,
+    /// Key that deliveries are signed with. It's never shown again.
+    pub secret: String,
+}
+
+impl WebhookParams {
+    /// Ensure the subscription can be delivered to and signed.
+    pub fn validate(&self) -> Result<()> {
+        let url: Uri = self
+            .url
+            .parse()
+            .with_context(|| format!("Webhook URL \"{}\" can't be read", self.url))?;
+        ensure!(
+            url.scheme_str() == Some("http") && url.host().is_some(),
+            format!(
+                "Webhook URL \"{}\" must be an \"http://\" URL with a host",
+                self.url
+            )
+        );
+        ensure!(
+            !self.event_kinds.is_empty(),
+            "Webhook needs at least one event kind"
+        );
+        for event_kind in &self.event_kinds {
+            ensure!(
+                !UNRECORDED_EVENT_KINDS.contains(event_kind),
+                format!("Arbiter doesn't record \"{event_kind}\" changes yet")
+            );
+        }
+        ensure!(
+            self.secret.len() >= MIN_SECRET_LENGTH,
+            format!("Webhook secret must be at least {MIN_SECRET_LENGTH} characters")
+        );
+        Ok(())
+    }
+}
+
+/// URL that's subscribed to changes.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct WebhookSubscription {
+    pub subscription_id: u32,
+    pub url: String,
+    pub event_kinds: Vec<ChangeEventKind>,
+    pub pool: Option<String>,
+    pub user_id: Option<u32>,
+    /// Key that deliveries are signed with, which is kept out of responses.
+    #[serde(skip)]
+    pub secret: String,
+    /// Last change event that was handed to the subscription's deliveries.
+    pub last_event_id: u32,
+    /// When the subscription was registered, represented by Unix epoch format.
+    pub created_at: u32,
+}
+
+impl WebhookSubscription {
+    /// Whether a change should be sent to the subscription.
+    ///
+    /// Like followers of the event stream, subscriptions with a user hear about changes that
+    /// aren't to anyone's reservation.
+    pub fn matches(&self, change_event: &ChangeEvent) -> bool {
+        change_event.event_id > self.last_event_id
+            && self.event_kinds.contains(&change_event.kind)
+            && self
+                .pool
+                .as_ref()
+                .is_none_or(|pool| *pool == change_event.pool)
+            && match (self.user_id, change_event.user_id) {
+                (Some(user_id), Some(changed_user_id)) => user_id == changed_user_id,
+                _ => true,
+            }
+    }
+}
+
+// Print instantiated struct nicely.
+impl fmt::Display for WebhookSubscription {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "webhook subscription \"{}\" sending to \"{}\"",
+            self.subscription_id, self.url
+        )
+    }
+}
+
+/// Attempt to deliver a change that's about to be logged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WebhookDeliveryParams {
+    pub subscription_id: u32,
+    pub event_id: u32,
+    /// Which try this was, starting at 1.
+    pub attempt: u32,
+    /// When the change was sent, represented by Unix epoch format.
+    pub attempted_at: u32,
+    /// Status that the receiver answered with, if it answered.
+    pub status_code: Option<u16>,
+    /// Why the attempt failed, if it did.
+    pub error: Option<String>,
+    /// Whether the receiver accepted the change.
+    pub is_delivered: bool,
+}
+
+/// Attempt to deliver a change that was logged.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub delivery_id: u32,
+    pub subscription_id: u32,
+    pub event_id: u32,
+    /// Which try this was, starting at 1.
+    pub attempt: u32,
+    /// When the change was sent, represented by Unix epoch format.
+    pub attempted_at: u32,
+    /// Status that the receiver answered with, if it answered.
+    pub status_code: Option<u16>,
+    /// Why the attempt failed, if it did.
+    pub error: Option<String>,
+    /// Whether the receiver accepted the change.
+    pub is_delivered: bool,
+}
+
+// Print instantiated struct nicely.
+impl fmt::Display for WebhookDelivery {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "webhook delivery \"{}\" of change event \"{}\" to subscription \"{}\" (attempt {})",
+            self.delivery_id, self.event_id, self.subscription_id, self.attempt
+        )
+    }
+}
+
+/// Write event kinds the way they're stored in the datastore, like
+/// `"reservation_started,reservation_ended"`.
+pub fn join_event_kinds(event_kinds: &[ChangeEventKind]) -> String {
+    event_kinds
+        .iter()
+        .map(ChangeEventKind::as_str)
+        .collect::<Vec<&str>>()
+        .join(",")
+}
+
+/// Read event kinds back out of the datastore.
+pub fn split_event_kinds(joined_kinds: &str) -> Result<Vec<ChangeEventKind>> {
+    joined_kinds.split(',').map(str::parse).collect()
+}
+
+/// Sign a delivery so its receiver can tell that it came from Arbiter and wasn't replayed.
+///
+/// # Returns
+/// `sha256=` followed by the HMAC-SHA256 of `"{timestamp}.{payload}"` in hex.
+pub fn sign_payload(secret: &str, timestamp: u32, payload: &[u8]) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload);
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|signature_byte| format!("{signature_byte:02x}"))
+        .collect();
+    Ok(format!("sha256={signature}"))
+}
+
+/// Find how long to wait after a failed attempt before trying again.
+///
+/// The wait doubles after every attempt, starting from `initial_backoff` after the first.
+pub fn retry_backoff(initial_backoff: Duration, attempt: u32) -> Duration {
+    initial_backoff.saturating_mul(1 << attempt.saturating_sub(1).min(16))
+}
+
+/// Send every change to the webhooks that are subscribed to it, for as long as Arbiter runs.
+///
+/// The dispatcher picks up after the last change that it handed out, so changes that happened
+/// while Arbiter was stopped are sent when it starts again. Retries that were waiting when it
+/// stopped aren't.
+pub async fn dispatch_webhooks(hostess: Arc<Hostess>, webhook_config: WebhookConfig) {
+    let http_client = Client::new();
+    info!("Dispatching webhooks");
+    loop {
+        let resume_hostess = hostess.clone();
+        let resume_event_id =
+            match tokio::task::spawn_blocking(move || resume_hostess.webhook_resume_id()).await {
+                Ok(Ok(resume_event_id)) => resume_event_id,
+                Ok(Err(error_message)) => {
+                    error!("Couldn't find where webhooks left off: {:#}", error_message);
+                    tokio::time::sleep(DISPATCH_RESUME_DELAY).await;
+                    continue;
+                }
+                Err(join_error) => {
+                    error!(
+                        "Hostess didn't finish finding where webhooks left off: {}",
+                        join_error
+                    );
+                    tokio::time::sleep(DISPATCH_RESUME_DELAY).await;
+                    continue;
+                }
+            };
+        let mut change_events = Box::pin(follow_changes(
+            hostess.clone(),
+            ChangeFilter::default(),
+            resume_event_id,
+        ));
+        while let Some(change_event) = change_events.next().await {
+            let claim_hostess = hostess.clone();
+            let claimed_event = change_event.clone();
+            let subscriptions = tokio::task::spawn_blocking(move || {
+                claim_hostess.claim_webhook_subscriptions(&claimed_event)
+            })
+            .await;
+            // Changes that couldn't be claimed weren't handed out, so following starts over from
+            // the last change that was, instead of skipping past this one.
+            let subscriptions = match subscriptions {
+                Ok(Ok(subscriptions)) => subscriptions,
+                Ok(Err(error_message)) => {
+                    error!(
+                        "Couldn't find webhooks for {}: {:#}",
+                        change_event, error_message
+                    );
+                    break;
+                }
+                Err(join_error) => {
+                    error!("Hostess didn't finish finding webhooks: {}", join_error);
+                    break;
+                }
+            };
+            for subscription in subscriptions {
+                tokio::spawn(deliver_webhook(
+                    hostess.clone(),
+                    http_client.clone(),
+                    webhook_config.clone(),
+                    subscription,
+                    change_event.clone(),
+                ));
+            }
+        }
+        warn!("Stopped following change events, webhooks will resume shortly");
+        tokio::time::sleep(DISPATCH_RESUME_DELAY).await;
+    }
+}
+
+/// Send a change to a subscription until it's accepted or the attempts run out, logging every
+/// attempt.
+async fn deliver_webhook(
+    hostess: Arc<Hostess>,
+    http_client: Client<HttpConnector>,
+    webhook_config: WebhookConfig,
+    subscription: WebhookSubscription,
+    change_event: ChangeEvent,
+) {
+    let payload = match serde_json::to_vec(&change_event) {
+        Ok(payload) => payload,
+        Err(json_error) => {
+            error!("Couldn't write {} as JSON: {}", change_event, json_error);
+            return;
+        }
+    };
+    for attempt in 1..=webhook_config.max_attempts {
+        let attempted_at = hostess.now();
+        let answer = send_webhook(
+            &http_client,
+            &subscription,
+            &change_event,
+            &payload,
+            attempted_at,
+            webhook_config.delivery_timeout,
+        )
+        .await;
+        let (status_code, error) = match answer {
+            Ok(status_code) if status_code.is_success() => (Some(status_code), None),
+            Ok(status_code) => (
+                Some(status_code),
+                Some(format!("Receiver answered \"{status_code}\"")),
+            ),
+            Err(error_message) => (None, Some(format!("{error_message:#}"))),
+        };
+        let delivery_params = WebhookDeliveryParams {
+            subscription_id: subscription.subscription_id,
+            event_id: change_event.event_id,
+            attempt,
+            attempted_at,
+            status_code: status_code.map(|status_code| status_code.as_u16()),
+            error,
+            is_delivered: status_code.is_some_and(|status_code| status_code.is_success()),
+        };
+        let is_delivered = delivery_params.is_delivered;
+        let log_hostess = hostess.clone();
+        match tokio::task::spawn_blocking(move || {
+            log_hostess.record_webhook_delivery(&delivery_params)
+        })
+        .await
+        {
+            Ok(Ok(_)) => {}
+            Ok(Err(error_message)) => {
+                error!("Couldn't log webhook delivery: {:#}", error_message)
+            }
+            Err(join_error) => error!("Hostess didn't finish logging delivery: {}", join_error),
+        }
+        if is_delivered {
+            return;
+        }
+        if attempt < webhook_config.max_attempts {
+            tokio::time::sleep(retry_backoff(webhook_config.initial_backoff, attempt)).await;
+        }
+    }
+    warn!(
+        "Gave up sending {} to {} after {} attempts",
+        change_event, subscription, webhook_config.max_attempts
+    );
+}
+
+/// Send a change to a subscription once.
+///
+/// # Returns
+/// The status that the receiver answered with.
+async fn send_webhook(
+    http_client: &Client<HttpConnector>,
+    subscription: &WebhookSubscription,
+    change_event: &ChangeEvent,
+    payload: &[u8],
+    timestamp: u32,
+    delivery_timeout: Duration,
+) -> Result<StatusCode> {
+    let webhook_request = Request::post(&subscription.url)
+        .header(CONTENT_TYPE, "application/json")
+        .header("x-arbiter-event", change_event.kind.as_str())
+        .header("x-arbiter-event-id", change_event.event_id)
+        .header("x-arbiter-timestamp", timestamp)
+        .header(
+            "x-arbiter-signature",
+            sign_payload(&subscription.secret, timestamp, payload)?,
+        )
+        .body(Body::from(payload.to_vec()))?;
+    let webhook_response =
+        tokio::time::timeout(delivery_timeout, http_client.request(webhook_request))
+            .await
+            .context("Receiver didn't answer in time")?
+            .context("Receiver couldn't be reached")?;
+    Ok(webhook_response.status())
+}
+
+#[cfg(test)]
+mod tests {
+    // Standard library crates.
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // External crates.
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use tokio::sync::mpsc;
+    use warp::http::{HeaderMap, StatusCode};
+    use warp::hyper::body::Bytes;
+    use warp::Filter;
+
+    // Project crates.
+    use super::{
+        dispatch_webhooks, retry_backoff, sign_payload, WebhookConfig, WebhookParams,
+        WebhookSubscription,
+    };
+    use crate::clock::test_examples::test_clock;
+    use crate::config::HostessConfig;
+    use crate::datastore::failing::FailingDatastore;
+    use crate::datastore::test_examples::{seed_test_pool, test_datastore};
+    use crate::events::{ChangeEvent, ChangeEventKind};
+    use crate::hostess::Hostess;
+    use crate::logging::setup_native_logging;
+    use crate::ReservationRequest;
+
+    fn test_subscription() -> WebhookSubscription {
+        WebhookSubscription {
+            subscription_id: 1,
+            url: String::from("http://127.0.0.1:9/arbiter"),
+            event_kinds: vec![
+                ChangeEventKind::ReservationStarted,
+                ChangeEventKind::ReservationEnded,
+            ],
+            pool: Some(String::from("default")),
+            user_id: Some(42),
+            secret: String::from("correct horse battery staple"),
+            last_event_id: 10,
+            created_at: 1707165008,
+        }
+    }
+
+    fn test_event(event_id: u32, kind: ChangeEventKind, user_id: Option<u32>) -> ChangeEvent {
+        ChangeEvent {
+            event_id,
+            kind,
+            recorded_at: 1707165008,
+            pool: String::from("default"),
+            user_id,
+            payload: serde_json::json!({}),
+        }
+    }
+
+    #[test]
+    fn test_sign_payload() {
+        // HMAC-SHA256 of "1707165008.{}" with the key "Jefe".
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"Jefe").unwrap();
+        mac.update(b"1707165008.{}");
+        let expected_signature = mac.finalize().into_bytes();
+        let signature = sign_payload("Jefe", 1707165008, b"{}").unwrap();
+        assert_eq!(signature.len(), "sha256=".len() + 64);
+        let signature_bytes: Vec<u8> = (7..signature.len())
+            .step_by(2)
+            .map(|hex_start| u8::from_str_radix(&signature[hex_start..hex_start + 2], 16).unwrap())
+            .collect();
+        assert_eq!(signature_bytes, expected_signature.to_vec());
+        // Changing the timestamp changes the signature, so old deliveries can't be replayed.
+        assert_ne!(sign_payload("Jefe", 1707165009, b"{}").unwrap(), signature);
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let initial_backoff = Duration::from_secs(1);
+        assert_eq!(retry_backoff(initial_backoff, 1), Duration::from_secs(1));
+        assert_eq!(retry_backoff(initial_backoff, 2), Duration::from_secs(2));
+        assert_eq!(retry_backoff(initial_backoff, 4), Duration::from_secs(8));
+        // Huge attempt counts don't overflow.
+        assert_eq!(
+            retry_backoff(initial_backoff, u32::MAX),
+            Duration::from_secs(65536)
+        );
+    }
+
+    #[test]
+    fn test_subscription_matches() {
+        let subscription = test_subscription();
+        assert!(subscription.matches(&test_event(
+            11,
+            ChangeEventKind::ReservationStarted,
+            Some(42)
+        )));
+        // Changes that were already handed out, that are the wrong kind, or that are someone
+        // else's aren't sent.
+        assert!(!subscription.matches(&test_event(
+            10,
+            ChangeEventKind::ReservationStarted,
+            Some(42)
+        )));
+        assert!(!subscription.matches(&test_event(
+            11,
+            ChangeEventKind::ReservationCreated,
+            Some(42)
+        )));
+        assert!(!subscription.matches(&test_event(11, ChangeEventKind::ReservationEnded, Some(7))));
+        let mut other_pool = test_event(11, ChangeEventKind::ReservationEnded, Some(42));
+        other_pool.pool = String::from("other");
+        assert!(!subscription.matches(&other_pool));
+    }
+
+    #[test]
+    fn test_validate_params() {
+        let webhook_params =
+            |url: &str, event_kinds: Vec<ChangeEventKind>, secret: &str| WebhookParams {
+                url: String::from(url),
+                event_kinds,
+                pool: None,
+                user_id: None,
+                secret: String::from(secret),
+            };
+        let event_kinds = vec![ChangeEventKind::ReservationEnded];
+        let secret = "correct horse battery staple";
+        assert!(
+            webhook_params("http://127.0.0.1:8080/hooks", event_kinds.clone(), secret)
+                .validate()
+                .is_ok()
+        );
+        assert!(
+            webhook_params("https://example.com/hooks", event_kinds.clone(), secret)
+                .validate()
+                .is_err()
+        );
+        assert!(webhook_params("/hooks", event_kinds.clone(), secret)
+            .validate()
+            .is_err());
+        assert!(
+            webhook_params("http://127.0.0.1:8080/hooks", vec![], secret)
+                .validate()
+                .is_err()
+        );
+        assert!(
+            webhook_params("http://127.0.0.1:8080/hooks", event_kinds, "hunter2")
+                .validate()
+                .is_err()
+        );
+        // Subscriptions to changes that are never recorded would never hear anything.
+        for unrecorded_kind in [
+            ChangeEventKind::ReservationPreempted,
+            ChangeEventKind::WaitlistBooked,
+        ] {
+            assert!(webhook_params(
+                "http://127.0.0.1:8080/hooks",
+                vec![ChangeEventKind::ReservationEnded, unrecorded_kind],
+                secret
+            )
+            .validate()
+            .is_err());
+        }
+    }
+
+    // Test if changes are signed, retried until they're accepted, and logged, with a local
+    // receiver that turns the first attempt away.
+    #[tokio::test]
+    async fn test_deliver_with_retries() {
+        let _ = setup_native_logging();
+        let test_pool = "test_deliver_with_retries";
+        let (delivery_sender, mut delivery_receiver) = mpsc::unbounded_channel();
+        let attempt_counter = Arc::new(AtomicU32::new(0));
+        let receiver_route = warp::post()
+            .and(warp::header::headers_cloned())
+            .and(warp::body::bytes())
+            .map(move |headers: HeaderMap, body: Bytes| {
+                delivery_sender.send((headers, body)).unwrap();
+                // Turn the first attempt away so it has to be retried.
+                match attempt_counter.fetch_add(1, Ordering::SeqCst) {
+                    0 => StatusCode::SERVICE_UNAVAILABLE,
+                    _ => StatusCode::OK,
+                }
+            });
+        let (receiver_address, receiver) =
+            warp::serve(receiver_route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(receiver);
+
+        let datastore = tokio::task::spawn_blocking(move || {
+            seed_test_pool(test_pool);
+            test_datastore()
+        })
+        .await
+        .unwrap();
+        let hostess = Arc::new(Hostess::new(
+            Arc::new(test_clock()),
+            datastore,
+            HostessConfig::default(),
+        ));
+        let secret = "correct horse battery staple";
+        let subscription = {
+            let hostess = hostess.clone();
+            tokio::task::spawn_blocking(move || {
+                hostess.register_webhook(&WebhookParams {
+                    url: format!("http://{receiver_address}/arbiter"),
+                    event_kinds: vec![ChangeEventKind::ReservationCreated],
+                    pool: Some(String::from(test_pool)),
+                    user_id: None,
+                    secret: String::from(secret),
+                })
+            })
+            .await
+            .unwrap()
+            .unwrap()
+        };
+        let webhook_config = WebhookConfig {
+            initial_backoff: Duration::from_millis(10),
+            ..Default::default()
+        };
+        tokio::spawn(dispatch_webhooks(hostess.clone(), webhook_config));
+        let reservation = {
+            let hostess = hostess.clone();
+            tokio::task::spawn_blocking(move || {
+                let test_request =
+                    ReservationRequest::new(1707165008, 1707168608, 1, 42).in_pool(test_pool);
+                hostess.process_reservation(&test_request).unwrap().unwrap()
+            })
+            .await
+            .unwrap()
+        };
+
+        // Both attempts carry the same signed change.
+        for _ in 0..2 {
+            let (headers, body) =
+                tokio::time::timeout(Duration::from_secs(10), delivery_receiver.recv())
+                    .await
+                    .expect("No delivery arrived")
+                    .unwrap();
+            assert_eq!(headers["x-arbiter-event"], "reservation_created");
+            let timestamp: u32 = headers["x-arbiter-timestamp"]
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert_eq!(
+                headers["x-arbiter-signature"],
+                sign_payload(secret, timestamp, &body).unwrap().as_str()
+            );
+            let change_event: ChangeEvent = serde_json::from_slice(&body).unwrap();
+            assert_eq!(change_event.kind, ChangeEventKind::ReservationCreated);
+            assert_eq!(
+                change_event.payload["reservation_id"],
+                reservation.reservation_id
+            );
+        }
+
+        // The refusal and the delivery are both logged.
+        let mut deliveries = Vec::new();
+        for _ in 0..100 {
+            let hostess = hostess.clone();
+            let subscription_id = subscription.subscription_id;
+            deliveries =
+                tokio::task::spawn_blocking(move || hostess.webhook_deliveries(subscription_id))
+                    .await
+                    .unwrap()
+                    .unwrap();
+            if deliveries.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(deliveries.len(), 2);
+        assert_eq!(deliveries[0].attempt, 1);
+        assert_eq!(deliveries[0].status_code, Some(503));
+        assert!(!deliveries[0].is_delivered);
+        assert!(deliveries[0].error.is_some());
+        assert_eq!(deliveries[1].attempt, 2);
+        assert_eq!(deliveries[1].status_code, Some(200));
+        assert!(deliveries[1].is_delivered);
+        assert_eq!(deliveries[1].error, None);
+        assert_eq!(deliveries[0].event_id, deliveries[1].event_id);
+    }
+
+    // Test if a change whose webhooks couldn't be claimed is still delivered once the datastore
+    // comes back.
+    #[tokio::test]
+    async fn test_deliver_after_failed_claim() {
+        let _ = setup_native_logging();
+        let test_pool = "test_deliver_after_failed_claim";
+        let (delivery_sender, mut delivery_receiver) = mpsc::unbounded_channel();
+        let receiver_route = warp::post()
+            .and(warp::body::bytes())
+            .map(move |body: Bytes| {
+                delivery_sender.send(body).unwrap();
+                StatusCode::OK
+            });
+        let (receiver_address, receiver) =
+            warp::serve(receiver_route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(receiver);
+
+        let failing_datastore = tokio::task::spawn_blocking(move || {
+            seed_test_pool(test_pool);
+            Arc::new(FailingDatastore::new(test_datastore()))
+        })
+        .await
+        .unwrap();
+        let hostess = Arc::new(Hostess::new(
+            Arc::new(test_clock()),
+            failing_datastore.clone(),
+            HostessConfig::default(),
+        ));
+        {
+            let hostess = hostess.clone();
+            tokio::task::spawn_blocking(move || {
+                hostess.register_webhook(&WebhookParams {
+                    url: format!("http://{receiver_address}/arbiter"),
+                    event_kinds: vec![ChangeEventKind::ReservationCreated],
+                    pool: Some(String::from(test_pool)),
+                    user_id: None,
+                    secret: String::from("correct horse battery staple"),
+                })
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        }
+        tokio::spawn(dispatch_webhooks(hostess.clone(), WebhookConfig::default()));
+        failing_datastore.fail_next("advance_webhook_subscriptions");
+        let reservation = {
+            let hostess = hostess.clone();
+            tokio::task::spawn_blocking(move || {
+                let test_request =
+                    ReservationRequest::new(1707165008, 1707168608, 1, 42).in_pool(test_pool);
+                hostess.process_reservation(&test_request).unwrap().unwrap()
+            })
+            .await
+            .unwrap()
+        };
+
+        // The dispatcher waits a moment before following changes again.
+        let body = tokio::time::timeout(Duration::from_secs(20), delivery_receiver.recv())
+            .await
+            .expect("No delivery arrived")
+            .unwrap();
+        let change_event: ChangeEvent = serde_json::from_slice(&body).unwrap();
+        assert_eq!(change_event.kind, ChangeEventKind::ReservationCreated);
+        assert_eq!(
+            change_event.payload["reservation_id"],
+            reservation.reservation_id
+        );
+    }
+}